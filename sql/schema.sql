@@ -11,6 +11,8 @@ CREATE TABLE IF NOT EXISTS users (
     created INT NOT NULL DEFAULT 0,
     login INT NOT NULL DEFAULT 0,
     theme VARCHAR(255) DEFAULT '',
+    tos_accepted INT NOT NULL DEFAULT 0,
+    picture VARCHAR(255) NOT NULL DEFAULT '',
     PRIMARY KEY (uid),
     UNIQUE KEY name (name),
     KEY mail (mail)
@@ -57,8 +59,8 @@ CREATE TABLE IF NOT EXISTS permission (
 
 -- Default permissions
 INSERT IGNORE INTO permission (rid, perm) VALUES (1, 'access content, access comments');
-INSERT IGNORE INTO permission (rid, perm) VALUES (2, 'access content, access comments, post comments, create page content');
-INSERT IGNORE INTO permission (rid, perm) VALUES (3, 'access content, access comments, post comments, administer comments, create page content, edit own page content, edit any page content, delete own page content, delete any page content, administer nodes, administer users');
+INSERT IGNORE INTO permission (rid, perm) VALUES (2, 'access content, access comments, post comments, create page content, nominate content');
+INSERT IGNORE INTO permission (rid, perm) VALUES (3, 'access content, access comments, post comments, administer comments, edit any comment, delete any comment, moderate comments, create page content, edit own page content, edit any page content, delete own page content, delete any page content, administer nodes, administer users, administer filters, administer site configuration, administer access control, access administration pages, access site reports, create url aliases');
 
 -- Node table
 CREATE TABLE IF NOT EXISTS node (
@@ -293,3 +295,114 @@ CREATE TABLE IF NOT EXISTS node_comment_statistics (
 -- Add comment module to system
 INSERT IGNORE INTO system (filename, name, type, description, status, weight) VALUES
 ('modules/comment', 'comment', 'module', 'Allows users to comment on and discuss published content.', 1, 0);
+
+-- URL aliases (path module)
+CREATE TABLE IF NOT EXISTS url_alias (
+    pid INT UNSIGNED NOT NULL AUTO_INCREMENT,
+    src VARCHAR(128) NOT NULL DEFAULT '',
+    dst VARCHAR(128) NOT NULL DEFAULT '',
+    PRIMARY KEY (pid),
+    UNIQUE KEY dst (dst),
+    KEY src (src)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Node view history, used to mark new content and comments
+CREATE TABLE IF NOT EXISTS history (
+    uid INT UNSIGNED NOT NULL DEFAULT 0,
+    nid INT UNSIGNED NOT NULL DEFAULT 0,
+    timestamp INT NOT NULL DEFAULT 0,
+    PRIMARY KEY (uid, nid),
+    KEY nid (nid)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Batched operations, processed one chunk per request or `cargo run -- batch`
+CREATE TABLE IF NOT EXISTS batch (
+    bid INT UNSIGNED NOT NULL AUTO_INCREMENT,
+    uid INT UNSIGNED NOT NULL DEFAULT 0,
+    job_type VARCHAR(64) NOT NULL DEFAULT '',
+    state TEXT NOT NULL,
+    total INT NOT NULL DEFAULT 0,
+    processed INT NOT NULL DEFAULT 0,
+    status VARCHAR(16) NOT NULL DEFAULT 'active',
+    message TEXT,
+    destination VARCHAR(255) NOT NULL DEFAULT '',
+    locked INT NOT NULL DEFAULT 0,
+    created INT NOT NULL DEFAULT 0,
+    updated INT NOT NULL DEFAULT 0,
+    PRIMARY KEY (bid),
+    KEY status (status)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Scheduled publishing: unpublished nodes and when cron should publish them
+CREATE TABLE IF NOT EXISTS scheduler (
+    nid INT UNSIGNED NOT NULL,
+    publish_on INT NOT NULL DEFAULT 0,
+    PRIMARY KEY (nid),
+    KEY publish_on (publish_on)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- System log of administrative actions (watchdog module)
+CREATE TABLE IF NOT EXISTS watchdog (
+    wid INT UNSIGNED NOT NULL AUTO_INCREMENT,
+    uid INT UNSIGNED NOT NULL DEFAULT 0,
+    type VARCHAR(16) NOT NULL DEFAULT '',
+    message LONGTEXT NOT NULL,
+    severity TINYINT UNSIGNED NOT NULL DEFAULT 0,
+    link VARCHAR(255) NOT NULL DEFAULT '',
+    location TEXT NOT NULL,
+    timestamp INT NOT NULL DEFAULT 0,
+    PRIMARY KEY (wid),
+    KEY type (type),
+    KEY severity (severity)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Messages sent through the site-wide contact form (contact module)
+CREATE TABLE IF NOT EXISTS contact_log (
+    mid INT UNSIGNED NOT NULL AUTO_INCREMENT,
+    uid INT UNSIGNED NOT NULL DEFAULT 0,
+    name VARCHAR(60) NOT NULL DEFAULT '',
+    mail VARCHAR(64) NOT NULL DEFAULT '',
+    subject VARCHAR(255) NOT NULL DEFAULT '',
+    message LONGTEXT NOT NULL,
+    hostname VARCHAR(128) NOT NULL DEFAULT '',
+    timestamp INT NOT NULL DEFAULT 0,
+    PRIMARY KEY (mid),
+    KEY hostname_timestamp (hostname, timestamp)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Local accounts of users who log in through an external provider
+CREATE TABLE IF NOT EXISTS authmap (
+    aid INT UNSIGNED NOT NULL AUTO_INCREMENT,
+    uid INT UNSIGNED NOT NULL DEFAULT 0,
+    authname VARCHAR(128) NOT NULL DEFAULT '',
+    module VARCHAR(128) NOT NULL DEFAULT '',
+    PRIMARY KEY (aid),
+    UNIQUE KEY module_authname (module, authname),
+    KEY uid (uid)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Posts their authors put forward for the front page; editors approve
+-- (promoting the post) or decline each one
+CREATE TABLE IF NOT EXISTS node_nomination (
+    nid INT UNSIGNED NOT NULL,
+    uid INT UNSIGNED NOT NULL DEFAULT 0,
+    status VARCHAR(16) NOT NULL DEFAULT 'pending',
+    created INT NOT NULL DEFAULT 0,
+    decided INT NOT NULL DEFAULT 0,
+    decided_by INT UNSIGNED NOT NULL DEFAULT 0,
+    PRIMARY KEY (nid),
+    KEY uid_created (uid, created),
+    KEY status_decided (status, decided)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
+
+-- Events counted for flood control, such as failed logins; `identifier`
+-- is who or what the event is counted against
+CREATE TABLE IF NOT EXISTS flood (
+    fid INT UNSIGNED NOT NULL AUTO_INCREMENT,
+    event VARCHAR(64) NOT NULL DEFAULT '',
+    identifier VARCHAR(255) NOT NULL DEFAULT '',
+    timestamp INT NOT NULL DEFAULT 0,
+    PRIMARY KEY (fid),
+    KEY allow (event, identifier, timestamp),
+    KEY timestamp (timestamp)
+) ENGINE=InnoDB DEFAULT CHARSET=utf8mb4;
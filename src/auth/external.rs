@@ -0,0 +1,78 @@
+//! Authentication against systems other than the local `users` table, such
+//! as LDAP or an OpenID provider. Each provider is a [`ExternalAuth`]
+//! registered in the [`ExternalAuthRegistry`] at startup; the login form
+//! tries them in order before checking the local password.
+
+use axum::async_trait;
+use std::sync::Arc;
+
+/// What the visitor typed into the login form.
+#[derive(Debug, Clone, Copy)]
+pub struct Credentials<'a> {
+    pub username: &'a str,
+    pub password: &'a str,
+}
+
+/// An identity vouched for by a provider. `authname` is the provider's own
+/// name for the account and is what gets mapped to a local uid in `authmap`.
+#[derive(Debug, Clone)]
+pub struct ExternalAccount {
+    pub authname: String,
+    pub mail: Option<String>,
+}
+
+#[async_trait]
+pub trait ExternalAuth: Send + Sync {
+    /// Stored as `authmap.module`, so it must not change once accounts have
+    /// been mapped.
+    fn name(&self) -> &'static str;
+
+    /// The account the credentials belong to, or `None` when this provider
+    /// does not accept them. Providers log their own failures; the login
+    /// form then falls through to the next provider.
+    async fn authenticate(&self, credentials: Credentials<'_>) -> Option<ExternalAccount>;
+}
+
+#[derive(Clone, Default)]
+pub struct ExternalAuthRegistry {
+    providers: Vec<Arc<dyn ExternalAuth>>,
+}
+
+impl ExternalAuthRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn register(&mut self, provider: impl ExternalAuth + 'static) {
+        self.providers.push(Arc::new(provider));
+    }
+
+    /// The first provider to accept the credentials, with the account it
+    /// vouches for.
+    pub async fn authenticate(
+        &self,
+        credentials: Credentials<'_>,
+    ) -> Option<(&'static str, ExternalAccount)> {
+        for provider in &self.providers {
+            if let Some(account) = provider.authenticate(credentials).await {
+                return Some((provider.name(), account));
+            }
+        }
+        None
+    }
+}
+
+/// Placeholder showing where a real provider plugs in. It never accepts
+/// anyone, so every login falls through to the local password check.
+pub struct StubAuth;
+
+#[async_trait]
+impl ExternalAuth for StubAuth {
+    fn name(&self) -> &'static str {
+        "stub"
+    }
+
+    async fn authenticate(&self, _credentials: Credentials<'_>) -> Option<ExternalAccount> {
+        None
+    }
+}
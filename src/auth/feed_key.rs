@@ -0,0 +1,46 @@
+//! Keys for private feeds. Feed readers fetch without the reader's session,
+//! so the URL of a feed only its owner may read carries an HMAC for the
+//! account instead. The account's password hash is part of it, so changing
+//! the password revokes every link given out before.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::auth::reset::{hashes_match, private_key};
+use crate::db::Pool;
+use crate::models::User;
+
+fn hash(key: &str, feed: &str, uid: u32, pass: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("feed:{}:{}:{}", feed, uid, pass).as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// The key that opens `user`'s `feed`.
+pub async fn key(pool: &Pool, feed: &str, user: &User) -> Result<String, sqlx::Error> {
+    Ok(hash(&private_key(pool).await?, feed, user.uid, &user.pass))
+}
+
+/// Whether `given` opens `user`'s `feed`.
+pub async fn verify(pool: &Pool, feed: &str, user: &User, given: &str) -> Result<bool, sqlx::Error> {
+    Ok(hashes_match(&key(pool, feed, user).await?, given))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_belong_to_one_feed_account_and_password() {
+        let key = hash("secret", "discussions", 2, "hash");
+        assert_eq!(key, hash("secret", "discussions", 2, "hash"));
+        assert_ne!(key, hash("secret", "tracker", 2, "hash"));
+        assert_ne!(key, hash("secret", "discussions", 3, "hash"));
+        assert_ne!(key, hash("secret", "discussions", 2, "changed"));
+        assert_ne!(key, hash("other", "discussions", 2, "hash"));
+    }
+}
@@ -3,25 +3,123 @@ use axum::{
     middleware::Next,
     response::Response,
 };
-use sqlx::MySqlPool;
+use std::sync::Arc;
 use tower_sessions::Session;
 
-use crate::models::{session::SESSION_USER_KEY, User};
+use crate::config::{Config, SessionConfig};
+use crate::db::Pool;
+use crate::models::{
+    session::{pass_fingerprint, SESSION_LOGIN_KEY, SESSION_PASS_KEY, SESSION_USER_KEY},
+    User,
+};
 
 #[derive(Clone)]
 pub struct CurrentUser(pub Option<User>);
 
 pub async fn auth_middleware(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
+    State(config): State<Arc<Config>>,
     session: Session,
     mut request: Request,
     next: Next,
 ) -> Response {
     let user = match session.get::<u32>(SESSION_USER_KEY).await {
-        Ok(Some(uid)) => User::find_by_uid(&pool, uid).await.ok().flatten(),
+        Ok(Some(_)) if login_expired(&session, &config.sessions).await => {
+            if let Err(e) = session.flush().await {
+                tracing::warn!("Could not end an expired session: {}", e);
+            }
+            None
+        }
+        Ok(Some(uid)) => match User::find_by_uid(&pool, uid).await.ok().flatten() {
+            Some(user) if password_changed(&session, &user).await => {
+                if let Err(e) = session.flush().await {
+                    tracing::warn!("Could not end a session after a password change: {}", e);
+                }
+                None
+            }
+            user => user,
+        },
         _ => None,
     };
 
     request.extensions_mut().insert(CurrentUser(user));
     next.run(request).await
 }
+
+/// Whether the login in `session` is older than the absolute session
+/// lifetime. Sessions opened before the login time was recorded start
+/// counting now.
+async fn login_expired(session: &Session, sessions: &SessionConfig) -> bool {
+    if sessions.max_lifetime == 0 {
+        return false;
+    }
+
+    let now = chrono::Utc::now().timestamp();
+    match session.get::<i64>(SESSION_LOGIN_KEY).await {
+        Ok(Some(login_time)) => now.saturating_sub(login_time) > sessions.max_lifetime as i64,
+        Ok(None) => {
+            if let Err(e) = session.insert(SESSION_LOGIN_KEY, now).await {
+                tracing::warn!("Could not record the session login time: {}", e);
+            }
+            false
+        }
+        Err(_) => true,
+    }
+}
+
+/// Whether `user`'s password changed since `session` was opened. Sessions
+/// opened before the fingerprint was recorded take the current one.
+async fn password_changed(session: &Session, user: &User) -> bool {
+    let fingerprint = pass_fingerprint(&user.pass);
+    match session.get::<String>(SESSION_PASS_KEY).await {
+        Ok(Some(stored)) => stored != fingerprint,
+        Ok(None) => {
+            if let Err(e) = session.insert(SESSION_PASS_KEY, fingerprint).await {
+                tracing::warn!("Could not record the session password fingerprint: {}", e);
+            }
+            false
+        }
+        Err(_) => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tower_sessions::MemoryStore;
+
+    const DAY: i64 = 86400;
+
+    fn lifetime(max_lifetime: u64) -> SessionConfig {
+        SessionConfig { idle_timeout: DAY as u64, max_lifetime }
+    }
+
+    /// A session whose login was `age` seconds ago.
+    async fn logged_in(age: i64) -> Session {
+        let session = Session::new(None, Arc::new(MemoryStore::default()), None);
+        session.insert(SESSION_USER_KEY, 2u32).await.unwrap();
+        session.insert(SESSION_LOGIN_KEY, chrono::Utc::now().timestamp() - age).await.unwrap();
+        session
+    }
+
+    #[tokio::test]
+    async fn sessions_past_the_absolute_lifetime_expire() {
+        let sessions = lifetime(30 * DAY as u64);
+        assert!(!login_expired(&logged_in(DAY).await, &sessions).await);
+        assert!(!login_expired(&logged_in(29 * DAY).await, &sessions).await);
+        assert!(login_expired(&logged_in(30 * DAY + 1).await, &sessions).await);
+    }
+
+    #[tokio::test]
+    async fn no_lifetime_never_expires() {
+        assert!(!login_expired(&logged_in(365 * DAY).await, &lifetime(0)).await);
+    }
+
+    #[tokio::test]
+    async fn older_sessions_start_counting_now() {
+        let session = Session::new(None, Arc::new(MemoryStore::default()), None);
+        session.insert(SESSION_USER_KEY, 2u32).await.unwrap();
+        assert!(!login_expired(&session, &lifetime(DAY as u64)).await);
+        assert!(session.get::<i64>(SESSION_LOGIN_KEY).await.unwrap().is_some());
+    }
+}
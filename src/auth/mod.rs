@@ -1,5 +1,11 @@
+pub mod external;
+pub mod feed_key;
 pub mod middleware;
 pub mod password;
+pub mod reset;
+pub mod throttle;
 
+pub use external::{Credentials, ExternalAuthRegistry};
 pub use middleware::auth_middleware;
 pub use password::{hash_password, verify_password};
+pub use throttle::LoginThrottle;
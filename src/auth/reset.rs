@@ -0,0 +1,89 @@
+//! One-time login links for users who lost their password. A link carries
+//! the account, the time it was issued and an HMAC over both together with
+//! the account's password hash and last login. Logging in with it updates
+//! the last login, and setting a new password changes the hash, so either
+//! way the link stops working once it has been used.
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+use crate::db::Pool;
+use crate::models::{User, Variable};
+
+/// Seconds a link stays valid unless `user_password_reset_timeout` says
+/// otherwise.
+const DEFAULT_TIMEOUT: i64 = 86400;
+
+/// Why a one-time login link was refused.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResetError {
+    Expired,
+    Invalid,
+}
+
+/// The site's signing secret, created on first use.
+pub(crate) async fn private_key(pool: &Pool) -> Result<String, sqlx::Error> {
+    if let Some(key) = Variable::get(pool, "drupal_private_key").await?.filter(|k| !k.is_empty()) {
+        return Ok(key);
+    }
+
+    let mut bytes = [0u8; 32];
+    OsRng.fill_bytes(&mut bytes);
+    let key: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    Variable::set(pool, "drupal_private_key", &key).await?;
+    Ok(key)
+}
+
+/// Seconds a link stays valid, from `user_password_reset_timeout`.
+pub async fn timeout(pool: &Pool) -> i64 {
+    Variable::get_or_default(pool, "user_password_reset_timeout", "86400")
+        .await
+        .parse()
+        .ok()
+        .filter(|t| *t > 0)
+        .unwrap_or(DEFAULT_TIMEOUT)
+}
+
+fn hash(key: &str, user: &User, timestamp: i64) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(key.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(format!("{}:{}:{}:{}", user.uid, timestamp, user.login, user.pass).as_bytes());
+    mac.finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Path of a fresh one-time login link for `user`.
+pub async fn reset_path(pool: &Pool, user: &User) -> Result<String, sqlx::Error> {
+    let timestamp = chrono::Utc::now().timestamp();
+    let key = private_key(pool).await?;
+    Ok(format!("/user/reset/{}/{}/{}", user.uid, timestamp, hash(&key, user, timestamp)))
+}
+
+/// Check a link issued at `timestamp` with `link_hash` for `user`.
+pub async fn verify(pool: &Pool, user: &User, timestamp: i64, link_hash: &str) -> Result<Result<(), ResetError>, sqlx::Error> {
+    let now = chrono::Utc::now().timestamp();
+    if timestamp > now {
+        return Ok(Err(ResetError::Invalid));
+    }
+    if now - timestamp > timeout(pool).await {
+        return Ok(Err(ResetError::Expired));
+    }
+
+    let key = private_key(pool).await?;
+    let expected = hash(&key, user, timestamp);
+    Ok(if hashes_match(&expected, link_hash) { Ok(()) } else { Err(ResetError::Invalid) })
+}
+
+/// Compare hashes without stopping at the first difference, so the time
+/// taken tells nothing about how much of `given` was right.
+pub(crate) fn hashes_match(expected: &str, given: &str) -> bool {
+    expected.len() == given.len()
+        && expected
+            .bytes()
+            .zip(given.bytes())
+            .fold(0u8, |diff, (a, b)| diff | (a ^ b))
+            == 0
+}
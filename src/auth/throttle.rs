@@ -0,0 +1,131 @@
+//! Progressive delay for failed logins. Every failed attempt for a username
+//! from one address makes the next answer slower, up to a cap, so guessing
+//! passwords by script becomes impractically slow while someone who
+//! mistyped is only held up for a moment and is never locked out.
+
+use serde::Serialize;
+use std::time::Duration;
+
+use crate::db::Pool;
+use crate::models::{Flood, Variable};
+
+/// Flood event failed logins are registered under.
+pub const FAILED_LOGIN_EVENT: &str = "failed_login";
+
+/// Failed logins older than this many seconds no longer add to the delay.
+pub const FAILED_LOGIN_WINDOW: i64 = 3600;
+
+const DEFAULT_MESSAGE: &str =
+    "There have been several failed login attempts. Each further attempt will take longer to answer.";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct LoginThrottle {
+    /// Delay after the first failure, in milliseconds. 0 turns the delay off.
+    pub base: u64,
+    /// Factor the delay grows by with each further failure.
+    pub multiplier: u64,
+    /// Longest delay, in milliseconds.
+    pub max: u64,
+    /// Shown with the error once the delay has started to grow.
+    pub message: String,
+}
+
+impl LoginThrottle {
+    pub async fn load(pool: &Pool) -> Self {
+        let number = |value: String, default: u64| value.trim().parse().unwrap_or(default);
+        LoginThrottle {
+            base: number(Variable::get_or_default(pool, "user_login_delay_base", "500").await, 500),
+            multiplier: number(Variable::get_or_default(pool, "user_login_delay_multiplier", "2").await, 2),
+            max: number(Variable::get_or_default(pool, "user_login_delay_max", "10000").await, 10000),
+            message: Variable::get_or_default(pool, "user_login_throttle_message", DEFAULT_MESSAGE).await,
+        }
+    }
+
+    /// How long to wait before answering after the `failures`th failed
+    /// attempt in the window: `base`, then multiplied by `multiplier` for
+    /// each further failure, never more than `max`.
+    pub fn delay(&self, failures: u32) -> Duration {
+        if failures == 0 {
+            return Duration::ZERO;
+        }
+        let growth = self.multiplier.max(1).saturating_pow(failures - 1);
+        Duration::from_millis(self.base.saturating_mul(growth).min(self.max))
+    }
+
+    /// The message for the login form after `failures` failed attempts,
+    /// once they are enough for the delay to have grown.
+    pub fn message(&self, failures: u32) -> Option<&str> {
+        let throttled = failures > 1 && self.base > 0;
+        Some(self.message.trim()).filter(|message| throttled && !message.is_empty())
+    }
+}
+
+/// The flood identifier failed logins for `username` from `ip` are counted
+/// against. Case-insensitive like usernames themselves.
+pub fn identifier(username: &str, ip: &str) -> String {
+    format!("{}@{}", username.trim().to_lowercase(), ip)
+}
+
+/// Record a failed login and wait out the resulting delay. Only the request
+/// that failed sleeps; nothing else is held up. Returns the number of
+/// failures in the window, including this one.
+pub async fn failed_login(pool: &Pool, throttle: &LoginThrottle, identifier: &str) -> Result<u32, sqlx::Error> {
+    Flood::register(pool, FAILED_LOGIN_EVENT, identifier).await?;
+    let failures = Flood::count(pool, FAILED_LOGIN_EVENT, identifier, FAILED_LOGIN_WINDOW).await?;
+    tokio::time::sleep(throttle.delay(failures)).await;
+    Ok(failures)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::test_site;
+    use std::time::Instant;
+
+    fn throttle(base: u64, multiplier: u64, max: u64) -> LoginThrottle {
+        LoginThrottle { base, multiplier, max, message: DEFAULT_MESSAGE.to_string() }
+    }
+
+    #[test]
+    fn delay_grows_with_failures_up_to_the_cap() {
+        let throttle = throttle(500, 2, 3000);
+        let delays: Vec<u64> = (0..6).map(|failures| throttle.delay(failures).as_millis() as u64).collect();
+        assert_eq!(delays, [0, 500, 1000, 2000, 3000, 3000]);
+        assert_eq!(throttle.delay(u32::MAX), Duration::from_millis(3000));
+    }
+
+    #[test]
+    fn no_delay_or_message_when_turned_off() {
+        let off = throttle(0, 2, 3000);
+        assert_eq!(off.delay(5), Duration::ZERO);
+        assert_eq!(off.message(5), None);
+
+        let on = throttle(500, 2, 3000);
+        assert_eq!(on.message(1), None, "a single typo gets no warning");
+        assert_eq!(on.message(2), Some(DEFAULT_MESSAGE));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn each_failed_login_waits_longer() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let throttle = throttle(20, 2, 1000);
+        let guesser = identifier("Victim", "10.0.0.1");
+
+        let mut waited = Vec::new();
+        for expected in 1..=3 {
+            let started = Instant::now();
+            assert_eq!(failed_login(pool, &throttle, &guesser).await.unwrap(), expected);
+            waited.push(started.elapsed());
+        }
+        for (elapsed, floor) in waited.iter().zip([20, 40, 80]) {
+            assert!(*elapsed >= Duration::from_millis(floor), "{:?} < {}ms", elapsed, floor);
+        }
+
+        // The count is per username and address.
+        let elsewhere = identifier("victim", "10.0.0.2");
+        assert_eq!(failed_login(pool, &throttle, &elsewhere).await.unwrap(), 1);
+        assert_eq!(identifier(" Victim ", "10.0.0.1"), guesser);
+    }
+}
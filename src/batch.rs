@@ -0,0 +1,379 @@
+//! Batched operations: long-running admin jobs that are split into chunks,
+//! one chunk per request to the batch progress page (or per pass of
+//! `cargo run -- batch`). Every job keeps a cursor in its serialized state
+//! and must tolerate re-running a chunk whose progress was never saved.
+
+use serde::{Deserialize, Serialize};
+use std::time::Duration;
+
+use crate::db::Pool;
+use crate::mail::{self, Mail};
+use crate::models::batch::{Batch, BATCH_ACTIVE, BATCH_DONE, BATCH_FAILED};
+use crate::models::{Alias, User, Variable, Watchdog, WATCHDOG_NOTICE, WATCHDOG_WARNING};
+
+/// Items handled per chunk unless the `batch_chunk_size` variable says otherwise.
+const DEFAULT_CHUNK_SIZE: i64 = 50;
+
+/// Numbered variants tried before a node is left without a generated alias.
+const MAX_ALIAS_SUFFIX: u32 = 100;
+
+/// Seconds after which a chunk lock is considered abandoned.
+const LOCK_TIMEOUT: i32 = 60;
+
+/// Most e-mails sent per chunk unless the `mail_batch_limit` variable says
+/// otherwise, whatever the general chunk size.
+const DEFAULT_MAIL_BATCH_LIMIT: i64 = 20;
+
+/// Pause between two e-mails, so a broadcast does not flood the mail server.
+const MAIL_SEND_INTERVAL: Duration = Duration::from_millis(200);
+
+/// The jobs the batch runner knows how to execute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BatchJob {
+    /// Give every node without a URL alias one derived from its title.
+    GenerateNodeAliases,
+    /// Send an e-mail to every active user, or every user with one role.
+    MailUsers,
+}
+
+impl BatchJob {
+    pub fn job_type(&self) -> &'static str {
+        match self {
+            BatchJob::GenerateNodeAliases => "generate_node_aliases",
+            BatchJob::MailUsers => "mail_users",
+        }
+    }
+
+    pub fn from_job_type(job_type: &str) -> Option<Self> {
+        match job_type {
+            "generate_node_aliases" => Some(BatchJob::GenerateNodeAliases),
+            "mail_users" => Some(BatchJob::MailUsers),
+            _ => None,
+        }
+    }
+
+    pub fn title(&self) -> &'static str {
+        match self {
+            BatchJob::GenerateNodeAliases => "Generating URL aliases",
+            BatchJob::MailUsers => "Sending e-mail",
+        }
+    }
+
+    /// Number of items the job will process, counted when it is queued.
+    async fn total(&self, pool: &Pool, state: &str) -> Result<i64, sqlx::Error> {
+        match self {
+            BatchJob::GenerateNodeAliases => {
+                let (count,): (i64,) = sqlx::query_as(
+                    "SELECT COUNT(*) FROM node n
+                     LEFT JOIN url_alias ua ON ua.src = CONCAT('node/', n.nid)
+                     WHERE ua.pid IS NULL",
+                )
+                .fetch_one(pool)
+                .await?;
+                Ok(count)
+            }
+            BatchJob::MailUsers => {
+                let state: MailUsersState = serde_json::from_str(state).unwrap_or_default();
+                User::count_mail_recipients(pool, state.rid).await
+            }
+        }
+    }
+
+    /// Run one chunk, advancing `state`. Returns how many items were handled
+    /// and whether the job has nothing left to do.
+    async fn run_chunk(
+        &self,
+        pool: &Pool,
+        state: &str,
+        limit: i64,
+    ) -> Result<(String, i64, bool), sqlx::Error> {
+        match self {
+            BatchJob::GenerateNodeAliases => {
+                let mut cursor: NodeCursor = serde_json::from_str(state).unwrap_or_default();
+                let (processed, finished) = generate_node_aliases(pool, &mut cursor, limit).await?;
+                let state = serde_json::to_string(&cursor).unwrap_or_default();
+                Ok((state, processed, finished))
+            }
+            BatchJob::MailUsers => {
+                let mut mail_state: MailUsersState = serde_json::from_str(state).unwrap_or_default();
+                let limit = Variable::get(pool, "mail_batch_limit")
+                    .await?
+                    .and_then(|v| v.parse().ok())
+                    .filter(|&n: &i64| n > 0)
+                    .unwrap_or(DEFAULT_MAIL_BATCH_LIMIT)
+                    .min(limit);
+                let (processed, finished) = mail_users(pool, &mut mail_state, limit).await?;
+                let state = serde_json::to_string(&mail_state).unwrap_or_default();
+                Ok((state, processed, finished))
+            }
+        }
+    }
+
+    /// What a finished job has to report, shown on the progress page.
+    fn summary(&self, state: &str) -> Option<String> {
+        match self {
+            BatchJob::GenerateNodeAliases => None,
+            BatchJob::MailUsers => {
+                let state: MailUsersState = serde_json::from_str(state).unwrap_or_default();
+                Some(state.summary())
+            }
+        }
+    }
+}
+
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+struct NodeCursor {
+    last_nid: u32,
+}
+
+/// The message a `MailUsers` batch sends, who it goes to and how far it
+/// has got.
+#[derive(Debug, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct MailUsersState {
+    /// The user who queued the broadcast.
+    sender: u32,
+    /// Only users with this role; everyone active when unset.
+    rid: Option<u32>,
+    subject: String,
+    body: String,
+    last_uid: u32,
+    sent: u32,
+    failed: u32,
+}
+
+impl MailUsersState {
+    pub fn new(sender: u32, rid: Option<u32>, subject: String, body: String) -> Self {
+        Self {
+            sender,
+            rid,
+            subject,
+            body,
+            ..Default::default()
+        }
+    }
+
+    fn summary(&self) -> String {
+        if self.failed > 0 {
+            format!(
+                "E-mail \"{}\" sent to {} user(s); sending to {} user(s) failed.",
+                self.subject, self.sent, self.failed
+            )
+        } else {
+            format!("E-mail \"{}\" sent to {} user(s).", self.subject, self.sent)
+        }
+    }
+}
+
+/// Queue a job for `uid`, starting from `state` (the job's JSON cursor,
+/// `"{}"` for a fresh start); the progress page sends them to
+/// `destination` once it has finished.
+pub async fn enqueue(
+    pool: &Pool,
+    job: BatchJob,
+    state: &str,
+    uid: u32,
+    destination: &str,
+) -> Result<u32, sqlx::Error> {
+    let total = job.total(pool, state).await?;
+    Batch::create(pool, uid, job.job_type(), state, total as i32, destination).await
+}
+
+/// Process the next chunk of a batch, if it is active and no other request
+/// is already working on it. Returns the batch as it stands afterwards.
+pub async fn process_chunk(pool: &Pool, bid: u32) -> Result<Option<Batch>, sqlx::Error> {
+    let Some(batch) = Batch::find_by_id(pool, bid).await? else {
+        return Ok(None);
+    };
+
+    if batch.status != BATCH_ACTIVE || !Batch::claim(pool, bid, LOCK_TIMEOUT).await? {
+        return Ok(Some(batch));
+    }
+
+    let Some(job) = BatchJob::from_job_type(&batch.job_type) else {
+        let message = format!("Unknown batch job type '{}'", batch.job_type);
+        Batch::save_progress(pool, bid, &batch.state, batch.processed, BATCH_FAILED, Some(&message))
+            .await?;
+        return Batch::find_by_id(pool, bid).await;
+    };
+
+    let limit = Variable::get(pool, "batch_chunk_size")
+        .await?
+        .and_then(|v| v.parse().ok())
+        .filter(|&n: &i64| n > 0)
+        .unwrap_or(DEFAULT_CHUNK_SIZE);
+
+    match job.run_chunk(pool, &batch.state, limit).await {
+        Ok((state, processed, finished)) => {
+            let processed = batch.processed + processed as i32;
+            let status = if finished { BATCH_DONE } else { BATCH_ACTIVE };
+            let summary = if finished { job.summary(&state) } else { None };
+            Batch::save_progress(pool, bid, &state, processed, status, summary.as_deref()).await?;
+        }
+        Err(e) => {
+            tracing::error!("Batch {} ({}) failed: {}", bid, batch.job_type, e);
+            let message = e.to_string();
+            Batch::save_progress(pool, bid, &batch.state, batch.processed, BATCH_FAILED, Some(&message))
+                .await?;
+        }
+    }
+
+    Batch::find_by_id(pool, bid).await
+}
+
+/// Drive every active batch to completion. Used by `cargo run -- batch`,
+/// which is meant to be run from cron.
+pub async fn run_pending(pool: &Pool) -> Result<usize, sqlx::Error> {
+    let ids = Batch::active_ids(pool).await?;
+    for &bid in &ids {
+        while let Some(batch) = process_chunk(pool, bid).await? {
+            if batch.status != BATCH_ACTIVE || batch.locked != 0 {
+                break;
+            }
+        }
+    }
+    Ok(ids.len())
+}
+
+async fn generate_node_aliases(
+    pool: &Pool,
+    cursor: &mut NodeCursor,
+    limit: i64,
+) -> Result<(i64, bool), sqlx::Error> {
+    let nodes: Vec<(u32, String)> = sqlx::query_as(
+        "SELECT n.nid, n.title FROM node n
+         LEFT JOIN url_alias ua ON ua.src = CONCAT('node/', n.nid)
+         WHERE ua.pid IS NULL AND n.nid > ?
+         ORDER BY n.nid
+         LIMIT ?",
+    )
+    .bind(cursor.last_nid)
+    .bind(limit)
+    .fetch_all(pool)
+    .await?;
+
+    for (nid, title) in &nodes {
+        let src = format!("node/{}", nid);
+        let slug = slugify(title);
+        if !slug.is_empty() {
+            let base = format!("content/{}", slug);
+            for suffix in 0..MAX_ALIAS_SUFFIX {
+                let alias = if suffix == 0 {
+                    base.clone()
+                } else {
+                    format!("{}-{}", base, suffix)
+                };
+                if Alias::validate(pool, &src, &alias).await?.is_none() {
+                    Alias::set(pool, &src, &alias).await?;
+                    break;
+                }
+            }
+        }
+        cursor.last_nid = *nid;
+    }
+
+    Ok((nodes.len() as i64, (nodes.len() as i64) < limit))
+}
+
+/// Send the next `limit` e-mails of a broadcast. A failed delivery is
+/// counted and logged but does not stop the rest; the finished broadcast is
+/// recorded in the watchdog.
+async fn mail_users(
+    pool: &Pool,
+    state: &mut MailUsersState,
+    limit: i64,
+) -> Result<(i64, bool), sqlx::Error> {
+    let recipients = User::mail_recipients(pool, state.rid, state.last_uid, limit).await?;
+
+    for (index, (uid, address)) in recipients.iter().enumerate() {
+        if index > 0 {
+            tokio::time::sleep(MAIL_SEND_INTERVAL).await;
+        }
+        let message = Mail {
+            to: address.clone(),
+            subject: state.subject.clone(),
+            body: state.body.clone(),
+        };
+        match mail::send(pool, &message).await {
+            Ok(()) => state.sent += 1,
+            Err(e) => {
+                tracing::warn!("Could not send e-mail to user {}: {}", uid, e);
+                state.failed += 1;
+            }
+        }
+        state.last_uid = *uid;
+    }
+
+    let finished = (recipients.len() as i64) < limit;
+    if finished {
+        let severity = if state.failed > 0 { WATCHDOG_WARNING } else { WATCHDOG_NOTICE };
+        Watchdog::log(pool, state.sender, "mail", &state.summary(), severity, "/admin/user/email", "")
+            .await?;
+    }
+    Ok((recipients.len() as i64, finished))
+}
+
+/// Lowercase ASCII words joined by hyphens, trimmed to fit the alias column.
+fn slugify(title: &str) -> String {
+    let mut slug = String::new();
+    for word in title
+        .split(|c: char| !c.is_ascii_alphanumeric())
+        .filter(|w| !w.is_empty())
+    {
+        if slug.len() + word.len() + 1 > 100 {
+            break;
+        }
+        if !slug.is_empty() {
+            slug.push('-');
+        }
+        slug.push_str(&word.to_ascii_lowercase());
+    }
+    slug
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{create_test_node, create_test_user, test_site};
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn an_interrupted_batch_resumes_to_completion() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let author = create_test_user(pool, &[]).await;
+        let mut nodes = Vec::new();
+        for _ in 0..5 {
+            nodes.push(create_test_node(pool, "page", &author, true).await);
+        }
+        Variable::set(pool, "batch_chunk_size", "2").await.unwrap();
+
+        let bid = enqueue(pool, BatchJob::GenerateNodeAliases, "{}", author.uid, "/admin/node").await.unwrap();
+        let batch = process_chunk(pool, bid).await.unwrap().unwrap();
+        assert_eq!((batch.status.as_str(), batch.processed), (BATCH_ACTIVE, 2));
+
+        // A request dies mid-chunk: it holds the lock and has done the work,
+        // but never saved its progress.
+        assert!(Batch::claim(pool, bid, LOCK_TIMEOUT).await.unwrap());
+        BatchJob::GenerateNodeAliases.run_chunk(pool, &batch.state, 2).await.unwrap();
+        let held = process_chunk(pool, bid).await.unwrap().unwrap();
+        assert_eq!((held.status.as_str(), held.processed), (BATCH_ACTIVE, 2), "the lock is respected");
+
+        // Once the lock is stale the batch is picked up again and finishes.
+        sqlx::query("UPDATE batch SET locked = locked - ? WHERE bid = ?")
+            .bind(LOCK_TIMEOUT + 1)
+            .bind(bid)
+            .execute(pool)
+            .await
+            .unwrap();
+        run_pending(pool).await.unwrap();
+        let batch = Batch::find_by_id(pool, bid).await.unwrap().unwrap();
+        assert_eq!(batch.status, BATCH_DONE);
+
+        for node in &nodes {
+            let alias = Alias::lookup(pool, &format!("node/{}", node.nid)).await.unwrap();
+            assert_eq!(alias, Some(format!("content/{}", slugify(&node.title))), "node {}", node.nid);
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use serde::Serialize;
+use std::time::Duration;
+
+use crate::cache::{cache_get_or_build, TAG_NODE};
+use crate::db::Pool;
+use crate::models::{statistics::PopularNode, Comment, NodeCounter, RecentComment, SystemItem, User, Variable};
+use crate::path;
+
+const POPULAR_CONTENT_TTL: Duration = Duration::from_secs(300);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct PopularContentBlock {
+    pub today: Vec<PopularNode>,
+    pub all_time: Vec<PopularNode>,
+    pub last_viewed: Vec<PopularNode>,
+}
+
+/// "Popular content" sidebar block (statistics module). Only available while
+/// the module is enabled and content views are being counted.
+pub async fn popular_content(pool: &Pool) -> Result<Option<PopularContentBlock>, sqlx::Error> {
+    if !SystemItem::is_module_enabled(pool, "statistics").await? {
+        return Ok(None);
+    }
+
+    let count_enabled = Variable::get_or_default(pool, "statistics_count_content_views", "0").await;
+    if count_enabled != "1" {
+        return Ok(None);
+    }
+
+    let limit: i32 = Variable::get_or_default(pool, "statistics_block_top_num", "5")
+        .await
+        .parse()
+        .unwrap_or(5);
+    if limit <= 0 {
+        return Ok(None);
+    }
+
+    let block = cache_get_or_build(
+        "block",
+        &format!("statistics:popular:{}", limit),
+        POPULAR_CONTENT_TTL,
+        &[TAG_NODE],
+        || async {
+            Ok::<_, sqlx::Error>(PopularContentBlock {
+                today: NodeCounter::popular_today(pool, limit).await?,
+                all_time: NodeCounter::popular_all_time(pool, limit).await?,
+                last_viewed: NodeCounter::recently_viewed(pool, limit).await?,
+            })
+        },
+    )
+    .await?;
+
+    Ok(Some(block))
+}
+
+/// The "User login" block's form, which returns the visitor to
+/// `destination` once they are logged in.
+#[derive(Debug, Clone, Serialize)]
+pub struct UserLoginBlock {
+    pub destination: Option<String>,
+}
+
+/// "User login" sidebar block, for anonymous visitors only. `destination`
+/// is kept when it is a path on this site.
+pub fn user_login(current_user: Option<&User>, destination: &str) -> Option<UserLoginBlock> {
+    if current_user.is_some() {
+        return None;
+    }
+    Some(UserLoginBlock {
+        destination: path::local_destination(destination).map(str::to_string),
+    })
+}
+
+/// "Recent comments" sidebar block: the latest published comments on
+/// published nodes. The `comment_block_count` variable sets how many, 10
+/// unless set; 0 hides the block.
+pub async fn recent_comments(pool: &Pool) -> Result<Option<Vec<RecentComment>>, sqlx::Error> {
+    let limit: i64 = Variable::get_or_default(pool, "comment_block_count", "10")
+        .await
+        .parse()
+        .unwrap_or(10);
+    if limit <= 0 {
+        return Ok(None);
+    }
+
+    let comments = Comment::recent(pool, true, limit, 0).await?;
+    Ok(Some(comments).filter(|comments| !comments.is_empty()))
+}
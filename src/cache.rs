@@ -0,0 +1,124 @@
+use serde::Serialize;
+use std::any::Any;
+use std::collections::HashMap;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, LazyLock, Mutex};
+use std::time::{Duration, Instant};
+
+/// Cache tag shared by every entry derived from node data. Saving,
+/// publishing or deleting a node invalidates it.
+pub const TAG_NODE: &str = "node";
+
+/// Cache tag for entries derived from the `variable` table; any change to a
+/// variable invalidates it.
+pub const TAG_VARIABLE: &str = "variable";
+
+/// Cache tag of the front page served to anonymous visitors. Only changes
+/// to which nodes are published or promoted invalidate it; other edits show
+/// up once it expires.
+pub const TAG_FRONT_PAGE: &str = "front_page";
+
+struct CacheEntry {
+    value: Arc<dyn Any + Send + Sync>,
+    expires: Instant,
+    tags: Vec<String>,
+}
+
+static CACHE: LazyLock<Mutex<HashMap<(String, String), CacheEntry>>> =
+    LazyLock::new(|| Mutex::new(HashMap::new()));
+static HITS: AtomicU64 = AtomicU64::new(0);
+static MISSES: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Debug, Clone, Serialize)]
+pub struct CacheStats {
+    pub hits: u64,
+    pub misses: u64,
+    pub entries: usize,
+}
+
+/// Fetch a cached value from `bin`. Expired entries count as a miss and are
+/// dropped on the way out.
+pub fn cache_get<T: Clone + 'static>(bin: &str, key: &str) -> Option<T> {
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    let cache_key = (bin.to_string(), key.to_string());
+
+    let value = match cache.get(&cache_key) {
+        Some(entry) if entry.expires > Instant::now() => entry.value.downcast_ref::<T>().cloned(),
+        Some(_) => {
+            cache.remove(&cache_key);
+            None
+        }
+        None => None,
+    };
+
+    match value {
+        Some(_) => HITS.fetch_add(1, Ordering::Relaxed),
+        None => MISSES.fetch_add(1, Ordering::Relaxed),
+    };
+
+    value
+}
+
+/// Store a value in `bin` for `ttl`, tagged for later invalidation.
+pub fn cache_set<T: Send + Sync + 'static>(
+    bin: &str,
+    key: &str,
+    value: T,
+    ttl: Duration,
+    tags: &[&str],
+) {
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    cache.insert(
+        (bin.to_string(), key.to_string()),
+        CacheEntry {
+            value: Arc::new(value),
+            expires: Instant::now() + ttl,
+            tags: tags.iter().map(|t| t.to_string()).collect(),
+        },
+    );
+}
+
+/// Return the cached value or build, store and return a fresh one. Builder
+/// errors are passed through and nothing is cached.
+pub async fn cache_get_or_build<T, E, F, Fut>(
+    bin: &str,
+    key: &str,
+    ttl: Duration,
+    tags: &[&str],
+    build: F,
+) -> Result<T, E>
+where
+    T: Clone + Send + Sync + 'static,
+    F: FnOnce() -> Fut,
+    Fut: Future<Output = Result<T, E>>,
+{
+    if let Some(value) = cache_get::<T>(bin, key) {
+        return Ok(value);
+    }
+
+    let value = build().await?;
+    cache_set(bin, key, value.clone(), ttl, tags);
+    Ok(value)
+}
+
+/// Drop every entry carrying any of the given tags.
+pub fn invalidate_tags(tags: &[&str]) {
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    cache.retain(|_, entry| !entry.tags.iter().any(|t| tags.contains(&t.as_str())));
+}
+
+/// Empty every bin. Backs the "Clear cached data" action.
+pub fn flush_all() {
+    let mut cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    cache.clear();
+}
+
+pub fn stats() -> CacheStats {
+    let cache = CACHE.lock().unwrap_or_else(|e| e.into_inner());
+    CacheStats {
+        hits: HITS.load(Ordering::Relaxed),
+        misses: MISSES.load(Ordering::Relaxed),
+        entries: cache.len(),
+    }
+}
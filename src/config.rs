@@ -1,11 +1,16 @@
 use serde::Deserialize;
 use std::env;
+use std::fs;
+use std::path::Path;
 
 #[derive(Debug, Clone, Deserialize)]
 pub struct Config {
     pub server: ServerConfig,
     pub database: DatabaseConfig,
     pub site: SiteConfig,
+    pub migrations: MigrationConfig,
+    pub uploads: UploadConfig,
+    pub sessions: SessionConfig,
 }
 
 #[derive(Debug, Clone, Deserialize)]
@@ -24,35 +29,342 @@ pub struct SiteConfig {
     pub name: String,
 }
 
+/// Throttling for the installer's background migration runner: statements
+/// run in batches of `batch_size` with a `batch_delay_ms` pause in between.
+#[derive(Debug, Clone, Deserialize)]
+pub struct MigrationConfig {
+    pub batch_size: usize,
+    pub batch_delay_ms: u64,
+}
+
+/// Size limits, in bytes, for `multipart/form-data` requests: the whole body
+/// and each uploaded file.
+#[derive(Debug, Clone, Deserialize)]
+pub struct UploadConfig {
+    pub max_body_size: usize,
+    pub max_file_size: usize,
+}
+
+/// How long a login lasts, in seconds: `idle_timeout` without a request,
+/// and `max_lifetime` after logging in however active the session is
+/// (0 for no limit).
+#[derive(Debug, Clone, Deserialize)]
+pub struct SessionConfig {
+    pub idle_timeout: u64,
+    pub max_lifetime: u64,
+}
+
+/// Database URL schemes the MySQL driver accepts.
+const DATABASE_SCHEMES: &[&str] = &["mysql", "mariadb"];
+
+/// Config file read when `DRUPAL_CONFIG` does not name another one.
+pub const DEFAULT_CONFIG_FILE: &str = "drupal.toml";
+
 impl Config {
-    pub fn from_env() -> Result<Self, ConfigError> {
+    /// Read configuration from a TOML file. Anything the file leaves out
+    /// falls back to the defaults, except the database URL.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ConfigError> {
+        ConfigFile::read(path.as_ref())?.resolve()
+    }
+
+    /// Read `DRUPAL_CONFIG` (or `drupal.toml` when it exists) and apply the
+    /// `DRUPAL_*` environment variables on top; environment values win.
+    pub fn load() -> Result<Self, ConfigError> {
+        let mut file = match env::var_os("DRUPAL_CONFIG") {
+            Some(path) => ConfigFile::read(Path::new(&path))?,
+            None if Path::new(DEFAULT_CONFIG_FILE).exists() => {
+                ConfigFile::read(Path::new(DEFAULT_CONFIG_FILE))?
+            }
+            None => ConfigFile::default(),
+        };
+        file.apply_env()?;
+        file.resolve()
+    }
+
+    pub fn bind_address(&self) -> String {
+        format!("{}:{}", self.server.host, self.server.port)
+    }
+}
+
+/// The file layer of the configuration. Every setting is optional so a file
+/// can hold just the values that differ between environments.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ConfigFile {
+    server: ServerFile,
+    database: DatabaseFile,
+    site: SiteFile,
+    migrations: MigrationFile,
+    uploads: UploadFile,
+    sessions: SessionFile,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct ServerFile {
+    host: Option<String>,
+    port: Option<i64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct DatabaseFile {
+    url: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SiteFile {
+    name: Option<String>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct MigrationFile {
+    batch_size: Option<usize>,
+    batch_delay_ms: Option<u64>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct UploadFile {
+    max_body_size: Option<usize>,
+    max_file_size: Option<usize>,
+}
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+struct SessionFile {
+    idle_timeout: Option<u64>,
+    max_lifetime: Option<u64>,
+}
+
+impl ConfigFile {
+    fn read(path: &Path) -> Result<Self, ConfigError> {
+        let contents = fs::read_to_string(path).map_err(|source| ConfigError::Read {
+            path: path.display().to_string(),
+            source,
+        })?;
+        toml::from_str(&contents).map_err(|source| ConfigError::Parse {
+            path: path.display().to_string(),
+            source,
+        })
+    }
+
+    fn apply_env(&mut self) -> Result<(), ConfigError> {
+        if let Ok(host) = env::var("DRUPAL_SERVER__HOST") {
+            self.server.host = Some(host);
+        }
+        if let Ok(port) = env::var("DRUPAL_SERVER__PORT") {
+            let port = port
+                .trim()
+                .parse()
+                .map_err(|_| ConfigError::InvalidPort(port.clone()))?;
+            self.server.port = Some(port);
+        }
+        if let Ok(url) = env::var("DRUPAL_DATABASE__URL") {
+            self.database.url = Some(url);
+        }
+        if let Ok(name) = env::var("DRUPAL_SITE__NAME") {
+            self.site.name = Some(name);
+        }
+        if let Ok(size) = env::var("DRUPAL_MIGRATIONS__BATCH_SIZE") {
+            let size = size.trim().parse().map_err(|_| ConfigError::InvalidMigrationSetting)?;
+            self.migrations.batch_size = Some(size);
+        }
+        if let Ok(delay) = env::var("DRUPAL_MIGRATIONS__BATCH_DELAY_MS") {
+            let delay = delay.trim().parse().map_err(|_| ConfigError::InvalidMigrationSetting)?;
+            self.migrations.batch_delay_ms = Some(delay);
+        }
+        if let Ok(size) = env::var("DRUPAL_UPLOADS__MAX_BODY_SIZE") {
+            let size = size.trim().parse().map_err(|_| ConfigError::InvalidUploadSetting)?;
+            self.uploads.max_body_size = Some(size);
+        }
+        if let Ok(size) = env::var("DRUPAL_UPLOADS__MAX_FILE_SIZE") {
+            let size = size.trim().parse().map_err(|_| ConfigError::InvalidUploadSetting)?;
+            self.uploads.max_file_size = Some(size);
+        }
+        if let Ok(timeout) = env::var("DRUPAL_SESSIONS__IDLE_TIMEOUT") {
+            let timeout = timeout.trim().parse().map_err(|_| ConfigError::InvalidSessionSetting)?;
+            self.sessions.idle_timeout = Some(timeout);
+        }
+        if let Ok(lifetime) = env::var("DRUPAL_SESSIONS__MAX_LIFETIME") {
+            let lifetime = lifetime.trim().parse().map_err(|_| ConfigError::InvalidSessionSetting)?;
+            self.sessions.max_lifetime = Some(lifetime);
+        }
+        Ok(())
+    }
+
+    /// Fill in defaults and validate the result.
+    fn resolve(self) -> Result<Config, ConfigError> {
+        let port = self.server.port.unwrap_or(8080);
+        let port = u16::try_from(port)
+            .ok()
+            .filter(|&port| port > 0)
+            .ok_or_else(|| ConfigError::InvalidPort(port.to_string()))?;
+
+        let url = self
+            .database
+            .url
+            .filter(|url| !url.trim().is_empty())
+            .ok_or(ConfigError::MissingDatabaseUrl)?;
+        let scheme = url.split_once("://").map_or("", |(scheme, _)| scheme);
+        if !DATABASE_SCHEMES.contains(&scheme) {
+            return Err(ConfigError::UnsupportedDatabaseScheme(scheme.to_string()));
+        }
+
+        let idle_timeout = self.sessions.idle_timeout.unwrap_or(7 * 86400);
+        let max_lifetime = self.sessions.max_lifetime.unwrap_or(30 * 86400);
+        if idle_timeout == 0 || idle_timeout > i64::MAX as u64 || max_lifetime > i64::MAX as u64 {
+            return Err(ConfigError::InvalidSessionSetting);
+        }
+
         Ok(Config {
             server: ServerConfig {
-                host: env::var("DRUPAL_SERVER__HOST").unwrap_or_else(|_| "0.0.0.0".to_string()),
-                port: env::var("DRUPAL_SERVER__PORT")
-                    .unwrap_or_else(|_| "8080".to_string())
-                    .parse()
-                    .map_err(|_| ConfigError::InvalidPort)?,
-            },
-            database: DatabaseConfig {
-                url: env::var("DRUPAL_DATABASE__URL")
-                    .map_err(|_| ConfigError::MissingDatabaseUrl)?,
+                host: self.server.host.unwrap_or_else(|| "0.0.0.0".to_string()),
+                port,
             },
+            database: DatabaseConfig { url },
             site: SiteConfig {
-                name: env::var("DRUPAL_SITE__NAME").unwrap_or_else(|_| "Drupal".to_string()),
+                name: self.site.name.unwrap_or_else(|| "Drupal".to_string()),
+            },
+            migrations: MigrationConfig {
+                batch_size: self.migrations.batch_size.unwrap_or(10),
+                batch_delay_ms: self.migrations.batch_delay_ms.unwrap_or(0),
+            },
+            uploads: UploadConfig {
+                max_body_size: self.uploads.max_body_size.unwrap_or(16 * 1024 * 1024),
+                max_file_size: self.uploads.max_file_size.unwrap_or(8 * 1024 * 1024),
+            },
+            sessions: SessionConfig {
+                idle_timeout,
+                max_lifetime,
             },
         })
     }
-
-    pub fn bind_address(&self) -> String {
-        format!("{}:{}", self.server.host, self.server.port)
-    }
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum ConfigError {
-    #[error("DRUPAL_DATABASE__URL environment variable is required")]
+    #[error("Could not read config file {path}: {source}")]
+    Read {
+        path: String,
+        #[source]
+        source: std::io::Error,
+    },
+    #[error("Invalid config file {path}: {source}")]
+    Parse {
+        path: String,
+        #[source]
+        source: toml::de::Error,
+    },
+    #[error("A database URL is required: set database.url in drupal.toml or DRUPAL_DATABASE__URL")]
     MissingDatabaseUrl,
-    #[error("Invalid port number")]
-    InvalidPort,
+    #[error("Unsupported database URL scheme '{0}': expected mysql:// or mariadb://")]
+    UnsupportedDatabaseScheme(String),
+    #[error("Invalid port number '{0}': expected 1-65535")]
+    InvalidPort(String),
+    #[error("Invalid migration batch size or delay")]
+    InvalidMigrationSetting,
+    #[error("Invalid upload size limit")]
+    InvalidUploadSetting,
+    #[error("Invalid session timeout: expected a number of seconds, above 0 for the idle timeout")]
+    InvalidSessionSetting,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolve(toml: &str) -> Result<Config, ConfigError> {
+        toml::from_str::<ConfigFile>(toml).unwrap().resolve()
+    }
+
+    const DATABASE: &str = "[database]\nurl = \"mysql://drupal@localhost/drupal\"\n";
+
+    #[test]
+    fn defaults_fill_in_what_the_file_leaves_out() {
+        let config = resolve(DATABASE).unwrap();
+        assert_eq!(config.bind_address(), "0.0.0.0:8080");
+        assert_eq!(config.site.name, "Drupal");
+        assert_eq!(config.migrations.batch_size, 10);
+        assert_eq!(config.sessions.idle_timeout, 7 * 86400);
+    }
+
+    #[test]
+    fn file_values_are_used() {
+        let config = resolve(&format!("{}[server]\nhost = \"127.0.0.1\"\nport = 3000\n[site]\nname = \"Mine\"\n", DATABASE)).unwrap();
+        assert_eq!(config.bind_address(), "127.0.0.1:3000");
+        assert_eq!(config.site.name, "Mine");
+    }
+
+    #[test]
+    fn database_url_is_required() {
+        assert!(matches!(resolve(""), Err(ConfigError::MissingDatabaseUrl)));
+        assert!(matches!(resolve("[database]\nurl = \" \"\n"), Err(ConfigError::MissingDatabaseUrl)));
+    }
+
+    #[test]
+    fn database_url_scheme_is_checked() {
+        assert!(resolve("[database]\nurl = \"mariadb://localhost/drupal\"\n").is_ok());
+        for url in ["postgres://localhost/drupal", "localhost/drupal", "sqlite::memory:"] {
+            let result = resolve(&format!("[database]\nurl = \"{}\"\n", url));
+            assert!(matches!(result, Err(ConfigError::UnsupportedDatabaseScheme(_))), "{}", url);
+        }
+    }
+
+    #[test]
+    fn port_must_be_in_range() {
+        for port in ["0", "65536", "-1"] {
+            let result = resolve(&format!("{}[server]\nport = {}\n", DATABASE, port));
+            assert!(matches!(result, Err(ConfigError::InvalidPort(_))), "{}", port);
+        }
+        assert!(resolve(&format!("{}[server]\nport = 65535\n", DATABASE)).is_ok());
+    }
+
+    #[test]
+    fn session_timeouts_are_checked() {
+        let result = resolve(&format!("{}[sessions]\nidle_timeout = 0\n", DATABASE));
+        assert!(matches!(result, Err(ConfigError::InvalidSessionSetting)));
+    }
+
+    #[test]
+    fn unknown_settings_are_rejected() {
+        assert!(toml::from_str::<ConfigFile>("[server]\nprot = 80\n").is_err());
+        assert!(toml::from_str::<ConfigFile>("[databse]\nurl = \"mysql://x/y\"\n").is_err());
+    }
+
+    #[test]
+    fn from_file_reads_and_reports_errors() {
+        let dir = std::env::temp_dir().join(format!("drupal-config-test-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+
+        let good = dir.join("good.toml");
+        fs::write(&good, DATABASE).unwrap();
+        assert_eq!(Config::from_file(&good).unwrap().database.url, "mysql://drupal@localhost/drupal");
+
+        let bad = dir.join("bad.toml");
+        fs::write(&bad, "[server\n").unwrap();
+        assert!(matches!(Config::from_file(&bad), Err(ConfigError::Parse { .. })));
+        assert!(matches!(Config::from_file(dir.join("missing.toml")), Err(ConfigError::Read { .. })));
+
+        fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn environment_overrides_the_file() {
+        let mut file: ConfigFile =
+            toml::from_str(&format!("{}[server]\nport = 3000\n[site]\nname = \"File\"\n", DATABASE)).unwrap();
+        env::set_var("DRUPAL_SERVER__PORT", "4000");
+        env::set_var("DRUPAL_SITE__NAME", "Env");
+        let result = file.apply_env();
+        env::remove_var("DRUPAL_SERVER__PORT");
+        env::remove_var("DRUPAL_SITE__NAME");
+        result.unwrap();
+
+        let config = file.resolve().unwrap();
+        assert_eq!(config.server.port, 4000);
+        assert_eq!(config.site.name, "Env");
+        assert_eq!(config.database.url, "mysql://drupal@localhost/drupal");
+    }
 }
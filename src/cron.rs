@@ -0,0 +1,170 @@
+//! Periodic maintenance. The server runs it every minute in the background;
+//! it can also be triggered with `cargo run -- cron`, from the status report,
+//! or by an external scheduler requesting `/cron/<cron_key>`.
+
+use argon2::password_hash::rand_core::{OsRng, RngCore};
+use std::time::Duration;
+
+use crate::{
+    auth::throttle,
+    batch, cache,
+    db::{migrations::is_installed, Pool},
+    models::{AccessLog, Flood, Node, NodeCounter, Variable, Watchdog},
+    watchdog,
+};
+
+const CRON_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Seconds after which the lock of a cron run that never finished is ignored.
+const LOCK_TIMEOUT: i64 = 240;
+
+/// Access log entries are kept this many seconds unless
+/// `statistics_flush_accesslog_timer` says otherwise; 0 keeps them forever.
+const DEFAULT_ACCESSLOG_MAX_AGE: u32 = 259200;
+
+const DAY: i64 = 86400;
+
+/// Run every periodic job: publish scheduled nodes that are due, work
+/// through queued batches, reset the daily view counts and discard old log
+/// entries. Returns `false` without doing anything when another run holds
+/// the lock.
+pub async fn run_cron(pool: &Pool) -> Result<bool, sqlx::Error> {
+    if !acquire_lock(pool).await? {
+        return Ok(false);
+    }
+
+    let result = run_jobs(pool).await;
+    Variable::delete(pool, "cron_semaphore").await?;
+    result?;
+
+    let now = chrono::Utc::now().timestamp();
+    Variable::set(pool, "cron_last", &now.to_string()).await?;
+    Ok(true)
+}
+
+async fn run_jobs(pool: &Pool) -> Result<(), sqlx::Error> {
+    let now = chrono::Utc::now().timestamp();
+    let published = Node::publish_due(pool, now as i32).await?;
+    if published > 0 {
+        tracing::info!("Published {} scheduled post(s)", published);
+        cache::invalidate_tags(&[cache::TAG_NODE, cache::TAG_FRONT_PAGE]);
+    }
+
+    batch::run_pending(pool).await?;
+
+    let day_started: i64 = Variable::get_or_default(pool, "statistics_day_timestamp", "0")
+        .await
+        .parse()
+        .unwrap_or(0);
+    if now - day_started >= DAY {
+        NodeCounter::reset_day_counts(pool).await?;
+        Variable::set(pool, "statistics_day_timestamp", &now.to_string()).await?;
+    }
+
+    let accesslog_max_age = Variable::get(pool, "statistics_flush_accesslog_timer")
+        .await?
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(DEFAULT_ACCESSLOG_MAX_AGE);
+    if accesslog_max_age > 0 {
+        AccessLog::flush_old_entries(pool, accesslog_max_age).await?;
+    }
+
+    let watchdog_max_age = Variable::get(pool, "watchdog_clear")
+        .await?
+        .and_then(|v| v.parse().ok())
+        .unwrap_or(watchdog::DEFAULT_MAX_AGE);
+    Watchdog::flush_old_entries(pool, watchdog_max_age).await?;
+    Flood::flush_old_entries(pool, throttle::FAILED_LOGIN_WINDOW).await?;
+
+    Ok(())
+}
+
+/// Take the `cron_semaphore` variable, unless a run that started less than
+/// `LOCK_TIMEOUT` seconds ago still holds it. Each statement only matches a
+/// row when it takes the lock, so of two concurrent runs one sees no row
+/// affected.
+async fn acquire_lock(pool: &Pool) -> Result<bool, sqlx::Error> {
+    let now = chrono::Utc::now().timestamp();
+    let inserted = sqlx::query("INSERT IGNORE INTO variable (name, value) VALUES ('cron_semaphore', ?)")
+        .bind(now.to_string())
+        .execute(pool)
+        .await?;
+    if inserted.rows_affected() == 1 {
+        return Ok(true);
+    }
+
+    // A stale lock is taken over; the row lock makes a concurrent run
+    // re-read the fresh value and match nothing.
+    let taken = sqlx::query(
+        "UPDATE variable SET value = ?
+         WHERE name = 'cron_semaphore' AND CAST(value AS SIGNED) < ?",
+    )
+    .bind(now.to_string())
+    .bind(now - LOCK_TIMEOUT)
+    .execute(pool)
+    .await?;
+    Ok(taken.rows_affected() == 1)
+}
+
+/// When cron last completed, if ever.
+pub async fn last_run(pool: &Pool) -> Result<Option<i64>, sqlx::Error> {
+    Ok(Variable::get(pool, "cron_last")
+        .await?
+        .and_then(|v| v.parse().ok()))
+}
+
+/// The secret external schedulers put in `/cron/<key>`, created on first use.
+pub async fn key(pool: &Pool) -> Result<String, sqlx::Error> {
+    if let Some(key) = Variable::get(pool, "cron_key").await?.filter(|k| !k.is_empty()) {
+        return Ok(key);
+    }
+
+    let mut bytes = [0u8; 16];
+    OsRng.fill_bytes(&mut bytes);
+    let key: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    Variable::set(pool, "cron_key", &key).await?;
+    Ok(key)
+}
+
+/// Run [`run_cron`] every minute for as long as the server is up.
+pub fn spawn(pool: Pool) {
+    tokio::spawn(async move {
+        let mut interval = tokio::time::interval(CRON_INTERVAL);
+        loop {
+            interval.tick().await;
+            if !is_installed(&pool).await.unwrap_or(false) {
+                continue;
+            }
+            if let Err(e) = run_cron(&pool).await {
+                tracing::warn!("Cron run failed: {}", e);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::test_site;
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn only_one_run_holds_the_lock() {
+        let site = test_site().await;
+        let pool = &site.pool;
+
+        let (first, second) = tokio::join!(acquire_lock(pool), acquire_lock(pool));
+        assert!(first.unwrap() ^ second.unwrap(), "exactly one run gets the lock");
+        assert!(!acquire_lock(pool).await.unwrap());
+
+        // A run turned away leaves the lock to the one holding it.
+        assert!(!run_cron(pool).await.unwrap());
+        assert!(Variable::get(pool, "cron_semaphore").await.unwrap().is_some());
+
+        // A lock older than the timeout is taken over, once.
+        let stale = chrono::Utc::now().timestamp() - LOCK_TIMEOUT - 1;
+        Variable::set(pool, "cron_semaphore", &stale.to_string()).await.unwrap();
+        let (first, second) = tokio::join!(acquire_lock(pool), acquire_lock(pool));
+        assert!(first.unwrap() ^ second.unwrap(), "exactly one run takes over the lock");
+    }
+}
@@ -1,29 +1,410 @@
-use sqlx::MySqlPool;
+use serde::Serialize;
+use std::sync::{LazyLock, Mutex};
+use std::time::Duration;
+
+use crate::config::MigrationConfig;
+use crate::db::Pool;
+use crate::models::{Permission, Role, Variable};
 
 const SCHEMA: &str = include_str!("../../sql/schema.sql");
 
-pub async fn run_migrations(pool: &MySqlPool) -> Result<(), sqlx::Error> {
-    tracing::info!("Running database migrations...");
+/// Columns added to tables after they were first created, as (table,
+/// column, definition). `CREATE TABLE IF NOT EXISTS` leaves the tables of an
+/// existing site alone, so these are added to them separately.
+const ADDED_COLUMNS: &[(&str, &str, &str)] = &[
+    ("users", "tos_accepted", "INT NOT NULL DEFAULT 0 AFTER theme"),
+    ("users", "picture", "VARCHAR(255) NOT NULL DEFAULT '' AFTER tos_accepted"),
+];
+
+/// Data changes an existing site needs once, applied in order after the
+/// schema. Unlike the schema's statements they are not repeated on every
+/// run: [`SCHEMA_VERSION`] counts how many a site has had, so an
+/// administrator's later changes stick.
+const UPDATES: &[Update] = &[
+    // The administration permissions were split out of "administer nodes".
+    Update::GrantAdministrator(&[
+        "administer site configuration",
+        "access administration pages",
+        "access site reports",
+    ]),
+    // Roles became manageable.
+    Update::GrantAdministrator(&["administer access control"]),
+    // Comment moderation was split from editing and deleting.
+    Update::GrantAdministrator(&["edit any comment", "delete any comment", "moderate comments"]),
+    // Setting URL aliases got a permission of its own.
+    Update::GrantAdministrator(&["create url aliases"]),
+    // The front page feed's item count moved to the feed settings.
+    Update::RenameVariable("feed_default_items", "feed_item_limit"),
+    // The installer started recording when it finished.
+    Update::MarkInstalled,
+];
+
+/// Variable holding how many of [`UPDATES`] the site has had.
+const SCHEMA_VERSION: &str = "schema_version";
+
+enum Update {
+    /// Give the administrator role permissions it had before they existed
+    /// on their own.
+    GrantAdministrator(&'static [&'static str]),
+    /// Move a setting to a new variable name, unless the new one has
+    /// already been set.
+    RenameVariable(&'static str, &'static str),
+    /// Record [`INSTALL_COMPLETE`] for a site installed before it existed.
+    MarkInstalled,
+}
 
-    for statement in SCHEMA.split(';') {
-        let statement = statement.trim();
-        if statement.is_empty() || statement.starts_with("--") {
-            continue;
+impl Update {
+    fn describe(&self) -> String {
+        match self {
+            Update::GrantAdministrator(permissions) => {
+                format!("grant the administrator role: {}", permissions.join(", "))
+            }
+            Update::RenameVariable(from, to) => format!("rename variable {} to {}", from, to),
+            Update::MarkInstalled => format!("record an existing installation in {}", INSTALL_COMPLETE),
         }
+    }
 
-        sqlx::query(statement).execute(pool).await?;
+    /// Apply the update. Each is safe to repeat, should a run fail before
+    /// the version is recorded.
+    async fn apply(&self, pool: &Pool) -> Result<(), sqlx::Error> {
+        match self {
+            Update::GrantAdministrator(permissions) => {
+                if let Some(role) = Role::administrator(pool).await? {
+                    Permission::grant(pool, role.rid, permissions).await?;
+                }
+                Ok(())
+            }
+            Update::RenameVariable(from, to) => {
+                if let Some(value) = Variable::get(pool, from).await? {
+                    if Variable::get(pool, to).await?.is_none() {
+                        Variable::set(pool, to, &value).await?;
+                    }
+                    Variable::delete(pool, from).await?;
+                }
+                Ok(())
+            }
+            Update::MarkInstalled => backfill_install_marker(pool).await.map(|_| ()),
+        }
     }
+}
+
+#[derive(Debug, thiserror::Error)]
+#[error("migration statement {index} failed: {source}\n{statement}")]
+pub struct MigrationError {
+    pub index: usize,
+    pub statement: String,
+    #[source]
+    pub source: sqlx::Error,
+}
+
+/// Progress of the background migration run, polled by the installer.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct MigrationStatus {
+    pub state: MigrationState,
+    pub completed: usize,
+    pub total: usize,
+    /// Why the run failed. Database errors can quote the schema, so this is
+    /// left out of the JSON the status endpoint serves; the failing
+    /// statement itself only goes to the log.
+    #[serde(skip)]
+    pub error: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum MigrationState {
+    #[default]
+    Idle,
+    Running,
+    Done,
+    Failed,
+}
+
+static STATUS: LazyLock<Mutex<MigrationStatus>> =
+    LazyLock::new(|| Mutex::new(MigrationStatus::default()));
+
+fn statements() -> Vec<&'static str> {
+    SCHEMA
+        .split(';')
+        .map(strip_leading_comments)
+        .filter(|s| !s.is_empty())
+        .collect()
+}
+
+/// Drop the `--` comment lines a statement starts with, so a statement
+/// preceded by a comment is not mistaken for a comment itself.
+fn strip_leading_comments(statement: &str) -> &str {
+    let mut rest = statement.trim();
+    while rest.starts_with("--") {
+        rest = rest.split_once('\n').map_or("", |(_, r)| r).trim_start();
+    }
+    rest
+}
+
+async fn execute_statement(
+    pool: &Pool,
+    index: usize,
+    statement: &str,
+) -> Result<(), MigrationError> {
+    let summary = statement
+        .lines()
+        .find(|line| !line.trim_start().starts_with("--"))
+        .unwrap_or(statement);
+    tracing::debug!("Migration statement {}: {}", index, summary);
+
+    sqlx::query(statement)
+        .execute(pool)
+        .await
+        .map(|_| ())
+        .map_err(|source| MigrationError {
+            index,
+            statement: statement.to_string(),
+            source,
+        })
+}
+
+/// Apply the whole schema and wait for it. Used by `drupal_rust migrate`.
+pub async fn run_migrations(pool: &Pool) -> Result<(), MigrationError> {
+    tracing::info!("Running database migrations...");
+
+    let statements = statements();
+    for (index, statement) in statements.iter().enumerate() {
+        execute_statement(pool, index, statement).await?;
+    }
+    upgrade(pool, statements.len()).await?;
 
     tracing::info!("Migrations completed successfully");
     Ok(())
 }
 
-pub async fn is_installed(pool: &MySqlPool) -> Result<bool, sqlx::Error> {
-    let result: Option<(i64,)> = sqlx::query_as(
-        "SELECT COUNT(*) FROM users WHERE uid > 0 AND status = 1",
-    )
-    .fetch_optional(pool)
-    .await?;
+/// Bring the tables of an existing site up to the schema. Runs after the
+/// schema's statements; `first_index` numbers the steps in errors.
+async fn upgrade(pool: &Pool, first_index: usize) -> Result<(), MigrationError> {
+    for (offset, (table, column, definition)) in ADDED_COLUMNS.iter().enumerate() {
+        let index = first_index + offset;
+        let exists: Result<(i64,), sqlx::Error> = sqlx::query_as(
+            "SELECT COUNT(*) FROM information_schema.COLUMNS
+             WHERE TABLE_SCHEMA = DATABASE() AND TABLE_NAME = ? AND COLUMN_NAME = ?",
+        )
+        .bind(table)
+        .bind(column)
+        .fetch_one(pool)
+        .await;
+        let statement = format!("ALTER TABLE {} ADD COLUMN {} {}", table, column, definition);
+        match exists {
+            Ok((0,)) => execute_statement(pool, index, &statement).await?,
+            Ok(_) => {}
+            Err(source) => return Err(MigrationError { index, statement, source }),
+        }
+    }
+
+    let first_index = first_index + ADDED_COLUMNS.len();
+    let version = Variable::get(pool, SCHEMA_VERSION)
+        .await
+        .map_err(|source| MigrationError {
+            index: first_index,
+            statement: format!("read {}", SCHEMA_VERSION),
+            source,
+        })?
+        .and_then(|v| v.parse::<usize>().ok())
+        .unwrap_or(0);
+    for (number, update) in UPDATES.iter().enumerate().skip(version) {
+        let index = first_index + number;
+        tracing::debug!("Migration update {}: {}", number + 1, update.describe());
+        let result = match update.apply(pool).await {
+            Ok(()) => Variable::set(pool, SCHEMA_VERSION, &(number + 1).to_string()).await,
+            Err(e) => Err(e),
+        };
+        result.map_err(|source| MigrationError {
+            index,
+            statement: update.describe(),
+            source,
+        })?;
+    }
+    Ok(())
+}
+
+pub fn migration_status() -> MigrationStatus {
+    STATUS.lock().unwrap_or_else(|e| e.into_inner()).clone()
+}
+
+fn update_status(update: impl FnOnce(&mut MigrationStatus)) {
+    update(&mut STATUS.lock().unwrap_or_else(|e| e.into_inner()));
+}
+
+/// Start applying the schema in a background task, in throttled batches.
+/// Does nothing if a run is already in progress or has finished; a failed
+/// run is restarted from the beginning, which is safe because every
+/// statement is idempotent.
+pub fn start_background_migrations(pool: Pool, config: &MigrationConfig) {
+    let statements = statements();
 
-    Ok(result.map(|(count,)| count > 0).unwrap_or(false))
+    {
+        let mut status = STATUS.lock().unwrap_or_else(|e| e.into_inner());
+        if matches!(status.state, MigrationState::Running | MigrationState::Done) {
+            return;
+        }
+        *status = MigrationStatus {
+            state: MigrationState::Running,
+            total: statements.len(),
+            ..Default::default()
+        };
+    }
+
+    let batch_size = config.batch_size.max(1);
+    let delay = Duration::from_millis(config.batch_delay_ms);
+
+    tokio::spawn(async move {
+        tracing::info!("Running database migrations in the background...");
+
+        for (batch, chunk) in statements.chunks(batch_size).enumerate() {
+            if batch > 0 && !delay.is_zero() {
+                tokio::time::sleep(delay).await;
+            }
+
+            for (offset, statement) in chunk.iter().enumerate() {
+                let index = batch * batch_size + offset;
+                if let Err(e) = execute_statement(&pool, index, statement).await {
+                    record_failure(e);
+                    return;
+                }
+                update_status(|status| status.completed = index + 1);
+            }
+        }
+        if let Err(e) = upgrade(&pool, statements.len()).await {
+            record_failure(e);
+            return;
+        }
+
+        tracing::info!("Migrations completed successfully");
+        update_status(|status| status.state = MigrationState::Done);
+    });
+}
+
+fn record_failure(e: MigrationError) {
+    tracing::error!("{}", e);
+    update_status(|status| {
+        status.state = MigrationState::Failed;
+        status.error = Some(format!("statement {} failed: {}", e.index, e.source));
+    });
+}
+
+/// Variable written by the installer once the admin account exists.
+pub const INSTALL_COMPLETE: &str = "install_complete";
+
+/// Whether the site is installed, which only the installer's
+/// [`INSTALL_COMPLETE`] marker says; blocking every account does not reopen
+/// the installer. Missing tables mean a fresh database; any other error is
+/// returned so callers can fail closed.
+pub async fn is_installed(pool: &Pool) -> Result<bool, sqlx::Error> {
+    match install_completed_at(pool).await {
+        Ok(completed) => Ok(completed.is_some()),
+        Err(e) if is_missing_table(&e) => Ok(false),
+        Err(e) => Err(e),
+    }
+}
+
+/// Record [`INSTALL_COMPLETE`] for a site installed before the installer
+/// wrote it, recognised by having any account. Returns whether it did.
+pub async fn backfill_install_marker(pool: &Pool) -> Result<bool, sqlx::Error> {
+    let accounts: Result<(i64,), sqlx::Error> =
+        sqlx::query_as("SELECT COUNT(*) FROM users WHERE uid > 0").fetch_one(pool).await;
+    let accounts = match accounts {
+        Ok((count,)) => count,
+        Err(e) if is_missing_table(&e) => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    if accounts == 0 || install_completed_at(pool).await?.is_some() {
+        return Ok(false);
+    }
+    mark_installed(pool).await?;
+    Ok(true)
+}
+
+/// When the installer finished, if it has recorded that.
+pub async fn install_completed_at(pool: &Pool) -> Result<Option<i64>, sqlx::Error> {
+    let result: Option<(Option<String>,)> =
+        sqlx::query_as("SELECT value FROM variable WHERE name = ?")
+            .bind(INSTALL_COMPLETE)
+            .fetch_optional(pool)
+            .await?;
+
+    Ok(result.map(|(value,)| value.and_then(|v| v.parse().ok()).unwrap_or(0)))
+}
+
+pub async fn mark_installed(pool: &Pool) -> Result<(), sqlx::Error> {
+    let now = chrono::Utc::now().timestamp();
+    Variable::set(pool, INSTALL_COMPLETE, &now.to_string()).await
+}
+
+fn is_missing_table(e: &sqlx::Error) -> bool {
+    // SQLSTATE 42S02: base table or view not found
+    matches!(e, sqlx::Error::Database(db) if db.code().as_deref() == Some("42S02"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::test_site;
+
+    #[test]
+    fn status_json_holds_no_sql() {
+        let status = MigrationStatus {
+            state: MigrationState::Failed,
+            completed: 3,
+            total: 10,
+            error: Some("statement 3 failed: near 'CREATE TABLE users'".to_string()),
+        };
+        let json = serde_json::to_value(&status).unwrap();
+        assert_eq!(json, serde_json::json!({"state": "failed", "completed": 3, "total": 10}));
+    }
+
+    /// Run the first update `update` matches, and those after it, again on a
+    /// site that has everything before it.
+    async fn rerun_from(pool: &Pool, update: fn(&Update) -> bool) {
+        let number = UPDATES.iter().position(update).expect("no such update");
+        Variable::set(pool, SCHEMA_VERSION, &number.to_string()).await.unwrap();
+        upgrade(pool, 0).await.unwrap();
+    }
+
+    fn is_feed_rename(update: &Update) -> bool {
+        matches!(update, Update::RenameVariable("feed_default_items", _))
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn feed_item_count_is_carried_over() {
+        let site = test_site().await;
+        let pool = &site.pool;
+
+        Variable::delete(pool, "feed_item_limit").await.unwrap();
+        Variable::set(pool, "feed_default_items", "25").await.unwrap();
+        rerun_from(pool, is_feed_rename).await;
+        assert_eq!(Variable::get(pool, "feed_item_limit").await.unwrap().as_deref(), Some("25"));
+        assert_eq!(Variable::get(pool, "feed_default_items").await.unwrap(), None);
+
+        // A value already under the new name wins.
+        Variable::set(pool, "feed_default_items", "5").await.unwrap();
+        rerun_from(pool, is_feed_rename).await;
+        assert_eq!(Variable::get(pool, "feed_item_limit").await.unwrap().as_deref(), Some("25"));
+        assert_eq!(Variable::get(pool, "feed_default_items").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn only_the_marker_says_the_site_is_installed() {
+        let site = test_site().await;
+        let pool = &site.pool;
+
+        sqlx::query("UPDATE users SET status = 0").execute(pool).await.unwrap();
+        assert!(is_installed(pool).await.unwrap(), "blocking every account keeps the installer shut");
+
+        Variable::delete(pool, INSTALL_COMPLETE).await.unwrap();
+        assert!(!is_installed(pool).await.unwrap());
+
+        // Upgrading a site installed before the marker existed records it.
+        rerun_from(pool, |update| matches!(update, Update::MarkInstalled)).await;
+        assert!(is_installed(pool).await.unwrap());
+        assert!(!backfill_install_marker(pool).await.unwrap(), "already recorded");
+    }
 }
@@ -1,11 +1,45 @@
 pub mod migrations;
 
 use sqlx::mysql::MySqlPoolOptions;
-use sqlx::MySqlPool;
+use sqlx::query::Query;
+use std::future::Future;
+use std::time::Duration;
 
-pub async fn create_pool(database_url: &str) -> Result<MySqlPool, sqlx::Error> {
+/// The database driver queries run against. Models take a [`Pool`] rather
+/// than naming the driver; the SQL they send is still MySQL's.
+pub type Db = sqlx::MySql;
+
+/// A pool of connections to the site database.
+pub type Pool = sqlx::Pool<Db>;
+
+/// One connection to the site database, as held by a transaction.
+pub type Connection = <Db as sqlx::Database>::Connection;
+
+/// How long a request waits for a connection. Kept short so that while the
+/// database is down requests fail fast with 503 instead of piling up.
+const ACQUIRE_TIMEOUT: Duration = Duration::from_secs(5);
+
+pub async fn create_pool(database_url: &str) -> Result<Pool, sqlx::Error> {
     MySqlPoolOptions::new()
         .max_connections(5)
+        .acquire_timeout(ACQUIRE_TIMEOUT)
         .connect(database_url)
         .await
 }
+
+/// Running an `INSERT` for the id the database gave the new row.
+pub trait InsertId {
+    fn insert_id<'c, E>(self, executor: E) -> impl Future<Output = Result<u32, sqlx::Error>> + Send
+    where
+        E: sqlx::Executor<'c, Database = Db> + 'c;
+}
+
+impl<'q> InsertId for Query<'q, Db, <Db as sqlx::Database>::Arguments<'q>> {
+    async fn insert_id<'c, E>(self, executor: E) -> Result<u32, sqlx::Error>
+    where
+        E: sqlx::Executor<'c, Database = Db> + 'c,
+    {
+        let result = self.execute(executor).await?;
+        Ok(result.last_insert_id() as u32)
+    }
+}
@@ -1,12 +1,20 @@
 use axum::{
-    http::StatusCode,
-    response::{IntoResponse, Response},
+    http::{header, StatusCode},
+    response::{Html, IntoResponse, Response},
 };
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use crate::models::{WATCHDOG_ERROR, WATCHDOG_WARNING};
 
 #[derive(Debug, thiserror::Error)]
 pub enum AppError {
     #[error("Database error: {0}")]
-    Database(#[from] sqlx::Error),
+    Database(sqlx::Error),
+
+    /// The database could not be reached at all, as opposed to a query
+    /// failing. Answered with a 503 the client is told to retry.
+    #[error("Database unavailable: {0}")]
+    DatabaseUnavailable(sqlx::Error),
 
     #[error("Template error: {0}")]
     Template(#[from] tera::Error),
@@ -25,24 +33,224 @@ pub enum AppError {
 
     #[error("Internal error: {0}")]
     Internal(String),
+
+    #[error("Service unavailable: {0}")]
+    ServiceUnavailable(String),
+
+    /// An error annotated with what the handler was doing when it happened.
+    /// The context only goes to the log; the response is the inner error's.
+    #[error("{context}: {source}")]
+    Context {
+        context: String,
+        #[source]
+        source: Box<AppError>,
+    },
 }
 
-impl IntoResponse for AppError {
-    fn into_response(self) -> Response {
-        let (status, message) = match &self {
+/// Seconds clients are asked to wait before retrying while the database is
+/// unavailable.
+const RETRY_AFTER_SECS: u32 = 30;
+
+/// While the database stays unavailable, its failure is logged at most once
+/// per this many seconds.
+const OUTAGE_LOG_INTERVAL: i64 = 60;
+
+/// When the last database outage error was logged, as a Unix timestamp.
+static OUTAGE_LOGGED_AT: AtomicI64 = AtomicI64::new(0);
+
+/// Shown for requests that fail because the database is unavailable. Static,
+/// since rendering a template may itself need the database.
+const UNAVAILABLE_PAGE: &str = "<!DOCTYPE html>
+<html>
+<head><title>Site temporarily unavailable</title></head>
+<body>
+<h1>Site temporarily unavailable</h1>
+<p>The site cannot reach its database right now. Please try again in a moment.</p>
+</body>
+</html>
+";
+
+/// A query that expected a row and found none means the thing asked for
+/// does not exist, not that the database is broken. Failing to connect at
+/// all is an outage, not a bug in the query.
+impl From<sqlx::Error> for AppError {
+    fn from(error: sqlx::Error) -> Self {
+        match error {
+            sqlx::Error::RowNotFound => AppError::NotFound,
+            error if is_connection_error(&error) => AppError::DatabaseUnavailable(error),
+            error => AppError::Database(error),
+        }
+    }
+}
+
+/// Whether `error` means the database could not be reached, rather than that
+/// a query was wrong or returned something unexpected.
+fn is_connection_error(error: &sqlx::Error) -> bool {
+    matches!(
+        error,
+        sqlx::Error::Io(_)
+            | sqlx::Error::Tls(_)
+            | sqlx::Error::PoolTimedOut
+            | sqlx::Error::PoolClosed
+            | sqlx::Error::WorkerCrashed
+    )
+}
+
+/// Whether an outage error should be logged now: true once per
+/// `OUTAGE_LOG_INTERVAL`, however many requests fail in the meantime.
+fn outage_log_due() -> bool {
+    let now = chrono::Utc::now().timestamp();
+    let last = OUTAGE_LOGGED_AT.load(Ordering::Relaxed);
+    now - last >= OUTAGE_LOG_INTERVAL
+        && OUTAGE_LOGGED_AT
+            .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+}
+
+impl AppError {
+    /// The error with any context layers peeled off.
+    pub fn root(&self) -> &AppError {
+        match self {
+            AppError::Context { source, .. } => source.root(),
+            error => error,
+        }
+    }
+
+    /// Watchdog message type and severity the error is recorded under.
+    fn log_type(&self) -> (&'static str, u8) {
+        match self.root() {
+            AppError::NotFound => ("page not found", WATCHDOG_WARNING),
+            AppError::Unauthorized | AppError::Forbidden => ("access denied", WATCHDOG_WARNING),
+            AppError::BadRequest(_) => ("error", WATCHDOG_WARNING),
+            _ => ("error", WATCHDOG_ERROR),
+        }
+    }
+
+    fn status_and_message(&self) -> (StatusCode, &'static str) {
+        match self.root() {
             AppError::Database(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Database error"),
+            AppError::DatabaseUnavailable(_) => (StatusCode::SERVICE_UNAVAILABLE, "Service unavailable"),
             AppError::Template(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Template error"),
             AppError::NotFound => (StatusCode::NOT_FOUND, "Not found"),
             AppError::Unauthorized => (StatusCode::UNAUTHORIZED, "Unauthorized"),
             AppError::Forbidden => (StatusCode::FORBIDDEN, "Forbidden"),
             AppError::BadRequest(_) => (StatusCode::BAD_REQUEST, "Bad request"),
             AppError::Internal(_) => (StatusCode::INTERNAL_SERVER_ERROR, "Internal error"),
-        };
+            AppError::ServiceUnavailable(_) => (StatusCode::SERVICE_UNAVAILABLE, "Service unavailable"),
+            AppError::Context { .. } => unreachable!("root() never returns a context layer"),
+        }
+    }
+
+    /// The static 503 page for a database outage. Only the first failure in
+    /// each `OUTAGE_LOG_INTERVAL` is logged and handed to the watchdog, so
+    /// an outage does not flood the logs with one entry per request.
+    fn unavailable_response(self) -> Response {
+        let mut response = (
+            StatusCode::SERVICE_UNAVAILABLE,
+            [(header::RETRY_AFTER, RETRY_AFTER_SECS.to_string())],
+            Html(UNAVAILABLE_PAGE),
+        )
+            .into_response();
+
+        if outage_log_due() {
+            tracing::error!("Error: {}", self);
+            response.extensions_mut().insert(ErrorLogEntry {
+                log_type: "database",
+                severity: WATCHDOG_ERROR,
+                message: self.to_string(),
+            });
+        } else {
+            tracing::debug!("Error: {}", self);
+        }
+        response
+    }
+}
+
+impl IntoResponse for AppError {
+    fn into_response(self) -> Response {
+        if let AppError::DatabaseUnavailable(_) = self.root() {
+            return self.unavailable_response();
+        }
+
+        let (status, message) = self.status_and_message();
 
         tracing::error!("Error: {}", self);
 
-        (status, message).into_response()
+        let (log_type, severity) = self.log_type();
+        let mut response = (status, message).into_response();
+        response.extensions_mut().insert(ErrorLogEntry {
+            log_type,
+            severity,
+            message: self.to_string(),
+        });
+        response
     }
 }
 
+/// Attached to error responses so `watchdog::watchdog_middleware`, which
+/// knows the request and the user, can record them.
+#[derive(Debug, Clone)]
+pub struct ErrorLogEntry {
+    pub log_type: &'static str,
+    pub severity: u8,
+    pub message: String,
+}
+
 pub type AppResult<T> = Result<T, AppError>;
+
+/// Attach context to a failing result, e.g.
+/// `Node::find_with_body(&pool, nid).await.context("loading node for edit")?`.
+pub trait ResultExt<T> {
+    fn context(self, context: &str) -> AppResult<T>;
+
+    fn with_context<F: FnOnce() -> String>(self, context: F) -> AppResult<T>;
+}
+
+impl<T, E: Into<AppError>> ResultExt<T> for Result<T, E> {
+    fn context(self, context: &str) -> AppResult<T> {
+        self.with_context(|| context.to_string())
+    }
+
+    fn with_context<F: FnOnce() -> String>(self, context: F) -> AppResult<T> {
+        self.map_err(|error| AppError::Context {
+            context: context(),
+            source: Box::new(error.into()),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::health;
+    use axum::extract::State;
+    use sqlx::mysql::MySqlPoolOptions;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn a_closed_pool_answers_503_with_retry_after() {
+        let pool = MySqlPoolOptions::new()
+            .acquire_timeout(Duration::from_secs(1))
+            .connect_lazy("mysql://drupal@127.0.0.1:1/drupal")
+            .unwrap();
+        pool.close().await;
+
+        let mut logged = 0;
+        for _ in 0..3 {
+            let error = health::check(State(pool.clone())).await.unwrap_err();
+            assert!(matches!(error, AppError::DatabaseUnavailable(sqlx::Error::PoolClosed)));
+            let response = error.into_response();
+            assert_eq!(response.status(), StatusCode::SERVICE_UNAVAILABLE);
+            assert_eq!(response.headers()[header::RETRY_AFTER], RETRY_AFTER_SECS.to_string());
+            logged += response.extensions().get::<ErrorLogEntry>().is_some() as u32;
+        }
+        assert!(logged <= 1, "the outage was logged {} times", logged);
+    }
+
+    #[test]
+    fn query_errors_are_not_outages() {
+        let error = AppError::from(sqlx::Error::ColumnNotFound("missing".to_string()));
+        assert_eq!(error.into_response().status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(matches!(AppError::from(sqlx::Error::RowNotFound), AppError::NotFound));
+    }
+}
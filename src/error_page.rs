@@ -0,0 +1,156 @@
+//! Themed pages for requests that were turned away. Handlers refuse access
+//! by returning `AppError::Unauthorized` or `AppError::Forbidden`, which on
+//! their own answer with a bare status line; for pages a browser navigated
+//! to, this middleware renders the site around the refusal and offers
+//! anonymous visitors the login form, so they can get in and come back.
+
+use axum::{
+    body::Body,
+    extract::State,
+    http::{Method, Request, StatusCode},
+    middleware::Next,
+    response::{Html, IntoResponse, Response},
+};
+use tera::Tera;
+
+use crate::{
+    auth::middleware::CurrentUser, blocks, error::ErrorLogEntry, handlers::page_context,
+    db::Pool,
+    templates::render,
+};
+
+/// Replace the plain response of a refused GET request with the themed
+/// "Access denied" page, keeping its status. Has to run inside the auth
+/// middleware to see the user.
+pub async fn access_denied_middleware(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let is_page = request.method() == Method::GET || request.method() == Method::HEAD;
+    let destination = request
+        .uri()
+        .path_and_query()
+        .map(|pq| pq.as_str().to_string())
+        .unwrap_or_default();
+    let current_user = request
+        .extensions()
+        .get::<CurrentUser>()
+        .and_then(|CurrentUser(user)| user.clone());
+
+    let response = next.run(request).await;
+
+    let status = response.status();
+    let refused = status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN;
+    if !is_page || !refused || response.extensions().get::<ErrorLogEntry>().is_none() {
+        return response;
+    }
+
+    let mut context = page_context(&pool, "Access denied").await;
+    context.insert("current_user", &current_user);
+    context.insert("user_login", &blocks::user_login(current_user.as_ref(), &destination));
+
+    match render(&tera, "access_denied.html", &context) {
+        Ok(html) => (status, Html(html)).into_response(),
+        Err(e) => {
+            tracing::warn!("Could not render the access denied page: {}", e);
+            response
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        auth::{external::ExternalAuthRegistry, hash_password},
+        handlers::{admin, user},
+        models::User,
+        test_support::{create_test_role, create_test_user, test_site, test_tera},
+    };
+    use axum::{
+        extract::{ConnectInfo, FromRef, Query},
+        http::header,
+        middleware,
+        routing::get,
+        Extension, Form, Router,
+    };
+    use std::{net::SocketAddr, sync::Arc};
+    use tower::Service;
+    use tower_sessions::{MemoryStore, Session};
+
+    #[derive(Clone)]
+    struct PageState {
+        pool: Pool,
+        tera: Tera,
+    }
+
+    impl FromRef<PageState> for Pool {
+        fn from_ref(state: &PageState) -> Self {
+            state.pool.clone()
+        }
+    }
+
+    impl FromRef<PageState> for Tera {
+        fn from_ref(state: &PageState) -> Self {
+            state.tera.clone()
+        }
+    }
+
+    /// The destination the login form on `page` sends the visitor back to.
+    fn login_destination(page: &str) -> Option<String> {
+        let start = page.find("action=\"/user/login?")? + "action=\"/user/login?".len();
+        let query = &page[start..start + page[start..].find('"')?];
+        let query = query.replace("&#x2F;", "/").replace("&amp;", "&");
+        let Query(login) = Query::<user::LoginQuery>::try_from_uri(&format!("/?{}", query).parse().ok()?).ok()?;
+        login.destination
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn logging_in_from_access_denied_returns_to_the_page() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let state = PageState { pool: pool.clone(), tera: test_tera() };
+        let mut app = Router::new()
+            .route("/admin", get(admin::index))
+            .layer(middleware::from_fn_with_state(state.clone(), access_denied_middleware))
+            .layer(Extension(CurrentUser(None)))
+            .with_state(state);
+
+        let request = Request::get("/admin?tab=1").body(Body::empty()).unwrap();
+        let response = app.call(request).await.unwrap();
+        assert!(matches!(response.status(), StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN));
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX).await.unwrap();
+        let page = String::from_utf8(body.to_vec()).unwrap();
+        let destination = login_destination(&page).expect("the page offers a login form");
+        assert_eq!(destination, "/admin?tab=1");
+
+        let role = create_test_role(pool, &["access administration pages"]).await;
+        let account = create_test_user(pool, &[&role.name]).await;
+        let hash = hash_password("secret123").unwrap();
+        User::update_password(pool, account.uid, &hash).await.unwrap();
+
+        let session = Session::new(None, Arc::new(MemoryStore::default()), None);
+        let result = user::login_submit(
+            State(pool.clone()),
+            State(test_tera()),
+            State(Arc::new(ExternalAuthRegistry::new())),
+            ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 0))),
+            session,
+            Query(user::LoginQuery { registered: None, destination: Some(destination) }),
+            Form(user::LoginForm { username: account.name.clone(), password: "secret123".to_string() }),
+        )
+        .await
+        .unwrap();
+        let Err(redirect) = result else { panic!("logging in did not redirect") };
+        let response = redirect.into_response();
+        assert_eq!(response.headers()[header::LOCATION], "/admin?tab=1");
+
+        let account = User::find_by_uid(pool, account.uid).await.unwrap().unwrap();
+        assert!(admin::index(State(pool.clone()), State(test_tera()), Extension(CurrentUser(Some(account))))
+            .await
+            .is_ok());
+    }
+}
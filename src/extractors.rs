@@ -1,11 +1,18 @@
 use axum::{
     async_trait,
-    extract::{FromRequest, Request},
-    http::StatusCode,
+    extract::{FromRef, FromRequest, Request},
+    http::{header, StatusCode},
     response::{IntoResponse, Response},
 };
 use bytes::Bytes;
+use http_body_util::LengthLimitError;
 use serde::de::DeserializeOwned;
+use std::sync::Arc;
+
+use crate::config::Config;
+
+/// How deeply bracketed form field names may nest, as serde_qs's default.
+const QS_MAX_DEPTH: usize = 5;
 
 pub struct QsForm<T>(pub T);
 
@@ -22,7 +29,10 @@ where
             .await
             .map_err(|_| QsFormRejection::BytesRejection)?;
 
-        let value = serde_qs::from_bytes(&bytes)
+        // Browsers percent-encode the brackets of names like `nids[0]`,
+        // which strict mode would take literally instead of nesting.
+        let value = serde_qs::Config::new(QS_MAX_DEPTH, false)
+            .deserialize_bytes(&bytes)
             .map_err(|e| QsFormRejection::FailedToDeserialize(e.to_string()))?;
 
         Ok(QsForm(value))
@@ -46,3 +56,206 @@ impl IntoResponse for QsFormRejection {
         }
     }
 }
+
+/// A `multipart/form-data` body, for forms with file uploads. Text fields
+/// are deserialized into `T` the way [`QsForm`] does it; uploaded files are
+/// kept in `files`. The body and each file are limited by the `[uploads]`
+/// section of the configuration.
+pub struct MultipartForm<T> {
+    pub fields: T,
+    pub files: Vec<MultipartPart>,
+}
+
+/// An uploaded file.
+pub struct MultipartPart {
+    pub name: String,
+    pub filename: Option<String>,
+    pub content_type: Option<String>,
+    pub data: Bytes,
+}
+
+impl<T> MultipartForm<T> {
+    /// The file uploaded in a file field, if one was chosen.
+    pub fn file(&self, name: &str) -> Option<&MultipartPart> {
+        self.files.iter().find(|part| part.name == name)
+    }
+}
+
+#[async_trait]
+impl<S, T> FromRequest<S> for MultipartForm<T>
+where
+    S: Send + Sync,
+    T: DeserializeOwned,
+    Arc<Config>: FromRef<S>,
+{
+    type Rejection = MultipartRejection;
+
+    async fn from_request(req: Request, state: &S) -> Result<Self, Self::Rejection> {
+        let limits = Arc::<Config>::from_ref(state).uploads.clone();
+        let boundary = req
+            .headers()
+            .get(header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok())
+            .and_then(multipart_boundary)
+            .ok_or(MultipartRejection::NotMultipart)?;
+
+        let bytes = axum::body::to_bytes(req.into_body(), limits.max_body_size)
+            .await
+            .map_err(|e| {
+                if e.into_inner().is::<LengthLimitError>() {
+                    MultipartRejection::BodyTooLarge(limits.max_body_size)
+                } else {
+                    MultipartRejection::BytesRejection
+                }
+            })?;
+
+        let parts = parse_multipart(&bytes, &boundary).ok_or(MultipartRejection::InvalidBody)?;
+
+        let mut query = String::new();
+        let mut files = Vec::new();
+        for part in parts {
+            match &part.filename {
+                // A file input left empty still sends a part, without a name.
+                Some(filename) if filename.is_empty() => {}
+                Some(_) => {
+                    if part.data.len() > limits.max_file_size {
+                        return Err(MultipartRejection::FileTooLarge(part.name, limits.max_file_size));
+                    }
+                    files.push(part);
+                }
+                None => {
+                    if !query.is_empty() {
+                        query.push('&');
+                    }
+                    query.push_str(&encode_component(part.name.as_bytes(), true));
+                    query.push('=');
+                    query.push_str(&encode_component(&part.data, false));
+                }
+            }
+        }
+
+        let fields = serde_qs::from_str(&query)
+            .map_err(|e| MultipartRejection::FailedToDeserialize(e.to_string()))?;
+
+        Ok(MultipartForm { fields, files })
+    }
+}
+
+pub enum MultipartRejection {
+    NotMultipart,
+    BytesRejection,
+    InvalidBody,
+    BodyTooLarge(usize),
+    FileTooLarge(String, usize),
+    FailedToDeserialize(String),
+}
+
+impl IntoResponse for MultipartRejection {
+    fn into_response(self) -> Response {
+        match self {
+            MultipartRejection::NotMultipart => {
+                (StatusCode::UNSUPPORTED_MEDIA_TYPE, "Expected a multipart/form-data body").into_response()
+            }
+            MultipartRejection::BytesRejection => {
+                (StatusCode::BAD_REQUEST, "Failed to read request body").into_response()
+            }
+            MultipartRejection::InvalidBody => {
+                (StatusCode::BAD_REQUEST, "Malformed multipart body").into_response()
+            }
+            MultipartRejection::BodyTooLarge(limit) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, format!("Request body exceeds {} bytes", limit)).into_response()
+            }
+            MultipartRejection::FileTooLarge(field, limit) => (
+                StatusCode::UNPROCESSABLE_ENTITY,
+                format!("The file uploaded in '{}' exceeds {} bytes", field, limit),
+            )
+                .into_response(),
+            MultipartRejection::FailedToDeserialize(e) => {
+                (StatusCode::UNPROCESSABLE_ENTITY, format!("Failed to deserialize form body: {}", e)).into_response()
+            }
+        }
+    }
+}
+
+/// Percent-encode a field name or value for the query string handed to
+/// serde_qs. Names keep their brackets so `items[0]` still nests.
+fn encode_component(data: &[u8], keep_brackets: bool) -> String {
+    let mut encoded = String::with_capacity(data.len());
+    for &byte in data {
+        match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                encoded.push(byte as char)
+            }
+            b'[' | b']' if keep_brackets => encoded.push(byte as char),
+            _ => encoded.push_str(&format!("%{:02X}", byte)),
+        }
+    }
+    encoded
+}
+
+fn multipart_boundary(content_type: &str) -> Option<String> {
+    let (mime, params) = content_type.split_once(';')?;
+    if !mime.trim().eq_ignore_ascii_case("multipart/form-data") {
+        return None;
+    }
+    header_param(params, "boundary").filter(|b| !b.is_empty())
+}
+
+/// A `key=value` parameter of a header such as Content-Type or
+/// Content-Disposition, unquoted.
+fn header_param(params: &str, key: &str) -> Option<String> {
+    params.split(';').find_map(|param| {
+        let (k, v) = param.split_once('=')?;
+        k.trim()
+            .eq_ignore_ascii_case(key)
+            .then(|| v.trim().trim_matches('"').to_string())
+    })
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack.windows(needle.len()).position(|window| window == needle)
+}
+
+fn parse_multipart(body: &Bytes, boundary: &str) -> Option<Vec<MultipartPart>> {
+    let delimiter = format!("--{}", boundary);
+    let next_delimiter = format!("\r\n{}", delimiter);
+
+    let mut pos = find(body, delimiter.as_bytes())? + delimiter.len();
+    let mut parts = Vec::new();
+    // Every delimiter is followed by CRLF and a part, or by "--" when it
+    // closes the body.
+    while !body[pos..].starts_with(b"--") {
+        pos += 2;
+        if !body.get(pos - 2..pos)?.eq(b"\r\n") {
+            return None;
+        }
+
+        let header_len = find(&body[pos..], b"\r\n\r\n")?;
+        let headers = std::str::from_utf8(&body[pos..pos + header_len]).ok()?;
+        let data_start = pos + header_len + 4;
+        let data_len = find(&body[data_start..], next_delimiter.as_bytes())?;
+
+        let mut part = MultipartPart {
+            name: String::new(),
+            filename: None,
+            content_type: None,
+            data: body.slice(data_start..data_start + data_len),
+        };
+        for line in headers.split("\r\n") {
+            let Some((name, value)) = line.split_once(':') else {
+                continue;
+            };
+            if name.trim().eq_ignore_ascii_case("content-disposition") {
+                part.name = header_param(value, "name").unwrap_or_default();
+                part.filename = header_param(value, "filename");
+            } else if name.trim().eq_ignore_ascii_case("content-type") {
+                part.content_type = Some(value.trim().to_ascii_lowercase());
+            }
+        }
+        parts.push(part);
+
+        pos = data_start + data_len + next_delimiter.len();
+    }
+
+    Some(parts)
+}
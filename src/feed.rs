@@ -0,0 +1,117 @@
+use axum::http::{header, HeaderMap, Uri};
+use serde::Serialize;
+
+use crate::db::Pool;
+use crate::models::SiteSettings;
+
+pub const RSS_CONTENT_TYPE: &str = "application/rss+xml; charset=utf-8";
+
+#[derive(Debug, Clone, Serialize)]
+pub struct FeedItem {
+    pub title: String,
+    pub link: String,
+    pub description: String,
+    pub author: Option<String>,
+    pub timestamp: i64,
+    pub guid: String,
+}
+
+pub fn xml_escape(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn rfc2822(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0)
+        .unwrap_or_default()
+        .to_rfc2822()
+}
+
+/// Render a complete RSS 2.0 document. Every text value is escaped, so
+/// descriptions may contain markup.
+pub fn render_channel(items: &[FeedItem], title: &str, link: &str, description: &str) -> String {
+    let mut xml = String::new();
+    xml.push_str("<?xml version=\"1.0\" encoding=\"utf-8\"?>\n");
+    xml.push_str("<rss version=\"2.0\" xml:base=\"");
+    xml.push_str(&xml_escape(link));
+    xml.push_str("\">\n<channel>\n");
+    xml.push_str(&format!(" <title>{}</title>\n", xml_escape(title)));
+    xml.push_str(&format!(" <link>{}</link>\n", xml_escape(link)));
+    xml.push_str(&format!(" <description>{}</description>\n", xml_escape(description)));
+    xml.push_str(" <language>en</language>\n");
+
+    for item in items {
+        xml.push_str("<item>\n");
+        xml.push_str(&format!(" <title>{}</title>\n", xml_escape(&item.title)));
+        xml.push_str(&format!(" <link>{}</link>\n", xml_escape(&item.link)));
+        xml.push_str(&format!(" <description>{}</description>\n", xml_escape(&item.description)));
+        if let Some(author) = &item.author {
+            xml.push_str(&format!(" <dc:creator xmlns:dc=\"http://purl.org/dc/elements/1.1/\">{}</dc:creator>\n", xml_escape(author)));
+        }
+        xml.push_str(&format!(" <pubDate>{}</pubDate>\n", rfc2822(item.timestamp)));
+        xml.push_str(&format!(" <guid isPermaLink=\"false\">{}</guid>\n", xml_escape(&item.guid)));
+        xml.push_str("</item>\n");
+    }
+
+    xml.push_str("</channel>\n</rss>\n");
+    xml
+}
+
+/// Origin for absolute links in feeds and pages: the `site_url` variable
+/// when it holds a valid origin, otherwise whatever the current request was
+/// addressed to.
+pub async fn base_url(pool: &Pool, headers: &HeaderMap) -> String {
+    match configured_base_url(pool).await {
+        Some(origin) => origin,
+        None => request_base_url(headers),
+    }
+}
+
+/// Origin for absolute links in e-mails: the `site_url` variable only.
+/// Request headers are chosen by whoever asks for a mail, so a link built
+/// from them could send its recipient to any site.
+pub async fn configured_base_url(pool: &Pool) -> Option<String> {
+    site_origin(&SiteSettings::load(pool).await.site_url)
+}
+
+/// `value` as a canonical site origin, `scheme://host[:port]` in lower
+/// case. Only http and https qualify, and nothing may follow the host but a
+/// trailing `/`; `None` for anything else, including an empty value.
+pub fn site_origin(value: &str) -> Option<String> {
+    let value = value.trim();
+    if value.contains(['?', '#', '@']) || value.contains(char::is_whitespace) {
+        return None;
+    }
+    let uri: Uri = value.parse().ok()?;
+    let scheme = uri.scheme_str()?.to_ascii_lowercase();
+    let authority = uri.authority()?;
+    if !matches!(scheme.as_str(), "http" | "https") || authority.host().is_empty() || !matches!(uri.path(), "" | "/") {
+        return None;
+    }
+    Some(format!("{}://{}", scheme, authority.as_str().to_ascii_lowercase()))
+}
+
+/// Absolute origin of the current request.
+fn request_base_url(headers: &HeaderMap) -> String {
+    let host = headers
+        .get("x-forwarded-host")
+        .or_else(|| headers.get(header::HOST))
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("localhost");
+    let scheme = headers
+        .get("x-forwarded-proto")
+        .and_then(|h| h.to_str().ok())
+        .unwrap_or("http");
+
+    format!("{}://{}", scheme, host)
+}
@@ -0,0 +1,411 @@
+use serde::Serialize;
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::sync::{LazyLock, RwLock};
+
+use crate::db::Pool;
+use crate::models::{User, Variable};
+
+/// Input format ids, stored in `node_revisions.format`, where 0 means "the
+/// content type's default". Ids follow Drupal; 2 (PHP code) is not ported.
+pub const FORMAT_FILTERED_HTML: i32 = 1;
+pub const FORMAT_FULL_HTML: i32 = 3;
+pub const FORMAT_PREFORMATTED: i32 = 4;
+
+#[derive(Debug, Clone, Serialize)]
+pub struct InputFormat {
+    pub id: i32,
+    pub name: &'static str,
+    pub description: Cow<'static, str>,
+}
+
+pub const INPUT_FORMATS: &[InputFormat] = &[
+    InputFormat {
+        id: FORMAT_FILTERED_HTML,
+        name: "Filtered HTML",
+        description: Cow::Borrowed("Web page addresses are turned into links automatically. Lines and paragraphs break automatically."),
+    },
+    InputFormat {
+        id: FORMAT_FULL_HTML,
+        name: "Full HTML",
+        description: Cow::Borrowed("Any HTML is allowed. Web page addresses are turned into links automatically. Lines and paragraphs break automatically."),
+    },
+    InputFormat {
+        id: FORMAT_PREFORMATTED,
+        name: "Preformatted text",
+        description: Cow::Borrowed("No HTML is allowed. Text is shown exactly as entered, which suits code snippets."),
+    },
+];
+
+/// Tags Filtered HTML allows until an administrator changes the
+/// `allowed_html_1` variable.
+pub const DEFAULT_ALLOWED_HTML: &str = "<a> <em> <strong> <cite> <code> <ul> <ol> <li> <dl> <dt> <dd>";
+
+/// Tags allowed in the site footer and mission. Fixed rather than taken from
+/// `allowed_html_1`, since they appear inside every page's layout.
+const SITE_TEXT_TAGS: &[&str] = &["a", "em", "strong", "cite", "code", "br"];
+
+/// Tags that are never allowed in Filtered HTML, whatever the variable says.
+/// Script and style contents are dropped entirely rather than escaped.
+const FORBIDDEN_TAGS: &[&str] = &[
+    "script", "style", "iframe", "object", "embed", "applet", "form", "input", "button",
+    "textarea", "select", "link", "meta", "base",
+];
+
+/// Filtered HTML tags currently in effect. Tera filters cannot reach the
+/// database, so the variable is loaded at startup and on every change.
+static ALLOWED_TAGS: LazyLock<RwLock<Vec<String>>> =
+    LazyLock::new(|| RwLock::new(parse_allowed_tags(DEFAULT_ALLOWED_HTML)));
+
+/// Parse a tag list such as `<a> <em> <strong>` into lowercase tag names,
+/// dropping anything in [`FORBIDDEN_TAGS`].
+pub fn parse_allowed_tags(list: &str) -> Vec<String> {
+    let mut tags: Vec<String> = list
+        .split(|c: char| c == '<' || c == '>' || c == ',' || c.is_whitespace())
+        .map(|tag| tag.trim_start_matches('/').to_ascii_lowercase())
+        .filter(|tag| !tag.is_empty() && tag.chars().all(|c| c.is_ascii_alphanumeric()))
+        .filter(|tag| !FORBIDDEN_TAGS.contains(&tag.as_str()))
+        .collect();
+    tags.sort();
+    tags.dedup();
+    tags
+}
+
+/// The allowed tags as shown to users, e.g. `<a> <em>`.
+pub fn allowed_tags_display() -> String {
+    let tags = ALLOWED_TAGS.read().unwrap_or_else(|e| e.into_inner());
+    tags.iter().map(|tag| format!("<{}>", tag)).collect::<Vec<_>>().join(" ")
+}
+
+/// Load the Filtered HTML tag list from the `allowed_html_1` variable.
+pub async fn load_settings(pool: &Pool) {
+    let list = Variable::get_or_default(pool, "allowed_html_1", DEFAULT_ALLOWED_HTML).await;
+    set_allowed_tags(&list);
+}
+
+pub fn set_allowed_tags(list: &str) {
+    let mut tags = ALLOWED_TAGS.write().unwrap_or_else(|e| e.into_inner());
+    *tags = parse_allowed_tags(list);
+}
+
+pub fn find_format(id: i32) -> Option<&'static InputFormat> {
+    INPUT_FORMATS.iter().find(|f| f.id == id)
+}
+
+/// Formats `user` may pick on the node form. Full HTML can inject scripts,
+/// so it is reserved for filter administrators.
+pub async fn allowed_formats(
+    pool: &Pool,
+    user: &User,
+) -> Result<Vec<InputFormat>, sqlx::Error> {
+    let full_html = user.has_permission(pool, "administer filters").await?;
+
+    Ok(INPUT_FORMATS
+        .iter()
+        .filter(|f| f.id != FORMAT_FULL_HTML || full_html)
+        .cloned()
+        .map(|mut f| {
+            if f.id == FORMAT_FILTERED_HTML {
+                f.description = Cow::Owned(format!(
+                    "Allowed HTML tags: {}. {}",
+                    allowed_tags_display(),
+                    f.description
+                ));
+            }
+            f
+        })
+        .collect())
+}
+
+/// Default body format of a content type, stored in the
+/// `node_body_format_<type>` variable.
+pub async fn type_default_format(pool: &Pool, node_type: &str) -> i32 {
+    Variable::get_or_default(pool, &format!("node_body_format_{}", node_type), "1")
+        .await
+        .parse()
+        .ok()
+        .filter(|id| find_format(*id).is_some())
+        .unwrap_or(FORMAT_FILTERED_HTML)
+}
+
+/// Resolve a stored format, falling back to the type default for 0 or an
+/// unknown id.
+pub async fn resolve_format(pool: &Pool, format: i32, node_type: &str) -> i32 {
+    if find_format(format).is_some() {
+        format
+    } else {
+        type_default_format(pool, node_type).await
+    }
+}
+
+/// Run `text` through the filters of `format`.
+pub fn check_markup(text: &str, format: i32) -> String {
+    match format {
+        FORMAT_FULL_HTML => autop(&url_filter(text)),
+        FORMAT_PREFORMATTED => format!("<pre>{}</pre>", html_escape(text)),
+        _ => {
+            let allowed = ALLOWED_TAGS.read().unwrap_or_else(|e| e.into_inner());
+            autop(&url_filter(&filter_html(text, &allowed)))
+        }
+    }
+}
+
+/// Strip every tag of `text` but the `allowed` ones.
+fn filter_html(text: &str, allowed: &[String]) -> String {
+    // Only these attributes survive, so event handlers such as onclick and
+    // inline styles are always stripped.
+    ammonia::Builder::default()
+        .tags(allowed.iter().map(String::as_str).collect())
+        .generic_attributes(HashSet::from(["title"]))
+        .tag_attributes(HashMap::from([("a", HashSet::from(["href", "title"]))]))
+        .clean(text)
+        .to_string()
+}
+
+/// The tags the site footer and mission allow, as shown to users.
+pub fn site_text_tags_display() -> String {
+    SITE_TEXT_TAGS.iter().map(|tag| format!("<{}>", tag)).collect::<Vec<_>>().join(" ")
+}
+
+/// Clean the site footer or mission for output, keeping only the tags in
+/// [`SITE_TEXT_TAGS`] and the same attributes as Filtered HTML.
+pub fn filter_site_text(text: &str) -> String {
+    ammonia::Builder::default()
+        .tags(SITE_TEXT_TAGS.iter().copied().collect())
+        .generic_attributes(HashSet::from(["title"]))
+        .tag_attributes(HashMap::from([("a", HashSet::from(["href", "title"]))]))
+        .clean(text.trim())
+        .to_string()
+}
+
+/// Turn bare web addresses into links. Text inside tags, existing links and
+/// script or style elements is left alone.
+fn url_filter(html: &str) -> String {
+    let mut output = String::with_capacity(html.len());
+    let mut skip_depth = 0usize;
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        if rest.starts_with('<') {
+            let end = rest.find('>').map(|i| i + 1).unwrap_or(rest.len());
+            let tag = &rest[..end];
+            let name = tag
+                .trim_start_matches('<')
+                .trim_start_matches('/')
+                .split(|c: char| !c.is_ascii_alphanumeric())
+                .next()
+                .unwrap_or("")
+                .to_ascii_lowercase();
+            if matches!(name.as_str(), "a" | "script" | "style") {
+                if tag.starts_with("</") {
+                    skip_depth = skip_depth.saturating_sub(1);
+                } else {
+                    skip_depth += 1;
+                }
+            }
+            output.push_str(tag);
+            rest = &rest[end..];
+            continue;
+        }
+
+        let end = rest.find('<').unwrap_or(rest.len());
+        let text = &rest[..end];
+        if skip_depth > 0 {
+            output.push_str(text);
+        } else {
+            link_urls(text, &mut output);
+        }
+        rest = &rest[end..];
+    }
+
+    output
+}
+
+fn link_urls(text: &str, output: &mut String) {
+    let mut rest = text;
+    while let Some(start) = find_url_start(rest) {
+        output.push_str(&rest[..start]);
+        let candidate = &rest[start..];
+        let len = candidate
+            .find(|c: char| c.is_whitespace() || c == '"' || c == '\'' || c == '<')
+            .unwrap_or(candidate.len());
+        let mut url = candidate[..len].trim_end_matches(['.', ',', ';', ':', '!', '?']);
+        // A closing parenthesis belongs to the address only if it opened one.
+        while url.ends_with(')') && url.matches(')').count() > url.matches('(').count() {
+            url = url[..url.len() - 1].trim_end_matches(['.', ',', ';', ':', '!', '?']);
+        }
+        let href = if url.starts_with("www.") {
+            format!("http://{}", url)
+        } else {
+            url.to_string()
+        };
+        output.push_str(&format!("<a href=\"{}\">{}</a>", href, url));
+        rest = &candidate[url.len()..];
+    }
+    output.push_str(rest);
+}
+
+/// Byte offset of the next `http://`, `https://` or `www.` address that
+/// starts a word.
+fn find_url_start(text: &str) -> Option<usize> {
+    ["http://", "https://", "www."]
+        .iter()
+        .filter_map(|prefix| {
+            text.match_indices(prefix)
+                .find(|(i, _)| {
+                    let at_word_start = text[..*i]
+                        .chars()
+                        .next_back()
+                        .is_none_or(|c| !c.is_alphanumeric() && c != '/' && c != '.');
+                    at_word_start
+                        && text[i + prefix.len()..].starts_with(|c: char| c.is_alphanumeric())
+                })
+                .map(|(i, _)| i)
+        })
+        .min()
+}
+
+fn html_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Convert blank-line separated chunks into paragraphs and single newlines
+/// into line breaks. Chunks that already start with a block-level tag are
+/// left alone.
+fn autop(text: &str) -> String {
+    let text = text.replace("\r\n", "\n").replace('\r', "\n");
+
+    text.split("\n\n")
+        .map(str::trim)
+        .filter(|chunk| !chunk.is_empty())
+        .map(|chunk| {
+            if is_block_start(chunk) {
+                chunk.to_string()
+            } else {
+                format!("<p>{}</p>", chunk.replace('\n', "<br />\n"))
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\n")
+}
+
+fn is_block_start(chunk: &str) -> bool {
+    const BLOCK_TAGS: &[&str] = &[
+        "<p", "<div", "<ul", "<ol", "<dl", "<table", "<pre", "<blockquote", "<h1", "<h2",
+        "<h3", "<h4", "<h5", "<h6", "<hr", "<form", "<address",
+    ];
+    let lower = chunk.to_ascii_lowercase();
+    BLOCK_TAGS.iter().any(|tag| lower.starts_with(tag))
+}
+
+/// Tera filter: `{{ node.body | check_markup(format=node.format) | safe }}`.
+/// The format must already be resolved by the handler.
+pub fn check_markup_filter(
+    value: &tera::Value,
+    args: &HashMap<String, tera::Value>,
+) -> tera::Result<tera::Value> {
+    let text = match value {
+        tera::Value::String(s) => s,
+        tera::Value::Null => return Ok(tera::Value::String(String::new())),
+        _ => return Ok(value.clone()),
+    };
+
+    let format = args
+        .get("format")
+        .and_then(|f| f.as_i64())
+        .map(|f| f as i32)
+        .unwrap_or(FORMAT_FILTERED_HTML);
+
+    Ok(tera::Value::String(check_markup(text, format)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn filtered(text: &str) -> String {
+        filter_html(text, &parse_allowed_tags(DEFAULT_ALLOWED_HTML))
+    }
+
+    #[test]
+    fn scripts_are_removed_with_their_contents() {
+        let html = filtered("Hello<script>alert('x')</script> <SCRIPT src=\"evil.js\"></SCRIPT>world");
+        assert!(!html.to_ascii_lowercase().contains("script"), "{}", html);
+        assert!(!html.contains("alert"), "{}", html);
+        assert!(html.contains("Hello") && html.contains("world"));
+    }
+
+    #[test]
+    fn forbidden_tags_cannot_be_allowed() {
+        let tags = parse_allowed_tags("<a> <script> <STYLE> <iframe> <em>");
+        assert_eq!(tags, ["a", "em"]);
+
+        let html = filter_html("<script>alert(1)</script><em>ok</em>", &tags);
+        assert_eq!(html, "<em>ok</em>");
+    }
+
+    #[test]
+    fn event_handler_attributes_are_removed() {
+        let html = filtered("<a href=\"/node/1\" onclick=\"steal()\" title=\"t\">x</a><em onmouseover=\"steal()\">y</em>");
+        assert!(!html.contains("onclick") && !html.contains("onmouseover"), "{}", html);
+        assert!(html.contains("href=\"/node/1\""));
+        assert!(html.contains("<em>y</em>"));
+    }
+
+    #[test]
+    fn javascript_urls_are_removed() {
+        for href in ["javascript:alert(1)", "JavaScript:alert(1)", " javascript:alert(1)", "vbscript:x"] {
+            let html = filtered(&format!("<a href=\"{}\">x</a>", href));
+            assert!(!html.to_ascii_lowercase().contains("script:"), "{}", html);
+        }
+    }
+
+    #[test]
+    fn tags_outside_the_list_are_stripped_but_text_kept() {
+        assert_eq!(filtered("<div><b>bold</b> <em>em</em></div>"), "bold <em>em</em>");
+    }
+
+    #[test]
+    fn filtered_html_applies_every_filter() {
+        let html = check_markup("<em>Hi</em> <script>x()</script>\nsee www.example.com", FORMAT_FILTERED_HTML);
+        assert_eq!(html, "<p><em>Hi</em> <br />\nsee <a href=\"http://www.example.com\">www.example.com</a></p>");
+    }
+
+    #[test]
+    fn url_filter_links_bare_addresses() {
+        assert_eq!(
+            url_filter("Go to https://example.com/a?b=1."),
+            "Go to <a href=\"https://example.com/a?b=1\">https://example.com/a?b=1</a>."
+        );
+        assert_eq!(
+            url_filter("(see http://example.com/x)"),
+            "(see <a href=\"http://example.com/x\">http://example.com/x</a>)"
+        );
+        assert_eq!(url_filter("www.example.com"), "<a href=\"http://www.example.com\">www.example.com</a>");
+    }
+
+    #[test]
+    fn url_filter_leaves_links_and_attributes_alone() {
+        let html = "<a href=\"http://example.com\">http://example.com</a> <em title=\"http://x.org\">y</em>";
+        assert_eq!(url_filter(html), html);
+        assert_eq!(url_filter("notwww.example.com and a/http://x"), "notwww.example.com and a/http://x");
+    }
+
+    #[test]
+    fn autop_builds_paragraphs_and_line_breaks() {
+        assert_eq!(autop("one\ntwo\r\n\r\nthree"), "<p>one<br />\ntwo</p>\n<p>three</p>");
+        assert_eq!(autop("<ul><li>a</li></ul>\n\nb"), "<ul><li>a</li></ul>\n<p>b</p>");
+        assert_eq!(autop("\n\n\n"), "");
+    }
+
+    #[test]
+    fn preformatted_text_is_escaped() {
+        assert_eq!(
+            check_markup("<b>&</b>", FORMAT_PREFORMATTED),
+            "<pre>&lt;b&gt;&amp;&lt;/b&gt;</pre>"
+        );
+    }
+}
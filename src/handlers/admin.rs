@@ -1,21 +1,78 @@
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
     response::{Html, Redirect},
-    Extension, Form,
+    Extension, Form, Json,
 };
 use crate::extractors::QsForm;
-use serde::Deserialize;
-use sqlx::MySqlPool;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use tera::Tera;
 
 use crate::{
-    auth::middleware::CurrentUser,
-    error::{AppError, AppResult},
-    models::{get_default_theme, AccessLog, Node, NodeType, SystemItem, User, Variable},
+    auth::{hash_password, middleware::CurrentUser, LoginThrottle},
+    batch::{self, BatchJob, MailUsersState},
+    cache, cron,
+    db::{migrations::install_completed_at, Pool},
+    error::{AppError, AppResult, ResultExt},
+    feed, filter,
+    handlers::{comment::CommentPermissions, page_context},
+    image_styles::{self, ImageDisplay, ImageStyle},
+    models::{
+        get_default_theme, AccessLog, Comment, CommentAnonymous, CommentLength, CommentMode, CommentOrder, CommentPreview, Node, NodeField, NodeFieldData,
+        NodeFieldInstance, NodeListFilter, NodeListQuery, NodeListSort, NodeRevision, NodeType, Nomination, Permission, Role, SiteSettings, SystemItem,
+        TypeOptions, User, Variable, Watchdog, WatchdogFilter, COMMENT_PUBLISHED, WATCHDOG_NOTICE,
+        WATCHDOG_SEVERITIES,
+    },
+    pager::{PageQuery, Pager},
+    pictures::PictureSettings,
+    statistics,
+    templates::render,
 };
 
+/// Sections of the administration index, as (label, path, permission needed
+/// to use the page) links.
+type AdminBlock = (&'static str, &'static [(&'static str, &'static str, &'static str)]);
+
+const ADMIN_BLOCKS: &[AdminBlock] = &[
+    ("Content management", &[
+        ("Content", "/admin/node", "administer nodes"),
+        ("Content types", "/admin/node/types", "administer nodes"),
+        ("Front page nominations", "/admin/node/nominations", "administer nodes"),
+        ("Comment approval queue", "/admin/comment/approval", "administer comments"),
+        ("Comment settings", "/admin/comment/settings", "administer comments"),
+    ]),
+    ("User management", &[
+        ("Users", "/admin/user", "administer users"),
+        ("Send e-mail", "/admin/user/email", "mail users"),
+        ("Roles", "/admin/user/roles", "administer access control"),
+        ("Access control", "/admin/user/permissions", "administer access control"),
+    ]),
+    ("Site building", &[
+        ("Modules", "/admin/modules", "administer site configuration"),
+        ("Themes", "/admin/themes", "administer site configuration"),
+    ]),
+    ("Site configuration", &[
+        ("Site information", "/admin/settings", "administer site configuration"),
+        ("Image styles", "/admin/settings/image-styles", "administer site configuration"),
+        ("Input formats", "/admin/settings/filters", "administer filters"),
+    ]),
+    ("Logs", &[
+        ("Recent hits", "/admin/logs/hits", "access site reports"),
+        ("Top pages", "/admin/logs/pages", "access site reports"),
+        ("Top visitors", "/admin/logs/visitors", "access site reports"),
+        ("Top referrers", "/admin/logs/referrers", "access site reports"),
+        ("Statistics settings", "/admin/logs/settings", "administer site configuration"),
+    ]),
+    ("Reports", &[
+        ("Recent log entries", "/admin/reports/dblog", "access site reports"),
+        ("Status report", "/admin/reports/status", "access site reports"),
+        ("Field usage", "/admin/reports/fields", "access site reports"),
+        ("Recent changes", "/admin/reports/recent-changes", "administer nodes"),
+    ]),
+];
+
 pub async fn index(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
 ) -> AppResult<Html<String>> {
@@ -23,50 +80,41 @@ pub async fn index(
         return Err(AppError::Unauthorized);
     };
 
-    if !user.has_permission(&pool, "administer nodes").await? {
-         return Err(AppError::Forbidden);
+    if !user.has_permission(&pool, "access administration pages").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    // Only the links the user may follow; blocks left empty are dropped.
+    let mut admin_blocks = Vec::new();
+    for (title, links) in ADMIN_BLOCKS {
+        let mut visible = Vec::new();
+        for &(label, path, permission) in *links {
+            if user.has_permission(&pool, permission).await? {
+                visible.push((label, path));
+            }
+        }
+        if !visible.is_empty() {
+            admin_blocks.push((title, visible));
+        }
     }
 
-    let current_theme = get_default_theme(&pool).await;
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", "Administer");
-    context.insert("current_user", &Some(user));
-
-    let admin_blocks = vec![
-        ("Content management", vec![
-            ("Content", "/admin/node"),
-            ("Content types", "/admin/node/types"),
-        ]),
-        ("User management", vec![
-            ("Users", "/admin/user"),
-        ]),
-        ("Site building", vec![
-            ("Modules", "/admin/modules"),
-            ("Themes", "/admin/themes"),
-        ]),
-        ("Site configuration", vec![
-            ("Site information", "/admin/settings"),
-        ]),
-        ("Logs", vec![
-            ("Recent hits", "/admin/logs/hits"),
-            ("Top pages", "/admin/logs/pages"),
-            ("Top visitors", "/admin/logs/visitors"),
-            ("Top referrers", "/admin/logs/referrers"),
-            ("Statistics settings", "/admin/logs/settings"),
-        ]),
-        ("Reports", vec![
-            ("Status report", "/admin/reports/status"),
-        ]),
-    ];
+    // Counts shown next to links to work waiting for the user.
+    let mut badges: HashMap<&str, i64> = HashMap::new();
+    if user.has_permission(&pool, "administer comments").await? {
+        badges.insert("/admin/comment/approval", Comment::count_unapproved(&pool).await?);
+    }
+
+    let mut context = page_context(&pool, "Administer").await;
+    context.insert("current_user", &Some(user));
     context.insert("admin_blocks", &admin_blocks);
+    context.insert("badges", &badges);
 
-    let html = tera.render("admin/index.html", &context)?;
+    let html = render(&tera, "admin/index.html", &context)?;
     Ok(Html(html))
 }
 
 pub async fn node_types(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
 ) -> AppResult<Html<String>> {
@@ -79,22 +127,67 @@ pub async fn node_types(
     }
 
     let types = NodeType::all(&pool).await?;
-    let current_theme = get_default_theme(&pool).await;
 
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", "Content types");
+    let mut context = page_context(&pool, "Content types").await;
     context.insert("current_user", &Some(user));
     context.insert("types", &types);
 
-    let html = tera.render("admin/node_types.html", &context)?;
+    let html = render(&tera, "admin/node_types.html", &context)?;
     Ok(Html(html))
 }
 
+const CONTENT_PER_PAGE: u32 = 50;
+
+/// Filter and page of the admin content list. Also posted back as hidden
+/// `filter[...]` fields by the bulk-action form, so admins return to the
+/// same view afterwards.
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct ContentFilterQuery {
+    #[serde(default, rename = "type", skip_serializing_if = "String::is_empty")]
+    pub node_type: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub status: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub author: String,
+    #[serde(default)]
+    pub page: u32,
+}
+
+impl ContentFilterQuery {
+    fn to_filter(&self) -> NodeListFilter {
+        let non_empty = |s: &str| Some(s.trim().to_string()).filter(|s| !s.is_empty());
+        NodeListFilter {
+            node_type: non_empty(&self.node_type),
+            status: match self.status.as_str() {
+                "published" => Some(1),
+                "unpublished" | "scheduled" => Some(0),
+                _ => None,
+            },
+            scheduled: self.status == "scheduled",
+            author: non_empty(&self.author),
+            ..Default::default()
+        }
+    }
+
+    /// Query string for links that keep the filter, without the page.
+    fn filter_query_string(&self) -> String {
+        serde_qs::to_string(&ContentFilterQuery {
+            page: 0,
+            ..self.clone()
+        })
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| !pair.is_empty() && !pair.starts_with("page="))
+        .collect::<Vec<_>>()
+        .join("&")
+    }
+}
+
 pub async fn content_list(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Query(query): Query<ContentFilterQuery>,
 ) -> AppResult<Html<String>> {
     let Some(user) = current_user else {
         return Err(AppError::Unauthorized);
@@ -104,21 +197,55 @@ pub async fn content_list(
         return Err(AppError::Forbidden);
     }
 
-    let nodes = Node::all_for_admin(&pool).await?;
-    let current_theme = get_default_theme(&pool).await;
+    let list = NodeListQuery {
+        filter: query.to_filter(),
+        sort: NodeListSort::Changed,
+        unapproved_counts: true,
+        ..Default::default()
+    };
+    let total = Node::list_count(&pool, &list.filter).await?;
+    let pager = Pager::new(query.page, CONTENT_PER_PAGE, total);
+    let nodes = Node::list(&pool, &list, pager.limit(), pager.offset()).await?;
+    let types = NodeType::all(&pool).await?;
+    let filter_query = query.filter_query_string();
+    let pager_query = if filter_query.is_empty() {
+        String::new()
+    } else {
+        format!("{}&", filter_query)
+    };
 
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", "Content");
+    let mut context = page_context(&pool, "Content").await;
     context.insert("current_user", &Some(user));
     context.insert("nodes", &nodes);
+    context.insert("types", &types);
+    context.insert("filter", &query);
+    context.insert("pager", &pager);
+    context.insert("pager_query", &pager_query);
 
-    let html = tera.render("admin/content.html", &context)?;
+    let html = render(&tera, "admin/content.html", &context)?;
     Ok(Html(html))
 }
 
+/// POST /admin/node/aliases - Queue URL alias generation for every node
+/// that has none and hand over to the batch progress page.
+pub async fn generate_aliases(
+    State(pool): State<Pool>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+) -> AppResult<Redirect> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer nodes").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let bid = batch::enqueue(&pool, BatchJob::GenerateNodeAliases, "{}", user.uid, "/admin/node").await?;
+    Ok(Redirect::to(&format!("/batch/{}", bid)))
+}
+
 pub async fn user_list(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
 ) -> AppResult<Html<String>> {
@@ -131,23 +258,460 @@ pub async fn user_list(
     }
 
     let users = User::all(&pool).await?;
-    let current_theme = get_default_theme(&pool).await;
 
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", "Users");
+    let mut context = page_context(&pool, "Users").await;
     context.insert("current_user", &Some(user));
     context.insert("users", &users);
 
-    let html = tera.render("admin/users.html", &context)?;
+    let html = render(&tera, "admin/users.html", &context)?;
+    Ok(Html(html))
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct MailUsersForm {
+    #[serde(default)]
+    pub subject: String,
+    #[serde(default)]
+    pub body: String,
+    /// Role the message goes to; empty for every active user.
+    #[serde(default)]
+    pub rid: String,
+}
+
+async fn render_mail_users(
+    pool: &Pool,
+    tera: &Tera,
+    user: User,
+    form: &MailUsersForm,
+    error: Option<&str>,
+) -> AppResult<Html<String>> {
+    let mut context = page_context(pool, "Send e-mail to users").await;
+    context.insert("current_user", &Some(user));
+    context.insert("roles", &Role::assignable(pool).await?);
+    context.insert("form", form);
+    if let Some(error) = error {
+        context.insert("error", error);
+    }
+
+    let html = render(tera, "admin/mail_users.html", &context)?;
+    Ok(Html(html))
+}
+
+/// GET /admin/user/email - Compose an e-mail to all active users or a role.
+pub async fn mail_users_form(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+) -> AppResult<Html<String>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "mail users").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    render_mail_users(&pool, &tera, user, &MailUsersForm::default(), None).await
+}
+
+/// POST /admin/user/email - Queue the e-mail as a batch, which sends it a
+/// few recipients at a time from the progress page or cron.
+pub async fn mail_users_submit(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Form(form): Form<MailUsersForm>,
+) -> AppResult<Result<Html<String>, Redirect>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "mail users").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let subject = form.subject.trim();
+    let body = form.body.trim();
+    let role = match form.rid.parse::<u32>() {
+        Ok(rid) => Role::assignable(&pool).await?.into_iter().find(|role| role.rid == rid),
+        Err(_) => None,
+    };
+    let error = if subject.is_empty() {
+        Some("The subject is required.")
+    } else if body.is_empty() {
+        Some("The message is required.")
+    } else if !form.rid.is_empty() && role.is_none() {
+        Some("The selected role does not exist.")
+    } else {
+        None
+    };
+    if let Some(error) = error {
+        return render_mail_users(&pool, &tera, user, &form, Some(error)).await.map(Ok);
+    }
+
+    let settings = SiteSettings::load(&pool).await;
+    let footer = format!(
+        "You are receiving this message because you have an account at {}. \
+         Ask the site administrators if you no longer wish to receive e-mail from the site.",
+        settings.site_name
+    );
+    let state = MailUsersState::new(
+        user.uid,
+        role.as_ref().map(|role| role.rid),
+        subject.to_string(),
+        format!("{}\n\n-- \n{}", body, footer),
+    );
+    let state = serde_json::to_string(&state).unwrap_or_default();
+    let bid = batch::enqueue(&pool, BatchJob::MailUsers, &state, user.uid, "/admin/user").await?;
+
+    let recipients = match &role {
+        Some(role) => format!("users with the {} role", role.name),
+        None => "all users".to_string(),
+    };
+    Watchdog::log(
+        &pool,
+        user.uid,
+        "mail",
+        &format!("E-mail \"{}\" queued for {}.", subject, recipients),
+        WATCHDOG_NOTICE,
+        &format!("/batch/{}", bid),
+        "/admin/user/email",
+    )
+    .await?;
+
+    Ok(Err(Redirect::to(&format!("/batch/{}", bid))))
+}
+
+/// The role list with its form for adding a role, showing `error` about the
+/// submitted `name` when adding failed.
+async fn render_roles(
+    pool: &Pool,
+    tera: &Tera,
+    user: User,
+    name: &str,
+    error: Option<String>,
+) -> AppResult<Html<String>> {
+    let roles = Role::all(pool).await?;
+
+    let mut context = page_context(pool, "Roles").await;
+    context.insert("current_user", &Some(user));
+    context.insert("roles", &roles);
+    context.insert("name", name);
+    context.insert("error", &error);
+
+    let html = render(tera, "admin/roles.html", &context)?;
+    Ok(Html(html))
+}
+
+pub async fn roles(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+) -> AppResult<Html<String>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer access control").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    render_roles(&pool, &tera, user, "", None).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RoleForm {
+    pub name: String,
+}
+
+pub async fn role_add(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Form(form): Form<RoleForm>,
+) -> AppResult<Result<Html<String>, Redirect>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer access control").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let name = form.name.trim();
+    if let Some(error) = Role::validate_name(&pool, name, None).await? {
+        return render_roles(&pool, &tera, user, name, Some(error)).await.map(Ok);
+    }
+
+    Role::create(&pool, name).await?;
+    Ok(Err(Redirect::to("/admin/user/roles")))
+}
+
+/// One module's rows of the permission matrix.
+#[derive(Debug, Serialize)]
+struct PermissionMatrixGroup {
+    module: String,
+    rows: Vec<PermissionMatrixRow>,
+}
+
+/// A permission and, for each role in column order, whether it is granted.
+#[derive(Debug, Serialize)]
+struct PermissionMatrixRow {
+    permission: String,
+    granted: Vec<bool>,
+}
+
+async fn render_access(
+    pool: &Pool,
+    tera: &Tera,
+    user: User,
+    message: Option<&str>,
+    error: Option<&str>,
+) -> AppResult<Html<String>> {
+    let roles = Role::all(pool).await?;
+    let mut role_permissions = Vec::with_capacity(roles.len());
+    for role in &roles {
+        role_permissions.push(Permission::get_for_role(pool, role.rid).await?);
+    }
+
+    let groups: Vec<PermissionMatrixGroup> = Permission::registry(pool)
+        .await?
+        .into_iter()
+        .map(|group| PermissionMatrixGroup {
+            module: group.module,
+            rows: group
+                .permissions
+                .into_iter()
+                .map(|permission| PermissionMatrixRow {
+                    granted: role_permissions.iter().map(|granted| granted.contains(&permission)).collect(),
+                    permission,
+                })
+                .collect(),
+        })
+        .collect();
+
+    let mut context = page_context(pool, "Access control").await;
+    context.insert("current_user", &Some(user));
+    context.insert("roles", &roles);
+    context.insert("groups", &groups);
+    context.insert("message", &message);
+    context.insert("error", &error);
+
+    let html = render(tera, "admin/access.html", &context)?;
+    Ok(Html(html))
+}
+
+/// GET /admin/user/permissions - Which role has which permission. Also
+/// answers at the old /admin/user/access path.
+pub async fn access_form(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+) -> AppResult<Html<String>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer access control").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    render_access(&pool, &tera, user, None, None).await
+}
+
+#[derive(Debug, Deserialize)]
+pub struct AccessForm {
+    /// Checked boxes, as `perms[<rid>][<permission>]`.
+    #[serde(default)]
+    pub perms: HashMap<u32, HashMap<String, String>>,
+}
+
+/// POST /admin/user/permissions - Rebuild every role's permissions from the
+/// matrix. Permissions the registry does not list are left as they are, and
+/// a change that would take this page away from the administrator making it
+/// is refused; the first user account keeps every permission regardless.
+pub async fn access_submit(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    QsForm(form): QsForm<AccessForm>,
+) -> AppResult<Html<String>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer access control").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let known: Vec<String> = Permission::registry(&pool)
+        .await?
+        .into_iter()
+        .flat_map(|group| group.permissions)
+        .collect();
+    let no_boxes = HashMap::new();
+
+    let mut updates = Vec::new();
+    for role in Role::all(&pool).await? {
+        let checked = form.perms.get(&role.rid).unwrap_or(&no_boxes);
+        let mut permissions: Vec<String> = Permission::get_for_role(&pool, role.rid)
+            .await?
+            .into_iter()
+            .filter(|permission| !known.contains(permission))
+            .collect();
+        permissions.extend(known.iter().filter(|p| checked.contains_key(*p)).cloned());
+        updates.push((role.rid, permissions));
+    }
+
+    if user.uid != 1 {
+        let own_roles = Role::rids_for_user(&pool, user.uid).await?;
+        let keeps_access = updates.iter().any(|(rid, permissions)| {
+            own_roles.contains(rid) && permissions.iter().any(|p| p == "administer access control")
+        });
+        if !keeps_access {
+            return render_access(
+                &pool,
+                &tera,
+                user,
+                None,
+                Some("The changes were not saved: none of your roles would keep the administer access control permission."),
+            )
+            .await;
+        }
+    }
+
+    // All roles change or none do, so a failure cannot leave the grid half
+    // saved.
+    let mut tx = pool.begin().await?;
+    for (rid, permissions) in &updates {
+        Permission::set_for_role(&mut *tx, *rid, permissions).await?;
+    }
+    tx.commit().await?;
+    // The cached front page reflects what anonymous visitors may see.
+    cache::invalidate_tags(&[cache::TAG_FRONT_PAGE]);
+
+    render_access(&pool, &tera, user, Some("The changes have been saved."), None).await
+}
+
+/// A role that may be changed, or `NotFound` for a missing or locked one.
+async fn editable_role(pool: &Pool, rid: u32) -> AppResult<Role> {
+    match Role::find_by_rid(pool, rid).await? {
+        Some(role) if !role.is_locked() => Ok(role),
+        _ => Err(AppError::NotFound),
+    }
+}
+
+async fn render_role_edit(
+    pool: &Pool,
+    tera: &Tera,
+    user: User,
+    role: &Role,
+    name: &str,
+    error: Option<String>,
+) -> AppResult<Html<String>> {
+    let mut context = page_context(pool, &format!("Edit role {}", role.name)).await;
+    context.insert("current_user", &Some(user));
+    context.insert("role", role);
+    context.insert("name", name);
+    context.insert("error", &error);
+
+    let html = render(tera, "admin/role_edit.html", &context)?;
     Ok(Html(html))
 }
 
+pub async fn role_edit_form(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Path(rid): Path<u32>,
+) -> AppResult<Html<String>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer access control").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let role = editable_role(&pool, rid).await?;
+    render_role_edit(&pool, &tera, user, &role, &role.name, None).await
+}
+
+pub async fn role_edit_submit(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Path(rid): Path<u32>,
+    Form(form): Form<RoleForm>,
+) -> AppResult<Result<Html<String>, Redirect>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer access control").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let role = editable_role(&pool, rid).await?;
+    let name = form.name.trim();
+    if let Some(error) = Role::validate_name(&pool, name, Some(rid)).await? {
+        return render_role_edit(&pool, &tera, user, &role, name, Some(error)).await.map(Ok);
+    }
+
+    Role::rename(&pool, rid, name).await?;
+    Ok(Err(Redirect::to("/admin/user/roles")))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct RoleDeleteForm {
+    #[serde(default)]
+    pub confirm: Option<String>,
+}
+
+/// POST /admin/user/roles/:rid/delete - Ask for confirmation, then delete
+/// the role together with its permissions and memberships.
+pub async fn role_delete(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Path(rid): Path<u32>,
+    Form(form): Form<RoleDeleteForm>,
+) -> AppResult<Result<Html<String>, Redirect>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer access control").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let role = editable_role(&pool, rid).await?;
+    if form.confirm.is_some() {
+        Role::delete(&pool, rid).await?;
+        return Ok(Err(Redirect::to("/admin/user/roles")));
+    }
+
+    let mut context = page_context(&pool, &format!("Delete role {}", role.name)).await;
+    context.insert("current_user", &Some(user));
+    context.insert("role", &role);
+    context.insert("member_count", &Role::member_count(&pool, rid).await?);
+
+    let html = render(&tera, "admin/role_delete.html", &context)?;
+    Ok(Ok(Html(html)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NodeTypeEditQuery {
+    /// Set after cloning, to the type the one being edited was copied from.
+    pub cloned_from: Option<String>,
+}
+
 pub async fn node_type_edit_form(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
     Path(type_name): Path<String>,
+    Query(query): Query<NodeTypeEditQuery>,
 ) -> AppResult<Html<String>> {
     let Some(user) = current_user else {
         return Err(AppError::Unauthorized);
@@ -160,15 +724,27 @@ pub async fn node_type_edit_form(
     let Some(node_type) = NodeType::find_by_type(&pool, &type_name).await? else {
         return Err(AppError::NotFound);
     };
-    let current_theme = get_default_theme(&pool).await;
 
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", &format!("Edit {}", node_type.name));
+    let mut context = page_context(&pool, &format!("Edit {}", node_type.name)).await;
     context.insert("current_user", &Some(user));
     context.insert("node_type", &node_type);
-
-    let html = tera.render("admin/node_type_edit.html", &context)?;
+    context.insert("formats", filter::INPUT_FORMATS);
+    context.insert("body_format", &filter::type_default_format(&pool, &type_name).await);
+    context.insert(
+        "comment_subject_field",
+        &Comment::subject_field_enabled(&pool, &type_name).await,
+    );
+    context.insert("comments_per_page", &Comment::per_page(&pool, &type_name).await);
+    context.insert("node_options", &TypeOptions::load(&pool, &type_name).await);
+    context.insert(
+        "show_updated",
+        &(Variable::get_or_default(&pool, &format!("node_show_updated_{}", type_name), "0").await == "1"),
+    );
+    context.insert("show_submitted", &NodeType::show_submitted(&pool, &type_name).await);
+    context.insert("fields", &NodeFieldInstance::for_node_type(&pool, &type_name).await?);
+    context.insert("cloned_from", &query.cloned_from);
+
+    let html = render(&tera, "admin/node_type_edit.html", &context)?;
     Ok(Html(html))
 }
 
@@ -177,10 +753,25 @@ pub struct NodeTypeEditForm {
     pub name: String,
     pub description: String,
     pub help: String,
+    pub body_format: Option<i32>,
+    pub comment_subject_field: Option<String>,
+    pub comments_per_page: Option<u32>,
+    #[serde(default)]
+    pub option_status: Option<String>,
+    #[serde(default)]
+    pub option_promote: Option<String>,
+    #[serde(default)]
+    pub option_sticky: Option<String>,
+    #[serde(default)]
+    pub option_revision: Option<String>,
+    #[serde(default)]
+    pub show_updated: Option<String>,
+    #[serde(default)]
+    pub show_submitted: Option<String>,
 }
 
 pub async fn node_type_edit_submit(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
     Path(type_name): Path<String>,
     Form(form): Form<NodeTypeEditForm>,
@@ -189,27 +780,1179 @@ pub async fn node_type_edit_submit(
         return Err(AppError::Unauthorized);
     };
 
-    if !user.has_permission(&pool, "administer nodes").await? {
+    if !user.has_permission(&pool, "administer nodes").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    NodeType::update(&pool, &type_name, &form.name, &form.description, &form.help).await?;
+
+    if let Some(format) = form.body_format.and_then(filter::find_format) {
+        Variable::set(&pool, &format!("node_body_format_{}", type_name), &format.id.to_string()).await?;
+    }
+
+    let subject_field = if form.comment_subject_field.is_some() { "1" } else { "0" };
+    Variable::set(&pool, &format!("comment_subject_field_{}", type_name), subject_field).await?;
+
+    if let Some(per_page) = form.comments_per_page.filter(|n| *n > 0) {
+        Variable::set(&pool, &format!("comment_default_per_page_{}", type_name), &per_page.to_string()).await?;
+    }
+
+    let node_options = TypeOptions {
+        status: form.option_status.is_some(),
+        promote: form.option_promote.is_some(),
+        sticky: form.option_sticky.is_some(),
+        revision: form.option_revision.is_some(),
+    };
+    node_options.save(&pool, &type_name).await?;
+
+    let show_updated = if form.show_updated.is_some() { "1" } else { "0" };
+    Variable::set(&pool, &format!("node_show_updated_{}", type_name), show_updated).await?;
+
+    let show_submitted = if form.show_submitted.is_some() { "1" } else { "0" };
+    Variable::set(&pool, &format!("node_show_submitted_{}", type_name), show_submitted).await?;
+
+    Ok(Redirect::to("/admin/node/types"))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct NodeTypeRenameForm {
+    pub new_type: String,
+    #[serde(default)]
+    pub confirm: Option<String>,
+}
+
+/// POST /admin/node/types/:type/rename - Ask for confirmation, then change
+/// the machine name of a content type and everything keyed by it.
+pub async fn node_type_rename(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Path(type_name): Path<String>,
+    Form(form): Form<NodeTypeRenameForm>,
+) -> AppResult<Html<String>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer nodes").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let Some(node_type) = NodeType::find_by_type(&pool, &type_name).await? else {
+        return Err(AppError::NotFound);
+    };
+    let new_type = form.new_type.trim();
+
+    let mut context = page_context(&pool, &format!("Rename {}", node_type.name)).await;
+    context.insert("node_type", &node_type);
+    context.insert("new_type", new_type);
+
+    if let Some(message) = NodeType::validate_machine_name(&pool, &type_name, new_type).await? {
+        context.insert("current_user", &Some(user));
+        context.insert("error", &message);
+        let html = render(&tera, "admin/node_type_rename.html", &context)?;
+        return Ok(Html(html));
+    }
+
+    if form.confirm.is_some() {
+        let migrated = NodeType::rename(&pool, &type_name, new_type, user.uid).await?;
+        cache::invalidate_tags(&[cache::TAG_NODE]);
+        context.insert("migrated", &migrated);
+    } else {
+        let (node_count, field_count) = NodeType::usage(&pool, &type_name).await?;
+        context.insert("node_count", &node_count);
+        context.insert("field_count", &field_count);
+    }
+    context.insert("current_user", &Some(user));
+
+    let html = render(&tera, "admin/node_type_rename.html", &context)?;
+    Ok(Html(html))
+}
+
+#[derive(Debug, Default, Deserialize)]
+pub struct NodeTypeCloneForm {
+    #[serde(default)]
+    pub new_type: String,
+    #[serde(default)]
+    pub name: String,
+}
+
+async fn render_node_type_clone(
+    pool: &Pool,
+    tera: &Tera,
+    user: User,
+    node_type: &NodeType,
+    form: &NodeTypeCloneForm,
+    error: Option<String>,
+) -> AppResult<Html<String>> {
+    let mut context = page_context(pool, &format!("Clone {}", node_type.name)).await;
+    context.insert("current_user", &Some(user));
+    context.insert("node_type", node_type);
+    context.insert("new_type", form.new_type.trim());
+    context.insert("name", form.name.trim());
+    context.insert("field_count", &NodeFieldInstance::for_node_type(pool, &node_type.type_name).await?.len());
+    context.insert("error", &error);
+
+    let html = render(tera, "admin/node_type_clone.html", &context)?;
+    Ok(Html(html))
+}
+
+/// GET /admin/node/types/:type/clone - Ask for the machine name and label
+/// of a new content type copied from this one.
+pub async fn node_type_clone_form(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Path(type_name): Path<String>,
+) -> AppResult<Html<String>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer nodes").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let Some(node_type) = NodeType::find_by_type(&pool, &type_name).await? else {
+        return Err(AppError::NotFound);
+    };
+
+    render_node_type_clone(&pool, &tera, user, &node_type, &NodeTypeCloneForm::default(), None).await
+}
+
+/// POST /admin/node/types/:type/clone - Create the copy and continue to its
+/// edit page.
+pub async fn node_type_clone_submit(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Path(type_name): Path<String>,
+    Form(form): Form<NodeTypeCloneForm>,
+) -> AppResult<Result<Html<String>, Redirect>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer nodes").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let Some(node_type) = NodeType::find_by_type(&pool, &type_name).await? else {
+        return Err(AppError::NotFound);
+    };
+    let new_type = form.new_type.trim();
+    let name = form.name.trim();
+
+    let error = if name.is_empty() {
+        Some("The human-readable name is required.".to_string())
+    } else {
+        NodeType::validate_machine_name(&pool, &type_name, new_type).await?
+    };
+    if let Some(error) = error {
+        return render_node_type_clone(&pool, &tera, user, &node_type, &form, Some(error)).await.map(Ok);
+    }
+
+    NodeType::duplicate(&pool, &type_name, new_type, name, user.uid).await?;
+    cache::invalidate_tags(&[cache::TAG_VARIABLE]);
+
+    Ok(Err(Redirect::to(&format!(
+        "/admin/node/types/{}?cloned_from={}",
+        new_type, type_name
+    ))))
+}
+
+/// Most items a bulk action takes by id in one request. The forms never
+/// send more than a page; everything beyond goes through "all items
+/// matching the filter", which never ships ids at all.
+const BULK_ACTION_MAX_ITEMS: usize = 500;
+
+fn check_bulk_size(ids: &[u32]) -> AppResult<()> {
+    if ids.len() > BULK_ACTION_MAX_ITEMS {
+        return Err(AppError::BadRequest(format!(
+            "{} items selected, at most {} can be updated at once",
+            ids.len(),
+            BULK_ACTION_MAX_ITEMS
+        )));
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ContentActionForm {
+    pub action: String,
+    #[serde(default)]
+    pub nids: Vec<u32>,
+    /// Apply the action to every node matching `filter` instead of `nids`.
+    #[serde(default)]
+    pub all_matching: Option<String>,
+    #[serde(default)]
+    pub filter: ContentFilterQuery,
+}
+
+/// POST /admin/node - Apply a bulk action to the selected nodes. Deletion
+/// is only confirmed here; it happens on `/admin/node/delete-confirm`.
+pub async fn content_action(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    QsForm(form): QsForm<ContentActionForm>,
+) -> AppResult<Result<Html<String>, Redirect>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer nodes").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    check_bulk_size(&form.nids)?;
+    let all_matching = form.all_matching.is_some();
+    let filter = form.filter.to_filter();
+
+    match form.action.as_str() {
+        action @ ("publish" | "unpublish") => {
+            let status = if action == "publish" { 1 } else { 0 };
+            if all_matching {
+                Node::set_status_matching(&pool, &filter, status).await?;
+            } else {
+                Node::set_status_multiple(&pool, &form.nids, status).await?;
+            }
+        }
+        "delete" if all_matching || !form.nids.is_empty() => {
+            let mut context = page_context(&pool, "Delete content").await;
+            if all_matching {
+                context.insert("match_count", &Node::list_count(&pool, &filter).await?);
+            } else {
+                context.insert("nodes", &Node::titles(&pool, &form.nids).await?);
+            }
+            context.insert("current_user", &Some(user));
+            context.insert("all_matching", &all_matching);
+            context.insert("filter", &form.filter);
+            context.insert("cancel_query", &serde_qs::to_string(&form.filter).unwrap_or_default());
+
+            let html = render(&tera, "admin/content_delete.html", &context)?;
+            return Ok(Ok(Html(html)));
+        }
+        _ => {}
+    }
+
+    cache::invalidate_tags(&[cache::TAG_NODE, cache::TAG_FRONT_PAGE]);
+
+    let query = serde_qs::to_string(&form.filter).unwrap_or_default();
+    Ok(Err(Redirect::to(&format!("/admin/node?{}", query))))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct ContentDeleteForm {
+    #[serde(default)]
+    pub nids: Vec<u32>,
+    #[serde(default)]
+    pub all_matching: Option<String>,
+    #[serde(default)]
+    pub filter: ContentFilterQuery,
+}
+
+/// POST /admin/node/delete-confirm - Delete the nodes confirmed on the
+/// bulk delete page.
+pub async fn content_delete_confirm(
+    State(pool): State<Pool>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    QsForm(form): QsForm<ContentDeleteForm>,
+) -> AppResult<Redirect> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer nodes").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    check_bulk_size(&form.nids)?;
+    if form.all_matching.is_some() {
+        let nids = Node::list_ids(&pool, &form.filter.to_filter()).await?;
+        Node::delete_multiple(&pool, &nids).await?;
+    } else {
+        Node::delete_multiple(&pool, &form.nids).await?;
+    }
+    cache::invalidate_tags(&[cache::TAG_NODE, cache::TAG_FRONT_PAGE]);
+
+    let query = serde_qs::to_string(&form.filter).unwrap_or_default();
+    Ok(Redirect::to(&format!("/admin/node?{}", query)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct UserActionForm {
+    pub action: String,
+    #[serde(default)]
+    pub uids: Vec<u32>,
+}
+
+pub async fn user_action(
+    State(pool): State<Pool>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    QsForm(form): QsForm<UserActionForm>,
+) -> AppResult<Redirect> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer users").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    check_bulk_size(&form.uids)?;
+    let (status, verb) = match form.action.as_str() {
+        "block" => (0, "Blocked"),
+        "unblock" => (1, "Unblocked"),
+        _ => return Ok(Redirect::to("/admin/user")),
+    };
+
+    for (uid, name) in User::set_status_multiple(&pool, &form.uids, status).await? {
+        Watchdog::log(
+            &pool,
+            user.uid,
+            "user",
+            &format!("{} user {}.", verb, name),
+            WATCHDOG_NOTICE,
+            &format!("/user/{}", uid),
+            "/admin/user",
+        )
+        .await?;
+    }
+
+    Ok(Redirect::to("/admin/user"))
+}
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct AccountForm {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub mail: String,
+    #[serde(default, skip_serializing)]
+    pub password: String,
+    #[serde(default, skip_serializing)]
+    pub password_confirm: String,
+    #[serde(default)]
+    pub status: i8,
+    /// Checked roles, as `roles[<n>]=<rid>`.
+    #[serde(default)]
+    pub roles: Vec<u32>,
+}
+
+/// The account form for a new account, or for `account` when editing one.
+/// The first account's status and roles are not offered, nor its
+/// credentials to anyone else; roles only to those who administer access
+/// control.
+async fn render_account(
+    pool: &Pool,
+    tera: &Tera,
+    user: User,
+    account: Option<&User>,
+    form: &AccountForm,
+    error: Option<&str>,
+) -> AppResult<Html<String>> {
+    let title = match account {
+        Some(account) => format!("Edit {}", account.name),
+        None => "Add user".to_string(),
+    };
+    let mut context = page_context(pool, &title).await;
+    context.insert("assign_roles", &user.has_permission(pool, "administer access control").await?);
+    context.insert("locked", &account.is_some_and(|a| a.uid == 1 && user.uid != 1));
+    context.insert("current_user", &Some(user));
+    context.insert("account", &account);
+    context.insert("form", form);
+    context.insert("roles", &Role::assignable(pool).await?);
+    context.insert("protected", &account.is_some_and(|a| a.uid == 1));
+    context.insert("error", &error);
+
+    let html = render(tera, "admin/user_form.html", &context)?;
+    Ok(Html(html))
+}
+
+/// Whether `form` describes a usable account: the checks of the
+/// registration form, with the password optional when editing `uid`.
+async fn validate_account(
+    pool: &Pool,
+    form: &AccountForm,
+    uid: Option<u32>,
+) -> Result<Option<String>, sqlx::Error> {
+    if let Some(error) = User::validate_name(pool, form.name.trim(), uid).await? {
+        return Ok(Some(error));
+    }
+    if uid.is_none() || !form.password.is_empty() {
+        if let Some(error) = User::validate_password(&form.password, &form.password_confirm) {
+            return Ok(Some(error.to_string()));
+        }
+    }
+    User::validate_mail(pool, form.mail.trim(), uid).await
+}
+
+/// The roles to give an account: `form`'s, keeping only those an account
+/// can hold, when `user` administers access control; `current` otherwise.
+async fn assignable_rids(
+    pool: &Pool,
+    user: &User,
+    form: &AccountForm,
+    current: Vec<u32>,
+) -> Result<Vec<u32>, sqlx::Error> {
+    if !user.has_permission(pool, "administer access control").await? {
+        return Ok(current);
+    }
+    let assignable = Role::assignable(pool).await?;
+    Ok(form
+        .roles
+        .iter()
+        .copied()
+        .filter(|rid| assignable.iter().any(|role| role.rid == *rid))
+        .collect())
+}
+
+/// GET /admin/user/create - Form for an administrator to add an account.
+pub async fn user_create_form(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+) -> AppResult<Html<String>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer users").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let form = AccountForm {
+        status: 1,
+        roles: Role::authenticated(&pool).await?.map(|role| role.rid).into_iter().collect(),
+        ..AccountForm::default()
+    };
+    render_account(&pool, &tera, user, None, &form, None).await
+}
+
+/// POST /admin/user/create - Add the account, active or blocked, with the
+/// chosen roles.
+pub async fn user_create_submit(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    QsForm(form): QsForm<AccountForm>,
+) -> AppResult<Result<Html<String>, Redirect>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer users").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    if let Some(error) = validate_account(&pool, &form, None).await? {
+        return render_account(&pool, &tera, user, None, &form, Some(&error)).await.map(Ok);
+    }
+
+    let name = form.name.trim();
+    let password_hash =
+        hash_password(&form.password).map_err(|e| AppError::Internal(e.to_string()))?;
+    let uid = User::create(&pool, name, &password_hash, form.mail.trim())
+        .await
+        .context("creating account")?;
+    if form.status == 0 {
+        User::set_status_multiple(&pool, &[uid], 0).await?;
+    }
+    let default_rids = Role::authenticated(&pool).await?.map(|role| role.rid).into_iter().collect();
+    User::set_roles(&pool, uid, &assignable_rids(&pool, &user, &form, default_rids).await?).await?;
+
+    Watchdog::log(
+        &pool,
+        user.uid,
+        "user",
+        &format!("New user: {} created by {}.", name, user.name),
+        WATCHDOG_NOTICE,
+        &format!("/user/{}", uid),
+        "/admin/user/create",
+    )
+    .await?;
+
+    Ok(Err(Redirect::to("/admin/user")))
+}
+
+/// An account administrators may edit: any but the anonymous one.
+async fn editable_account(pool: &Pool, uid: u32) -> AppResult<User> {
+    match User::find_by_uid(pool, uid).await? {
+        Some(account) if account.uid > 0 => Ok(account),
+        _ => Err(AppError::NotFound),
+    }
+}
+
+/// GET /admin/user/:uid/edit - Form for an administrator to change an
+/// account, including its username and roles.
+pub async fn user_edit_form(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Path(uid): Path<u32>,
+) -> AppResult<Html<String>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer users").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let account = editable_account(&pool, uid).await?;
+    let form = AccountForm {
+        name: account.name.clone(),
+        mail: account.mail.clone().unwrap_or_default(),
+        status: account.status,
+        roles: Role::rids_for_user(&pool, uid).await?,
+        ..AccountForm::default()
+    };
+    render_account(&pool, &tera, user, Some(&account), &form, None).await
+}
+
+/// POST /admin/user/:uid/edit - Save the account. The password is only
+/// changed when a new one is given; the first account keeps its status and
+/// roles whatever the form says, and only its owner may change its
+/// username, e-mail address or password.
+pub async fn user_edit_submit(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Path(uid): Path<u32>,
+    QsForm(form): QsForm<AccountForm>,
+) -> AppResult<Result<Html<String>, Redirect>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer users").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let account = editable_account(&pool, uid).await?;
+    if uid == 1
+        && user.uid != 1
+        && (form.name.trim() != account.name
+            || form.mail.trim() != account.mail.as_deref().unwrap_or_default()
+            || !form.password.is_empty())
+    {
+        let error = "Only the first account itself may change its username, e-mail address or password.";
+        return render_account(&pool, &tera, user, Some(&account), &form, Some(error)).await.map(Ok);
+    }
+    if let Some(error) = validate_account(&pool, &form, Some(uid)).await? {
+        return render_account(&pool, &tera, user, Some(&account), &form, Some(&error)).await.map(Ok);
+    }
+
+    let name = form.name.trim();
+    if name != account.name {
+        User::update_name(&pool, uid, name).await?;
+    }
+    User::update_mail(&pool, uid, form.mail.trim()).await?;
+    if !form.password.is_empty() {
+        let password_hash =
+            hash_password(&form.password).map_err(|e| AppError::Internal(e.to_string()))?;
+        User::update_password(&pool, uid, &password_hash).await?;
+    }
+    if uid != 1 {
+        let status = if form.status == 0 { 0 } else { 1 };
+        User::set_status_multiple(&pool, &[uid], status).await?;
+        let current_rids = Role::rids_for_user(&pool, uid).await?;
+        User::set_roles(&pool, uid, &assignable_rids(&pool, &user, &form, current_rids).await?).await?;
+    }
+
+    Watchdog::log(
+        &pool,
+        user.uid,
+        "user",
+        &format!("Account {} edited by {}.", name, user.name),
+        WATCHDOG_NOTICE,
+        &format!("/user/{}", uid),
+        &format!("/admin/user/{}/edit", uid),
+    )
+    .await?;
+
+    Ok(Err(Redirect::to("/admin/user")))
+}
+
+pub async fn settings_form(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+) -> AppResult<Html<String>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer site configuration").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let settings = SiteSettings::load(&pool).await;
+    let user_register_tos = Variable::get_or_default(&pool, "user_register_tos", "").await;
+    let comment_homepage_links =
+        Variable::get_or_default(&pool, "comment_homepage_links", "nofollow").await;
+    let feed_item_limit = Variable::get_or_default(&pool, "feed_item_limit", "10").await;
+    let feed_item_length = Variable::get_or_default(&pool, "feed_item_length", "teaser").await;
+    let cache_front_page_ttl = Variable::get_or_default(&pool, "cache_front_page_ttl", "0").await;
+    let contact_require_login =
+        Variable::get_or_default(&pool, "contact_require_login", "0").await == "1";
+    let user_external_register =
+        Variable::get_or_default(&pool, "user_external_register", "0").await == "1";
+    let picture_settings = PictureSettings::load(&pool).await;
+    let login_throttle = LoginThrottle::load(&pool).await;
+    let default_role = Role::authenticated(&pool).await?.map(|role| role.name);
+    let admin_role = Role::administrator(&pool).await?.map(|role| role.name);
+
+    let mut context = page_context(&pool, "Site information").await;
+    context.insert("current_user", &Some(user));
+    context.insert("site_url", &settings.site_url);
+    context.insert("site_default_image", &settings.site_default_image);
+    context.insert("site_mail", &settings.site_mail);
+    // The page context holds the filtered footer and mission; the form
+    // edits them as entered.
+    context.insert("footer_text", &settings.site_footer);
+    context.insert("mission_text", &settings.site_mission);
+    context.insert("site_text_tags", &filter::site_text_tags_display());
+    context.insert("anonymous", &settings.anonymous);
+    context.insert("timezone", &settings.date_default_timezone);
+    context.insert("timezones", &timezone_options());
+    context.insert("user_register_tos", &user_register_tos);
+    context.insert("comment_homepage_links", &comment_homepage_links);
+    context.insert("feed_item_limit", &feed_item_limit);
+    context.insert("feed_item_length", &feed_item_length);
+    context.insert("cache_front_page_ttl", &cache_front_page_ttl);
+    context.insert("cache_lifetimes", CACHE_LIFETIME_OPTIONS);
+    context.insert("contact_require_login", &contact_require_login);
+    context.insert("user_external_register", &user_external_register);
+    context.insert("picture_settings", &picture_settings);
+    context.insert("login_throttle", &login_throttle);
+    context.insert("roles", &Role::assignable(&pool).await?);
+    context.insert("default_role", &default_role);
+    context.insert("admin_role", &admin_role);
+
+    let html = render(&tera, "admin/settings.html", &context)?;
+    Ok(Html(html))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SettingsForm {
+    pub site_name: String,
+    pub site_slogan: String,
+    #[serde(default)]
+    pub site_url: String,
+    #[serde(default)]
+    pub site_default_image: String,
+    pub site_mail: String,
+    pub site_footer: String,
+    #[serde(default)]
+    pub site_mission: String,
+    #[serde(default)]
+    pub anonymous: String,
+    #[serde(default)]
+    pub date_default_timezone: i32,
+    #[serde(default)]
+    pub user_register_tos: String,
+    #[serde(default)]
+    pub comment_homepage_links: String,
+    #[serde(default)]
+    pub feed_item_limit: u32,
+    #[serde(default)]
+    pub feed_item_length: String,
+    #[serde(default)]
+    pub cache_front_page_ttl: u32,
+    pub contact_require_login: Option<String>,
+    pub user_external_register: Option<String>,
+    #[serde(default)]
+    pub user_picture_max_size: u32,
+    #[serde(default)]
+    pub user_picture_width: u32,
+    #[serde(default)]
+    pub user_picture_height: u32,
+    #[serde(default)]
+    pub user_login_delay_base: u64,
+    #[serde(default)]
+    pub user_login_delay_multiplier: u64,
+    #[serde(default)]
+    pub user_login_delay_max: u64,
+    #[serde(default)]
+    pub user_login_throttle_message: String,
+    #[serde(default)]
+    pub user_default_role: String,
+    #[serde(default)]
+    pub user_admin_role: String,
+}
+
+/// Choices for cache lifetimes, as (seconds, label) pairs.
+const CACHE_LIFETIME_OPTIONS: &[(u32, &str)] = &[
+    (0, "none"),
+    (60, "1 min"),
+    (300, "5 min"),
+    (900, "15 min"),
+    (1800, "30 min"),
+    (3600, "1 hour"),
+];
+
+/// UTC offsets in half-hour steps, as (seconds, label) pairs.
+fn timezone_options() -> Vec<(i32, String)> {
+    (-24..=28)
+        .map(|step: i32| {
+            let offset = step * 1800;
+            let sign = if offset < 0 { '-' } else { '+' };
+            let minutes = offset.abs() / 60;
+            (offset, format!("UTC{}{:02}:{:02}", sign, minutes / 60, minutes % 60))
+        })
+        .collect()
+}
+
+pub async fn settings_submit(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Form(form): Form<SettingsForm>,
+) -> AppResult<Html<String>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer site configuration").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    Variable::set(&pool, "site_name", &form.site_name).await?;
+    Variable::set(&pool, "site_slogan", &form.site_slogan).await?;
+    // A malformed site URL is not saved; the rest of the form still is.
+    let site_url_error = match (form.site_url.trim(), feed::site_origin(&form.site_url)) {
+        ("", _) => {
+            Variable::set(&pool, "site_url", "").await?;
+            None
+        }
+        (_, Some(origin)) => {
+            Variable::set(&pool, "site_url", &origin).await?;
+            None
+        }
+        (_, None) => Some(
+            "The site URL must be an http or https address without a path, such as https://example.com. It was not changed.",
+        ),
+    };
+    Variable::set(&pool, "site_default_image", form.site_default_image.trim()).await?;
+    Variable::set(&pool, "site_mail", &form.site_mail).await?;
+    Variable::set(&pool, "site_footer", &form.site_footer).await?;
+    Variable::set(&pool, "site_mission", &form.site_mission).await?;
+    // An empty name restores the default.
+    match form.anonymous.trim() {
+        "" => Variable::delete(&pool, "anonymous").await?,
+        name => Variable::set(&pool, "anonymous", name).await?,
+    }
+    let timezone = form.date_default_timezone.clamp(-43200, 50400);
+    Variable::set(&pool, "date_default_timezone", &timezone.to_string()).await?;
+    Variable::set(&pool, "user_register_tos", form.user_register_tos.trim()).await?;
+    let comment_homepage_links = match form.comment_homepage_links.as_str() {
+        links @ ("follow" | "none") => links,
+        _ => "nofollow",
+    };
+    Variable::set(&pool, "comment_homepage_links", comment_homepage_links).await?;
+    let feed_item_limit = form.feed_item_limit.clamp(1, 100).to_string();
+    Variable::set(&pool, "feed_item_limit", &feed_item_limit).await?;
+    let feed_item_length = match form.feed_item_length.as_str() {
+        length @ ("title" | "fulltext") => length,
+        _ => "teaser",
+    };
+    Variable::set(&pool, "feed_item_length", feed_item_length).await?;
+    let cache_front_page_ttl = form.cache_front_page_ttl.to_string();
+    Variable::set(&pool, "cache_front_page_ttl", &cache_front_page_ttl).await?;
+    let contact_require_login = form.contact_require_login.is_some();
+    Variable::set(&pool, "contact_require_login", if contact_require_login { "1" } else { "0" }).await?;
+    let user_external_register = form.user_external_register.is_some();
+    Variable::set(&pool, "user_external_register", if user_external_register { "1" } else { "0" }).await?;
+    let max_size = form.user_picture_max_size.max(1);
+    Variable::set(&pool, "user_picture_max_size", &max_size.to_string()).await?;
+    let dimensions = format!(
+        "{}x{}",
+        form.user_picture_width.max(1),
+        form.user_picture_height.max(1)
+    );
+    Variable::set(&pool, "user_picture_dimensions", &dimensions).await?;
+    Variable::set(&pool, "user_login_delay_base", &form.user_login_delay_base.to_string()).await?;
+    let multiplier = form.user_login_delay_multiplier.max(1);
+    Variable::set(&pool, "user_login_delay_multiplier", &multiplier.to_string()).await?;
+    let delay_max = form.user_login_delay_max.max(form.user_login_delay_base);
+    Variable::set(&pool, "user_login_delay_max", &delay_max.to_string()).await?;
+    Variable::set(&pool, "user_login_throttle_message", form.user_login_throttle_message.trim()).await?;
+    // Only names of existing roles are stored; anything else is ignored.
+    for (variable, name) in [
+        ("user_default_role", &form.user_default_role),
+        ("user_admin_role", &form.user_admin_role),
+    ] {
+        if Role::find_by_name(&pool, name).await?.is_some() {
+            Variable::set(&pool, variable, name).await?;
+        }
+    }
+    let settings = SiteSettings::load(&pool).await;
+    let picture_settings = PictureSettings::load(&pool).await;
+    let login_throttle = LoginThrottle::load(&pool).await;
+    let default_role = Role::authenticated(&pool).await?.map(|role| role.name);
+    let admin_role = Role::administrator(&pool).await?.map(|role| role.name);
+
+    let mut context = page_context(&pool, "Site information").await;
+    context.insert("current_user", &Some(user));
+    match site_url_error {
+        Some(error) => {
+            context.insert("site_url", form.site_url.trim());
+            context.insert("error", error);
+        }
+        None => context.insert("site_url", &settings.site_url),
+    }
+    context.insert("site_default_image", &settings.site_default_image);
+    context.insert("site_mail", &settings.site_mail);
+    // The page context holds the filtered footer and mission; the form
+    // edits them as entered.
+    context.insert("footer_text", &settings.site_footer);
+    context.insert("mission_text", &settings.site_mission);
+    context.insert("site_text_tags", &filter::site_text_tags_display());
+    context.insert("anonymous", &settings.anonymous);
+    context.insert("timezone", &settings.date_default_timezone);
+    context.insert("timezones", &timezone_options());
+    context.insert("user_register_tos", form.user_register_tos.trim());
+    context.insert("comment_homepage_links", comment_homepage_links);
+    context.insert("feed_item_limit", &feed_item_limit);
+    context.insert("feed_item_length", feed_item_length);
+    context.insert("cache_front_page_ttl", &cache_front_page_ttl);
+    context.insert("cache_lifetimes", CACHE_LIFETIME_OPTIONS);
+    context.insert("contact_require_login", &contact_require_login);
+    context.insert("user_external_register", &user_external_register);
+    context.insert("picture_settings", &picture_settings);
+    context.insert("login_throttle", &login_throttle);
+    context.insert("roles", &Role::assignable(&pool).await?);
+    context.insert("default_role", &default_role);
+    context.insert("admin_role", &admin_role);
+    context.insert("message", "The configuration options have been saved.");
+
+    let html = render(&tera, "admin/settings.html", &context)?;
+    Ok(Html(html))
+}
+
+pub async fn filters_form(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+) -> AppResult<Html<String>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer filters").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let mut context = page_context(&pool, "Input formats").await;
+    context.insert("current_user", &Some(user));
+    context.insert("allowed_html", &filter::allowed_tags_display());
+
+    let html = render(&tera, "admin/filters.html", &context)?;
+    Ok(Html(html))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FiltersForm {
+    pub allowed_html: String,
+}
+
+pub async fn filters_submit(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Form(form): Form<FiltersForm>,
+) -> AppResult<Html<String>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer filters").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    // Store the normalised list so forbidden or malformed tags never
+    // reach the variable.
+    let tags = filter::parse_allowed_tags(&form.allowed_html);
+    let allowed_html = tags
+        .iter()
+        .map(|tag| format!("<{}>", tag))
+        .collect::<Vec<_>>()
+        .join(" ");
+    Variable::set(&pool, "allowed_html_1", &allowed_html).await?;
+    filter::set_allowed_tags(&allowed_html);
+    cache::invalidate_tags(&[cache::TAG_NODE]);
+
+    let mut context = page_context(&pool, "Input formats").await;
+    context.insert("current_user", &Some(user));
+    context.insert("allowed_html", &allowed_html);
+    context.insert("message", "The configuration options have been saved.");
+
+    let html = render(&tera, "admin/filters.html", &context)?;
+    Ok(Html(html))
+}
+
+pub async fn image_styles_form(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+) -> AppResult<Html<String>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer site configuration").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let context = image_styles_context(&pool, user, &HashMap::new()).await;
+    let html = render(&tera, "admin/image_styles.html", &context)?;
+    Ok(Html(html))
+}
+
+/// Context for the image styles page. `new` holds the fields of a style
+/// that could not be added, so they can be corrected.
+async fn image_styles_context(
+    pool: &Pool,
+    user: User,
+    new: &HashMap<String, String>,
+) -> tera::Context {
+    // (form field, label, selected style) for each display.
+    let mut displays = Vec::new();
+    for display in ImageDisplay::ALL {
+        displays.push((display.variable(), display.label(), display.style(pool).await));
+    }
+
+    let mut context = page_context(pool, "Image styles").await;
+    context.insert("current_user", &Some(user));
+    context.insert("styles", &ImageStyle::all(pool).await);
+    context.insert("displays", &displays);
+    context.insert("new", new);
+    context
+}
+
+pub async fn image_styles_submit(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Form(form): Form<HashMap<String, String>>,
+) -> AppResult<Html<String>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer site configuration").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let dimensions = |prefix: &str| {
+        let width = form.get(&format!("{}_width", prefix)).and_then(|v| v.trim().parse::<u32>().ok());
+        let height = form.get(&format!("{}_height", prefix)).and_then(|v| v.trim().parse::<u32>().ok());
+        let crop = form.contains_key(&format!("{}_crop", prefix));
+        match (width, height) {
+            (Some(width), Some(height)) if width > 0 && height > 0 => {
+                Some(ImageStyle::setting(width, height, crop))
+            }
+            _ => None,
+        }
+    };
+
+    for style in ImageStyle::all(&pool).await {
+        if !style.builtin && form.contains_key(&format!("{}_delete", style.name)) {
+            ImageStyle::delete(&pool, &style.name).await?;
+            continue;
+        }
+        let Some(setting) = dimensions(&style.name) else {
+            continue;
+        };
+        if setting != ImageStyle::setting(style.width, style.height, style.crop) {
+            Variable::set(&pool, &format!("image_style_{}", style.name), &setting).await?;
+            image_styles::flush_style(&style.name);
+        }
+    }
+
+    let new_name = form.get("new_name").map(|name| name.trim()).unwrap_or("");
+    let mut error = None;
+    if !new_name.is_empty() {
+        error = ImageStyle::validate_name(&pool, new_name).await;
+        match dimensions("new") {
+            Some(setting) if error.is_none() => ImageStyle::create(&pool, new_name, &setting).await?,
+            Some(_) => {}
+            None => {
+                error = error.or(Some("The new image style needs a width and height of at least 1."));
+            }
+        }
+    }
+
+    for display in ImageDisplay::ALL {
+        if let Some(name) = form.get(display.variable()) {
+            if ImageStyle::load(&pool, name).await.is_some() {
+                Variable::set(&pool, display.variable(), name).await?;
+            }
+        }
+    }
+
+    let context = match error {
+        Some(error) => {
+            let new: HashMap<String, String> = form
+                .iter()
+                .filter(|(key, _)| key.starts_with("new_"))
+                .map(|(key, value)| (key.clone(), value.clone()))
+                .collect();
+            let mut context = image_styles_context(&pool, user, &new).await;
+            context.insert("error", error);
+            context
+        }
+        None => {
+            let mut context = image_styles_context(&pool, user, &HashMap::new()).await;
+            context.insert("message", "The configuration options have been saved.");
+            context
+        }
+    };
+
+    let html = render(&tera, "admin/image_styles.html", &context)?;
+    Ok(Html(html))
+}
+
+/// Seconds of recent activity the status report counts comments and new
+/// accounts over.
+const ACTIVITY_PERIOD: i64 = 86400;
+
+/// Seconds since their last request within which visitors count as online.
+const ONLINE_PERIOD: i64 = 900;
+
+pub async fn status_report(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+) -> AppResult<Html<String>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "access site reports").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let node_count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM node")
+        .fetch_one(&pool)
+        .await?;
+    let user_count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM users WHERE uid > 0")
+        .fetch_one(&pool)
+        .await?;
+    let install_completed = install_completed_at(&pool).await?;
+    let cron_last = cron::last_run(&pool).await?;
+    let cron_key = cron::key(&pool).await?;
+
+    let now = chrono::Utc::now().timestamp();
+    let comments_today = Comment::count_since(&pool, now - ACTIVITY_PERIOD).await?;
+    let users_today = User::count_created_since(&pool, now - ACTIVITY_PERIOD).await?;
+    // Only the access log knows who has been around lately.
+    let online = if statistics::access_log_enabled(&pool).await? {
+        Some(AccessLog::count_online(&pool, now - ONLINE_PERIOD).await?)
+    } else {
+        None
+    };
+
+    let mut context = page_context(&pool, "Status report").await;
+    context.insert("install_completed", &install_completed);
+    context.insert("cron_last", &cron_last);
+    context.insert("cron_key", &cron_key);
+    context.insert("current_user", &Some(user));
+    context.insert("drupal_version", "4.7.0-rust");
+    context.insert("node_count", &node_count.0);
+    context.insert("user_count", &user_count.0);
+    context.insert("cache_stats", &cache::stats());
+    context.insert("statistics_dropped", &statistics::dropped_events());
+    context.insert("comments_today", &comments_today);
+    context.insert("users_today", &users_today);
+    context.insert("online", &online);
+    context.insert("online_minutes", &(ONLINE_PERIOD / 60));
+
+    let html = render(&tera, "admin/status.html", &context)?;
+    Ok(Html(html))
+}
+
+pub async fn flush_caches(
+    State(pool): State<Pool>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+) -> AppResult<Redirect> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer site configuration").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    cache::flush_all();
+
+    Ok(Redirect::to("/admin/reports/status"))
+}
+
+/// The counters of the status report, for monitoring to poll.
+#[derive(Debug, Serialize)]
+pub struct Metrics {
+    pub cache: cache::CacheStats,
+    pub statistics_dropped: u64,
+}
+
+/// GET /admin/reports/status/metrics - Cache hits and misses and dropped
+/// page views since the server started, as JSON.
+pub async fn metrics(
+    State(pool): State<Pool>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+) -> AppResult<Json<Metrics>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "access site reports").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    Ok(Json(Metrics {
+        cache: cache::stats(),
+        statistics_dropped: statistics::dropped_events(),
+    }))
+}
+
+/// GET /admin/reports/fields - Every field with its instances and stored
+/// values, plus instances and values left behind by deleted types and fields.
+pub async fn field_report(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+) -> AppResult<Html<String>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "access site reports").await? {
         return Err(AppError::Forbidden);
     }
 
-    NodeType::update(&pool, &type_name, &form.name, &form.description, &form.help).await?;
+    let can_clean_up = user.has_permission(&pool, "administer nodes").await?;
 
-    Ok(Redirect::to("/admin/node/types"))
-}
+    let mut context = page_context(&pool, "Field usage").await;
+    context.insert("fields", &NodeField::usage(&pool).await?);
+    context.insert("dangling_instances", &NodeFieldInstance::dangling(&pool).await?);
+    context.insert("orphaned_data", &NodeFieldData::orphaned(&pool).await?);
+    context.insert("can_clean_up", &can_clean_up);
+    context.insert("current_user", &Some(user));
 
-#[derive(Debug, Deserialize)]
-pub struct ContentActionForm {
-    pub action: String,
-    #[serde(default)]
-    pub nids: Vec<u32>,
+    let html = render(&tera, "admin/field_report.html", &context)?;
+    Ok(Html(html))
 }
 
-pub async fn content_action(
-    State(pool): State<MySqlPool>,
+/// GET /admin/node/nominations - Posts their authors want on the front
+/// page, oldest nomination first.
+pub async fn nominations(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
-    QsForm(form): QsForm<ContentActionForm>,
-) -> AppResult<Redirect> {
+) -> AppResult<Html<String>> {
     let Some(user) = current_user else {
         return Err(AppError::Unauthorized);
     };
@@ -218,54 +1961,72 @@ pub async fn content_action(
         return Err(AppError::Forbidden);
     }
 
-    for nid in form.nids {
-        match form.action.as_str() {
-            "publish" => Node::set_status(&pool, nid, 1).await?,
-            "unpublish" => Node::set_status(&pool, nid, 0).await?,
-            "delete" => Node::delete(&pool, nid).await?,
-            _ => {}
-        }
-    }
+    let mut context = page_context(&pool, "Front page nominations").await;
+    context.insert("nominations", &Nomination::pending(&pool).await?);
+    context.insert("anonymous_name", &User::anonymous_name(&pool).await);
+    context.insert("current_user", &Some(user));
 
-    Ok(Redirect::to("/admin/node"))
+    let html = render(&tera, "admin/nominations.html", &context)?;
+    Ok(Html(html))
 }
 
 #[derive(Debug, Deserialize)]
-pub struct UserActionForm {
-    pub action: String,
-    #[serde(default)]
-    pub uids: Vec<u32>,
+pub struct NominationDecisionForm {
+    /// "approve" or "decline".
+    pub op: String,
 }
 
-pub async fn user_action(
-    State(pool): State<MySqlPool>,
+/// POST /admin/node/nominations/:nid - Approve a nomination, promoting the
+/// post, or decline it.
+pub async fn nomination_decide(
+    State(pool): State<Pool>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
-    QsForm(form): QsForm<UserActionForm>,
+    Path(nid): Path<u32>,
+    Form(form): Form<NominationDecisionForm>,
 ) -> AppResult<Redirect> {
     let Some(user) = current_user else {
         return Err(AppError::Unauthorized);
     };
 
-    if !user.has_permission(&pool, "administer users").await? {
+    if !user.has_permission(&pool, "administer nodes").await? {
         return Err(AppError::Forbidden);
     }
 
-    for uid in form.uids {
-        if uid == 1 {
-            continue;
-        }
-        match form.action.as_str() {
-            "block" => User::set_status(&pool, uid, 0).await?,
-            "unblock" => User::set_status(&pool, uid, 1).await?,
-            _ => {}
+    let node = Node::find_by_nid(&pool, nid).await?.ok_or(AppError::NotFound)?;
+    let decided = match form.op.as_str() {
+        "approve" => Nomination::approve(&pool, nid, user.uid).await?,
+        "decline" => Nomination::decline(&pool, nid, user.uid).await?,
+        _ => return Err(AppError::BadRequest(format!("Unknown operation '{}'", form.op))),
+    };
+
+    if decided {
+        if form.op == "approve" {
+            cache::invalidate_tags(&[cache::TAG_NODE, cache::TAG_FRONT_PAGE]);
         }
+        let verb = if form.op == "approve" { "promoted to the front page" } else { "declined for the front page" };
+        Watchdog::log(
+            &pool,
+            user.uid,
+            "content",
+            &format!("{}: nomination {}.", node.title, verb),
+            WATCHDOG_NOTICE,
+            &format!("/node/{}", nid),
+            &format!("/admin/node/nominations/{}", nid),
+        )
+        .await?;
     }
 
-    Ok(Redirect::to("/admin/user"))
+    Ok(Redirect::to("/admin/node/nominations"))
 }
 
-pub async fn settings_form(
-    State(pool): State<MySqlPool>,
+/// Revisions listed on the recent changes report.
+const RECENT_CHANGES_LIMIT: i64 = 50;
+
+/// GET /admin/reports/recent-changes - The latest revisions across all
+/// content, so editors can follow who changed what without visiting each
+/// node.
+pub async fn recent_changes(
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
 ) -> AppResult<Html<String>> {
@@ -277,39 +2038,30 @@ pub async fn settings_form(
         return Err(AppError::Forbidden);
     }
 
-    let site_name = Variable::get_or_default(&pool, "site_name", "Drupal").await;
-    let site_slogan = Variable::get_or_default(&pool, "site_slogan", "").await;
-    let site_mail = Variable::get_or_default(&pool, "site_mail", "").await;
-    let site_footer = Variable::get_or_default(&pool, "site_footer", "").await;
-    let current_theme = get_default_theme(&pool).await;
-
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", "Site information");
+    let mut context = page_context(&pool, "Recent changes").await;
+    context.insert("revisions", &NodeRevision::recent(&pool, RECENT_CHANGES_LIMIT).await?);
+    context.insert("anonymous_name", &User::anonymous_name(&pool).await);
     context.insert("current_user", &Some(user));
-    context.insert("site_name", &site_name);
-    context.insert("site_slogan", &site_slogan);
-    context.insert("site_mail", &site_mail);
-    context.insert("site_footer", &site_footer);
 
-    let html = tera.render("admin/settings.html", &context)?;
+    let html = render(&tera, "admin/recent_changes.html", &context)?;
     Ok(Html(html))
 }
 
 #[derive(Debug, Deserialize)]
-pub struct SettingsForm {
-    pub site_name: String,
-    pub site_slogan: String,
-    pub site_mail: String,
-    pub site_footer: String,
+pub struct FieldCleanupForm {
+    #[serde(default)]
+    pub confirm: Option<String>,
 }
 
-pub async fn settings_submit(
-    State(pool): State<MySqlPool>,
+/// POST /admin/reports/fields/instance/:id/delete - Ask for confirmation,
+/// then remove an instance whose content type no longer exists.
+pub async fn field_instance_delete(
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
-    Form(form): Form<SettingsForm>,
-) -> AppResult<Html<String>> {
+    Path(id): Path<u32>,
+    Form(form): Form<FieldCleanupForm>,
+) -> AppResult<Result<Html<String>, Redirect>> {
     let Some(user) = current_user else {
         return Err(AppError::Unauthorized);
     };
@@ -318,31 +2070,60 @@ pub async fn settings_submit(
         return Err(AppError::Forbidden);
     }
 
-    Variable::set(&pool, "site_name", &form.site_name).await?;
-    Variable::set(&pool, "site_slogan", &form.site_slogan).await?;
-    Variable::set(&pool, "site_mail", &form.site_mail).await?;
-    Variable::set(&pool, "site_footer", &form.site_footer).await?;
-    let current_theme = get_default_theme(&pool).await;
+    let instance = NodeFieldInstance::find_by_id(&pool, id)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    if NodeType::find_by_type(&pool, &instance.node_type).await?.is_some() {
+        return Err(AppError::BadRequest(format!(
+            "The content type {} still exists",
+            instance.node_type
+        )));
+    }
 
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", "Site information");
-    context.insert("current_user", &Some(user));
-    context.insert("site_name", &form.site_name);
-    context.insert("site_slogan", &form.site_slogan);
-    context.insert("site_mail", &form.site_mail);
-    context.insert("site_footer", &form.site_footer);
-    context.insert("message", "The configuration options have been saved.");
+    if form.confirm.is_none() {
+        let mut context = page_context(&pool, "Remove field instance").await;
+        context.insert(
+            "question",
+            &format!(
+                "Are you sure you want to remove the field {} from the deleted content type {}?",
+                instance.field_name, instance.node_type
+            ),
+        );
+        context.insert("action", &format!("/admin/reports/fields/instance/{}/delete", id));
+        context.insert("submit", "Remove");
+        context.insert("current_user", &Some(user));
+        let html = render(&tera, "admin/field_cleanup.html", &context)?;
+        return Ok(Ok(Html(html)));
+    }
 
-    let html = tera.render("admin/settings.html", &context)?;
-    Ok(Html(html))
+    NodeFieldInstance::delete(&pool, id).await?;
+    Watchdog::log(
+        &pool,
+        user.uid,
+        "content",
+        &format!(
+            "Removed field {} from deleted content type {}.",
+            instance.field_name, instance.node_type
+        ),
+        WATCHDOG_NOTICE,
+        "/admin/reports/fields",
+        &format!("/admin/reports/fields/instance/{}/delete", id),
+    )
+    .await?;
+
+    Ok(Err(Redirect::to("/admin/reports/fields")))
 }
 
-pub async fn status_report(
-    State(pool): State<MySqlPool>,
+/// POST /admin/reports/fields/data/:field_name/delete - Ask for
+/// confirmation, then delete the values stored for a field that no longer
+/// exists.
+pub async fn field_data_delete(
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
-) -> AppResult<Html<String>> {
+    Path(field_name): Path<String>,
+    Form(form): Form<FieldCleanupForm>,
+) -> AppResult<Result<Html<String>, Redirect>> {
     let Some(user) = current_user else {
         return Err(AppError::Unauthorized);
     };
@@ -351,29 +2132,63 @@ pub async fn status_report(
         return Err(AppError::Forbidden);
     }
 
-    let node_count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM node")
-        .fetch_one(&pool)
-        .await?;
-    let user_count: (i64,) = sqlx::query_as("SELECT COUNT(*) FROM users WHERE uid > 0")
-        .fetch_one(&pool)
-        .await?;
-    let current_theme = get_default_theme(&pool).await;
+    if NodeField::find_by_name(&pool, &field_name).await?.is_some() {
+        return Err(AppError::BadRequest(format!("The field {} still exists", field_name)));
+    }
 
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", "Status report");
-    context.insert("current_user", &Some(user));
-    context.insert("drupal_version", "4.7.0-rust");
-    context.insert("node_count", &node_count.0);
-    context.insert("user_count", &user_count.0);
+    if form.confirm.is_none() {
+        let mut context = page_context(&pool, "Delete field data").await;
+        context.insert(
+            "question",
+            &format!(
+                "Are you sure you want to delete all values stored for the deleted field {}?",
+                field_name
+            ),
+        );
+        context.insert("action", &format!("/admin/reports/fields/data/{}/delete", field_name));
+        context.insert("submit", "Delete");
+        context.insert("current_user", &Some(user));
+        let html = render(&tera, "admin/field_cleanup.html", &context)?;
+        return Ok(Ok(Html(html)));
+    }
 
-    let html = tera.render("admin/status.html", &context)?;
-    Ok(Html(html))
+    let deleted = NodeFieldData::delete_for_field(&pool, &field_name).await?;
+    cache::invalidate_tags(&[cache::TAG_NODE]);
+    Watchdog::log(
+        &pool,
+        user.uid,
+        "content",
+        &format!("Deleted {} value(s) of deleted field {}.", deleted, field_name),
+        WATCHDOG_NOTICE,
+        "/admin/reports/fields",
+        &format!("/admin/reports/fields/data/{}/delete", field_name),
+    )
+    .await?;
+
+    Ok(Err(Redirect::to("/admin/reports/fields")))
+}
+
+/// POST /admin/cron - Run cron now, from the button on the status report.
+pub async fn run_cron(
+    State(pool): State<Pool>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+) -> AppResult<Redirect> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer site configuration").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    cron::run_cron(&pool).await?;
+
+    Ok(Redirect::to("/admin/reports/status"))
 }
 
 // Module administration
 pub async fn modules_list(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
 ) -> AppResult<Html<String>> {
@@ -381,20 +2196,17 @@ pub async fn modules_list(
         return Err(AppError::Unauthorized);
     };
 
-    if !user.has_permission(&pool, "administer nodes").await? {
+    if !user.has_permission(&pool, "administer site configuration").await? {
         return Err(AppError::Forbidden);
     }
 
     let modules = SystemItem::all_modules(&pool).await?;
-    let current_theme = get_default_theme(&pool).await;
 
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", "Modules");
+    let mut context = page_context(&pool, "Modules").await;
     context.insert("current_user", &Some(user));
     context.insert("modules", &modules);
 
-    let html = tera.render("admin/modules.html", &context)?;
+    let html = render(&tera, "admin/modules.html", &context)?;
     Ok(Html(html))
 }
 
@@ -405,7 +2217,7 @@ pub struct ModulesForm {
 }
 
 pub async fn modules_submit(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
     QsForm(form): QsForm<ModulesForm>,
 ) -> AppResult<Redirect> {
@@ -413,7 +2225,7 @@ pub async fn modules_submit(
         return Err(AppError::Unauthorized);
     };
 
-    if !user.has_permission(&pool, "administer nodes").await? {
+    if !user.has_permission(&pool, "administer site configuration").await? {
         return Err(AppError::Forbidden);
     }
 
@@ -437,7 +2249,7 @@ pub async fn modules_submit(
 
 // Theme administration
 pub async fn themes_list(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
 ) -> AppResult<Html<String>> {
@@ -445,21 +2257,19 @@ pub async fn themes_list(
         return Err(AppError::Unauthorized);
     };
 
-    if !user.has_permission(&pool, "administer nodes").await? {
+    if !user.has_permission(&pool, "administer site configuration").await? {
         return Err(AppError::Forbidden);
     }
 
     let themes = SystemItem::all_themes(&pool).await?;
-    let current_theme = get_default_theme(&pool).await;
+    let default_theme = get_default_theme(&pool).await;
 
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", "Themes");
+    let mut context = page_context(&pool, "Themes").await;
     context.insert("current_user", &Some(user));
     context.insert("themes", &themes);
-    context.insert("default_theme", &current_theme);
+    context.insert("default_theme", &default_theme);
 
-    let html = tera.render("admin/themes.html", &context)?;
+    let html = render(&tera, "admin/themes.html", &context)?;
     Ok(Html(html))
 }
 
@@ -471,7 +2281,7 @@ pub struct ThemesForm {
 }
 
 pub async fn themes_submit(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
     QsForm(form): QsForm<ThemesForm>,
 ) -> AppResult<Redirect> {
@@ -479,7 +2289,7 @@ pub async fn themes_submit(
         return Err(AppError::Unauthorized);
     };
 
-    if !user.has_permission(&pool, "administer nodes").await? {
+    if !user.has_permission(&pool, "administer site configuration").await? {
         return Err(AppError::Forbidden);
     }
 
@@ -501,7 +2311,7 @@ pub async fn themes_submit(
 
 // Statistics/Logs administration
 pub async fn logs_hits(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
 ) -> AppResult<Html<String>> {
@@ -509,7 +2319,7 @@ pub async fn logs_hits(
         return Err(AppError::Unauthorized);
     };
 
-    if !user.has_permission(&pool, "administer nodes").await? {
+    if !user.has_permission(&pool, "access site reports").await? {
         return Err(AppError::Forbidden);
     }
 
@@ -519,21 +2329,18 @@ pub async fn logs_hits(
     } else {
         vec![]
     };
-    let current_theme = get_default_theme(&pool).await;
 
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", "Recent hits");
+    let mut context = page_context(&pool, "Recent hits").await;
     context.insert("current_user", &Some(user));
     context.insert("hits", &hits);
     context.insert("stats_enabled", &stats_enabled);
 
-    let html = tera.render("admin/logs_hits.html", &context)?;
+    let html = render(&tera, "admin/logs_hits.html", &context)?;
     Ok(Html(html))
 }
 
 pub async fn logs_pages(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
 ) -> AppResult<Html<String>> {
@@ -541,7 +2348,7 @@ pub async fn logs_pages(
         return Err(AppError::Unauthorized);
     };
 
-    if !user.has_permission(&pool, "administer nodes").await? {
+    if !user.has_permission(&pool, "access site reports").await? {
         return Err(AppError::Forbidden);
     }
 
@@ -551,21 +2358,18 @@ pub async fn logs_pages(
     } else {
         vec![]
     };
-    let current_theme = get_default_theme(&pool).await;
 
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", "Top pages");
+    let mut context = page_context(&pool, "Top pages").await;
     context.insert("current_user", &Some(user));
     context.insert("pages", &pages);
     context.insert("stats_enabled", &stats_enabled);
 
-    let html = tera.render("admin/logs_pages.html", &context)?;
+    let html = render(&tera, "admin/logs_pages.html", &context)?;
     Ok(Html(html))
 }
 
 pub async fn logs_visitors(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
 ) -> AppResult<Html<String>> {
@@ -573,7 +2377,7 @@ pub async fn logs_visitors(
         return Err(AppError::Unauthorized);
     };
 
-    if !user.has_permission(&pool, "administer nodes").await? {
+    if !user.has_permission(&pool, "access site reports").await? {
         return Err(AppError::Forbidden);
     }
 
@@ -583,21 +2387,18 @@ pub async fn logs_visitors(
     } else {
         vec![]
     };
-    let current_theme = get_default_theme(&pool).await;
 
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", "Top visitors");
+    let mut context = page_context(&pool, "Top visitors").await;
     context.insert("current_user", &Some(user));
     context.insert("visitors", &visitors);
     context.insert("stats_enabled", &stats_enabled);
 
-    let html = tera.render("admin/logs_visitors.html", &context)?;
+    let html = render(&tera, "admin/logs_visitors.html", &context)?;
     Ok(Html(html))
 }
 
 pub async fn logs_referrers(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
 ) -> AppResult<Html<String>> {
@@ -605,7 +2406,7 @@ pub async fn logs_referrers(
         return Err(AppError::Unauthorized);
     };
 
-    if !user.has_permission(&pool, "administer nodes").await? {
+    if !user.has_permission(&pool, "access site reports").await? {
         return Err(AppError::Forbidden);
     }
 
@@ -615,21 +2416,18 @@ pub async fn logs_referrers(
     } else {
         vec![]
     };
-    let current_theme = get_default_theme(&pool).await;
 
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", "Top referrers");
+    let mut context = page_context(&pool, "Top referrers").await;
     context.insert("current_user", &Some(user));
     context.insert("referrers", &referrers);
     context.insert("stats_enabled", &stats_enabled);
 
-    let html = tera.render("admin/logs_referrers.html", &context)?;
+    let html = render(&tera, "admin/logs_referrers.html", &context)?;
     Ok(Html(html))
 }
 
 pub async fn logs_access_detail(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
     Path(aid): Path<u32>,
@@ -638,25 +2436,22 @@ pub async fn logs_access_detail(
         return Err(AppError::Unauthorized);
     };
 
-    if !user.has_permission(&pool, "administer nodes").await? {
+    if !user.has_permission(&pool, "access site reports").await? {
         return Err(AppError::Forbidden);
     }
 
     let entry = AccessLog::find_by_aid(&pool, aid).await?;
-    let current_theme = get_default_theme(&pool).await;
 
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", "Access log detail");
+    let mut context = page_context(&pool, "Access log detail").await;
     context.insert("current_user", &Some(user));
     context.insert("entry", &entry);
 
-    let html = tera.render("admin/logs_detail.html", &context)?;
+    let html = render(&tera, "admin/logs_detail.html", &context)?;
     Ok(Html(html))
 }
 
 pub async fn statistics_settings_form(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
 ) -> AppResult<Html<String>> {
@@ -664,22 +2459,24 @@ pub async fn statistics_settings_form(
         return Err(AppError::Unauthorized);
     };
 
-    if !user.has_permission(&pool, "administer nodes").await? {
+    if !user.has_permission(&pool, "administer site configuration").await? {
         return Err(AppError::Forbidden);
     }
 
     let enable_access_log = Variable::get_or_default(&pool, "statistics_enable_access_log", "0").await;
     let count_content_views = Variable::get_or_default(&pool, "statistics_count_content_views", "0").await;
-    let current_theme = get_default_theme(&pool).await;
+    let count_own_views = Variable::get_or_default(&pool, "statistics_count_own_views", "0").await;
+    let exclude_paths =
+        Variable::get_or_default(&pool, "statistics_exclude_paths", statistics::DEFAULT_EXCLUDED_PATHS).await;
 
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", "Statistics settings");
+    let mut context = page_context(&pool, "Statistics settings").await;
     context.insert("current_user", &Some(user));
     context.insert("enable_access_log", &(enable_access_log == "1"));
     context.insert("count_content_views", &(count_content_views == "1"));
+    context.insert("count_own_views", &(count_own_views == "1"));
+    context.insert("exclude_paths", &exclude_paths);
 
-    let html = tera.render("admin/statistics_settings.html", &context)?;
+    let html = render(&tera, "admin/statistics_settings.html", &context)?;
     Ok(Html(html))
 }
 
@@ -689,10 +2486,14 @@ pub struct StatisticsSettingsForm {
     pub enable_access_log: Option<String>,
     #[serde(default)]
     pub count_content_views: Option<String>,
+    #[serde(default)]
+    pub count_own_views: Option<String>,
+    #[serde(default)]
+    pub exclude_paths: String,
 }
 
 pub async fn statistics_settings_submit(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
     Form(form): Form<StatisticsSettingsForm>,
 ) -> AppResult<Redirect> {
@@ -700,15 +2501,657 @@ pub async fn statistics_settings_submit(
         return Err(AppError::Unauthorized);
     };
 
-    if !user.has_permission(&pool, "administer nodes").await? {
+    if !user.has_permission(&pool, "administer site configuration").await? {
         return Err(AppError::Forbidden);
     }
 
     let enable_access_log = if form.enable_access_log.is_some() { "1" } else { "0" };
     let count_content_views = if form.count_content_views.is_some() { "1" } else { "0" };
+    let count_own_views = if form.count_own_views.is_some() { "1" } else { "0" };
 
     Variable::set(&pool, "statistics_enable_access_log", enable_access_log).await?;
     Variable::set(&pool, "statistics_count_content_views", count_content_views).await?;
+    Variable::set(&pool, "statistics_count_own_views", count_own_views).await?;
+
+    let exclude_paths: Vec<&str> = form
+        .exclude_paths
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty())
+        .collect();
+    Variable::set(&pool, "statistics_exclude_paths", &exclude_paths.join("\n")).await?;
 
     Ok(Redirect::to("/admin/logs/settings"))
 }
+
+pub async fn comment_settings_form(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+) -> AppResult<Html<String>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer comments").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let mut context = page_context(&pool, "Comment settings").await;
+    context.insert("current_user", &Some(user));
+    context.insert("per_page", &Comment::default_per_page(&pool).await);
+    context.insert("order", CommentOrder::load(&pool).await.value());
+    context.insert("order_options", CommentOrder::OPTIONS);
+    context.insert("mode", CommentMode::load(&pool).await.value());
+    context.insert("mode_options", CommentMode::OPTIONS);
+    context.insert("max_depth", &Comment::max_depth(&pool).await.unwrap_or(0));
+    context.insert("zero_text", &Comment::zero_text(&pool).await);
+    context.insert("anonymous", CommentAnonymous::load(&pool).await.value());
+    context.insert("length", &CommentLength::load(&pool).await);
+    context.insert("anonymous_options", CommentAnonymous::OPTIONS);
+    context.insert("preview", CommentPreview::load(&pool).await.value());
+    context.insert("preview_options", CommentPreview::OPTIONS);
+
+    let html = render(&tera, "admin/comment_settings.html", &context)?;
+    Ok(Html(html))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CommentSettingsForm {
+    pub per_page: u32,
+    pub order: String,
+    #[serde(default)]
+    pub mode: String,
+    #[serde(default)]
+    pub max_depth: u32,
+    #[serde(default)]
+    pub zero_text: String,
+    #[serde(default)]
+    pub anonymous: String,
+    #[serde(default)]
+    pub preview: String,
+    #[serde(default)]
+    pub minlength: usize,
+    #[serde(default)]
+    pub maxlength: usize,
+}
+
+pub async fn comment_settings_submit(
+    State(pool): State<Pool>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Form(form): Form<CommentSettingsForm>,
+) -> AppResult<Redirect> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "administer comments").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    if form.per_page > 0 {
+        Variable::set(&pool, "comment_default_per_page", &form.per_page.to_string()).await?;
+    }
+    let order = CommentOrder::from_value(&form.order);
+    Variable::set(&pool, "comment_default_order", order.value()).await?;
+    let mode = CommentMode::from_value(&form.mode);
+    Variable::set(&pool, "comment_default_mode", mode.value()).await?;
+    Variable::set(&pool, "comment_max_depth", &form.max_depth.to_string()).await?;
+    Variable::set(&pool, "comment_zero_text", form.zero_text.trim()).await?;
+    let anonymous = CommentAnonymous::from_value(&form.anonymous);
+    Variable::set(&pool, "comment_anonymous", anonymous.value()).await?;
+    let preview = CommentPreview::from_value(&form.preview);
+    Variable::set(&pool, "comment_preview", preview.value()).await?;
+    Variable::set(&pool, "comment_minlength", &form.minlength.to_string()).await?;
+    Variable::set(&pool, "comment_maxlength", &form.maxlength.to_string()).await?;
+
+    Ok(Redirect::to("/admin/comment/settings"))
+}
+
+const COMMENT_APPROVAL_PER_PAGE: u32 = 50;
+
+/// GET /admin/comment/approval - Comments waiting to be published.
+pub async fn comment_approval(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Query(query): Query<PageQuery>,
+) -> AppResult<Html<String>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    let permissions = CommentPermissions::load(&pool, Some(&user)).await?;
+    if !permissions.moderate {
+        return Err(AppError::Forbidden);
+    }
+
+    let total = Comment::count_unapproved(&pool).await?;
+    let pager = Pager::new(query.page, COMMENT_APPROVAL_PER_PAGE, total);
+    let comments = Comment::unapproved(&pool, pager.limit(), pager.offset()).await?;
+
+    let mut context = page_context(&pool, "Comment approval queue").await;
+    context.insert("current_user", &Some(user));
+    context.insert("comments", &comments);
+    context.insert("anonymous_name", &User::anonymous_name(&pool).await);
+    context.insert("pager", &pager);
+    context.insert("can_delete", &permissions.delete_any);
+
+    let html = render(&tera, "admin/comment_approval.html", &context)?;
+    Ok(Html(html))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct CommentApprovalForm {
+    pub action: String,
+    #[serde(default)]
+    pub cids: Vec<u32>,
+}
+
+/// POST /admin/comment/approval - Publish or delete the selected comments.
+pub async fn comment_approval_action(
+    State(pool): State<Pool>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    QsForm(form): QsForm<CommentApprovalForm>,
+) -> AppResult<Redirect> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    let permissions = CommentPermissions::load(&pool, Some(&user)).await?;
+    if !permissions.moderate {
+        return Err(AppError::Forbidden);
+    }
+
+    check_bulk_size(&form.cids)?;
+
+    match form.action.as_str() {
+        "publish" => {
+            for &cid in &form.cids {
+                if let Some(comment) = Comment::find_by_cid(&pool, cid).await? {
+                    if comment.status != COMMENT_PUBLISHED {
+                        Comment::update(&pool, cid, &comment.subject, &comment.comment, COMMENT_PUBLISHED)
+                            .await?;
+                    }
+                }
+            }
+        }
+        "delete" => {
+            if !permissions.delete_any {
+                return Err(AppError::Forbidden);
+            }
+            for &cid in &form.cids {
+                Comment::delete(&pool, cid).await?;
+            }
+        }
+        _ => {}
+    }
+
+    Ok(Redirect::to("/admin/comment/approval"))
+}
+
+const DBLOG_PER_PAGE: u32 = 50;
+
+#[derive(Debug, Clone, Default, Deserialize, Serialize)]
+pub struct DblogQuery {
+    #[serde(default, rename = "type", skip_serializing_if = "String::is_empty")]
+    pub log_type: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub severity: String,
+    #[serde(default, skip_serializing)]
+    pub page: u32,
+}
+
+impl DblogQuery {
+    fn to_filter(&self) -> WatchdogFilter {
+        WatchdogFilter {
+            log_type: Some(self.log_type.trim().to_string()).filter(|t| !t.is_empty()),
+            severity: self.severity.parse().ok(),
+        }
+    }
+}
+
+/// GET /admin/reports/dblog - Recent watchdog entries, newest first.
+pub async fn dblog(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Query(query): Query<DblogQuery>,
+) -> AppResult<Html<String>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "access site reports").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let filter = query.to_filter();
+    let total = Watchdog::count(&pool, &filter).await?;
+    let pager = Pager::new(query.page, DBLOG_PER_PAGE, total);
+    let entries = Watchdog::recent(&pool, &filter, pager.limit(), pager.offset()).await?;
+    let types = Watchdog::types(&pool).await?;
+    let filter_query = serde_qs::to_string(&query).unwrap_or_default();
+    let pager_query = if filter_query.is_empty() {
+        String::new()
+    } else {
+        format!("{}&", filter_query)
+    };
+
+    let mut context = page_context(&pool, "Recent log entries").await;
+    context.insert("current_user", &Some(user));
+    context.insert("entries", &entries);
+    context.insert("types", &types);
+    context.insert("severities", WATCHDOG_SEVERITIES);
+    context.insert("filter", &query);
+    context.insert("selected_severity", &filter.severity);
+    context.insert("pager", &pager);
+    context.insert("pager_query", &pager_query);
+
+    let html = render(&tera, "admin/dblog.html", &context)?;
+    Ok(Html(html))
+}
+
+/// GET /admin/reports/dblog/:wid - Everything recorded about one event.
+pub async fn dblog_event(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Path(wid): Path<u32>,
+) -> AppResult<Html<String>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if !user.has_permission(&pool, "access site reports").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let entry = Watchdog::find_by_wid(&pool, wid)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    let mut context = page_context(&pool, &format!("Details of event {}", wid)).await;
+    context.insert("current_user", &Some(user));
+    context.insert("entry", &entry);
+    context.insert("severities", WATCHDOG_SEVERITIES);
+
+    let html = render(&tera, "admin/dblog_event.html", &context)?;
+    Ok(Html(html))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::IN_LIST_CHUNK;
+    use crate::test_support::{create_test_node, create_test_role, create_test_user, test_site, test_tera};
+
+    /// Whether `user` may open each page guarded by one of the split
+    /// administration permissions.
+    async fn pages_open_to(pool: &Pool, user: &User) -> [bool; 4] {
+        let as_user = || (State(pool.clone()), State(test_tera()), Extension(CurrentUser(Some(user.clone()))));
+        let (p, t, u) = as_user();
+        let index = index(p, t, u).await;
+        let (p, t, u) = as_user();
+        let settings = settings_form(p, t, u).await;
+        let (p, t, u) = as_user();
+        let users = user_list(p, t, u).await;
+        let (p, t, u) = as_user();
+        let status = status_report(p, t, u).await;
+
+        [index, settings, users, status].map(|result| match result {
+            Ok(_) => true,
+            Err(AppError::Forbidden) => false,
+            Err(e) => panic!("unexpected error: {}", e),
+        })
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn administration_pages_follow_their_permissions() {
+        let site = test_site().await;
+        let pool = &site.pool;
+
+        let member = create_test_user(pool, &[]).await;
+        assert_eq!(pages_open_to(pool, &member).await, [false, false, false, false]);
+
+        let admin = create_test_user(pool, &["administrator"]).await;
+        assert_eq!(pages_open_to(pool, &admin).await, [true, true, true, true]);
+
+        let cases: [(&str, [bool; 4]); 4] = [
+            ("access administration pages", [true, false, false, false]),
+            ("administer site configuration", [false, true, false, false]),
+            ("administer users", [false, false, true, false]),
+            ("access site reports", [false, false, false, true]),
+        ];
+        for (permission, expected) in cases {
+            let role = create_test_role(pool, &[permission]).await;
+            let user = create_test_user(pool, &[&role.name]).await;
+            assert_eq!(pages_open_to(pool, &user).await, expected, "{}", permission);
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn metrics_are_reported_to_those_who_see_the_status_report() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let as_user = |user: Option<&User>| (State(pool.clone()), Extension(CurrentUser(user.cloned())));
+
+        let (p, u) = as_user(None);
+        assert!(matches!(metrics(p, u).await, Err(AppError::Unauthorized)));
+        let member = create_test_user(pool, &[]).await;
+        let (p, u) = as_user(Some(&member));
+        assert!(matches!(metrics(p, u).await, Err(AppError::Forbidden)));
+
+        let role = create_test_role(pool, &["access site reports"]).await;
+        let reporter = create_test_user(pool, &[&role.name]).await;
+        let misses = cache::stats().misses;
+        assert!(cache::cache_get::<String>("metrics", "missing").is_none());
+        let (p, u) = as_user(Some(&reporter));
+        let Json(counters) = metrics(p, u).await.unwrap();
+        assert!(counters.cache.misses > misses);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn permission_grid_saves_every_role() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let admin = create_test_user(pool, &["administrator"]).await;
+        let editor = create_test_role(pool, &[]).await;
+        let writer = create_test_role(pool, &[]).await;
+
+        // Resubmit every role as it is, then tick one box for each new role.
+        let mut perms = HashMap::new();
+        for role in Role::all(pool).await.unwrap() {
+            let boxes: HashMap<String, String> = Permission::get_for_role(pool, role.rid)
+                .await
+                .unwrap()
+                .into_iter()
+                .map(|permission| (permission, "1".to_string()))
+                .collect();
+            perms.insert(role.rid, boxes);
+        }
+        perms.get_mut(&editor.rid).unwrap().insert("administer nodes".to_string(), "1".to_string());
+        perms.get_mut(&writer.rid).unwrap().insert("post comments".to_string(), "1".to_string());
+
+        let Html(page) = access_submit(
+            State(pool.clone()),
+            State(test_tera()),
+            Extension(CurrentUser(Some(admin))),
+            QsForm(AccessForm { perms }),
+        )
+        .await
+        .unwrap();
+        assert!(page.contains("The changes have been saved."));
+
+        assert_eq!(Permission::get_for_role(pool, editor.rid).await.unwrap(), ["administer nodes"]);
+        assert_eq!(Permission::get_for_role(pool, writer.rid).await.unwrap(), ["post comments"]);
+    }
+
+    /// Submit the account form for `uid` as `user`, returning the error
+    /// page if it is refused.
+    async fn edit_account(pool: &Pool, user: &User, uid: u32, form: AccountForm) -> Option<String> {
+        let result = user_edit_submit(
+            State(pool.clone()),
+            State(test_tera()),
+            Extension(CurrentUser(Some(user.clone()))),
+            Path(uid),
+            QsForm(form),
+        )
+        .await
+        .unwrap();
+        match result {
+            Ok(Html(page)) => Some(page),
+            Err(_) => None,
+        }
+    }
+
+    fn account_form(account: &User, roles: Vec<u32>) -> AccountForm {
+        AccountForm {
+            name: account.name.clone(),
+            mail: account.mail.clone().unwrap_or_default(),
+            status: 1,
+            roles,
+            ..AccountForm::default()
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn roles_need_access_control_to_change() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let administrator = Role::administrator(pool).await.unwrap().unwrap();
+        let user_admin = create_test_user(pool, &[&create_test_role(pool, &["administer users"]).await.name]).await;
+        let before = Role::rids_for_user(pool, user_admin.uid).await.unwrap();
+
+        // Granting oneself the administrator role is ignored.
+        let mut rids = before.clone();
+        rids.push(administrator.rid);
+        assert_eq!(edit_account(pool, &user_admin, user_admin.uid, account_form(&user_admin, rids.clone())).await, None);
+        assert_eq!(Role::rids_for_user(pool, user_admin.uid).await.unwrap(), before);
+
+        // So is granting it to a new account.
+        let form = AccountForm {
+            name: "newcomer".to_string(),
+            mail: "newcomer@example.com".to_string(),
+            password: "secret123".to_string(),
+            password_confirm: "secret123".to_string(),
+            status: 1,
+            roles: vec![administrator.rid],
+        };
+        let created = user_create_submit(
+            State(pool.clone()),
+            State(test_tera()),
+            Extension(CurrentUser(Some(user_admin.clone()))),
+            QsForm(form),
+        )
+        .await
+        .unwrap();
+        assert!(created.is_err(), "the account is created and the form redirects");
+        let newcomer = User::find_by_name(pool, "newcomer").await.unwrap().unwrap();
+        assert!(!Role::rids_for_user(pool, newcomer.uid).await.unwrap().contains(&administrator.rid));
+
+        // Someone who administers access control may grant it.
+        let admin = create_test_user(pool, &["administrator"]).await;
+        assert_eq!(edit_account(pool, &admin, user_admin.uid, account_form(&user_admin, rids.clone())).await, None);
+        assert_eq!(Role::rids_for_user(pool, user_admin.uid).await.unwrap(), rids);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn only_the_first_account_changes_its_credentials() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let first = User::find_by_uid(pool, 1).await.unwrap().unwrap();
+        let admin = create_test_user(pool, &["administrator"]).await;
+
+        let changes = [
+            AccountForm { name: "taken".to_string(), ..account_form(&first, vec![]) },
+            AccountForm { mail: "taken@example.com".to_string(), ..account_form(&first, vec![]) },
+            AccountForm {
+                password: "secret123".to_string(),
+                password_confirm: "secret123".to_string(),
+                ..account_form(&first, vec![])
+            },
+        ];
+        for form in changes {
+            let page = edit_account(pool, &admin, 1, form).await.expect("the change is refused");
+            assert!(page.contains("Only the first account itself may change"));
+        }
+        let unchanged = User::find_by_uid(pool, 1).await.unwrap().unwrap();
+        assert_eq!((unchanged.name, unchanged.mail, unchanged.pass), (first.name.clone(), first.mail.clone(), first.pass.clone()));
+
+        // The first account may change its own.
+        let form = AccountForm { mail: "owner@example.com".to_string(), ..account_form(&first, vec![]) };
+        assert_eq!(edit_account(pool, &first, 1, form).await, None);
+        let changed = User::find_by_uid(pool, 1).await.unwrap().unwrap();
+        assert_eq!(changed.mail.as_deref(), Some("owner@example.com"));
+    }
+
+    /// A bulk action on `nids`, or on every node of `all_matching_type`.
+    fn content_action_form(
+        action: &str,
+        nids: Vec<u32>,
+        all_matching_type: Option<&str>,
+    ) -> QsForm<ContentActionForm> {
+        QsForm(ContentActionForm {
+            action: action.to_string(),
+            nids,
+            all_matching: all_matching_type.map(|_| "1".to_string()),
+            filter: ContentFilterQuery {
+                node_type: all_matching_type.unwrap_or("").to_string(),
+                ..Default::default()
+            },
+        })
+    }
+
+    async fn node_status(pool: &Pool, nid: u32) -> Option<i32> {
+        Node::find_by_nid(pool, nid).await.unwrap().map(|node| node.status)
+    }
+
+    #[test]
+    fn bulk_actions_refuse_oversized_selections() {
+        assert!(check_bulk_size(&vec![1; BULK_ACTION_MAX_ITEMS]).is_ok());
+        assert!(matches!(check_bulk_size(&vec![1; BULK_ACTION_MAX_ITEMS + 1]), Err(AppError::BadRequest(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn bulk_status_changes_reach_every_chunk() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let admin = create_test_user(pool, &["administrator"]).await;
+        let first = create_test_node(pool, "page", &admin, true).await;
+        let last = create_test_node(pool, "page", &admin, true).await;
+
+        // The two real nodes land in the first and the third chunk.
+        let mut nids = vec![first.nid];
+        nids.extend((0..2 * IN_LIST_CHUNK as u32 - 1).map(|n| 1_000_000 + n));
+        nids.push(last.nid);
+        let result = content_action(
+            State(pool.clone()),
+            State(test_tera()),
+            Extension(CurrentUser(Some(admin))),
+            content_action_form("unpublish", nids, None),
+        )
+        .await;
+
+        assert!(matches!(result, Ok(Err(_))));
+        assert_eq!(node_status(pool, first.nid).await, Some(0));
+        assert_eq!(node_status(pool, last.nid).await, Some(0));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn all_matching_actions_follow_the_filter() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let admin = create_test_user(pool, &["administrator"]).await;
+        let pages = [
+            create_test_node(pool, "page", &admin, true).await.nid,
+            create_test_node(pool, "page", &admin, true).await.nid,
+        ];
+        let story = create_test_node(pool, "story", &admin, true).await.nid;
+
+        let result = content_action(
+            State(pool.clone()),
+            State(test_tera()),
+            Extension(CurrentUser(Some(admin.clone()))),
+            content_action_form("unpublish", Vec::new(), Some("page")),
+        )
+        .await;
+        assert!(matches!(result, Ok(Err(_))));
+        for nid in pages {
+            assert_eq!(node_status(pool, nid).await, Some(0));
+        }
+        assert_eq!(node_status(pool, story).await, Some(1));
+
+        let QsForm(form) = content_action_form("delete", Vec::new(), Some("page"));
+        let form = ContentDeleteForm { nids: form.nids, all_matching: form.all_matching, filter: form.filter };
+        let result = content_delete_confirm(State(pool.clone()), Extension(CurrentUser(Some(admin))), QsForm(form)).await;
+        assert!(result.is_ok());
+        for nid in pages {
+            assert_eq!(node_status(pool, nid).await, None);
+        }
+        assert_eq!(node_status(pool, story).await, Some(1));
+    }
+
+    /// The rename page `admin` gets for renaming `type_name` to `new_type`.
+    async fn rename_type(pool: &Pool, admin: &User, type_name: &str, new_type: &str, confirm: bool) -> String {
+        let form = NodeTypeRenameForm { new_type: new_type.to_string(), confirm: confirm.then(|| "1".to_string()) };
+        let Html(page) = node_type_rename(
+            State(pool.clone()),
+            State(test_tera()),
+            Extension(CurrentUser(Some(admin.clone()))),
+            Path(type_name.to_string()),
+            Form(form),
+        )
+        .await
+        .unwrap();
+        page
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn renaming_a_type_refuses_taken_or_invalid_names() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let admin = create_test_user(pool, &["administrator"]).await;
+
+        for (new_type, error) in [
+            ("story", "already in use"),
+            ("Bad Name", "may only contain"),
+            ("page", "same as the current one"),
+            ("", "between 1 and 32"),
+        ] {
+            let page = rename_type(pool, &admin, "page", new_type, true).await;
+            assert!(page.contains(error), "{:?}: {}", new_type, page);
+        }
+        assert!(NodeType::find_by_type(pool, "page").await.unwrap().is_some());
+        assert!(NodeType::find_by_type(pool, "story").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn renaming_a_type_moves_its_posts_fields_and_settings() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let admin = create_test_user(pool, &["administrator"]).await;
+        NodeField::create(pool, "note", "text", 1, None).await.unwrap();
+        NodeFieldInstance::create(pool, "note", "page", "Note", None, false, 0, "text").await.unwrap();
+        Variable::set(pool, "node_show_updated_page", "1").await.unwrap();
+        Variable::set(pool, "comment_subject_field_page", "0").await.unwrap();
+        let role = create_test_role(pool, &["create page content", "edit own page content"]).await;
+        let node = create_test_node(pool, "page", &admin, true).await;
+
+        let page = rename_type(pool, &admin, "page", "article", false).await;
+        assert!(page.contains("1 post and 1 field will be moved"), "{}", page);
+        assert!(NodeType::find_by_type(pool, "article").await.unwrap().is_none(), "nothing moves unconfirmed");
+
+        let page = rename_type(pool, &admin, "page", "article", true).await;
+        assert!(page.contains("1 post was migrated"), "{}", page);
+
+        assert!(NodeType::find_by_type(pool, "page").await.unwrap().is_none());
+        let moved = Node::find_by_nid(pool, node.nid).await.unwrap().unwrap();
+        assert_eq!(moved.node_type, "article");
+        let fields = NodeFieldInstance::for_node_type(pool, "article").await.unwrap();
+        assert_eq!(fields.iter().map(|field| field.field_name.as_str()).collect::<Vec<_>>(), ["note"]);
+        assert_eq!(Variable::get(pool, "node_show_updated_article").await.unwrap().as_deref(), Some("1"));
+        assert_eq!(Variable::get(pool, "node_show_updated_page").await.unwrap(), None);
+        assert!(!Comment::subject_field_enabled(pool, "article").await);
+        assert_eq!(
+            Permission::get_for_role(pool, role.rid).await.unwrap(),
+            ["create article content", "edit own article content"]
+        );
+
+        let (logged,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM watchdog WHERE message LIKE ?")
+            .bind("Renamed content type page to article;%")
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        assert_eq!(logged, 1);
+    }
+}
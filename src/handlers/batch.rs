@@ -0,0 +1,56 @@
+use axum::{
+    extract::{Path, State},
+    response::Html,
+    Extension,
+};
+use tera::Tera;
+
+use crate::{
+    auth::middleware::CurrentUser,
+    batch::{self, BatchJob},
+    db::Pool,
+    error::{AppError, AppResult},
+    handlers::page_context,
+    models::batch::{Batch, BATCH_ACTIVE, BATCH_FAILED},
+    templates::render,
+};
+
+/// GET /batch/:bid - Process the next chunk of a batch and show its
+/// progress. While work remains the page refreshes itself, which drives
+/// the batch forward one chunk per request.
+pub async fn progress(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Path(bid): Path<u32>,
+) -> AppResult<Html<String>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    let Some(batch) = Batch::find_by_id(&pool, bid).await? else {
+        return Err(AppError::NotFound);
+    };
+    if batch.uid != user.uid {
+        return Err(AppError::Forbidden);
+    }
+
+    let batch = batch::process_chunk(&pool, bid)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let title = BatchJob::from_job_type(&batch.job_type)
+        .map(|job| job.title())
+        .unwrap_or("Processing");
+    let mut context = page_context(&pool, title).await;
+    context.insert("current_user", &Some(user));
+    context.insert("percentage", &batch.percentage());
+    context.insert("finished", &(batch.status != BATCH_ACTIVE));
+    context.insert("failed", &(batch.status == BATCH_FAILED));
+    if batch.status == BATCH_ACTIVE {
+        context.insert("refresh_url", &format!("/batch/{}", bid));
+    }
+    context.insert("batch", &batch);
+
+    let html = render(&tera, "batch/progress.html", &context)?;
+    Ok(Html(html))
+}
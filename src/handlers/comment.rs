@@ -1,34 +1,129 @@
 use axum::{
-    extract::{ConnectInfo, Path, State},
+    extract::{ConnectInfo, Path, Query, State},
     response::{Html, Redirect},
     Extension, Form,
 };
 use serde::{Deserialize, Serialize};
-use sqlx::MySqlPool;
 use std::net::SocketAddr;
 use tera::Tera;
 
 use crate::{
     auth::middleware::CurrentUser,
-    error::{AppError, AppResult},
+    db::Pool,
+    error::{AppError, AppResult, ResultExt},
+    handlers::{node::may_view, page_context},
+    pager::{PageQuery, Pager},
+    pictures::PictureSettings,
     models::{
-        get_default_theme, Comment, Node, COMMENT_NODE_DISABLED, COMMENT_NODE_READ_WRITE,
-        COMMENT_NOT_PUBLISHED, COMMENT_PUBLISHED,
+        Comment, CommentAnonymous, CommentLength, CommentDisplay, CommentListing, CommentPreview, CommentWithAuthor, RecentComment, User, Node, Variable, COMMENT_NODE_DISABLED,
+        COMMENT_NODE_READ_WRITE, COMMENT_NOT_PUBLISHED, COMMENT_PUBLISHED,
     },
+    templates::render,
 };
 
+const RECENT_COMMENTS_PER_PAGE: u32 = 30;
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct CommentForm {
+    #[serde(default)]
     pub subject: String,
     pub comment: String,
     pub name: Option<String>,
     pub mail: Option<String>,
     pub homepage: Option<String>,
+    /// The button pressed: "preview" or "save". Forms without one save
+    /// unless previews are required.
+    #[serde(default)]
+    pub op: String,
+}
+
+/// GET /comment/:cid - Permalink, redirects to the page of the node's
+/// comment listing the comment is on. Unpublished comments are only found
+/// by viewers who may administer or moderate comments, since nobody else
+/// sees them listed, and comments on a node the viewer may not see are not
+/// found at all.
+pub async fn permalink(
+    State(pool): State<Pool>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Path(cid): Path<u32>,
+) -> AppResult<Redirect> {
+    let comment = Comment::find_by_cid(&pool, cid)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    let listing = CommentListing::for_viewer(&pool, current_user.as_ref()).await?;
+    if comment.status != COMMENT_PUBLISHED && listing == CommentListing::Published {
+        return Err(AppError::NotFound);
+    }
+
+    let node = Node::find_with_body(&pool, comment.nid)
+        .await?
+        .filter(|node| may_view(current_user.as_ref(), node))
+        .ok_or(AppError::NotFound)?;
+
+    let per_page = Comment::per_page(&pool, &node.node_type).await;
+    let display = CommentDisplay::load(&pool).await;
+    let page = Comment::page_for_cid(&pool, cid, per_page, listing, display).await?;
+
+    let url = if page > 0 {
+        format!("/node/{}?comments_page={}#comment-{}", node.nid, page, cid)
+    } else {
+        format!("/node/{}#comment-{}", node.nid, cid)
+    };
+    Ok(Redirect::to(&url))
+}
+
+/// A recent comment with its anonymous author's homepage, when it may be
+/// linked.
+#[derive(Serialize)]
+struct RecentCommentView {
+    #[serde(flatten)]
+    comment: RecentComment,
+    homepage: Option<HomepageLink>,
+}
+
+/// GET /comments/recent - The latest published comments across the site.
+/// Comments on unpublished nodes are only listed for node administrators.
+pub async fn recent(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Query(query): Query<PageQuery>,
+) -> AppResult<Html<String>> {
+    let published_nodes_only = match &current_user {
+        Some(user) => !user.has_permission(&pool, "administer nodes").await?,
+        None => true,
+    };
+
+    let total = Comment::recent_count(&pool, published_nodes_only).await?;
+    let pager = Pager::new(query.page, RECENT_COMMENTS_PER_PAGE, total);
+    let homepage_links = HomepageLinks::load(&pool).await;
+    let comments: Vec<RecentCommentView> =
+        Comment::recent(&pool, published_nodes_only, pager.limit(), pager.offset())
+            .await?
+            .into_iter()
+            .map(|comment| RecentCommentView {
+                homepage: match comment.uid {
+                    0 => homepage_link(comment.homepage.as_deref(), homepage_links),
+                    _ => None,
+                },
+                comment,
+            })
+            .collect();
+
+    let mut context = page_context(&pool, "Recent comments").await;
+    context.insert("current_user", &current_user);
+    context.insert("comments", &comments);
+    context.insert("anonymous_name", &User::anonymous_name(&pool).await);
+    context.insert("pager", &pager);
+
+    let html = render(&tera, "comment/recent.html", &context)?;
+    Ok(Html(html))
 }
 
 /// GET /comment/reply/:nid - Show comment form for a node
 pub async fn add_form(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
     Path(nid): Path<u32>,
@@ -47,21 +142,25 @@ pub async fn add_form(
         return Err(AppError::Forbidden);
     }
 
-    let current_theme = get_default_theme(&pool).await;
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", &format!("Reply to {}", node.title));
+    let show_subject = Comment::subject_field_enabled(&pool, &node.node_type).await;
+    let anonymous = CommentAnonymous::load(&pool).await;
+    let mut context = page_context(&pool, &format!("Reply to {}", node.title)).await;
     context.insert("node", &node);
+    context.insert("show_subject", &show_subject);
+    let comment_length = CommentLength::load(&pool).await;
+    context.insert("comment_length", &comment_length);
+    context.insert("anonymous_contact", anonymous.value());
+    context.insert("comment_preview", CommentPreview::load(&pool).await.value());
     context.insert("current_user", &current_user);
     context.insert("pid", &0u32);
 
-    let html = tera.render("comment/form.html", &context)?;
+    let html = render(&tera, "comment/form.html", &context)?;
     Ok(Html(html))
 }
 
 /// POST /comment/reply/:nid - Submit a new comment
 pub async fn add_submit(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
@@ -81,11 +180,16 @@ pub async fn add_submit(
         return Err(AppError::Forbidden);
     }
 
-    let current_theme = get_default_theme(&pool).await;
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", &format!("Reply to {}", node.title));
+    let show_subject = Comment::subject_field_enabled(&pool, &node.node_type).await;
+    let anonymous = CommentAnonymous::load(&pool).await;
+    let mut context = page_context(&pool, &format!("Reply to {}", node.title)).await;
     context.insert("node", &node);
+    context.insert("show_subject", &show_subject);
+    let comment_length = CommentLength::load(&pool).await;
+    context.insert("comment_length", &comment_length);
+    context.insert("anonymous_contact", anonymous.value());
+    let preview = CommentPreview::load(&pool).await;
+    context.insert("comment_preview", preview.value());
     context.insert("current_user", &current_user);
     context.insert("form", &form);
     context.insert("pid", &0u32);
@@ -93,27 +197,40 @@ pub async fn add_submit(
     // Validation
     if form.comment.trim().is_empty() {
         context.insert("error", "Comment body is required");
-        let html = tera.render("comment/form.html", &context)?;
+        let html = render(&tera, "comment/form.html", &context)?;
         return Ok(Ok(Html(html)));
     }
 
-    // For anonymous users, name is required
-    if current_user.is_none() && form.name.as_ref().map(|n| n.trim().is_empty()).unwrap_or(true) {
-        context.insert("error", "Your name is required");
-        let html = tera.render("comment/form.html", &context)?;
+    if let Err(message) = comment_length.check(&form.comment) {
+        context.insert("error", &message);
+        let html = render(&tera, "comment/form.html", &context)?;
+        return Ok(Ok(Html(html)));
+    }
+
+    let contact = match current_user {
+        Some(_) => AnonymousContact::default(),
+        None => match AnonymousContact::from_form(&form, anonymous) {
+            Ok(contact) => contact,
+            Err(message) => {
+                context.insert("error", message);
+                let html = render(&tera, "comment/form.html", &context)?;
+                return Ok(Ok(Html(html)));
+            }
+        },
+    };
+
+    let subject = comment_subject(show_subject, &form);
+    if wants_preview(&form, preview) {
+        let author = preview_author(&pool, &current_user, &contact).await;
+        let homepage = homepage_link(contact.homepage.as_deref(), HomepageLinks::load(&pool).await);
+        insert_preview(&mut context, &subject, &form, &author, homepage);
+        let html = render(&tera, "comment/form.html", &context)?;
         return Ok(Ok(Html(html)));
     }
 
     let uid = current_user.as_ref().map(|u| u.uid).unwrap_or(0);
     let hostname = addr.ip().to_string();
 
-    // Use subject if provided, otherwise generate from comment
-    let subject = if form.subject.trim().is_empty() {
-        truncate_subject(&form.comment)
-    } else {
-        form.subject.clone()
-    };
-
     // Check if user can post without approval
     let status = if check_post_without_approval(&pool, &current_user).await? {
         COMMENT_PUBLISHED
@@ -129,19 +246,20 @@ pub async fn add_submit(
         &subject,
         &form.comment,
         &hostname,
-        form.name.as_deref(),
-        form.mail.as_deref(),
-        form.homepage.as_deref(),
+        contact.name.as_deref(),
+        contact.mail.as_deref(),
+        contact.homepage.as_deref(),
         status,
     )
-    .await?;
+    .await
+    .with_context(|| format!("saving comment on node {}", nid))?;
 
-    Ok(Err(Redirect::to(&format!("/node/{}#comment-{}", nid, cid))))
+    Ok(Err(saved_comment_redirect(nid, cid, status == COMMENT_PUBLISHED)))
 }
 
 /// GET /comment/reply/:cid/reply - Show reply form for a comment
 pub async fn reply_form(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
     Path(cid): Path<u32>,
@@ -163,22 +281,35 @@ pub async fn reply_form(
         return Err(AppError::Forbidden);
     }
 
-    let current_theme = get_default_theme(&pool).await;
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", &format!("Reply to comment"));
+    let reply_to = Comment::reply_parent(&pool, parent.clone(), Comment::max_depth(&pool).await).await?;
+
+    let show_subject = Comment::subject_field_enabled(&pool, &node.node_type).await;
+    let anonymous = CommentAnonymous::load(&pool).await;
+    let mut context = page_context(&pool, "Reply to comment").await;
     context.insert("node", &node);
-    context.insert("parent", &parent);
+    context.insert("show_subject", &show_subject);
+    let comment_length = CommentLength::load(&pool).await;
+    context.insert("comment_length", &comment_length);
+    context.insert("anonymous_contact", anonymous.value());
+    let preview = CommentPreview::load(&pool).await;
+    context.insert("comment_preview", preview.value());
+    let viewer = CommentViewer::load(&pool, current_user.as_ref()).await?;
+    if let Some(parent) = Comment::find_with_author(&pool, parent.cid).await? {
+        context.insert("parent", &viewer.public_comment(parent));
+    }
+    if reply_to.cid != parent.cid {
+        context.insert("reply_to_subject", &reply_to.subject);
+    }
     context.insert("current_user", &current_user);
-    context.insert("pid", &cid);
+    context.insert("pid", &reply_to.cid);
 
-    let html = tera.render("comment/form.html", &context)?;
+    let html = render(&tera, "comment/form.html", &context)?;
     Ok(Html(html))
 }
 
 /// POST /comment/reply/:cid/reply - Submit a reply to a comment
 pub async fn reply_submit(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
     ConnectInfo(addr): ConnectInfo<SocketAddr>,
@@ -202,38 +333,66 @@ pub async fn reply_submit(
         return Err(AppError::Forbidden);
     }
 
-    let current_theme = get_default_theme(&pool).await;
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", "Reply to comment");
+    let reply_to = Comment::reply_parent(&pool, parent.clone(), Comment::max_depth(&pool).await).await?;
+
+    let show_subject = Comment::subject_field_enabled(&pool, &node.node_type).await;
+    let anonymous = CommentAnonymous::load(&pool).await;
+    let mut context = page_context(&pool, "Reply to comment").await;
     context.insert("node", &node);
-    context.insert("parent", &parent);
+    context.insert("show_subject", &show_subject);
+    let comment_length = CommentLength::load(&pool).await;
+    context.insert("comment_length", &comment_length);
+    context.insert("anonymous_contact", anonymous.value());
+    let preview = CommentPreview::load(&pool).await;
+    context.insert("comment_preview", preview.value());
+    let viewer = CommentViewer::load(&pool, current_user.as_ref()).await?;
+    if let Some(parent) = Comment::find_with_author(&pool, parent.cid).await? {
+        context.insert("parent", &viewer.public_comment(parent));
+    }
+    if reply_to.cid != parent.cid {
+        context.insert("reply_to_subject", &reply_to.subject);
+    }
     context.insert("current_user", &current_user);
     context.insert("form", &form);
-    context.insert("pid", &cid);
+    context.insert("pid", &reply_to.cid);
 
     // Validation
     if form.comment.trim().is_empty() {
         context.insert("error", "Comment body is required");
-        let html = tera.render("comment/form.html", &context)?;
+        let html = render(&tera, "comment/form.html", &context)?;
+        return Ok(Ok(Html(html)));
+    }
+
+    if let Err(message) = comment_length.check(&form.comment) {
+        context.insert("error", &message);
+        let html = render(&tera, "comment/form.html", &context)?;
         return Ok(Ok(Html(html)));
     }
 
-    if current_user.is_none() && form.name.as_ref().map(|n| n.trim().is_empty()).unwrap_or(true) {
-        context.insert("error", "Your name is required");
-        let html = tera.render("comment/form.html", &context)?;
+    let contact = match current_user {
+        Some(_) => AnonymousContact::default(),
+        None => match AnonymousContact::from_form(&form, anonymous) {
+            Ok(contact) => contact,
+            Err(message) => {
+                context.insert("error", message);
+                let html = render(&tera, "comment/form.html", &context)?;
+                return Ok(Ok(Html(html)));
+            }
+        },
+    };
+
+    let subject = comment_subject(show_subject, &form);
+    if wants_preview(&form, preview) {
+        let author = preview_author(&pool, &current_user, &contact).await;
+        let homepage = homepage_link(contact.homepage.as_deref(), HomepageLinks::load(&pool).await);
+        insert_preview(&mut context, &subject, &form, &author, homepage);
+        let html = render(&tera, "comment/form.html", &context)?;
         return Ok(Ok(Html(html)));
     }
 
     let uid = current_user.as_ref().map(|u| u.uid).unwrap_or(0);
     let hostname = addr.ip().to_string();
 
-    let subject = if form.subject.trim().is_empty() {
-        truncate_subject(&form.comment)
-    } else {
-        form.subject.clone()
-    };
-
     let status = if check_post_without_approval(&pool, &current_user).await? {
         COMMENT_PUBLISHED
     } else {
@@ -243,27 +402,25 @@ pub async fn reply_submit(
     let new_cid = Comment::create(
         &pool,
         parent.nid,
-        cid, // pid = parent comment
+        reply_to.cid, // pid = parent comment, or its ancestor at the depth limit
         uid,
         &subject,
         &form.comment,
         &hostname,
-        form.name.as_deref(),
-        form.mail.as_deref(),
-        form.homepage.as_deref(),
+        contact.name.as_deref(),
+        contact.mail.as_deref(),
+        contact.homepage.as_deref(),
         status,
     )
-    .await?;
+    .await
+    .with_context(|| format!("saving reply to comment {}", cid))?;
 
-    Ok(Err(Redirect::to(&format!(
-        "/node/{}#comment-{}",
-        parent.nid, new_cid
-    ))))
+    Ok(Err(saved_comment_redirect(parent.nid, new_cid, status == COMMENT_PUBLISHED)))
 }
 
 /// GET /comment/:cid/edit - Show edit form
 pub async fn edit_form(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
     Path(cid): Path<u32>,
@@ -281,11 +438,12 @@ pub async fn edit_form(
         .await?
         .ok_or(AppError::NotFound)?;
 
-    let current_theme = get_default_theme(&pool).await;
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", "Edit comment");
+    let show_subject = Comment::subject_field_enabled(&pool, &node.node_type).await;
+    let mut context = page_context(&pool, "Edit comment").await;
     context.insert("node", &node);
+    context.insert("show_subject", &show_subject);
+    let comment_length = CommentLength::load(&pool).await;
+    context.insert("comment_length", &comment_length);
     context.insert("comment", &comment);
     context.insert("current_user", &current_user);
     context.insert("editing", &true);
@@ -297,16 +455,17 @@ pub async fn edit_form(
         name: comment.name.clone(),
         mail: comment.mail.clone(),
         homepage: comment.homepage.clone(),
+        op: String::new(),
     };
     context.insert("form", &form);
 
-    let html = tera.render("comment/form.html", &context)?;
+    let html = render(&tera, "comment/form.html", &context)?;
     Ok(Html(html))
 }
 
 /// POST /comment/:cid/edit - Submit edit
 pub async fn edit_submit(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
     Path(cid): Path<u32>,
@@ -325,11 +484,12 @@ pub async fn edit_submit(
         .await?
         .ok_or(AppError::NotFound)?;
 
-    let current_theme = get_default_theme(&pool).await;
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", "Edit comment");
+    let show_subject = Comment::subject_field_enabled(&pool, &node.node_type).await;
+    let mut context = page_context(&pool, "Edit comment").await;
     context.insert("node", &node);
+    context.insert("show_subject", &show_subject);
+    let comment_length = CommentLength::load(&pool).await;
+    context.insert("comment_length", &comment_length);
     context.insert("comment", &comment);
     context.insert("current_user", &current_user);
     context.insert("form", &form);
@@ -337,27 +497,28 @@ pub async fn edit_submit(
 
     if form.comment.trim().is_empty() {
         context.insert("error", "Comment body is required");
-        let html = tera.render("comment/form.html", &context)?;
+        let html = render(&tera, "comment/form.html", &context)?;
         return Ok(Ok(Html(html)));
     }
 
-    let subject = if form.subject.trim().is_empty() {
-        truncate_subject(&form.comment)
-    } else {
-        form.subject.clone()
-    };
+    if let Err(message) = comment_length.check(&form.comment) {
+        context.insert("error", &message);
+        let html = render(&tera, "comment/form.html", &context)?;
+        return Ok(Ok(Html(html)));
+    }
+
+    let subject = comment_subject(show_subject, &form);
 
     Comment::update(&pool, cid, &subject, &form.comment, comment.status).await?;
 
-    Ok(Err(Redirect::to(&format!(
-        "/node/{}#comment-{}",
-        comment.nid, cid
-    ))))
+    let visible = comment.status == COMMENT_PUBLISHED
+        || CommentListing::for_viewer(&pool, current_user.as_ref()).await? != CommentListing::Published;
+    Ok(Err(saved_comment_redirect(comment.nid, cid, visible)))
 }
 
 /// GET /comment/:cid/delete - Show delete confirmation
 pub async fn delete_confirm(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
     Path(cid): Path<u32>,
@@ -366,7 +527,7 @@ pub async fn delete_confirm(
         .await?
         .ok_or(AppError::NotFound)?;
 
-    let can_delete = check_delete_permission(&pool, &current_user, &comment).await?;
+    let can_delete = check_delete_permission(&pool, &current_user).await?;
     if !can_delete {
         return Err(AppError::Forbidden);
     }
@@ -375,21 +536,18 @@ pub async fn delete_confirm(
         .await?
         .ok_or(AppError::NotFound)?;
 
-    let current_theme = get_default_theme(&pool).await;
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", "Delete comment");
+    let mut context = page_context(&pool, "Delete comment").await;
     context.insert("node", &node);
     context.insert("comment", &comment);
     context.insert("current_user", &current_user);
 
-    let html = tera.render("comment/delete.html", &context)?;
+    let html = render(&tera, "comment/delete.html", &context)?;
     Ok(Html(html))
 }
 
 /// POST /comment/:cid/delete - Execute delete
 pub async fn delete_submit(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
     Path(cid): Path<u32>,
 ) -> AppResult<Redirect> {
@@ -397,7 +555,7 @@ pub async fn delete_submit(
         .await?
         .ok_or(AppError::NotFound)?;
 
-    let can_delete = check_delete_permission(&pool, &current_user, &comment).await?;
+    let can_delete = check_delete_permission(&pool, &current_user).await?;
     if !can_delete {
         return Err(AppError::Forbidden);
     }
@@ -411,26 +569,17 @@ pub async fn delete_submit(
 // Helper functions
 
 async fn check_post_permission(
-    pool: &MySqlPool,
+    pool: &Pool,
     current_user: &Option<crate::models::User>,
 ) -> Result<bool, sqlx::Error> {
     match current_user {
         Some(user) => user.has_permission(pool, "post comments").await,
-        None => {
-            // Check if anonymous role has post comments permission
-            let result: Option<(String,)> =
-                sqlx::query_as("SELECT perm FROM permission WHERE rid = 1")
-                    .fetch_optional(pool)
-                    .await?;
-            Ok(result
-                .map(|(perm,)| perm.contains("post comments"))
-                .unwrap_or(false))
-        }
+        None => User::anonymous().has_permission(pool, "post comments").await,
     }
 }
 
 async fn check_post_without_approval(
-    pool: &MySqlPool,
+    pool: &Pool,
     current_user: &Option<crate::models::User>,
 ) -> Result<bool, sqlx::Error> {
     match current_user {
@@ -446,8 +595,34 @@ async fn check_post_without_approval(
     }
 }
 
+/// What a viewer may do to other people's comments. "administer comments"
+/// grants all of it; otherwise editing, deleting and publishing or
+/// unpublishing each need their own permission.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub(crate) struct CommentPermissions {
+    pub edit_any: bool,
+    pub delete_any: bool,
+    pub moderate: bool,
+}
+
+impl CommentPermissions {
+    pub(crate) async fn load(pool: &Pool, viewer: Option<&User>) -> Result<Self, sqlx::Error> {
+        let Some(user) = viewer else {
+            return Ok(Self::default());
+        };
+        if user.has_permission(pool, "administer comments").await? {
+            return Ok(CommentPermissions { edit_any: true, delete_any: true, moderate: true });
+        }
+        Ok(CommentPermissions {
+            edit_any: user.has_permission(pool, "edit any comment").await?,
+            delete_any: user.has_permission(pool, "delete any comment").await?,
+            moderate: user.has_permission(pool, "moderate comments").await?,
+        })
+    }
+}
+
 async fn check_edit_permission(
-    pool: &MySqlPool,
+    pool: &Pool,
     current_user: &Option<crate::models::User>,
     comment: &Comment,
 ) -> Result<bool, sqlx::Error> {
@@ -455,37 +630,716 @@ async fn check_edit_permission(
         return Ok(false);
     };
 
-    // Admin can always edit
-    if user.has_permission(pool, "administer comments").await? {
-        return Ok(true);
-    }
-
     // Owner can edit own comments
     if user.uid == comment.uid && comment.uid != 0 {
         return Ok(true);
     }
 
-    Ok(false)
+    Ok(CommentPermissions::load(pool, Some(user)).await?.edit_any)
 }
 
 async fn check_delete_permission(
-    pool: &MySqlPool,
+    pool: &Pool,
     current_user: &Option<crate::models::User>,
-    _comment: &Comment,
 ) -> Result<bool, sqlx::Error> {
+    Ok(CommentPermissions::load(pool, current_user.as_ref()).await?.delete_any)
+}
+
+/// Contact details stored with an anonymous comment.
+#[derive(Debug, Default)]
+struct AnonymousContact {
+    name: Option<String>,
+    mail: Option<String>,
+    homepage: Option<String>,
+}
+
+impl AnonymousContact {
+    /// The details to store from an anonymous commenter's form, as
+    /// `comment_anonymous` allows or requires them. In "may not contact"
+    /// mode whatever was posted is dropped.
+    fn from_form(form: &CommentForm, mode: CommentAnonymous) -> Result<Self, &'static str> {
+        if mode == CommentAnonymous::Hidden {
+            return Ok(Self::default());
+        }
+
+        let field = |value: &Option<String>| {
+            value.as_deref().map(str::trim).filter(|v| !v.is_empty()).map(str::to_string)
+        };
+        let name = field(&form.name);
+        let mail = field(&form.mail);
+
+        if mode == CommentAnonymous::Required {
+            if name.is_none() {
+                return Err("Your name is required.");
+            }
+            if mail.is_none() {
+                return Err("Your e-mail address is required.");
+            }
+        }
+        if name.as_deref().is_some_and(|name| name.chars().count() > NAME_MAX_LENGTH) {
+            return Err("Your name is too long.");
+        }
+        if mail.as_deref().is_some_and(|mail| !is_valid_mail(mail)) {
+            return Err("The e-mail address is not valid.");
+        }
+
+        Ok(Self {
+            name,
+            mail,
+            homepage: validate_homepage(form.homepage.as_deref().unwrap_or(""))?,
+        })
+    }
+}
+
+/// Longest name the `comments.name` column holds.
+const NAME_MAX_LENGTH: usize = 60;
+
+/// Longest e-mail address the `comments.mail` column holds.
+const MAIL_MAX_LENGTH: usize = 64;
+
+/// A plausible address: one `@` with text before it and a dotted domain
+/// after it, without spaces.
+fn is_valid_mail(mail: &str) -> bool {
+    let Some((local, domain)) = mail.split_once('@') else {
+        return false;
+    };
+    mail.chars().count() <= MAIL_MAX_LENGTH
+        && !local.is_empty()
+        && !domain.contains('@')
+        && domain.contains('.')
+        && domain.split('.').all(|part| !part.is_empty())
+        && !mail.chars().any(|c| c.is_whitespace() || c.is_control())
+}
+
+/// Longest homepage accepted from an anonymous commenter.
+const HOMEPAGE_MAX_LENGTH: usize = 255;
+
+/// Check a homepage entered by an anonymous commenter. An address without
+/// a scheme is taken to be http; anything but http and https is refused.
+fn validate_homepage(value: &str) -> Result<Option<String>, &'static str> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(None);
+    }
+
+    let has_scheme = value
+        .split_once(':')
+        .is_some_and(|(scheme, _)| !scheme.is_empty() && scheme.chars().all(|c| c.is_ascii_alphabetic()));
+    let url = if has_scheme {
+        value.to_string()
+    } else {
+        format!("http://{}", value)
+    };
+    if url.len() > HOMEPAGE_MAX_LENGTH {
+        return Err("The homepage address is too long.");
+    }
+    if !is_web_url(&url) {
+        return Err("The homepage must be a valid http:// or https:// address.");
+    }
+    Ok(Some(url))
+}
+
+fn is_web_url(url: &str) -> bool {
+    let lower = url.to_ascii_lowercase();
+    let Some(rest) = lower
+        .strip_prefix("http://")
+        .or_else(|| lower.strip_prefix("https://"))
+    else {
+        return false;
+    };
+    let host = rest.split(['/', '?', '#']).next().unwrap_or("");
+    !host.is_empty()
+        && url.len() <= HOMEPAGE_MAX_LENGTH
+        && !url.chars().any(|c| c.is_whitespace() || c.is_control() || c == '"' || c == '<' || c == '>')
+}
+
+/// How anonymous commenters' homepages are linked, from the
+/// `comment_homepage_links` variable: "follow", "nofollow" (the default) or
+/// "none".
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HomepageLinks {
+    Follow,
+    NoFollow,
+    Disabled,
+}
+
+impl HomepageLinks {
+    pub async fn load(pool: &Pool) -> Self {
+        match Variable::get_or_default(pool, "comment_homepage_links", "nofollow")
+            .await
+            .as_str()
+        {
+            "follow" => HomepageLinks::Follow,
+            "none" => HomepageLinks::Disabled,
+            _ => HomepageLinks::NoFollow,
+        }
+    }
+}
+
+/// A commenter's homepage as the templates link it.
+#[derive(Debug, Clone, Serialize)]
+pub struct HomepageLink {
+    pub url: String,
+    pub nofollow: bool,
+}
+
+/// The link for a stored homepage, or `None` when it should be shown as
+/// plain text. Stored values are checked again, so addresses saved before
+/// validation existed can never produce a javascript: or oversized link.
+pub fn homepage_link(homepage: Option<&str>, links: HomepageLinks) -> Option<HomepageLink> {
+    if links == HomepageLinks::Disabled {
+        return None;
+    }
+    let url = homepage.map(str::trim).filter(|url| is_web_url(url))?;
+    Some(HomepageLink {
+        url: url.to_string(),
+        nofollow: links == HomepageLinks::NoFollow,
+    })
+}
+
+/// Who wrote a comment, as pages show them.
+#[derive(Debug, Clone, Serialize)]
+pub struct PublicUserRef {
+    /// 0 for an anonymous commenter.
+    pub uid: u32,
+    /// The account name, or the name an anonymous commenter left, or the
+    /// site's name for anonymous users.
+    pub name: String,
+    /// An anonymous commenter's homepage, when it may be linked.
+    pub homepage: Option<HomepageLink>,
+    pub picture: String,
+    /// Only filled in for viewers with "administer users".
+    pub mail: Option<String>,
+}
+
+/// A comment with only what its viewer may see. Pages get these instead of
+/// the stored rows, so the commenter's e-mail address and IP address never
+/// reach a template unless the viewer may see them.
+#[derive(Debug, Clone, Serialize)]
+pub struct PublicComment {
+    pub cid: u32,
+    pub pid: u32,
+    pub nid: u32,
+    pub subject: String,
+    pub comment: String,
+    pub timestamp: i32,
+    pub status: i32,
+    pub depth: i32,
+    pub author: PublicUserRef,
+    /// Only filled in for viewers with "administer users".
+    pub hostname: Option<String>,
+}
+
+/// What one viewer gets to see of comments, loaded once per page.
+pub struct CommentViewer {
+    show_private: bool,
+    homepage_links: HomepageLinks,
+    pictures: PictureSettings,
+    anonymous_name: String,
+}
+
+impl CommentViewer {
+    pub async fn load(pool: &Pool, viewer: Option<&User>) -> Result<Self, sqlx::Error> {
+        let show_private = match viewer {
+            Some(user) => user.has_permission(pool, "administer users").await?,
+            None => false,
+        };
+        Ok(Self {
+            show_private,
+            homepage_links: HomepageLinks::load(pool).await,
+            pictures: PictureSettings::load(pool).await,
+            anonymous_name: User::anonymous_name(pool).await,
+        })
+    }
+
+    pub fn public_comment(&self, comment: CommentWithAuthor) -> PublicComment {
+        let (name, homepage) = if comment.uid > 0 {
+            (comment.author_name.unwrap_or_else(|| self.anonymous_name.clone()), None)
+        } else {
+            let name = comment
+                .name
+                .filter(|name| !name.trim().is_empty())
+                .unwrap_or_else(|| self.anonymous_name.clone());
+            (name, homepage_link(comment.homepage.as_deref(), self.homepage_links))
+        };
+
+        PublicComment {
+            cid: comment.cid,
+            pid: comment.pid,
+            nid: comment.nid,
+            subject: comment.subject,
+            comment: comment.comment,
+            timestamp: comment.timestamp,
+            status: comment.status,
+            depth: comment.depth,
+            author: PublicUserRef {
+                uid: comment.uid,
+                name,
+                homepage,
+                picture: self.pictures.url(&comment.author_picture),
+                mail: comment.mail.filter(|_| self.show_private),
+            },
+            hostname: Some(comment.hostname).filter(|_| self.show_private),
+        }
+    }
+}
+
+/// Characters the `comments.subject` column holds.
+const SUBJECT_MAX_LENGTH: usize = 64;
+
+/// Characters of the comment body used for a derived subject.
+const DERIVED_SUBJECT_LENGTH: usize = 60;
+
+/// Where to send the author once a comment is saved: to its permalink, or,
+/// when the comment awaits approval and the permalink would not find it
+/// for them, to the node with a note saying so.
+#[derive(Debug, Deserialize)]
+pub struct CommentStatusForm {
+    /// "publish" or "unpublish".
+    pub action: String,
+}
+
+/// POST /comment/:cid/status - Publish or unpublish a comment, for
+/// moderators who may not edit or delete it.
+pub async fn status_submit(
+    State(pool): State<Pool>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Path(cid): Path<u32>,
+    Form(form): Form<CommentStatusForm>,
+) -> AppResult<Redirect> {
     let Some(user) = current_user else {
-        return Ok(false);
+        return Err(AppError::Unauthorized);
     };
 
-    // Only admins can delete
-    user.has_permission(pool, "administer comments").await
+    if !CommentPermissions::load(&pool, Some(&user)).await?.moderate {
+        return Err(AppError::Forbidden);
+    }
+
+    let comment = Comment::find_by_cid(&pool, cid)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let status = match form.action.as_str() {
+        "publish" => COMMENT_PUBLISHED,
+        "unpublish" => COMMENT_NOT_PUBLISHED,
+        _ => return Err(AppError::BadRequest(format!("Unknown comment action '{}'", form.action))),
+    };
+    if comment.status != status {
+        Comment::set_status(&pool, cid, status).await?;
+    }
+
+    Ok(Redirect::to(&format!("/comment/{}", cid)))
 }
 
-fn truncate_subject(text: &str) -> String {
-    let clean = text.trim();
-    if clean.len() <= 60 {
-        clean.to_string()
+fn saved_comment_redirect(nid: u32, cid: u32, visible: bool) -> Redirect {
+    if visible {
+        Redirect::to(&format!("/comment/{}", cid))
     } else {
-        format!("{}...", &clean[..57])
+        Redirect::to(&format!("/node/{}?comment=queued#comments", nid))
+    }
+}
+
+/// Whether a submitted comment is shown for preview instead of being saved.
+/// With previews required, only the "save" button offered alongside a
+/// preview stores the comment.
+fn wants_preview(form: &CommentForm, preview: CommentPreview) -> bool {
+    match form.op.as_str() {
+        "preview" => true,
+        "save" => false,
+        _ => preview == CommentPreview::Required,
+    }
+}
+
+/// The name a previewed comment is shown under.
+async fn preview_author(pool: &Pool, current_user: &Option<User>, contact: &AnonymousContact) -> String {
+    match (current_user, &contact.name) {
+        (Some(user), _) => user.name.clone(),
+        (None, Some(name)) => name.clone(),
+        (None, None) => User::anonymous_name(pool).await,
+    }
+}
+
+/// A comment as it would be stored, shown above the form for preview.
+#[derive(Serialize)]
+struct PreviewComment<'a> {
+    subject: &'a str,
+    comment: &'a str,
+    author: &'a str,
+    homepage: Option<HomepageLink>,
+}
+
+/// Add the comment as it would be stored to `context`, so the form shows
+/// it above the fields with the option to save it.
+fn insert_preview(
+    context: &mut tera::Context,
+    subject: &str,
+    form: &CommentForm,
+    author: &str,
+    homepage: Option<HomepageLink>,
+) {
+    let preview = PreviewComment {
+        subject,
+        comment: &form.comment,
+        author,
+        homepage,
+    };
+    context.insert("preview", &preview);
+}
+
+/// The subject to store: the submitted one when the subject field is shown
+/// and filled in, otherwise one derived from the comment body. Either way
+/// it is a single line that fits the column.
+fn comment_subject(show_subject: bool, form: &CommentForm) -> String {
+    let subject = truncate_subject(&form.subject, SUBJECT_MAX_LENGTH);
+    if show_subject && !subject.is_empty() {
+        subject
+    } else {
+        truncate_subject(&plain_text(&form.comment), DERIVED_SUBJECT_LENGTH)
+    }
+}
+
+/// Strip markup and entities from `text` and collapse runs of whitespace, so
+/// a subject derived from an HTML body reads as plain text.
+fn plain_text(text: &str) -> String {
+    let mut stripped = String::with_capacity(text.len());
+    let mut in_tag = false;
+    for c in text.chars() {
+        match c {
+            '<' => {
+                in_tag = true;
+                stripped.push(' ');
+            }
+            '>' if in_tag => in_tag = false,
+            _ if !in_tag => stripped.push(c),
+            _ => {}
+        }
+    }
+
+    let decoded = stripped
+        .replace("&nbsp;", " ")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&#39;", "'")
+        .replace("&amp;", "&");
+
+    decoded.split_whitespace().collect::<Vec<_>>().join(" ")
+}
+
+/// `text` on one line with whitespace collapsed, cut to at most `max_chars`
+/// characters. Counts and cuts whole characters, never bytes, so multibyte
+/// text is safe; an ellipsis is added only when something was cut off.
+fn truncate_subject(text: &str, max_chars: usize) -> String {
+    let clean = text.split_whitespace().collect::<Vec<_>>().join(" ");
+    if clean.chars().count() <= max_chars {
+        return clean;
+    }
+    let truncated: String = clean.chars().take(max_chars.saturating_sub(3)).collect();
+    format!("{}...", truncated.trim_end())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::response::IntoResponse;
+    use crate::test_support::{
+        create_test_comment, create_test_node, create_test_role, create_test_user, test_site, test_tera,
+    };
+
+    /// Where the permalink of comment `cid` sends `user`.
+    async fn permalink_target(pool: &Pool, user: Option<User>, cid: u32) -> AppResult<String> {
+        let redirect = permalink(State(pool.clone()), Extension(CurrentUser(user)), Path(cid)).await?;
+        let response = redirect.into_response();
+        Ok(response.headers()["location"].to_str().unwrap().to_string())
+    }
+
+    /// Whether `user` may open the edit form, open the delete confirmation
+    /// and unpublish comment `cid`, in that order.
+    async fn allowed_actions(pool: &Pool, user: &User, cid: u32) -> (bool, bool, bool) {
+        let as_user = || (State(pool.clone()), Extension(CurrentUser(Some(user.clone()))), Path(cid));
+
+        let (p, u, c) = as_user();
+        let edit = edit_form(p, State(test_tera()), u, c).await;
+        let (p, u, c) = as_user();
+        let delete = delete_confirm(p, State(test_tera()), u, c).await;
+        let (p, u, c) = as_user();
+        let unpublish = status_submit(p, u, c, Form(CommentStatusForm { action: "unpublish".to_string() })).await;
+
+        (
+            !matches!(edit, Err(AppError::Forbidden)),
+            !matches!(delete, Err(AppError::Forbidden)),
+            !matches!(unpublish, Err(AppError::Forbidden)),
+        )
+    }
+
+    #[test]
+    fn truncate_subject_cuts_on_character_boundaries() {
+        // Two-byte characters from the start, so byte 57 falls inside one.
+        let text = "é".repeat(80);
+        assert!(!text.is_char_boundary(57));
+        let subject = truncate_subject(&text, DERIVED_SUBJECT_LENGTH);
+        assert_eq!(subject, format!("{}...", "é".repeat(57)));
+        assert_eq!(subject.chars().count(), DERIVED_SUBJECT_LENGTH);
+    }
+
+    #[test]
+    fn truncate_subject_handles_emoji_and_cjk() {
+        for text in ["😀".repeat(70), "漢字".repeat(35), format!("a{}", "😀".repeat(70))] {
+            let subject = truncate_subject(&text, DERIVED_SUBJECT_LENGTH);
+            assert!(subject.chars().count() <= DERIVED_SUBJECT_LENGTH, "{}", subject);
+            assert!(subject.ends_with("..."));
+            assert!(text.starts_with(subject.trim_end_matches("...")));
+        }
+    }
+
+    #[test]
+    fn truncate_subject_only_adds_an_ellipsis_when_cutting() {
+        let exact = "x".repeat(DERIVED_SUBJECT_LENGTH);
+        assert_eq!(truncate_subject(&exact, DERIVED_SUBJECT_LENGTH), exact);
+
+        let subject = truncate_subject(&format!("{}y", exact), DERIVED_SUBJECT_LENGTH);
+        assert_eq!(subject, format!("{}...", "x".repeat(DERIVED_SUBJECT_LENGTH - 3)));
+
+        assert_eq!(truncate_subject("短い", DERIVED_SUBJECT_LENGTH), "短い");
+    }
+
+    #[test]
+    fn truncate_subject_keeps_to_one_line() {
+        assert_eq!(truncate_subject("  first\nsecond\r\n\tthird  ", 60), "first second third");
+        assert_eq!(truncate_subject(" \n ", 60), "");
+    }
+
+    fn comment_form(subject: &str, comment: &str) -> CommentForm {
+        CommentForm {
+            subject: subject.to_string(),
+            comment: comment.to_string(),
+            name: None,
+            mail: None,
+            homepage: None,
+            op: String::new(),
+        }
+    }
+
+    #[test]
+    fn comment_subject_is_derived_from_plain_text() {
+        let body = "<p>Hello <strong>there,</strong>\n\n  friend &amp; neighbour</p>";
+        let form = comment_form("Typed", body);
+        assert_eq!(comment_subject(true, &form), "Typed");
+        assert_eq!(comment_subject(false, &form), "Hello there, friend & neighbour");
+        assert_eq!(comment_subject(true, &comment_form("  ", body)), "Hello there, friend & neighbour");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn hidden_subject_field_is_left_out_and_derived() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let author = create_test_user(pool, &[]).await;
+        let node = create_test_node(pool, "story", &author, true).await;
+        let comment = create_test_comment(pool, node.nid, 0, &author).await;
+        let as_author = || (State(pool.clone()), State(test_tera()), Extension(CurrentUser(Some(author.clone()))));
+
+        for (shown, expected) in [("1", "Typed"), ("0", "Derived from the body")] {
+            Variable::set(pool, "comment_subject_field_story", shown).await.unwrap();
+
+            let (p, t, u) = as_author();
+            let Html(page) = edit_form(p, t, u, Path(comment.cid)).await.unwrap();
+            assert_eq!(page.contains(r#"name="subject""#), shown == "1");
+
+            let (p, t, u) = as_author();
+            let saved = edit_submit(p, t, u, Path(comment.cid), Form(comment_form("Typed", "Derived from the body"))).await;
+            assert!(matches!(saved, Ok(Err(_))));
+            let saved = Comment::find_by_cid(pool, comment.cid).await.unwrap().unwrap();
+            assert_eq!(saved.subject, expected);
+        }
+    }
+
+    #[test]
+    fn homepages_must_be_web_addresses() {
+        assert_eq!(validate_homepage(""), Ok(None));
+        assert_eq!(validate_homepage(" example.org/me "), Ok(Some("http://example.org/me".to_string())));
+        assert_eq!(validate_homepage("HTTPS://example.org"), Ok(Some("HTTPS://example.org".to_string())));
+        for refused in ["javascript:alert(1)", "JavaScript:alert(1)", "data:text/html,hi", "http://", "http://a b"] {
+            assert!(validate_homepage(refused).is_err(), "{} was accepted", refused);
+        }
+    }
+
+    #[test]
+    fn homepage_links_are_nofollow_by_default() {
+        let link = |homepage: &str, links| homepage_link(Some(homepage), links).map(|link| (link.url, link.nofollow));
+        let url = "http://example.org".to_string();
+
+        assert_eq!(link("http://example.org", HomepageLinks::NoFollow), Some((url.clone(), true)));
+        assert_eq!(link(" http://example.org ", HomepageLinks::Follow), Some((url, false)));
+        assert_eq!(link("http://example.org", HomepageLinks::Disabled), None);
+        // Stored before validation existed.
+        assert_eq!(link("javascript:alert(1)", HomepageLinks::Follow), None);
+        assert_eq!(link("http://example.org/\"><script>", HomepageLinks::Follow), None);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn recent_comments_link_homepages_with_nofollow() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let author = create_test_user(pool, &[]).await;
+        let node = create_test_node(pool, "story", &author, true).await;
+        for homepage in ["http://visitor.example.org", "javascript:alert(1)"] {
+            Comment::create(
+                pool,
+                node.nid,
+                0,
+                0,
+                "Hi",
+                "Hello.",
+                "10.0.0.1",
+                Some("Visitor"),
+                None,
+                Some(homepage),
+                COMMENT_PUBLISHED,
+            )
+            .await
+            .unwrap();
+        }
+
+        let query = Query(PageQuery::default());
+        let Html(page) = recent(State(pool.clone()), State(test_tera()), Extension(CurrentUser(None)), query).await.unwrap();
+        assert!(page.contains(r#"<a href="http://visitor.example.org" rel="nofollow">Visitor</a>"#), "{}", page);
+        assert!(!page.contains("javascript:"));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn permalink_points_at_the_comment_on_its_node() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let author = create_test_user(pool, &[]).await;
+        let node = create_test_node(pool, "story", &author, true).await;
+        let comment = create_test_comment(pool, node.nid, 0, &author).await;
+
+        assert_eq!(
+            permalink_target(pool, None, comment.cid).await.unwrap(),
+            format!("/node/{}#comment-{}", node.nid, comment.cid)
+        );
+        assert!(matches!(permalink_target(pool, None, comment.cid + 1).await, Err(AppError::NotFound)));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn permalink_hides_comments_on_unpublished_nodes() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let author = create_test_user(pool, &[]).await;
+        let other = create_test_user(pool, &[]).await;
+        let node = create_test_node(pool, "story", &author, false).await;
+        let comment = create_test_comment(pool, node.nid, 0, &author).await;
+
+        for viewer in [None, Some(other)] {
+            assert!(matches!(permalink_target(pool, viewer, comment.cid).await, Err(AppError::NotFound)));
+        }
+        assert_eq!(
+            permalink_target(pool, Some(author), comment.cid).await.unwrap(),
+            format!("/node/{}#comment-{}", node.nid, comment.cid)
+        );
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn each_comment_permission_grants_only_its_action() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let author = create_test_user(pool, &[]).await;
+        let node = create_test_node(pool, "story", &author, true).await;
+        let comment = create_test_comment(pool, node.nid, 0, &author).await;
+
+        for (permissions, expected) in [
+            (vec![], (false, false, false)),
+            (vec!["edit any comment"], (true, false, false)),
+            (vec!["delete any comment"], (false, true, false)),
+            (vec!["moderate comments"], (false, false, true)),
+            (vec!["administer comments"], (true, true, true)),
+        ] {
+            let role = create_test_role(pool, &permissions).await;
+            let user = create_test_user(pool, &[&role.name]).await;
+            assert_eq!(allowed_actions(pool, &user, comment.cid).await, expected, "{:?}", permissions);
+        }
+
+        // Owners edit their own comments without any of them.
+        assert_eq!(allowed_actions(pool, &author, comment.cid).await, (true, false, false));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn replies_past_the_depth_limit_attach_higher_up() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        Variable::set(pool, "comment_max_depth", "2").await.unwrap();
+        let role = create_test_role(pool, &["post comments", "post comments without approval"]).await;
+        let author = create_test_user(pool, &[&role.name]).await;
+        let node = create_test_node(pool, "story", &author, true).await;
+        let top = create_test_comment(pool, node.nid, 0, &author).await;
+        let middle = create_test_comment(pool, node.nid, top.cid, &author).await;
+        let deepest = create_test_comment(pool, node.nid, middle.cid, &author).await;
+        assert_eq!(deepest.depth(), 2);
+
+        let author = &author;
+        let reply_to = |cid: u32| async move {
+            let saved = reply_submit(
+                State(pool.clone()),
+                State(test_tera()),
+                Extension(CurrentUser(Some(author.clone()))),
+                ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 0))),
+                Path(cid),
+                Form(comment_form("", "A reply.")),
+            )
+            .await;
+            let Ok(Err(redirect)) = saved else { panic!("the reply was not saved") };
+            let response = redirect.into_response();
+            let location = response.headers()["location"].to_str().unwrap();
+            let cid = location.trim_start_matches("/comment/").parse().unwrap();
+            Comment::find_by_cid(pool, cid).await.unwrap().unwrap()
+        };
+
+        let reply = reply_to(middle.cid).await;
+        assert_eq!((reply.pid, reply.depth()), (middle.cid, 2), "replies within the limit stay put");
+
+        let reply = reply_to(deepest.cid).await;
+        assert_eq!(reply.pid, middle.cid, "re-parented to the comment at the limit's parent");
+        assert_eq!(reply.depth(), 2);
+        assert!(reply.thread.starts_with(middle.thread.trim_end_matches('/')));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn comments_outside_the_length_limits_are_sent_back() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        Variable::set(pool, "comment_minlength", "10").await.unwrap();
+        Variable::set(pool, "comment_maxlength", "20").await.unwrap();
+        let author = create_test_user(pool, &[]).await;
+        let node = create_test_node(pool, "story", &author, true).await;
+        let comment = create_test_comment(pool, node.nid, 0, &author).await;
+        let edit = |text: &str| {
+            edit_submit(
+                State(pool.clone()),
+                State(test_tera()),
+                Extension(CurrentUser(Some(author.clone()))),
+                Path(comment.cid),
+                Form(comment_form("", text)),
+            )
+        };
+
+        for (text, error) in [("Too short", "too short"), ("Much, much too long here", "too long")] {
+            let Ok(Ok(Html(page))) = edit(text).await else {
+                panic!("{:?} was saved", text);
+            };
+            assert!(page.contains(error), "{}", page);
+            assert!(page.contains(text), "the entered text is kept");
+        }
+        let unchanged = Comment::find_by_cid(pool, comment.cid).await.unwrap().unwrap();
+        assert_eq!(unchanged.comment, comment.comment);
+
+        assert!(matches!(edit("Just about right").await, Ok(Err(_))));
+        let saved = Comment::find_by_cid(pool, comment.cid).await.unwrap().unwrap();
+        assert_eq!(saved.comment, "Just about right");
     }
 }
@@ -0,0 +1,145 @@
+use axum::{
+    extract::{ConnectInfo, State},
+    response::Html,
+    Extension, Form,
+};
+use serde::{Deserialize, Serialize};
+use std::net::SocketAddr;
+use tera::Tera;
+
+use crate::{
+    auth::middleware::CurrentUser,
+    db::Pool,
+    error::{AppError, AppResult, ResultExt},
+    handlers::page_context,
+    models::{ContactMessage, User, Variable},
+    templates,
+};
+
+/// Messages one host may send per hour unless `contact_hourly_threshold`
+/// says otherwise.
+const DEFAULT_HOURLY_THRESHOLD: i64 = 3;
+
+const HOUR: i64 = 3600;
+
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct ContactForm {
+    #[serde(default)]
+    pub name: String,
+    #[serde(default)]
+    pub mail: String,
+    #[serde(default)]
+    pub subject: String,
+    #[serde(default)]
+    pub message: String,
+}
+
+/// Logged-in users send as their account; everyone else may only use the
+/// form when `contact_require_login` is off.
+async fn check_access(pool: &Pool, current_user: &Option<User>) -> AppResult<()> {
+    if current_user.is_none()
+        && Variable::get_or_default(pool, "contact_require_login", "0").await == "1"
+    {
+        return Err(AppError::Unauthorized);
+    }
+    Ok(())
+}
+
+async fn render(
+    pool: &Pool,
+    tera: &Tera,
+    current_user: &Option<User>,
+    form: &ContactForm,
+    error: Option<&str>,
+    message: Option<&str>,
+) -> AppResult<Html<String>> {
+    let mut context = page_context(pool, "Contact").await;
+    context.insert("current_user", current_user);
+    context.insert("form", form);
+    if let Some(error) = error {
+        context.insert("error", error);
+    }
+    if let Some(message) = message {
+        context.insert("message", message);
+    }
+
+    let html = templates::render(tera, "contact/form.html", &context)?;
+    Ok(Html(html))
+}
+
+/// GET /contact - Site-wide contact form
+pub async fn form(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+) -> AppResult<Html<String>> {
+    check_access(&pool, &current_user).await?;
+    render(&pool, &tera, &current_user, &ContactForm::default(), None, None).await
+}
+
+/// POST /contact - Record a message sent through the contact form
+pub async fn submit(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
+    Form(form): Form<ContactForm>,
+) -> AppResult<Html<String>> {
+    check_access(&pool, &current_user).await?;
+
+    let hostname = addr.ip().to_string();
+    let threshold = Variable::get_or_default(&pool, "contact_hourly_threshold", "3")
+        .await
+        .parse()
+        .unwrap_or(DEFAULT_HOURLY_THRESHOLD);
+    if ContactMessage::count_recent(&pool, &hostname, HOUR).await? >= threshold {
+        let error = format!(
+            "You cannot send more than {} messages per hour. Please try again later.",
+            threshold
+        );
+        return render(&pool, &tera, &current_user, &form, Some(&error), None).await;
+    }
+
+    // Anonymous senders identify themselves, as they do when commenting.
+    let (uid, name, mail) = match &current_user {
+        Some(user) => (user.uid, user.name.clone(), user.mail.clone().unwrap_or_default()),
+        None => (0, form.name.trim().to_string(), form.mail.trim().to_string()),
+    };
+
+    let error = if name.is_empty() {
+        Some("Your name is required")
+    } else if mail.is_empty() || !mail.contains('@') {
+        Some("A valid e-mail address is required")
+    } else if form.subject.trim().is_empty() {
+        Some("Subject is required")
+    } else if form.message.trim().is_empty() {
+        Some("Message is required")
+    } else {
+        None
+    };
+    if let Some(error) = error {
+        return render(&pool, &tera, &current_user, &form, Some(error), None).await;
+    }
+
+    ContactMessage::create(
+        &pool,
+        uid,
+        &name,
+        &mail,
+        form.subject.trim(),
+        &form.message,
+        &hostname,
+    )
+    .await
+    .context("saving contact message")?;
+
+    render(
+        &pool,
+        &tera,
+        &current_user,
+        &ContactForm::default(),
+        None,
+        Some("Your message has been sent."),
+    )
+    .await
+}
@@ -0,0 +1,28 @@
+use axum::extract::{Path, State};
+
+use crate::{
+    cron,
+    db::Pool,
+    error::{AppError, AppResult},
+};
+
+/// GET /cron/:key - Run cron for an external scheduler, e.g.
+/// `curl https://example.com/cron/<key>` from a crontab. The key is shown
+/// on the status report.
+pub async fn run(State(pool): State<Pool>, Path(key): Path<String>) -> AppResult<&'static str> {
+    let expected = cron::key(&pool).await?;
+    if key.len() != expected.len()
+        || !key
+            .bytes()
+            .zip(expected.bytes())
+            .fold(true, |equal, (a, b)| equal & (a == b))
+    {
+        return Err(AppError::Forbidden);
+    }
+
+    if cron::run_cron(&pool).await? {
+        Ok("Cron ran successfully.")
+    } else {
+        Ok("Cron is already running.")
+    }
+}
@@ -0,0 +1,163 @@
+use axum::{
+    extract::{Path, State},
+    http::{header, HeaderMap},
+    response::{IntoResponse, Response},
+    Json,
+};
+
+use crate::{
+    db::Pool,
+    error::{AppError, AppResult},
+    feed::{self, FeedItem},
+    filter,
+    handlers::home,
+    models::{
+        Alias, Node, NodeListFilter, NodeListItem, NodeListQuery, NodeListSort, NodeType, SiteSettings, User,
+        Variable, NOMINATION_APPROVED,
+    },
+};
+
+/// What an item's description holds, from the `feed_item_length` variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ItemLength {
+    Title,
+    Teaser,
+    Fulltext,
+}
+
+impl ItemLength {
+    async fn load(pool: &Pool) -> Self {
+        match Variable::get_or_default(pool, "feed_item_length", "teaser").await.as_str() {
+            "title" => ItemLength::Title,
+            "fulltext" => ItemLength::Fulltext,
+            _ => ItemLength::Teaser,
+        }
+    }
+
+    fn text(self, node: &NodeListItem) -> String {
+        let teaser = node.teaser.as_deref().filter(|t| !t.is_empty());
+        let body = node.body.as_deref();
+        match self {
+            ItemLength::Title => String::new(),
+            ItemLength::Teaser => teaser.or(body).unwrap_or_default().to_string(),
+            ItemLength::Fulltext => body.or(teaser).unwrap_or_default().to_string(),
+        }
+    }
+}
+
+/// Number of items per feed, from `feed_item_limit`.
+async fn item_limit(pool: &Pool) -> i64 {
+    Variable::get_or_default(pool, "feed_item_limit", "10")
+        .await
+        .parse::<i64>()
+        .unwrap_or(10)
+        .max(0)
+}
+
+/// Turn nodes into feed items with absolute links and filtered descriptions.
+async fn feed_items(
+    pool: &Pool,
+    nodes: Vec<NodeListItem>,
+    base_url: &str,
+) -> AppResult<Vec<FeedItem>> {
+    let length = ItemLength::load(pool).await;
+    let anonymous_name = User::anonymous_name(pool).await;
+
+    let mut items = Vec::with_capacity(nodes.len());
+    for node in nodes {
+        let format = filter::resolve_format(pool, node.format, &node.node_type).await;
+        let src = format!("node/{}", node.nid);
+        let path = Alias::lookup(pool, &src).await?.unwrap_or(src);
+        let description = filter::check_markup(&length.text(&node), format);
+        let author = match node.author_name {
+            Some(name) if node.uid > 0 && !name.is_empty() => name,
+            _ => anonymous_name.clone(),
+        };
+
+        items.push(FeedItem {
+            title: node.title,
+            link: format!("{}/{}", base_url, path),
+            description,
+            author: Some(author),
+            timestamp: node.created as i64,
+            guid: format!("{} at {}", node.nid, base_url),
+        });
+    }
+    Ok(items)
+}
+
+fn rss_response(xml: String) -> Response {
+    ([(header::CONTENT_TYPE, feed::RSS_CONTENT_TYPE)], xml).into_response()
+}
+
+/// GET /rss.xml - RSS feed of the promoted nodes shown on the front page.
+pub async fn front_page(State(pool): State<Pool>, headers: HeaderMap) -> AppResult<Response> {
+    // Anonymous, like the feed's readers.
+    let query = home::front_page_query(&None);
+    let nodes = Node::list(&pool, &query, item_limit(&pool).await, 0).await?;
+
+    let settings = SiteSettings::load(&pool).await;
+    let base_url = feed::base_url(&pool, &headers).await;
+    let items = feed_items(&pool, nodes, &base_url).await?;
+
+    let xml = feed::render_channel(&items, &settings.site_name, &base_url, &settings.site_slogan);
+    Ok(rss_response(xml))
+}
+
+/// GET /node/feed/:type - RSS feed of the latest published posts of one
+/// content type.
+pub async fn node_type(
+    State(pool): State<Pool>,
+    Path(type_name): Path<String>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    let node_type = NodeType::find_by_type(&pool, &type_name)
+        .await?
+        .ok_or(AppError::NotFound)?;
+    let limit = item_limit(&pool).await;
+    let nodes = Node::find_published_by_type(&pool, &node_type.type_name, limit).await?;
+
+    let site_name = SiteSettings::load(&pool).await.site_name;
+    let base_url = feed::base_url(&pool, &headers).await;
+    let items = feed_items(&pool, nodes, &base_url).await?;
+
+    let title = format!("{} - {}", node_type.name, site_name);
+    let description = node_type.description.as_deref().unwrap_or_default();
+    let xml = feed::render_channel(&items, &title, &base_url, description);
+    Ok(rss_response(xml))
+}
+
+/// Published posts promoted through an approved nomination, most recently
+/// approved first.
+async fn promoted_items(pool: &Pool, headers: &HeaderMap) -> AppResult<(Vec<FeedItem>, String)> {
+    let query = NodeListQuery {
+        filter: NodeListFilter {
+            status: Some(1),
+            promoted: Some(true),
+            nomination: Some(NOMINATION_APPROVED),
+            ..Default::default()
+        },
+        sort: NodeListSort::NominationDecided,
+        ..Default::default()
+    };
+    let nodes = Node::list(pool, &query, item_limit(pool).await, 0).await?;
+    let base_url = feed::base_url(pool, headers).await;
+    let items = feed_items(pool, nodes, &base_url).await?;
+    Ok((items, base_url))
+}
+
+/// GET /promoted/rss.xml - RSS feed of the posts editors picked for the
+/// front page, for the newsletter.
+pub async fn promoted(State(pool): State<Pool>, headers: HeaderMap) -> AppResult<Response> {
+    let (items, base_url) = promoted_items(&pool, &headers).await?;
+    let site_name = SiteSettings::load(&pool).await.site_name;
+    let title = format!("Editors' picks - {}", site_name);
+    let xml = feed::render_channel(&items, &title, &base_url, "Posts recently picked for the front page");
+    Ok(rss_response(xml))
+}
+
+/// GET /promoted.json - The same items as `/promoted/rss.xml`, as JSON.
+pub async fn promoted_json(State(pool): State<Pool>, headers: HeaderMap) -> AppResult<Json<Vec<FeedItem>>> {
+    let (items, _) = promoted_items(&pool, &headers).await?;
+    Ok(Json(items))
+}
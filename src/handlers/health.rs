@@ -0,0 +1,12 @@
+use axum::extract::State;
+
+use crate::db::Pool;
+use crate::error::AppResult;
+
+/// GET /health - For load balancers: "OK" while the database answers, a 503
+/// with Retry-After while it cannot be reached, so traffic is sent elsewhere
+/// during an outage.
+pub async fn check(State(pool): State<Pool>) -> AppResult<&'static str> {
+    sqlx::query("SELECT 1").execute(&pool).await?;
+    Ok("OK")
+}
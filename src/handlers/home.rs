@@ -1,38 +1,162 @@
 use axum::{extract::State, response::Html, Extension};
-use sqlx::MySqlPool;
+use std::collections::HashMap;
+use std::time::Duration;
 use tera::Tera;
 
 use crate::{
     auth::middleware::CurrentUser,
-    db::migrations::is_installed,
+    blocks,
+    cache::{self, TAG_FRONT_PAGE, TAG_VARIABLE},
+    db::{migrations::is_installed, Pool},
     error::AppResult,
-    models::{get_default_theme, Node, Variable},
+    filter,
+    handlers::{node::teaser_links, page_context},
+    image_styles::ImageDisplay,
+    models::{first_image, get_fields_with_values, Node, NodeListFilter, NodeListQuery, NodeType, User, Variable},
+    templates::render,
 };
 
+/// Published, promoted nodes, sticky ones first, newest first.
+pub fn front_page_query(current_user: &Option<User>) -> NodeListQuery {
+    NodeListQuery {
+        filter: NodeListFilter {
+            promoted: Some(true),
+            status: Some(1),
+            ..Default::default()
+        },
+        sticky_first: true,
+        viewer: current_user.as_ref().map(|user| user.uid),
+        ..Default::default()
+    }
+}
+
+const FRONT_PAGE_CACHE_BIN: &str = "page";
+const FRONT_PAGE_CACHE_KEY: &str = "front";
+
+/// How long the front page is cached for anonymous visitors: the
+/// `cache_front_page_ttl` variable in seconds. `None` (the default, 0)
+/// disables the cache.
+async fn front_page_ttl(pool: &Pool) -> Option<Duration> {
+    Variable::get_or_default(pool, "cache_front_page_ttl", "0")
+        .await
+        .parse()
+        .ok()
+        .filter(|&secs: &u64| secs > 0)
+        .map(Duration::from_secs)
+}
+
 pub async fn index(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
 ) -> AppResult<Html<String>> {
     let installed = is_installed(&pool).await.unwrap_or(false);
 
-    let nodes = if installed {
-        Node::find_promoted(&pool, 10).await?
+    // Every anonymous visitor gets the same front page, so it can be served
+    // from the cache; logged-in users see their own name and new markers.
+    let cache_ttl = if installed && current_user.is_none() {
+        front_page_ttl(&pool).await
+    } else {
+        None
+    };
+    if cache_ttl.is_some() {
+        if let Some(html) = cache::cache_get::<String>(FRONT_PAGE_CACHE_BIN, FRONT_PAGE_CACHE_KEY) {
+            return Ok(Html(html));
+        }
+    }
+
+    let mut nodes = if installed {
+        Node::list(&pool, &front_page_query(&current_user), 10, 0).await?
+    } else {
+        vec![]
+    };
+    for node in &mut nodes {
+        node.format = filter::resolve_format(&pool, node.format, &node.node_type).await;
+    }
+
+    let teaser_links = if installed {
+        teaser_links(&pool, &current_user, &nodes).await?
     } else {
         vec![]
     };
 
-    let site_name = Variable::get_or_default(&pool, "site_name", "Drupal").await;
-    let current_theme = get_default_theme(&pool).await;
+    // Whether each teaser shows its byline, looked up once per content type.
+    let mut bylines: HashMap<String, bool> = HashMap::new();
+    let mut teaser_submitted = Vec::with_capacity(nodes.len());
+    for node in &nodes {
+        if !bylines.contains_key(&node.node_type) {
+            let show = NodeType::show_submitted(&pool, &node.node_type).await;
+            bylines.insert(node.node_type.clone(), show);
+        }
+        teaser_submitted.push(bylines[&node.node_type]);
+    }
 
-    let mut context = tera::Context::new();
-    context.insert("title", "Home");
+    // The first image of each teaser, shown in the teaser image style.
+    let mut teaser_images = Vec::with_capacity(nodes.len());
+    for node in &nodes {
+        let fields = get_fields_with_values(&pool, &node.node_type, node.vid).await?;
+        teaser_images.push(first_image(&fields).map(str::to_string));
+    }
+    let teaser_image_style = ImageDisplay::Teaser.style(&pool).await;
+
+    let popular_content = if installed {
+        blocks::popular_content(&pool).await?
+    } else {
+        None
+    };
+
+    let recent_comments = if installed {
+        blocks::recent_comments(&pool).await?
+    } else {
+        None
+    };
+
+    let mut context = page_context(&pool, "Home").await;
     context.insert("nodes", &nodes);
+    context.insert("teaser_links", &teaser_links);
+    context.insert("teaser_submitted", &teaser_submitted);
+    context.insert("teaser_images", &teaser_images);
+    context.insert("teaser_image_style", &teaser_image_style);
     context.insert("current_user", &current_user);
     context.insert("installed", &installed);
-    context.insert("site_name", &site_name);
-    context.insert("current_theme", &current_theme);
+    context.insert("popular_content", &popular_content);
+    context.insert("recent_comments", &recent_comments);
+    context.insert("feed_url", "/rss.xml");
 
-    let html = tera.render("home.html", &context)?;
+    let html = render(&tera, "home.html", &context)?;
+    if let Some(ttl) = cache_ttl {
+        cache::cache_set(
+            FRONT_PAGE_CACHE_BIN,
+            FRONT_PAGE_CACHE_KEY,
+            html.clone(),
+            ttl,
+            &[TAG_FRONT_PAGE, TAG_VARIABLE],
+        );
+    }
     Ok(Html(html))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::NodeOptions;
+    use crate::test_support::{create_test_comment, create_test_user, test_site, test_tera};
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn only_cut_teasers_read_more() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let author = create_test_user(pool, &[]).await;
+        let options = NodeOptions { promote: true, ..NodeOptions::new(author.uid, filter::FORMAT_FILTERED_HTML) };
+        let (short, _) = Node::create(pool, "story", "Short", "All of it.", "All of it.", &options).await.unwrap();
+        let (long, _) = Node::create(pool, "story", "Long", "Start. And the rest.", "Start.", &options).await.unwrap();
+        create_test_comment(pool, long, 0, &author).await;
+
+        let Html(page) = index(State(pool.clone()), State(test_tera()), Extension(CurrentUser(None))).await.unwrap();
+        assert!(page.contains("All of it."), "nodes without comments are listed");
+        assert!(!page.contains(&format!(r#"<a href="/node/{}">Read more</a>"#, short)));
+        assert!(page.contains(&format!(r#"<a href="/node/{}">Read more</a>"#, long)));
+        assert!(page.contains("1 comment"));
+    }
+}
@@ -0,0 +1,48 @@
+use axum::{
+    extract::{Path, State},
+    http::header,
+    response::{IntoResponse, Response},
+};
+
+use crate::{
+    db::Pool,
+    error::{AppError, AppResult},
+    image_styles::{derivative_path, generate_derivative, is_safe_file_path, original_path, ImageStyle},
+};
+
+/// GET /files/styles/:style/*file - Serve an image derivative, generating
+/// it first when it does not exist yet.
+pub async fn style_derivative(
+    State(pool): State<Pool>,
+    Path((style_name, file)): Path<(String, String)>,
+) -> AppResult<Response> {
+    if !is_safe_file_path(&file) {
+        return Err(AppError::NotFound);
+    }
+
+    let style = ImageStyle::load(&pool, &style_name)
+        .await
+        .ok_or(AppError::NotFound)?;
+
+    let mut path = derivative_path(&style.name, &file);
+    if !path.exists() {
+        if !original_path(&file).exists() {
+            return Err(AppError::NotFound);
+        }
+        path = generate_derivative(&style, &file)
+            .await
+            .map_err(AppError::Internal)?;
+    }
+
+    let bytes = tokio::fs::read(&path)
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+
+    let content_type = match path.extension().and_then(|e| e.to_str()).map(|e| e.to_ascii_lowercase()) {
+        Some(ext) if ext == "png" => "image/png",
+        Some(ext) if ext == "gif" => "image/gif",
+        _ => "image/jpeg",
+    };
+
+    Ok(([(header::CONTENT_TYPE, content_type)], bytes).into_response())
+}
@@ -1,24 +1,40 @@
 use axum::{
     extract::State,
     response::{Html, Redirect},
-    Form,
+    Form, Json,
 };
 use serde::Deserialize;
-use sqlx::MySqlPool;
+use std::sync::Arc;
 use tera::Tera;
 
 use crate::{
     auth::hash_password,
-    db::migrations::{is_installed, run_migrations},
+    config::Config,
+    db::{
+        migrations::{
+            is_installed, mark_installed, migration_status, start_background_migrations,
+            MigrationState, MigrationStatus,
+        },
+        Pool,
+    },
     error::{AppError, AppResult},
-    models::User,
+    models::{Role, User},
+    templates::render,
 };
 
+/// Whether the install pages must stay closed. If the check itself fails
+/// the site may well be live, so answer 503 rather than show the installer.
+async fn installer_locked(pool: &Pool) -> AppResult<bool> {
+    is_installed(pool)
+        .await
+        .map_err(|e| AppError::ServiceUnavailable(format!("install state check failed: {}", e)))
+}
+
 pub async fn welcome(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
 ) -> AppResult<Result<Html<String>, Redirect>> {
-    if is_installed(&pool).await.unwrap_or(false) {
+    if installer_locked(&pool).await? {
         return Ok(Err(Redirect::to("/")));
     }
 
@@ -26,46 +42,63 @@ pub async fn welcome(
     context.insert("title", "Install Drupal");
     context.insert("step", "welcome");
 
-    let html = tera.render("install/welcome.html", &context)?;
+    let html = render(&tera, "install/welcome.html", &context)?;
     Ok(Ok(Html(html)))
 }
 
 pub async fn database(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
+    State(config): State<Arc<Config>>,
 ) -> AppResult<Result<Html<String>, Redirect>> {
-    if is_installed(&pool).await.unwrap_or(false) {
+    if installer_locked(&pool).await? {
         return Ok(Err(Redirect::to("/")));
     }
 
-    if let Err(e) = run_migrations(&pool).await {
-        let mut context = tera::Context::new();
-        context.insert("title", "Database Error");
-        context.insert("error", &e.to_string());
-        let html = tera.render("install/database.html", &context)?;
-        return Ok(Ok(Html(html)));
-    }
+    start_background_migrations(pool.clone(), &config.migrations);
+    let status = migration_status();
 
     let mut context = tera::Context::new();
-    context.insert("title", "Database Setup Complete");
-    context.insert("success", &true);
+    match status.state {
+        MigrationState::Failed => {
+            context.insert("title", "Database Error");
+            context.insert("error", &status.error);
+        }
+        MigrationState::Done => {
+            context.insert("title", "Database Setup Complete");
+            context.insert("success", &true);
+        }
+        MigrationState::Idle | MigrationState::Running => {
+            context.insert("title", "Setting up database");
+        }
+    }
+    context.insert("status", &status);
 
-    let html = tera.render("install/database.html", &context)?;
+    let html = render(&tera, "install/database.html", &context)?;
     Ok(Ok(Html(html)))
 }
 
+/// Polled by the database step while migrations run in the background.
+pub async fn database_status(State(pool): State<Pool>) -> AppResult<Json<MigrationStatus>> {
+    if installer_locked(&pool).await? {
+        return Err(AppError::NotFound);
+    }
+
+    Ok(Json(migration_status()))
+}
+
 pub async fn admin_form(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
 ) -> AppResult<Result<Html<String>, Redirect>> {
-    if is_installed(&pool).await.unwrap_or(false) {
+    if installer_locked(&pool).await? {
         return Ok(Err(Redirect::to("/")));
     }
 
     let mut context = tera::Context::new();
     context.insert("title", "Create Admin Account");
 
-    let html = tera.render("install/admin.html", &context)?;
+    let html = render(&tera, "install/admin.html", &context)?;
     Ok(Ok(Html(html)))
 }
 
@@ -78,11 +111,11 @@ pub struct AdminForm {
 }
 
 pub async fn admin_submit(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Form(form): Form<AdminForm>,
 ) -> AppResult<Result<Html<String>, Redirect>> {
-    if is_installed(&pool).await.unwrap_or(false) {
+    if installer_locked(&pool).await? {
         return Ok(Err(Redirect::to("/")));
     }
 
@@ -91,19 +124,19 @@ pub async fn admin_submit(
 
     if form.username.is_empty() {
         context.insert("error", "Username is required");
-        let html = tera.render("install/admin.html", &context)?;
+        let html = render(&tera, "install/admin.html", &context)?;
         return Ok(Ok(Html(html)));
     }
 
     if form.password != form.password_confirm {
         context.insert("error", "Passwords do not match");
-        let html = tera.render("install/admin.html", &context)?;
+        let html = render(&tera, "install/admin.html", &context)?;
         return Ok(Ok(Html(html)));
     }
 
     if form.password.len() < 6 {
         context.insert("error", "Password must be at least 6 characters");
-        let html = tera.render("install/admin.html", &context)?;
+        let html = render(&tera, "install/admin.html", &context)?;
         return Ok(Ok(Html(html)));
     }
 
@@ -112,17 +145,21 @@ pub async fn admin_submit(
 
     let uid = User::create(&pool, &form.username, &password_hash, &form.email).await?;
 
-    User::add_role(&pool, uid, 2).await?;
-    User::add_role(&pool, uid, 3).await?;
+    User::add_default_role(&pool, uid).await?;
+    match Role::administrator(&pool).await? {
+        Some(role) => User::add_role(&pool, uid, role.rid).await?,
+        None => tracing::warn!("No administrator role found for the site's first account"),
+    }
+    mark_installed(&pool).await?;
 
     Ok(Err(Redirect::to("/install/complete")))
 }
 
 pub async fn complete(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
 ) -> AppResult<Html<String>> {
-    if !is_installed(&pool).await.unwrap_or(false) {
+    if !installer_locked(&pool).await? {
         return Err(AppError::BadRequest(
             "Installation not complete".to_string(),
         ));
@@ -131,6 +168,35 @@ pub async fn complete(
     let mut context = tera::Context::new();
     context.insert("title", "Installation Complete");
 
-    let html = tera.render("install/complete.html", &context)?;
+    let html = render(&tera, "install/complete.html", &context)?;
     Ok(Html(html))
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{test_site, test_tera};
+    use sqlx::mysql::MySqlPoolOptions;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn installer_fails_closed_when_the_check_fails() {
+        // Nothing listens on port 1, so every query fails to connect.
+        let pool = MySqlPoolOptions::new()
+            .acquire_timeout(Duration::from_secs(1))
+            .connect_lazy("mysql://drupal@127.0.0.1:1/drupal")
+            .unwrap();
+        let result = welcome(State(pool.clone()), State(test_tera())).await;
+        assert!(matches!(result, Err(AppError::ServiceUnavailable(_))));
+        let result = database_status(State(pool)).await;
+        assert!(matches!(result, Err(AppError::ServiceUnavailable(_))));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn database_status_is_gone_once_installed() {
+        let site = test_site().await;
+        let result = database_status(State(site.pool.clone())).await;
+        assert!(matches!(result, Err(AppError::NotFound)));
+    }
+}
@@ -1,6 +1,35 @@
 pub mod admin;
+pub mod batch;
 pub mod comment;
+pub mod contact;
+pub mod cron;
+pub mod feed;
+pub mod health;
 pub mod home;
+pub mod image;
 pub mod install;
 pub mod node;
-pub mod user;
\ No newline at end of file
+pub mod tracker;
+pub mod user;
+
+use serde::Serialize;
+
+use crate::db::Pool;
+use crate::models::SiteSettings;
+
+/// A tab above the page content, linking to another view of the same thing.
+#[derive(Debug, Clone, Serialize)]
+pub struct Tab {
+    pub title: &'static str,
+    pub path: String,
+    pub active: bool,
+}
+
+/// A template context holding the page title and the site-wide settings
+/// every page shows.
+pub async fn page_context(pool: &Pool, title: &str) -> tera::Context {
+    let mut context = tera::Context::new();
+    SiteSettings::load(pool).await.insert_into(&mut context);
+    context.insert("title", title);
+    context
+}
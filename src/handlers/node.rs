@@ -1,115 +1,453 @@
 use axum::{
-    extract::{Path, State},
+    extract::{Path, Query, State},
+    http::HeaderMap,
     response::{Html, Redirect},
-    Extension, Form,
+    Extension,
 };
 use serde::{Deserialize, Serialize};
-use sqlx::MySqlPool;
 use std::collections::HashMap;
 use tera::Tera;
 
 use crate::{
     auth::middleware::CurrentUser,
-    error::{AppError, AppResult},
-    models::{get_default_theme, get_fields_with_values, save_field_values, Comment, Node, NodeFieldInstance, NodeType, COMMENT_NODE_DISABLED},
+    cache,
+    db::Pool,
+    error::{AppError, AppResult, ResultExt},
+    extractors::MultipartForm,
+    handlers::{
+        comment::{CommentPermissions, CommentViewer, PublicComment},
+        page_context,
+    },
+    filter::{self, InputFormat},
+    image_styles::ImageDisplay,
+    opengraph::OpenGraph,
+    pager::Pager,
+    models::{add_more_requested, field_widgets, get_fields_with_values, normalize_alias, save_field_values, validate_field_values, Alias, Comment, CommentAnonymous, CommentLength, CommentDisplay, CommentListing, CommentMode, CommentPreview, History, Node, NodeFieldInstance, NodeListItem, NodeOptions, NodeWithBody, Nomination, NodeType, Role, User, Variable, Watchdog, COMMENT_NODE_DISABLED, COMMENT_NODE_READ_WRITE, WATCHDOG_NOTICE},
+    templates::render,
 };
 
+#[derive(Debug, Default, Deserialize)]
+pub struct NodeViewQuery {
+    /// Page of the comment listing; `page` is accepted for older links.
+    #[serde(default, alias = "page")]
+    pub comments_page: u32,
+    /// "unapproved" lists only the comments awaiting approval.
+    #[serde(default)]
+    pub comments: String,
+    /// "queued" after posting a comment that awaits approval.
+    #[serde(default)]
+    pub comment: String,
+    /// Outcome of nominating the node for the front page: "sent" or "limit".
+    #[serde(default)]
+    pub nomination: String,
+    /// "flat" or "threaded", overriding the site's comment display mode.
+    #[serde(default)]
+    pub mode: String,
+}
+
 pub async fn view(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    headers: HeaderMap,
     Path(nid): Path<u32>,
+    Query(query): Query<NodeViewQuery>,
 ) -> AppResult<Html<String>> {
-    let node = Node::find_with_body(&pool, nid)
-        .await?
+    let mut node = Node::find_with_body(&pool, nid)
+        .await
+        .context("loading node for view")?
         .ok_or(AppError::NotFound)?;
+    node.format = filter::resolve_format(&pool, node.format, &node.node_type).await;
 
-    if node.status != 1 {
-        let can_view = current_user
-            .as_ref()
-            .map(|u| u.uid == node.uid || u.uid == 1)
-            .unwrap_or(false);
-
-        if !can_view {
-            return Err(AppError::NotFound);
-        }
+    if !may_view(current_user.as_ref(), &node) {
+        return Err(AppError::NotFound);
     }
 
     let fields = get_fields_with_values(&pool, &node.node_type, node.vid).await?;
-    let current_theme = get_default_theme(&pool).await;
-
-    // Load comments if enabled
-    let comments = if node.comment != COMMENT_NODE_DISABLED {
-        let is_admin = current_user.as_ref().map(|u| u.uid == 1).unwrap_or(false);
-        Comment::find_for_node(&pool, nid, is_admin).await?
+    // Load the requested page of comments if enabled
+    let listing = match CommentListing::for_viewer(&pool, current_user.as_ref()).await? {
+        CommentListing::All if query.comments == "unapproved" => CommentListing::Unapproved,
+        listing => listing,
+    };
+    let mode_override = CommentMode::from_query(&query.mode);
+    let mut display = CommentDisplay::load(&pool).await;
+    if let Some(mode) = mode_override {
+        display.mode = mode;
+    }
+    let (comments, comment_pager) = if node.comment != COMMENT_NODE_DISABLED {
+        let per_page = Comment::per_page(&pool, &node.node_type).await;
+        let total = Comment::count_visible_for_node(&pool, nid, listing).await?;
+        let pager = Pager::new(query.comments_page, per_page, total);
+        let max_depth = Comment::max_depth(&pool).await;
+        let comments = Comment::find_for_node(
+            &pool,
+            nid,
+            listing,
+            display,
+            max_depth,
+            pager.limit(),
+            pager.offset(),
+        )
+        .await?;
+        (comments, Some(pager))
     } else {
-        vec![]
+        (vec![], None)
     };
 
     // Check comment permissions
     let can_post_comments = check_post_comment_permission(&pool, &current_user).await?;
-    let can_administer_comments = match &current_user {
-        Some(user) => user.has_permission(&pool, "administer comments").await?,
-        None => false,
-    };
+    let comment_permissions = CommentPermissions::load(&pool, current_user.as_ref()).await?;
+
+    // Mark the node as read; comments posted after this count as new.
+    if let Some(user) = &current_user {
+        History::touch(&pool, user.uid, nid).await?;
+    }
 
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", &node.title);
+    let mut context = page_context(&pool, &node.title).await;
     context.insert("node", &node);
     context.insert("fields", &fields);
+    context.insert("full_image_style", &ImageDisplay::Full.style(&pool).await);
+    context.insert("open_graph", &OpenGraph::for_node(&pool, &headers, &node, &fields).await);
     context.insert("current_user", &current_user);
+    let (can_edit, can_delete) = match &current_user {
+        Some(user) => (
+            may_modify(&pool, user, &node, NodeOp::Edit).await?,
+            may_modify(&pool, user, &node, NodeOp::Delete).await?,
+        ),
+        None => (false, false),
+    };
+    context.insert("can_edit", &can_edit);
+    context.insert("can_delete", &can_delete);
+    let viewer = CommentViewer::load(&pool, current_user.as_ref()).await?;
+    let comments: Vec<PublicComment> = comments
+        .into_iter()
+        .map(|comment| viewer.public_comment(comment))
+        .collect();
     context.insert("comments", &comments);
+    context.insert("comment_pager", &comment_pager);
+    context.insert("unapproved_only", &(listing == CommentListing::Unapproved));
+    context.insert("comment_queued", &(query.comment == "queued"));
+    context.insert("comment_mode", display.mode.name());
+    context.insert("comment_mode_override", &mode_override.map(CommentMode::name));
     context.insert("can_post_comments", &can_post_comments);
-    context.insert("can_administer_comments", &can_administer_comments);
+    context.insert("comment_length", &CommentLength::load(&pool).await);
+    context.insert("anonymous_contact", CommentAnonymous::load(&pool).await.value());
+    context.insert("comment_preview", CommentPreview::load(&pool).await.value());
+    context.insert("comment_permissions", &comment_permissions);
+    context.insert(
+        "show_comment_subject",
+        &Comment::subject_field_enabled(&pool, &node.node_type).await,
+    );
+
+    let administer_nodes = match &current_user {
+        Some(user) => user.has_permission(&pool, "administer nodes").await?,
+        None => false,
+    };
+    context.insert("last_edited", &last_edited(&node, administer_nodes));
+    let show_updated =
+        Variable::get_or_default(&pool, &format!("node_show_updated_{}", node.node_type), "0").await == "1";
+    context.insert("show_updated", &(show_updated && updated_after_creation(&node)));
+    context.insert("show_submitted", &NodeType::show_submitted(&pool, &node.node_type).await);
 
-    let html = tera.render("node/view.html", &context)?;
+    let nomination = Nomination::find(&pool, nid).await?;
+    let can_nominate = match &current_user {
+        Some(user) => nomination.is_none() && may_nominate(&pool, user, &node).await?,
+        None => false,
+    };
+    if current_user.as_ref().is_some_and(|user| user.uid == node.uid) {
+        context.insert("nomination", &nomination);
+    }
+    context.insert("can_nominate", &can_nominate);
+    match query.nomination.as_str() {
+        "sent" => context.insert("nomination_message", "Your post has been nominated for the front page."),
+        "limit" => context.insert(
+            "nomination_error",
+            &format!(
+                "You can nominate at most {} posts a day. Please try again tomorrow.",
+                nomination_daily_limit(&pool).await
+            ),
+        ),
+        _ => {}
+    }
+
+    let html = render(&tera, "node/view.html", &context)?;
     Ok(Html(html))
 }
 
+/// Nominations a user may make per day unless `nomination_daily_limit` says
+/// otherwise.
+const DEFAULT_NOMINATION_DAILY_LIMIT: i64 = 3;
+
+const DAY: i64 = 86400;
+
+async fn nomination_daily_limit(pool: &Pool) -> i64 {
+    Variable::get_or_default(pool, "nomination_daily_limit", "3")
+        .await
+        .parse()
+        .unwrap_or(DEFAULT_NOMINATION_DAILY_LIMIT)
+}
+
+/// Whether `user` may put `node` forward for the front page: it is their
+/// own published post, it is not on the front page yet and they have
+/// "nominate content".
+async fn may_nominate(pool: &Pool, user: &User, node: &NodeWithBody) -> Result<bool, sqlx::Error> {
+    Ok(user.uid == node.uid
+        && node.status == 1
+        && node.promote == 0
+        && user.has_permission(pool, "nominate content").await?)
+}
+
+/// POST /node/:nid/nominate - Ask the editors to promote one's own post.
+pub async fn nominate(
+    State(pool): State<Pool>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Path(nid): Path<u32>,
+) -> AppResult<Redirect> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    let node = Node::find_with_body(&pool, nid)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    if !may_nominate(&pool, &user, &node).await? {
+        return Err(AppError::Forbidden);
+    }
+
+    if Nomination::count_recent(&pool, user.uid, DAY).await? >= nomination_daily_limit(&pool).await {
+        return Ok(Redirect::to(&format!("/node/{}?nomination=limit", nid)));
+    }
+
+    if Nomination::create(&pool, nid, user.uid).await? {
+        Watchdog::log(
+            &pool,
+            user.uid,
+            "content",
+            &format!("{} nominated for the front page.", node.title),
+            WATCHDOG_NOTICE,
+            &format!("/node/{}", nid),
+            &format!("/node/{}/nominate", nid),
+        )
+        .await?;
+    }
+
+    Ok(Redirect::to(&format!("/node/{}?nomination=sent", nid)))
+}
+
+/// Changes saved within this many seconds of creation do not count as an
+/// update for the public "Updated on" line.
+const UPDATED_GRACE_PERIOD: i32 = 600;
+
+/// The editor of the current revision, when it is someone other than the
+/// author. Only administrators get to see this.
+#[derive(Debug, Serialize)]
+struct LastEdited<'a> {
+    uid: u32,
+    name: Option<&'a str>,
+    timestamp: i32,
+}
+
+fn last_edited(node: &NodeWithBody, administer_nodes: bool) -> Option<LastEdited<'_>> {
+    if !administer_nodes || node.revision_uid == node.uid {
+        return None;
+    }
+    Some(LastEdited {
+        uid: node.revision_uid,
+        name: node.revision_author.as_deref(),
+        timestamp: node.revision_timestamp,
+    })
+}
+
+fn updated_after_creation(node: &NodeWithBody) -> bool {
+    node.changed - node.created > UPDATED_GRACE_PERIOD
+}
+
+/// Links shown under a node teaser in listings.
+#[derive(Debug, Clone, Serialize)]
+pub struct TeaserLinks {
+    /// The teaser leaves part of the body out.
+    pub read_more: bool,
+    /// The comment count, or what stands in for it while there are none.
+    pub comments: Option<CommentCountLink>,
+    /// The viewer may add to the node's existing comments: comments are
+    /// open on the node and the viewer has "post comments".
+    pub add_comment: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CommentCountLink {
+    pub label: String,
+    pub href: String,
+}
+
+/// The comment link for a node with `count` published comments and the
+/// given comment setting: "1 comment" or "N comments" once there are some;
+/// before that "Add new comment" when the viewer can post one, otherwise
+/// `zero_text` (empty for no link). Nothing when comments are disabled.
+pub fn comment_count_link(
+    nid: u32,
+    comment: i32,
+    count: u64,
+    can_post: bool,
+    zero_text: &str,
+) -> Option<CommentCountLink> {
+    if comment == COMMENT_NODE_DISABLED {
+        return None;
+    }
+
+    let (label, href) = match count {
+        1 => ("1 comment".to_string(), format!("/node/{}#comments", nid)),
+        n if n > 1 => (format!("{} comments", n), format!("/node/{}#comments", nid)),
+        _ if can_post && comment == COMMENT_NODE_READ_WRITE => {
+            ("Add new comment".to_string(), format!("/comment/reply/{}", nid))
+        }
+        _ if !zero_text.is_empty() => (zero_text.to_string(), format!("/node/{}#comments", nid)),
+        _ => return None,
+    };
+    Some(CommentCountLink { label, href })
+}
+
+/// Links for each of `nodes`, in the same order.
+pub async fn teaser_links(
+    pool: &Pool,
+    current_user: &Option<User>,
+    nodes: &[NodeListItem],
+) -> Result<Vec<TeaserLinks>, sqlx::Error> {
+    let can_post_comments = check_post_comment_permission(pool, current_user).await?;
+    let zero_text = Comment::zero_text(pool).await;
+
+    Ok(nodes
+        .iter()
+        .map(|node| TeaserLinks {
+            read_more: teaser_truncated(node),
+            comments: comment_count_link(
+                node.nid,
+                node.comment,
+                node.comment_count,
+                can_post_comments,
+                &zero_text,
+            ),
+            add_comment: can_post_comments
+                && node.comment == COMMENT_NODE_READ_WRITE
+                && node.comment_count > 0,
+        })
+        .collect())
+}
+
+fn teaser_truncated(node: &NodeListItem) -> bool {
+    let body = node.body.as_deref().unwrap_or("").trim();
+    let teaser = node.teaser.as_deref().unwrap_or("").trim();
+    !body.is_empty() && teaser != body
+}
+
 async fn check_post_comment_permission(
-    pool: &MySqlPool,
+    pool: &Pool,
     current_user: &Option<crate::models::User>,
 ) -> Result<bool, sqlx::Error> {
     match current_user {
         Some(user) => user.has_permission(pool, "post comments").await,
-        None => {
-            let result: Option<(String,)> =
-                sqlx::query_as("SELECT perm FROM permission WHERE rid = 1")
-                    .fetch_optional(pool)
-                    .await?;
-            Ok(result
-                .map(|(perm,)| perm.contains("post comments"))
-                .unwrap_or(false))
+        None => User::anonymous().has_permission(pool, "post comments").await,
+    }
+}
+
+/// The account a new node is submitted as, which must have the "create
+/// <type> content" permission. Visitors who are not logged in go through
+/// the anonymous role.
+async fn node_author(
+    pool: &Pool,
+    current_user: Option<User>,
+    node_type: &str,
+) -> AppResult<User> {
+    let user = current_user.unwrap_or_else(User::anonymous);
+    if !may_create(pool, &user, node_type).await? {
+        return Err(if user.is_anonymous() {
+            AppError::Unauthorized
+        } else {
+            AppError::Forbidden
+        });
+    }
+    Ok(user)
+}
+
+async fn may_create(pool: &Pool, user: &User, node_type: &str) -> Result<bool, sqlx::Error> {
+    user.has_permission(pool, &format!("create {} content", node_type)).await
+}
+
+/// Changes to an existing node that are granted per content type.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum NodeOp {
+    Edit,
+    Delete,
+}
+
+impl NodeOp {
+    fn verb(self) -> &'static str {
+        match self {
+            NodeOp::Edit => "edit",
+            NodeOp::Delete => "delete",
         }
     }
 }
 
+/// Whether `user` may see `node`. Unpublished nodes are shown only to their
+/// author and the first account; anyone else is told they do not exist.
+pub fn may_view(user: Option<&User>, node: &NodeWithBody) -> bool {
+    node.status == 1 || user.is_some_and(|u| u.uid == node.uid || u.uid == 1)
+}
+
+/// Whether `user` may edit or delete `node`: with "administer nodes", with
+/// "<op> any <type> content", or as its author with "<op> own <type>
+/// content". Sites that grant neither permission for the type to any role
+/// keep the older rule that authors may change their own posts.
+async fn may_modify(pool: &Pool, user: &User, node: &NodeWithBody, op: NodeOp) -> Result<bool, sqlx::Error> {
+    if user.is_anonymous() {
+        return Ok(false);
+    }
+    if user.has_permission(pool, "administer nodes").await? {
+        return Ok(true);
+    }
+
+    let any = format!("{} any {} content", op.verb(), node.node_type);
+    if user.has_permission(pool, &any).await? {
+        return Ok(true);
+    }
+    if user.uid != node.uid {
+        return Ok(false);
+    }
+
+    let own = format!("{} own {} content", op.verb(), node.node_type);
+    if user.has_permission(pool, &own).await? {
+        return Ok(true);
+    }
+    Ok(!Role::any_granted(pool, &own).await? && !Role::any_granted(pool, &any).await?)
+}
+
 pub async fn add_form(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
     Path(node_type): Path<String>,
 ) -> AppResult<Html<String>> {
-    let Some(user) = current_user else {
-        return Err(AppError::Unauthorized);
-    };
+    let user = node_author(&pool, current_user, &node_type).await?;
 
     let type_info = NodeType::find_by_type(&pool, &node_type)
         .await?
         .ok_or(AppError::NotFound)?;
 
     let fields = NodeFieldInstance::with_field_info(&pool, &node_type).await?;
-    let current_theme = get_default_theme(&pool).await;
-
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", &format!("Create {}", type_info.name));
+    let timezone = Variable::default_timezone(&pool).await;
+    let mut context = page_context(&pool, &format!("Create {}", type_info.name)).await;
     context.insert("node_type", &type_info);
     context.insert("fields", &fields);
-    context.insert("current_user", &Some(user));
+    context.insert("field_widgets", &field_widgets(&fields, None, timezone));
+    context.insert("sections", &NodeFormSections::for_user(&pool, &user).await?);
+    let format = filter::type_default_format(&pool, &node_type).await;
+    context.insert("options", &NodeOptions::for_type(&pool, &node_type, user.uid, format).await);
+    context.insert("author_name", &user.name);
+    context.insert("current_user", &Some(user).filter(User::is_authenticated));
 
-    let html = tera.render("node/form.html", &context)?;
+    let html = render(&tera, "node/form.html", &context)?;
     Ok(Html(html))
 }
 
@@ -117,54 +455,97 @@ pub async fn add_form(
 pub struct NodeForm {
     pub title: String,
     pub body: String,
+    #[serde(default)]
+    pub format: Option<String>,
     pub promote: Option<String>,
     pub sticky: Option<String>,
+    #[serde(default)]
+    pub revision: Option<String>,
+    #[serde(default)]
+    pub status: Option<String>,
+    #[serde(default)]
+    pub comment: Option<String>,
+    #[serde(default)]
+    pub author: Option<String>,
+    #[serde(default)]
+    pub date: Option<String>,
+    #[serde(default)]
+    pub publish_on: Option<String>,
+    #[serde(default)]
+    pub path: Option<String>,
     #[serde(flatten)]
     pub field_values: HashMap<String, String>,
 }
 
 pub async fn add_submit(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
     Path(node_type): Path<String>,
-    Form(form): Form<NodeForm>,
+    MultipartForm { fields: form, files }: MultipartForm<NodeForm>,
 ) -> AppResult<Result<Html<String>, Redirect>> {
-    let Some(user) = current_user else {
-        return Err(AppError::Unauthorized);
-    };
+    let user = node_author(&pool, current_user, &node_type).await?;
 
     let type_info = NodeType::find_by_type(&pool, &node_type)
         .await?
         .ok_or(AppError::NotFound)?;
 
     let fields = NodeFieldInstance::with_field_info(&pool, &node_type).await?;
-    let current_theme = get_default_theme(&pool).await;
-
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", &format!("Create {}", type_info.name));
+    let timezone = Variable::default_timezone(&pool).await;
+    let mut context = page_context(&pool, &format!("Create {}", type_info.name)).await;
     context.insert("node_type", &type_info);
     context.insert("fields", &fields);
-    context.insert("current_user", &Some(&user));
+    context.insert("field_widgets", &field_widgets(&fields, Some(&form.field_values), timezone));
+    context.insert("current_user", &Some(&user).filter(|u| u.is_authenticated()));
     context.insert("form", &form);
 
+    let sections = NodeFormSections::for_user(&pool, &user).await?;
+    context.insert("sections", &sections);
+    let format = filter::type_default_format(&pool, &node_type).await;
+    let defaults = NodeOptions::for_type(&pool, &node_type, user.uid, format).await;
+    let mut options = match sections.apply(&pool, &form, defaults.clone()).await? {
+        Ok(options) => options,
+        Err(message) => {
+            context.insert("options", &defaults);
+            context.insert("error", &message);
+            let html = render(&tera, "node/form.html", &context)?;
+            return Ok(Ok(Html(html)));
+        }
+    };
+    if user.is_anonymous() {
+        // Anonymous posts are held back until an administrator publishes them.
+        options.uid = 0;
+        options.status = false;
+        options.promote = false;
+        options.sticky = false;
+    }
+    context.insert("options", &options);
+
+    if add_more_requested(&form.field_values) {
+        let html = render(&tera, "node/form.html", &context)?;
+        return Ok(Ok(Html(html)));
+    }
+
     if form.title.is_empty() {
         context.insert("error", "Title is required");
-        let html = tera.render("node/form.html", &context)?;
+        let html = render(&tera, "node/form.html", &context)?;
         return Ok(Ok(Html(html)));
     }
 
-    for field in &fields {
-        if field.required == 1 {
-            let key = format!("field_{}", field.field_name);
-            let value = form.field_values.get(&key).map(|s| s.as_str()).unwrap_or("");
-            if value.is_empty() {
-                context.insert("error", &format!("{} is required", field.label));
-                let html = tera.render("node/form.html", &context)?;
-                return Ok(Ok(Html(html)));
-            }
-        }
+    if let Some(message) = validate_field_values(&fields, &form.field_values, &files) {
+        context.insert("error", &message);
+        let html = render(&tera, "node/form.html", &context)?;
+        return Ok(Ok(Html(html)));
+    }
+
+    let alias = match sections.path {
+        true => normalize_alias(form.path.as_deref().unwrap_or("")),
+        false => String::new(),
+    };
+    if let Some(message) = Alias::validate(&pool, "", &alias).await? {
+        context.insert("error", message);
+        let html = render(&tera, "node/form.html", &context)?;
+        return Ok(Ok(Html(html)));
     }
 
     let teaser = form
@@ -177,28 +558,42 @@ pub async fn add_submit(
         .unwrap_or("")
         .to_string();
 
-    let promote = form.promote.is_some();
-    let sticky = form.sticky.is_some();
+    let (nid, vid) = Node::create(&pool, &node_type, &form.title, &form.body, &teaser, &options)
+        .await
+        .with_context(|| format!("creating {} node", node_type))?;
 
-    let (nid, vid) = Node::create(
+    save_field_values(&pool, nid, vid, None, &node_type, &form.field_values, &files)
+        .await
+        .with_context(|| format!("saving field values of node {}", nid))?;
+
+    if !alias.is_empty() {
+        Alias::set(&pool, &format!("node/{}", nid), &alias).await?;
+    }
+    cache::invalidate_tags(&[cache::TAG_NODE]);
+    if options.status && options.promote {
+        cache::invalidate_tags(&[cache::TAG_FRONT_PAGE]);
+    }
+    Watchdog::log(
         &pool,
-        &node_type,
-        &form.title,
-        &form.body,
-        &teaser,
         user.uid,
-        promote,
-        sticky,
+        "content",
+        &format!("{}: added {}.", node_type, form.title),
+        WATCHDOG_NOTICE,
+        &format!("/node/{}", nid),
+        &format!("/node/add/{}", node_type),
     )
     .await?;
 
-    save_field_values(&pool, nid, vid, &node_type, &form.field_values).await?;
+    if user.is_anonymous() {
+        let html = render(&tera, "node/queued.html", &context)?;
+        return Ok(Ok(Html(html)));
+    }
 
     Ok(Err(Redirect::to(&format!("/node/{}", nid))))
 }
 
 pub async fn edit_form(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
     Path(nid): Path<u32>,
@@ -207,12 +602,13 @@ pub async fn edit_form(
         return Err(AppError::Unauthorized);
     };
 
-    let node = Node::find_with_body(&pool, nid)
-        .await?
+    let mut node = Node::find_with_body(&pool, nid)
+        .await
+        .context("loading node for edit")?
         .ok_or(AppError::NotFound)?;
+    node.format = filter::resolve_format(&pool, node.format, &node.node_type).await;
 
-    let can_edit = user.uid == node.uid || user.uid == 1;
-    if !can_edit {
+    if !may_modify(&pool, &user, &node, NodeOp::Edit).await? {
         return Err(AppError::Forbidden);
     }
 
@@ -221,38 +617,46 @@ pub async fn edit_form(
         .ok_or(AppError::NotFound)?;
 
     let fields = get_fields_with_values(&pool, &node.node_type, node.vid).await?;
-    let current_theme = get_default_theme(&pool).await;
-
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", &format!("Edit {}", node.title));
+    let path_alias = Alias::lookup(&pool, &format!("node/{}", nid)).await?;
+    let timezone = Variable::default_timezone(&pool).await;
+    let mut context = page_context(&pool, &format!("Edit {}", node.title)).await;
     context.insert("node", &node);
     context.insert("node_type", &type_info);
     context.insert("fields", &fields);
+    context.insert("field_widgets", &field_widgets(&fields, None, timezone));
+    context.insert("sections", &NodeFormSections::for_user(&pool, &user).await?);
+    context.insert("options", &NodeOptions::for_edit(&pool, &node).await);
+    context.insert("author_name", &node.author_name);
+    context.insert("authored_on", &format_authored_on(node.created));
+    if let Some(publish_on) = node.publish_on {
+        context.insert("publish_on", &format_publish_on(publish_on, timezone));
+    }
     context.insert("current_user", &Some(user));
     context.insert("editing", &true);
+    context.insert("path_alias", &path_alias);
 
-    let html = tera.render("node/form.html", &context)?;
+    let html = render(&tera, "node/form.html", &context)?;
     Ok(Html(html))
 }
 
 pub async fn edit_submit(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
     Path(nid): Path<u32>,
-    Form(form): Form<NodeForm>,
+    MultipartForm { fields: form, files }: MultipartForm<NodeForm>,
 ) -> AppResult<Result<Html<String>, Redirect>> {
     let Some(user) = current_user else {
         return Err(AppError::Unauthorized);
     };
 
-    let node = Node::find_with_body(&pool, nid)
-        .await?
+    let mut node = Node::find_with_body(&pool, nid)
+        .await
+        .context("loading node for edit")?
         .ok_or(AppError::NotFound)?;
+    node.format = filter::resolve_format(&pool, node.format, &node.node_type).await;
 
-    let can_edit = user.uid == node.uid || user.uid == 1;
-    if !can_edit {
+    if !may_modify(&pool, &user, &node, NodeOp::Edit).await? {
         return Err(AppError::Forbidden);
     }
 
@@ -261,33 +665,54 @@ pub async fn edit_submit(
         .ok_or(AppError::NotFound)?;
 
     let fields = get_fields_with_values(&pool, &node.node_type, node.vid).await?;
-    let current_theme = get_default_theme(&pool).await;
-
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", &format!("Edit {}", node.title));
+    let timezone = Variable::default_timezone(&pool).await;
+    let mut context = page_context(&pool, &format!("Edit {}", node.title)).await;
     context.insert("node", &node);
     context.insert("node_type", &type_info);
     context.insert("fields", &fields);
+    context.insert("field_widgets", &field_widgets(&fields, Some(&form.field_values), timezone));
     context.insert("current_user", &Some(&user));
     context.insert("editing", &true);
     context.insert("form", &form);
 
+    let sections = NodeFormSections::for_user(&pool, &user).await?;
+    context.insert("sections", &sections);
+    let current_options = NodeOptions::for_edit(&pool, &node).await;
+    let options = match sections.apply(&pool, &form, current_options.clone()).await? {
+        Ok(options) => options,
+        Err(message) => {
+            context.insert("options", &current_options);
+            context.insert("error", &message);
+            let html = render(&tera, "node/form.html", &context)?;
+            return Ok(Ok(Html(html)));
+        }
+    };
+    context.insert("options", &options);
+
+    if add_more_requested(&form.field_values) {
+        let html = render(&tera, "node/form.html", &context)?;
+        return Ok(Ok(Html(html)));
+    }
+
     if form.title.is_empty() {
         context.insert("error", "Title is required");
-        let html = tera.render("node/form.html", &context)?;
+        let html = render(&tera, "node/form.html", &context)?;
         return Ok(Ok(Html(html)));
     }
 
-    for field in &fields {
-        if field.required == 1 {
-            let key = format!("field_{}", field.field_name);
-            let value = form.field_values.get(&key).map(|s| s.as_str()).unwrap_or("");
-            if value.is_empty() {
-                context.insert("error", &format!("{} is required", field.label));
-                let html = tera.render("node/form.html", &context)?;
-                return Ok(Ok(Html(html)));
-            }
+    if let Some(message) = validate_field_values(&fields, &form.field_values, &files) {
+        context.insert("error", &message);
+        let html = render(&tera, "node/form.html", &context)?;
+        return Ok(Ok(Html(html)));
+    }
+
+    let src = format!("node/{}", nid);
+    let alias = normalize_alias(form.path.as_deref().unwrap_or(""));
+    if sections.path {
+        if let Some(message) = Alias::validate(&pool, &src, &alias).await? {
+            context.insert("error", message);
+            let html = render(&tera, "node/form.html", &context)?;
+            return Ok(Ok(Html(html)));
         }
     }
 
@@ -301,44 +726,648 @@ pub async fn edit_submit(
         .unwrap_or("")
         .to_string();
 
-    let promote = form.promote.is_some();
-    let sticky = form.sticky.is_some();
+    let vid = Node::update(&pool, nid, &form.title, &form.body, &teaser, user.uid, &options)
+        .await
+        .with_context(|| format!("saving node {}", nid))?;
 
-    let vid = Node::update(
+    save_field_values(&pool, nid, vid, Some(node.vid), &node.node_type, &form.field_values, &files)
+        .await
+        .with_context(|| format!("saving field values of node {}", nid))?;
+    if sections.path {
+        Alias::set(&pool, &src, &alias).await?;
+    }
+    cache::invalidate_tags(&[cache::TAG_NODE]);
+    if (node.status == 1) != options.status || (node.promote == 1) != options.promote {
+        cache::invalidate_tags(&[cache::TAG_FRONT_PAGE]);
+    }
+    Watchdog::log(
         &pool,
-        nid,
-        &form.title,
-        &form.body,
-        &teaser,
         user.uid,
-        promote,
-        sticky,
+        "content",
+        &format!("{}: updated {}.", node.node_type, form.title),
+        WATCHDOG_NOTICE,
+        &format!("/node/{}", nid),
+        &format!("/node/{}/edit", nid),
     )
     .await?;
 
-    save_field_values(&pool, nid, vid, &node.node_type, &form.field_values).await?;
-
     Ok(Err(Redirect::to(&format!("/node/{}", nid))))
 }
 
-pub async fn list_types(
-    State(pool): State<MySqlPool>,
+/// GET /node/:nid/delete - Ask whether to delete the node.
+pub async fn delete_form(
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Path(nid): Path<u32>,
 ) -> AppResult<Html<String>> {
     let Some(user) = current_user else {
         return Err(AppError::Unauthorized);
     };
 
-    let types = NodeType::all(&pool).await?;
-    let current_theme = get_default_theme(&pool).await;
+    let node = Node::find_with_body(&pool, nid)
+        .await
+        .context("loading node for delete")?
+        .ok_or(AppError::NotFound)?;
 
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", "Add content");
-    context.insert("types", &types);
+    if !may_modify(&pool, &user, &node, NodeOp::Delete).await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let mut context = page_context(&pool, &format!("Delete {}", node.title)).await;
+    context.insert("node", &node);
     context.insert("current_user", &Some(user));
 
-    let html = tera.render("node/list.html", &context)?;
+    let html = render(&tera, "node/delete.html", &context)?;
     Ok(Html(html))
 }
+
+/// POST /node/:nid/delete - Delete the node with its revisions and field
+/// values.
+pub async fn delete_submit(
+    State(pool): State<Pool>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Path(nid): Path<u32>,
+) -> AppResult<Redirect> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    let node = Node::find_with_body(&pool, nid)
+        .await
+        .context("loading node for delete")?
+        .ok_or(AppError::NotFound)?;
+
+    if !may_modify(&pool, &user, &node, NodeOp::Delete).await? {
+        return Err(AppError::Forbidden);
+    }
+
+    Node::delete_multiple(&pool, &[nid])
+        .await
+        .with_context(|| format!("deleting node {}", nid))?;
+    cache::invalidate_tags(&[cache::TAG_NODE, cache::TAG_FRONT_PAGE]);
+    Watchdog::log(
+        &pool,
+        user.uid,
+        "content",
+        &format!("{}: deleted {}.", node.node_type, node.title),
+        WATCHDOG_NOTICE,
+        "",
+        &format!("/node/{}/delete", nid),
+    )
+    .await?;
+
+    Ok(Redirect::to("/"))
+}
+
+pub async fn revisions(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Path(nid): Path<u32>,
+) -> AppResult<Html<String>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    let node = Node::find_with_body(&pool, nid)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    if !may_modify(&pool, &user, &node, NodeOp::Edit).await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let revisions = Node::revisions(&pool, nid).await?;
+    let mut context = page_context(&pool, &format!("Revisions for {}", node.title)).await;
+    context.insert("node", &node);
+    context.insert("revisions", &revisions);
+    context.insert("current_user", &Some(user));
+
+    let html = render(&tera, "node/revisions.html", &context)?;
+    Ok(Html(html))
+}
+
+pub async fn revert_revision(
+    State(pool): State<Pool>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Path((nid, vid)): Path<(u32, u32)>,
+) -> AppResult<Redirect> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    let node = Node::find_with_body(&pool, nid)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    if !may_modify(&pool, &user, &node, NodeOp::Edit).await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let revision = Node::find_revision(&pool, nid, vid)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    if revision.vid != node.vid {
+        Node::revert_to_revision(&pool, &revision, user.uid).await?;
+        cache::invalidate_tags(&[cache::TAG_NODE]);
+    }
+
+    Ok(Redirect::to(&format!("/node/{}/revisions", nid)))
+}
+
+/// Node form sections outside "content", each gated by a permission. Values
+/// posted for a section the user cannot see are ignored rather than trusted.
+#[derive(Debug, Clone, Serialize)]
+struct NodeFormSections {
+    formats: Vec<InputFormat>,
+    publishing: bool,
+    comment_settings: bool,
+    authoring: bool,
+    path: bool,
+}
+
+impl NodeFormSections {
+    async fn for_user(pool: &Pool, user: &User) -> Result<Self, sqlx::Error> {
+        let administer_nodes = user.has_permission(pool, "administer nodes").await?;
+
+        Ok(NodeFormSections {
+            formats: filter::allowed_formats(pool, user).await?,
+            publishing: administer_nodes,
+            comment_settings: user.has_permission(pool, "administer comments").await?,
+            authoring: administer_nodes,
+            path: user.has_permission(pool, "create url aliases").await?,
+        })
+    }
+
+    /// Overlay the submitted values of the visible sections on `options`.
+    async fn apply(
+        &self,
+        pool: &Pool,
+        form: &NodeForm,
+        mut options: NodeOptions,
+    ) -> Result<Result<NodeOptions, String>, sqlx::Error> {
+        let format = form.format.as_deref().unwrap_or("").parse::<i32>();
+        match format {
+            Ok(format) if self.formats.iter().any(|f| f.id == format) => options.format = format,
+            _ => return Ok(Err("An illegal choice has been detected. Please select a valid input format.".to_string())),
+        }
+
+        if self.publishing {
+            options.status = form.status.is_some();
+            options.promote = form.promote.is_some();
+            options.sticky = form.sticky.is_some();
+            options.revision = form.revision.is_some();
+
+            let publish_on = form.publish_on.as_deref().unwrap_or("").trim();
+            if publish_on.is_empty() {
+                options.publish_on = None;
+            } else {
+                let timezone = Variable::default_timezone(pool).await;
+                match parse_publish_on(publish_on, timezone) {
+                    Some(time) if time > chrono::Utc::now().timestamp() as i32 => {
+                        options.publish_on = Some(time)
+                    }
+                    Some(_) => return Ok(Err("The 'publish on' date must be in the future.".to_string())),
+                    None => return Ok(Err("The 'publish on' value does not match the expected format of YYYY-MM-DD HH:MM.".to_string())),
+                }
+            }
+        }
+
+        if self.comment_settings {
+            let comment = form.comment.as_deref().unwrap_or("").parse::<i32>();
+            match comment {
+                Ok(comment @ COMMENT_NODE_DISABLED..=COMMENT_NODE_READ_WRITE) => options.comment = comment,
+                _ => return Ok(Err("Invalid comment setting".to_string())),
+            }
+        }
+
+        if self.authoring {
+            let author = form.author.as_deref().unwrap_or("").trim();
+            if author.is_empty() {
+                options.uid = 0;
+            } else {
+                match User::find_by_name(pool, author).await? {
+                    Some(account) => options.uid = account.uid,
+                    None => return Ok(Err(format!("The username {} does not exist.", author))),
+                }
+            }
+
+            let date = form.date.as_deref().unwrap_or("").trim();
+            if date.is_empty() {
+                options.created = chrono::Utc::now().timestamp() as i32;
+            } else {
+                match parse_authored_on(date) {
+                    Some(created) => options.created = created,
+                    None => return Ok(Err("You have to specify a valid date.".to_string())),
+                }
+            }
+        }
+
+        Ok(Ok(options))
+    }
+}
+
+fn format_authored_on(timestamp: i32) -> String {
+    chrono::DateTime::from_timestamp(timestamp as i64, 0)
+        .unwrap_or_default()
+        .format("%Y-%m-%d %H:%M:%S")
+        .to_string()
+}
+
+fn format_publish_on(timestamp: i32, timezone: chrono::FixedOffset) -> String {
+    chrono::DateTime::from_timestamp(timestamp as i64, 0)
+        .unwrap_or_default()
+        .with_timezone(&timezone)
+        .format("%Y-%m-%d %H:%M")
+        .to_string()
+}
+
+/// Parse a "publish on" time given in the site's default time zone.
+fn parse_publish_on(value: &str, timezone: chrono::FixedOffset) -> Option<i32> {
+    use chrono::TimeZone;
+
+    ["%Y-%m-%d %H:%M", "%Y-%m-%dT%H:%M", "%Y-%m-%d %H:%M:%S"]
+        .iter()
+        .find_map(|format| chrono::NaiveDateTime::parse_from_str(value, format).ok())
+        .and_then(|datetime| timezone.from_local_datetime(&datetime).single())
+        .map(|datetime| datetime.timestamp() as i32)
+}
+
+fn parse_authored_on(date: &str) -> Option<i32> {
+    ["%Y-%m-%d %H:%M:%S", "%Y-%m-%d %H:%M"]
+        .iter()
+        .find_map(|format| chrono::NaiveDateTime::parse_from_str(date, format).ok())
+        .map(|datetime| datetime.and_utc().timestamp() as i32)
+}
+
+pub async fn list_types(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+) -> AppResult<Html<String>> {
+    let user = current_user.unwrap_or_else(User::anonymous);
+
+    // Only the types the user may create are offered.
+    let mut types = Vec::new();
+    for node_type in NodeType::all(&pool).await? {
+        if may_create(&pool, &user, &node_type.type_name).await? {
+            types.push(node_type);
+        }
+    }
+    if types.is_empty() && user.is_anonymous() {
+        return Err(AppError::Unauthorized);
+    }
+
+    let mut context = page_context(&pool, "Add content").await;
+    context.insert("types", &types);
+    context.insert("current_user", &Some(user).filter(User::is_authenticated));
+
+    let html = render(&tera, "node/list.html", &context)?;
+    Ok(Html(html))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::handlers::{home, user};
+    use crate::models::{role::ANONYMOUS_RID, Permission, COMMENT_NODE_READ_ONLY, COMMENT_PUBLISHED};
+    use crate::test_support::{
+        create_test_comment, create_test_node, create_test_role, create_test_user, test_site, test_tera,
+    };
+    use axum::{http::header, response::IntoResponse};
+
+    async fn view_as(pool: &Pool, user: Option<User>, nid: u32) -> AppResult<Html<String>> {
+        view(
+            State(pool.clone()),
+            State(test_tera()),
+            Extension(CurrentUser(user)),
+            HeaderMap::new(),
+            Path(nid),
+            Query(NodeViewQuery::default()),
+        )
+        .await
+    }
+
+    #[test]
+    fn comment_count_link_counts_in_words() {
+        let link = |comment: i32, count: u64, can_post: bool| {
+            comment_count_link(7, comment, count, can_post, "No comments")
+                .map(|link| (link.label, link.href))
+        };
+        let label = |text: &str, href: &str| Some((text.to_string(), href.to_string()));
+
+        assert_eq!(link(COMMENT_NODE_READ_WRITE, 0, true), label("Add new comment", "/comment/reply/7"));
+        assert_eq!(link(COMMENT_NODE_READ_WRITE, 0, false), label("No comments", "/node/7#comments"));
+        assert_eq!(link(COMMENT_NODE_READ_ONLY, 0, true), label("No comments", "/node/7#comments"));
+        assert_eq!(link(COMMENT_NODE_READ_WRITE, 1, true), label("1 comment", "/node/7#comments"));
+        assert_eq!(link(COMMENT_NODE_READ_ONLY, 12, false), label("12 comments", "/node/7#comments"));
+        for count in [0, 1, 12] {
+            assert_eq!(link(COMMENT_NODE_DISABLED, count, true), None);
+        }
+        assert_eq!(comment_count_link(7, COMMENT_NODE_READ_WRITE, 0, false, ""), None);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn unpublished_nodes_are_only_shown_to_their_author() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let author = create_test_user(pool, &[]).await;
+        let other = create_test_user(pool, &[]).await;
+        let node = create_test_node(pool, "story", &author, false).await;
+
+        assert!(matches!(view_as(pool, None, node.nid).await, Err(AppError::NotFound)));
+        assert!(matches!(view_as(pool, Some(other), node.nid).await, Err(AppError::NotFound)));
+        let Html(html) = view_as(pool, Some(author), node.nid).await.unwrap();
+        assert!(html.contains(&node.title));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn revisions_need_edit_access() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let author = create_test_user(pool, &[]).await;
+        let other = create_test_user(pool, &[]).await;
+        let admin = create_test_user(pool, &["administrator"]).await;
+        let node = create_test_node(pool, "page", &author, true).await;
+
+        let list = |user: User| {
+            revisions(State(pool.clone()), State(test_tera()), Extension(CurrentUser(Some(user))), Path(node.nid))
+        };
+        assert!(matches!(list(other).await, Err(AppError::Forbidden)));
+        assert!(list(admin).await.is_ok());
+    }
+
+    /// A filled-in node form by `author` that asks for the post to be
+    /// promoted and made sticky.
+    fn promoted_form(title: &str, author: &User) -> MultipartForm<NodeForm> {
+        let form = NodeForm {
+            title: title.to_string(),
+            body: "Body".to_string(),
+            format: Some(filter::FORMAT_FILTERED_HTML.to_string()),
+            promote: Some("1".to_string()),
+            sticky: Some("1".to_string()),
+            revision: None,
+            status: Some("1".to_string()),
+            comment: Some(COMMENT_NODE_READ_WRITE.to_string()),
+            author: Some(author.name.clone()),
+            date: None,
+            publish_on: None,
+            path: None,
+            field_values: HashMap::new(),
+        };
+        MultipartForm { fields: form, files: Vec::new() }
+    }
+
+    /// The node a successful submission redirected to.
+    fn saved_nid(result: AppResult<Result<Html<String>, Redirect>>) -> u32 {
+        let Ok(Err(redirect)) = result else { panic!("the form was not saved") };
+        let response = redirect.into_response();
+        let location = response.headers()[header::LOCATION].to_str().unwrap();
+        location.trim_start_matches("/node/").parse().unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn only_administrators_promote_posts() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let role = create_test_role(pool, &["create page content", "edit own page content"]).await;
+        let writer = create_test_user(pool, &[&role.name]).await;
+        let admin = create_test_user(pool, &["administrator"]).await;
+        let as_user = |user: &User| (State(pool.clone()), State(test_tera()), Extension(CurrentUser(Some(user.clone()))));
+
+        let (p, t, u) = as_user(&writer);
+        let nid = saved_nid(add_submit(p, t, u, Path("page".to_string()), promoted_form("Added", &writer)).await);
+        let node = Node::find_with_body(pool, nid).await.unwrap().unwrap();
+        assert_eq!((node.promote, node.sticky), (0, 0), "ignored when adding");
+
+        let (p, t, u) = as_user(&writer);
+        saved_nid(edit_submit(p, t, u, Path(nid), promoted_form("Edited", &writer)).await);
+        let node = Node::find_with_body(pool, nid).await.unwrap().unwrap();
+        assert_eq!(node.title, "Edited");
+        assert_eq!((node.promote, node.sticky), (0, 0), "ignored when editing");
+
+        let (p, t, u) = as_user(&admin);
+        saved_nid(edit_submit(p, t, u, Path(nid), promoted_form("Promoted", &writer)).await);
+        let node = Node::find_with_body(pool, nid).await.unwrap().unwrap();
+        assert_eq!((node.promote, node.sticky), (1, 1));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn anonymous_pages_hold_no_mail_addresses() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let author = create_test_user(pool, &[]).await;
+        let node = create_test_node(pool, "story", &author, true).await;
+        let member_comment = create_test_comment(pool, node.nid, 0, &author).await;
+        let visitor_cid = Comment::create(
+            pool,
+            node.nid,
+            0,
+            0,
+            "Drive-by",
+            "Nice post.",
+            "10.0.0.1",
+            Some("Visitor"),
+            Some("visitor@example.org"),
+            None,
+            COMMENT_PUBLISHED,
+        )
+        .await
+        .unwrap();
+
+        let addresses = [author.mail.clone().unwrap(), "visitor@example.org".to_string()];
+        let Html(page) = view_as(pool, None, node.nid).await.unwrap();
+        assert!(page.contains("Nice post."));
+        let Html(profile) = user::profile(
+            State(pool.clone()),
+            State(test_tera()),
+            Extension(CurrentUser(None)),
+            Path(author.uid),
+        )
+        .await
+        .unwrap();
+        let viewer = CommentViewer::load(pool, None).await.unwrap();
+        let mut json = Vec::new();
+        for cid in [member_comment.cid, visitor_cid] {
+            let comment = Comment::find_with_author(pool, cid).await.unwrap().unwrap();
+            json.push(serde_json::to_string(&viewer.public_comment(comment)).unwrap());
+        }
+        for address in &addresses {
+            assert!(!page.contains(address.as_str()), "{} reached the anonymous node page", address);
+            assert!(!profile.contains(address.as_str()), "{} reached the anonymous profile page", address);
+            assert!(!json.iter().any(|json| json.contains(address.as_str())), "{} reached the comment JSON", address);
+        }
+
+        // Administrators do see them.
+        let admin = create_test_user(pool, &["administrator"]).await;
+        let Html(page) = view_as(pool, Some(admin), node.nid).await.unwrap();
+        assert!(page.contains("visitor@example.org"));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn anonymous_posts_are_held_and_shown_under_the_anonymous_name() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        Permission::grant(pool, ANONYMOUS_RID, &["create page content"]).await.unwrap();
+        Variable::set(pool, "anonymous", "Passer-by").await.unwrap();
+
+        let Ok(Ok(Html(page))) = add_submit(
+            State(pool.clone()),
+            State(test_tera()),
+            Extension(CurrentUser(None)),
+            Path("page".to_string()),
+            promoted_form("From a visitor", &User::anonymous()),
+        )
+        .await
+        else {
+            panic!("the anonymous post was not queued");
+        };
+        assert!(page.contains("will be published once it has been reviewed"));
+
+        let (nid,): (u32,) = sqlx::query_as("SELECT nid FROM node WHERE title = 'From a visitor'")
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        let node = Node::find_with_body(pool, nid).await.unwrap().unwrap();
+        assert_eq!((node.uid, node.status, node.promote, node.sticky), (0, 0, 0, 0));
+        assert!(matches!(view_as(pool, None, nid).await, Err(AppError::NotFound)));
+
+        Node::set_status_multiple(pool, &[nid], 1).await.unwrap();
+        cache::invalidate_tags(&[cache::TAG_NODE]);
+        let Html(page) = view_as(pool, None, nid).await.unwrap();
+        assert!(page.contains("By Passer-by"), "{}", page);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn teaser_links_lead_somewhere() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let role = create_test_role(pool, &["access content", "post comments"]).await;
+        let reader = create_test_user(pool, &[&role.name]).await;
+        let create = |title: &str, body: &str, teaser: &str, comment: i32| {
+            let options = NodeOptions { comment, ..NodeOptions::new(reader.uid, filter::FORMAT_FILTERED_HTML) };
+            let (title, body, teaser) = (title.to_string(), body.to_string(), teaser.to_string());
+            async move { Node::create(pool, "story", &title, &body, &teaser, &options).await.unwrap().0 }
+        };
+        let open = create("Open", "First part. Second part.", "First part.", COMMENT_NODE_READ_WRITE).await;
+        let closed = create("Closed", "Short.", "Short.", COMMENT_NODE_DISABLED).await;
+        for nid in [open, closed] {
+            create_test_comment(pool, nid, 0, &reader).await;
+        }
+
+        let nodes = Node::find_published_by_type(pool, "story", 10).await.unwrap();
+        let links = teaser_links(pool, &Some(reader), &nodes).await.unwrap();
+        let links_of = |nid: u32| &links[nodes.iter().position(|node| node.nid == nid).unwrap()];
+
+        let open = links_of(open);
+        assert!(open.read_more);
+        assert!(open.add_comment);
+        assert_eq!(open.comments.as_ref().map(|link| link.label.as_str()), Some("1 comment"));
+
+        let closed = links_of(closed);
+        assert!(!closed.read_more, "the whole body is in the teaser");
+        assert!(!closed.add_comment, "comments are disabled");
+        assert_eq!(closed.comments, None);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn administrators_see_who_edited_a_post_last() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let role = create_test_role(pool, &["create page content", "edit own page content"]).await;
+        let author = create_test_user(pool, &[&role.name]).await;
+        let admin = create_test_user(pool, &["administrator"]).await;
+        let node = create_test_node(pool, "page", &author, true).await;
+        let edit_as = |user: &User, title: &str| {
+            edit_submit(
+                State(pool.clone()),
+                State(test_tera()),
+                Extension(CurrentUser(Some(user.clone()))),
+                Path(node.nid),
+                promoted_form(title, &author),
+            )
+        };
+
+        saved_nid(edit_as(&author, "Edited by the author").await);
+        let Html(page) = view_as(pool, Some(admin.clone()), node.nid).await.unwrap();
+        assert!(!page.contains("Last edited by"), "the author's own edits are not called out");
+
+        saved_nid(edit_as(&admin, "Edited by an administrator").await);
+        let Html(page) = view_as(pool, Some(admin.clone()), node.nid).await.unwrap();
+        assert!(page.contains(&format!(r#"Last edited by <a href="/user/{}">{}</a>"#, admin.uid, admin.name)));
+        for viewer in [None, Some(author)] {
+            let Html(page) = view_as(pool, viewer, node.nid).await.unwrap();
+            assert!(!page.contains("Last edited by"), "only administrators see the editor");
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn updated_date_shows_after_the_grace_period_where_enabled() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let author = create_test_user(pool, &[]).await;
+        let node = create_test_node(pool, "page", &author, true).await;
+        let updated_shown = || async {
+            let Html(page) = view_as(pool, None, node.nid).await.unwrap();
+            page.contains("Updated on")
+        };
+
+        Variable::set(pool, "node_show_updated_page", "1").await.unwrap();
+        assert!(!updated_shown().await, "a fresh post is not updated");
+
+        sqlx::query("UPDATE node SET created = created - ? WHERE nid = ?")
+            .bind(UPDATED_GRACE_PERIOD + 1)
+            .bind(node.nid)
+            .execute(pool)
+            .await
+            .unwrap();
+        cache::invalidate_tags(&[cache::TAG_NODE]);
+        assert!(updated_shown().await);
+
+        Variable::set(pool, "node_show_updated_page", "0").await.unwrap();
+        assert!(!updated_shown().await, "the line is off for the type");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn promoting_a_post_refreshes_the_cached_front_page() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        Variable::set(pool, "cache_front_page_ttl", "300").await.unwrap();
+        let admin = create_test_user(pool, &["administrator"]).await;
+        let node = create_test_node(pool, "story", &admin, true).await;
+        let front_page = || async {
+            let Html(page) = home::index(State(pool.clone()), State(test_tera()), Extension(CurrentUser(None))).await.unwrap();
+            page
+        };
+
+        assert!(!front_page().await.contains(&node.title));
+        // Behind the cache's back: the cached page is still served.
+        sqlx::query("UPDATE node SET promote = 1 WHERE nid = ?").bind(node.nid).execute(pool).await.unwrap();
+        assert!(!front_page().await.contains(&node.title), "the front page is cached");
+        sqlx::query("UPDATE node SET promote = 0 WHERE nid = ?").bind(node.nid).execute(pool).await.unwrap();
+
+        let title = node.title.clone();
+        saved_nid(
+            edit_submit(
+                State(pool.clone()),
+                State(test_tera()),
+                Extension(CurrentUser(Some(admin.clone()))),
+                Path(node.nid),
+                promoted_form(&title, &admin),
+            )
+            .await,
+        );
+        assert!(front_page().await.contains(&node.title), "promoting cleared the cached page");
+    }
+}
@@ -0,0 +1,55 @@
+use axum::{
+    extract::{Query, State},
+    response::Html,
+    Extension,
+};
+use tera::Tera;
+
+use crate::{
+    auth::middleware::CurrentUser,
+    db::Pool,
+    error::{AppError, AppResult},
+    handlers::page_context,
+    models::{Node, NodeListFilter, NodeListQuery, NodeListSort, User},
+    pager::{PageQuery, Pager},
+    templates::render,
+};
+
+const TRACKER_PER_PAGE: u32 = 25;
+
+/// GET /tracker - Recent posts across the site, most recently active first
+pub async fn index(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Query(query): Query<PageQuery>,
+) -> AppResult<Html<String>> {
+    let can_access = match &current_user {
+        Some(user) => user.has_permission(&pool, "access content").await?,
+        None => User::anonymous().has_permission(&pool, "access content").await?,
+    };
+    if !can_access {
+        return Err(AppError::Forbidden);
+    }
+
+    let list = NodeListQuery {
+        filter: NodeListFilter {
+            status: Some(1),
+            ..Default::default()
+        },
+        sort: NodeListSort::Activity,
+        viewer: current_user.as_ref().map(|u| u.uid),
+        ..Default::default()
+    };
+    let total = Node::list_count(&pool, &list.filter).await?;
+    let pager = Pager::new(query.page, TRACKER_PER_PAGE, total);
+    let nodes = Node::list(&pool, &list, pager.limit(), pager.offset()).await?;
+    let mut context = page_context(&pool, "Recent posts").await;
+    context.insert("current_user", &current_user);
+    context.insert("nodes", &nodes);
+    context.insert("anonymous_name", &User::anonymous_name(&pool).await);
+    context.insert("pager", &pager);
+
+    let html = render(&tera, "tracker.html", &context)?;
+    Ok(Html(html))
+}
@@ -1,42 +1,95 @@
 use axum::{
-    extract::{Path, Query, State},
-    response::{Html, Redirect},
+    extract::{ConnectInfo, Path, Query, State},
+    http::{header, HeaderMap},
+    response::{Html, IntoResponse, Redirect, Response},
     Extension, Form,
 };
 use serde::{Deserialize, Serialize};
-use sqlx::MySqlPool;
 use std::collections::HashMap;
-use tera::Tera;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use tera::{Context, Tera};
 use tower_sessions::Session;
 
 use crate::{
-    auth::{hash_password, middleware::CurrentUser, verify_password},
-    error::{AppError, AppResult},
-    models::{get_default_theme, session::SESSION_USER_KEY, ProfileField, ProfileValue, User},
+    auth::{
+        external::ExternalAccount,
+        feed_key, hash_password,
+        middleware::CurrentUser,
+        reset::{self, ResetError},
+        throttle, verify_password, Credentials, ExternalAuthRegistry, LoginThrottle,
+    },
+    cache,
+    db::Pool,
+    error::{AppError, AppResult, ResultExt},
+    extractors::MultipartForm,
+    feed::{self, FeedItem},
+    filter,
+    handlers::{page_context, Tab},
+    mail::{self, Mail, MailError},
+    models::{
+        session::{pass_fingerprint, SESSION_LOGIN_KEY, SESSION_PASS_KEY, SESSION_PASS_RESET_KEY, SESSION_USER_KEY},
+        ContentDisposition, Flood, History, Node, ProfileField, ProfileValue,
+        SiteSettings, User, Variable, Watchdog, WATCHDOG_ERROR, WATCHDOG_NOTICE, WATCHDOG_WARNING,
+    },
+    pager::{PageQuery, Pager},
+    path,
+    pictures::{self, PictureSettings},
+    templates::render,
 };
 
+const DISCUSSIONS_PER_PAGE: u32 = 25;
+const TRACK_PER_PAGE: u32 = 25;
+const DISCUSSIONS_FEED_ITEMS: i64 = 15;
+
+/// Feed name the discussions feed's keys are made for.
+const DISCUSSIONS_FEED: &str = "discussions";
+
 #[derive(Debug, Deserialize)]
 pub struct LoginQuery {
     pub registered: Option<String>,
+    /// Where to go after logging in.
+    pub destination: Option<String>,
+}
+
+impl LoginQuery {
+    /// `destination` when it is a path on this site; see
+    /// [`path::local_destination`].
+    fn destination(&self) -> Option<&str> {
+        self.destination.as_deref().and_then(path::local_destination)
+    }
+}
+
+/// GET /user - The visitor's own account page, or the login form for
+/// anonymous visitors.
+pub async fn account(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Query(query): Query<LoginQuery>,
+) -> AppResult<Result<Html<String>, Redirect>> {
+    if let Some(user) = current_user {
+        return Ok(Err(Redirect::to(&format!("/user/{}", user.uid))));
+    }
+
+    login_form(State(pool), State(tera), Extension(CurrentUser(None)), Query(query)).await
 }
 
 pub async fn login_form(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
     Query(query): Query<LoginQuery>,
 ) -> AppResult<Result<Html<String>, Redirect>> {
     if current_user.is_some() {
-        return Ok(Err(Redirect::to("/")));
+        return Ok(Err(Redirect::to(query.destination().unwrap_or("/"))));
     }
 
-    let current_theme = get_default_theme(&pool).await;
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", "Log in");
+    let mut context = page_context(&pool, "Log in").await;
     context.insert("registered", &query.registered.is_some());
+    context.insert("destination", &query.destination());
 
-    let html = tera.render("user/login.html", &context)?;
+    let html = render(&tera, "user/login.html", &context)?;
     Ok(Ok(Html(html)))
 }
 
@@ -47,42 +100,330 @@ pub struct LoginForm {
 }
 
 pub async fn login_submit(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
+    State(external_auth): State<Arc<ExternalAuthRegistry>>,
+    ConnectInfo(addr): ConnectInfo<SocketAddr>,
     session: Session,
+    Query(query): Query<LoginQuery>,
     Form(form): Form<LoginForm>,
 ) -> AppResult<Result<Html<String>, Redirect>> {
-    let current_theme = get_default_theme(&pool).await;
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", "Log in");
+    let mut context = page_context(&pool, "Log in").await;
+    context.insert("destination", &query.destination());
+    let destination = query.destination().unwrap_or("/");
+    let identifier = throttle::identifier(&form.username, &addr.ip().to_string());
+
+    let credentials = Credentials {
+        username: &form.username,
+        password: &form.password,
+    };
+    if let Some((module, account)) = external_auth.authenticate(credentials).await {
+        let user = match User::find_by_authname(&pool, module, &account.authname).await? {
+            Some(user) => user,
+            None => match provision_external_account(&pool, module, &account).await? {
+                Ok(user) => user,
+                Err(message) => {
+                    context.insert("error", &message);
+                    let html = render(&tera, "user/login.html", &context)?;
+                    return Ok(Ok(Html(html)));
+                }
+            },
+        };
+
+        if user.status != 1 {
+            context.insert("error", "This account is blocked");
+            let html = render(&tera, "user/login.html", &context)?;
+            return Ok(Ok(Html(html)));
+        }
+
+        open_session(&pool, &session, &user).await?;
+        return Ok(Err(Redirect::to(destination)));
+    }
 
     let Some(user) = User::find_by_name(&pool, &form.username).await? else {
-        context.insert("error", "Invalid username or password");
-        let html = tera.render("user/login.html", &context)?;
-        return Ok(Ok(Html(html)));
+        return login_failed(&pool, &tera, context, &form.username, &identifier).await.map(Ok);
     };
 
     if user.status != 1 {
         context.insert("error", "This account is blocked");
-        let html = tera.render("user/login.html", &context)?;
+        let html = render(&tera, "user/login.html", &context)?;
         return Ok(Ok(Html(html)));
     }
 
     if !verify_password(&form.password, &user.pass) {
-        context.insert("error", "Invalid username or password");
-        let html = tera.render("user/login.html", &context)?;
+        return login_failed(&pool, &tera, context, &form.username, &identifier).await.map(Ok);
+    }
+
+    Flood::clear(&pool, throttle::FAILED_LOGIN_EVENT, &identifier).await?;
+    open_session(&pool, &session, &user).await?;
+    Ok(Err(Redirect::to(destination)))
+}
+
+/// Record a failed login, hold the answer back for the throttle delay and
+/// show the form again.
+async fn login_failed(
+    pool: &Pool,
+    tera: &Tera,
+    mut context: Context,
+    username: &str,
+    identifier: &str,
+) -> AppResult<Html<String>> {
+    log_failed_login(pool, username).await?;
+    let throttle = LoginThrottle::load(pool).await;
+    let failures = throttle::failed_login(pool, &throttle, identifier).await?;
+
+    context.insert("error", "Invalid username or password");
+    if let Some(message) = throttle.message(failures) {
+        context.insert("throttle_message", message);
+    }
+    let html = render(tera, "user/login.html", &context)?;
+    Ok(Html(html))
+}
+
+/// GET /user/password - Ask for a one-time login link
+pub async fn password_form(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+) -> AppResult<Result<Html<String>, Redirect>> {
+    if current_user.is_some() {
+        return Ok(Err(Redirect::to("/")));
+    }
+
+    let context = page_context(&pool, "Request new password").await;
+    let html = render(&tera, "user/password.html", &context)?;
+    Ok(Ok(Html(html)))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct PasswordForm {
+    pub name: String,
+}
+
+/// POST /user/password - E-mail a one-time login link to the account with
+/// the given username or e-mail address. The answer is the same whether or
+/// not there is such an account, so the form cannot be used to find out.
+pub async fn password_submit(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Form(form): Form<PasswordForm>,
+) -> AppResult<Html<String>> {
+    let mut context = page_context(&pool, "Request new password").await;
+    let name = form.name.trim();
+    if name.is_empty() {
+        context.insert("error", "Enter your username or e-mail address.");
+        let html = render(&tera, "user/password.html", &context)?;
+        return Ok(Html(html));
+    }
+
+    let account = match User::find_by_name(&pool, name).await? {
+        Some(account) => Some(account),
+        None => User::find_by_mail(&pool, name).await?,
+    };
+    if let Some(account) = account.filter(|a| a.uid > 0 && a.status == 1) {
+        if let Err(e) = send_reset_mail(&pool, &tera, &account).await? {
+            Watchdog::log(
+                &pool,
+                account.uid,
+                "user",
+                &format!("Error mailing one-time login link to {}: {}", account.name, e),
+                WATCHDOG_ERROR,
+                "",
+                "/user/password",
+            )
+            .await?;
+            context.insert("error", "Unable to send mail. Please contact the site administrator.");
+            let html = render(&tera, "user/password.html", &context)?;
+            return Ok(Html(html));
+        }
+        Watchdog::log(
+            &pool,
+            account.uid,
+            "user",
+            &format!("One-time login link mailed to {}.", account.name),
+            WATCHDOG_NOTICE,
+            "",
+            "/user/password",
+        )
+        .await?;
+    }
+
+    context.insert("sent", &true);
+    let html = render(&tera, "user/password.html", &context)?;
+    Ok(Html(html))
+}
+
+/// Mail `account` a fresh one-time login link.
+async fn send_reset_mail(
+    pool: &Pool,
+    tera: &Tera,
+    account: &User,
+) -> AppResult<Result<(), MailError>> {
+    let Some(to) = account.mail.clone().filter(|mail| !mail.is_empty()) else {
+        return Ok(Err(MailError::InvalidAddress(String::new())));
+    };
+    let Some(base_url) = feed::configured_base_url(pool).await else {
+        return Ok(Err(MailError::NoSiteUrl));
+    };
+    let settings = SiteSettings::load(pool).await;
+    let url = format!("{}{}", base_url, reset::reset_path(pool, account).await?);
+
+    let mut context = tera::Context::new();
+    context.insert("account", account);
+    context.insert("site_name", &settings.site_name);
+    context.insert("url", &url);
+    context.insert("valid_hours", &(reset::timeout(pool).await / 3600).max(1));
+    let body = render(tera, "mail/password_reset.txt", &context)?;
+
+    let mail = Mail {
+        to,
+        subject: format!("Replacement login information for {} at {}", account.name, settings.site_name),
+        body,
+    };
+    Ok(mail::send(pool, &mail).await)
+}
+
+/// GET /user/reset/:uid/:timestamp/:hash - Log in with a one-time login link
+/// and go on to the account page to pick a new password.
+pub async fn reset_login(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    session: Session,
+    Path((uid, timestamp, hash)): Path<(u32, i64, String)>,
+) -> AppResult<Result<Html<String>, Redirect>> {
+    let mut context = page_context(&pool, "Request new password").await;
+    if let Some(user) = current_user {
+        if user.uid == uid {
+            return Ok(Err(Redirect::to(&format!("/user/{}/edit", uid))));
+        }
+        context.insert("current_user", &Some(user));
+        context.insert("error", "Another user is already logged in to this site. Log out and try the link again.");
+        let html = render(&tera, "user/password.html", &context)?;
         return Ok(Ok(Html(html)));
     }
 
-    user.update_login(&pool).await?;
+    let account = User::find_by_uid(&pool, uid).await?.filter(|a| a.uid > 0 && a.status == 1);
+    let result = match &account {
+        Some(account) => reset::verify(&pool, account, timestamp, &hash).await?,
+        None => Err(ResetError::Invalid),
+    };
+    let account = match (account, result) {
+        (Some(account), Ok(())) => account,
+        (_, Err(ResetError::Expired)) => {
+            context.insert("error", "You have tried to use a one-time login link that has expired. Please request a new one using the form below.");
+            let html = render(&tera, "user/password.html", &context)?;
+            return Ok(Ok(Html(html)));
+        }
+        _ => {
+            context.insert("error", "You have tried to use a one-time login link that has either been used or is no longer valid. Please request a new one using the form below.");
+            let html = render(&tera, "user/password.html", &context)?;
+            return Ok(Ok(Html(html)));
+        }
+    };
+
+    Watchdog::log(
+        &pool,
+        account.uid,
+        "user",
+        &format!("User {} used one-time login link.", account.name),
+        WATCHDOG_NOTICE,
+        "",
+        "/user/reset",
+    )
+    .await?;
+    open_session(&pool, &session, &account).await?;
+    session
+        .insert(SESSION_PASS_RESET_KEY, account.uid)
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    Ok(Err(Redirect::to(&format!("/user/{}/edit?reset=1", account.uid))))
+}
+
+async fn open_session(pool: &Pool, session: &Session, user: &User) -> AppResult<()> {
+    user.update_login(pool).await?;
+    Watchdog::log(
+        pool,
+        user.uid,
+        "user",
+        &format!("Session opened for {}.", user.name),
+        WATCHDOG_NOTICE,
+        "",
+        "/user/login",
+    )
+    .await?;
 
     session
         .insert(SESSION_USER_KEY, user.uid)
         .await
         .map_err(|e| AppError::Internal(e.to_string()))?;
+    session
+        .insert(SESSION_LOGIN_KEY, chrono::Utc::now().timestamp())
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))?;
+    session
+        .insert(SESSION_PASS_KEY, pass_fingerprint(&user.pass))
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))
+}
+
+/// Create the local account for someone logging in through an external
+/// provider for the first time, when `user_external_register` allows it.
+/// Returns a message for the login form when no account can be created.
+async fn provision_external_account(
+    pool: &Pool,
+    module: &str,
+    account: &ExternalAccount,
+) -> AppResult<Result<User, String>> {
+    if Variable::get_or_default(pool, "user_external_register", "0").await != "1" {
+        return Ok(Err(
+            "Your account is not yet set up on this site. Please contact the site administrator."
+                .to_string(),
+        ));
+    }
+    if User::find_by_name(pool, &account.authname).await?.is_some() {
+        return Ok(Err(format!(
+            "The name {} is already taken by a local account.",
+            account.authname
+        )));
+    }
+
+    // External accounts never log in with a local password; an empty hash
+    // fails verification.
+    let mail = account.mail.as_deref().unwrap_or("");
+    let uid = User::create(pool, &account.authname, "", mail)
+        .await
+        .context("creating account for external login")?;
+    User::set_authname(pool, uid, module, &account.authname).await?;
+    User::add_default_role(pool, uid).await?;
+    Watchdog::log(
+        pool,
+        uid,
+        "user",
+        &format!("New external user: {} using module {}.", account.authname, module),
+        WATCHDOG_NOTICE,
+        &format!("/user/{}", uid),
+        "/user/login",
+    )
+    .await?;
 
-    Ok(Err(Redirect::to("/")))
+    User::find_by_uid(pool, uid)
+        .await?
+        .map(Ok)
+        .ok_or(AppError::NotFound)
+}
+
+async fn log_failed_login(pool: &Pool, name: &str) -> Result<(), sqlx::Error> {
+    Watchdog::log(
+        pool,
+        0,
+        "user",
+        &format!("Login attempt failed for {}.", name),
+        WATCHDOG_WARNING,
+        "",
+        "/user/login",
+    )
+    .await
 }
 
 pub async fn logout(session: Session) -> AppResult<Redirect> {
@@ -95,7 +436,7 @@ pub async fn logout(session: Session) -> AppResult<Redirect> {
 }
 
 pub async fn register_form(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
 ) -> AppResult<Result<Html<String>, Redirect>> {
@@ -104,14 +445,13 @@ pub async fn register_form(
     }
 
     let profile_fields = ProfileField::for_registration(&pool).await?;
-    let current_theme = get_default_theme(&pool).await;
+    let terms = registration_terms(&pool).await?;
 
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", "Create new account");
+    let mut context = page_context(&pool, "Create new account").await;
     context.insert("profile_fields", &profile_fields);
+    context.insert("terms", &terms);
 
-    let html = tera.render("user/register.html", &context)?;
+    let html = render(&tera, "user/register.html", &context)?;
     Ok(Ok(Html(html)))
 }
 
@@ -121,12 +461,14 @@ pub struct RegisterForm {
     pub email: String,
     pub password: String,
     pub password_confirm: String,
+    #[serde(default)]
+    pub agree_terms: Option<String>,
     #[serde(flatten)]
     pub profile: HashMap<String, String>,
 }
 
 pub async fn register_submit(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
     Form(form): Form<RegisterForm>,
@@ -136,66 +478,23 @@ pub async fn register_submit(
     }
 
     let profile_fields = ProfileField::for_registration(&pool).await?;
-    let current_theme = get_default_theme(&pool).await;
+    let terms = registration_terms(&pool).await?;
 
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", "Create new account");
+    let mut context = page_context(&pool, "Create new account").await;
     context.insert("profile_fields", &profile_fields);
+    context.insert("terms", &terms);
     context.insert("form", &form);
 
-    if form.username.is_empty() {
-        context.insert("error", "Username is required");
-        let html = tera.render("user/register.html", &context)?;
-        return Ok(Ok(Html(html)));
-    }
-
-    if form.username.len() < 3 {
-        context.insert("error", "Username must be at least 3 characters");
-        let html = tera.render("user/register.html", &context)?;
-        return Ok(Ok(Html(html)));
-    }
-
-    if !form
-        .username
-        .chars()
-        .all(|c| c.is_alphanumeric() || c == '_' || c == '-')
-    {
-        context.insert(
-            "error",
-            "Username may only contain letters, numbers, underscores, and hyphens",
-        );
-        let html = tera.render("user/register.html", &context)?;
-        return Ok(Ok(Html(html)));
-    }
-
-    if form.email.is_empty() || !form.email.contains('@') {
-        context.insert("error", "Valid email address is required");
-        let html = tera.render("user/register.html", &context)?;
-        return Ok(Ok(Html(html)));
-    }
-
-    if form.password.len() < 6 {
-        context.insert("error", "Password must be at least 6 characters");
-        let html = tera.render("user/register.html", &context)?;
-        return Ok(Ok(Html(html)));
-    }
-
-    if form.password != form.password_confirm {
-        context.insert("error", "Passwords do not match");
-        let html = tera.render("user/register.html", &context)?;
-        return Ok(Ok(Html(html)));
-    }
-
-    if User::find_by_name(&pool, &form.username).await?.is_some() {
-        context.insert("error", "Username is already taken");
-        let html = tera.render("user/register.html", &context)?;
-        return Ok(Ok(Html(html)));
-    }
-
-    if User::find_by_mail(&pool, &form.email).await?.is_some() {
-        context.insert("error", "Email address is already registered");
-        let html = tera.render("user/register.html", &context)?;
+    let error = match User::validate_name(&pool, &form.username, None).await? {
+        Some(error) => Some(error),
+        None => match User::validate_password(&form.password, &form.password_confirm) {
+            Some(error) => Some(error.to_string()),
+            None => User::validate_mail(&pool, &form.email, None).await?,
+        },
+    };
+    if let Some(error) = error {
+        context.insert("error", &error);
+        let html = render(&tera, "user/register.html", &context)?;
         return Ok(Ok(Html(html)));
     }
 
@@ -208,24 +507,38 @@ pub async fn register_submit(
                     "error",
                     &format!("{} is required", field.title.as_deref().unwrap_or(&field.name)),
                 );
-                let html = tera.render("user/register.html", &context)?;
+                let html = render(&tera, "user/register.html", &context)?;
                 return Ok(Ok(Html(html)));
             }
         }
     }
 
+    if terms.is_some() && form.agree_terms.is_none() {
+        context.insert("error", "You must agree to the terms of service to create an account");
+        let html = render(&tera, "user/register.html", &context)?;
+        return Ok(Ok(Html(html)));
+    }
+
     let password_hash =
         hash_password(&form.password).map_err(|e| AppError::Internal(e.to_string()))?;
 
-    let uid = User::create(&pool, &form.username, &password_hash, &form.email).await?;
+    let uid = User::create(&pool, &form.username, &password_hash, &form.email)
+        .await
+        .context("creating account")?;
 
-    User::add_role(&pool, uid, 2).await?;
+    User::add_default_role(&pool, uid).await?;
+
+    if terms.is_some() {
+        User::accept_terms(&pool, uid).await?;
+    }
 
     for field in &profile_fields {
         let field_name = format!("profile_{}", field.fid);
         if let Some(value) = form.profile.get(&field_name) {
             if !value.is_empty() {
-                ProfileValue::set(&pool, field.fid, uid, value).await?;
+                ProfileValue::set(&pool, field.fid, uid, value)
+                    .await
+                    .context("saving profile values")?;
             }
         }
     }
@@ -233,8 +546,53 @@ pub async fn register_submit(
     Ok(Err(Redirect::to("/user/login?registered=1")))
 }
 
+/// Terms of service shown on the registration form, from the
+/// `user_register_tos` variable. Either text or a link to a page holding it.
+#[derive(Debug, Serialize)]
+pub struct RegistrationTerms {
+    pub text: String,
+    pub is_url: bool,
+}
+
+async fn registration_terms(pool: &Pool) -> Result<Option<RegistrationTerms>, sqlx::Error> {
+    let Some(value) = Variable::get(pool, "user_register_tos").await? else {
+        return Ok(None);
+    };
+    let text = value.trim();
+    if text.is_empty() {
+        return Ok(None);
+    }
+
+    let is_url = !text.contains(char::is_whitespace)
+        && (text.starts_with("http://") || text.starts_with("https://") || text.starts_with('/'));
+    Ok(Some(RegistrationTerms {
+        text: text.to_string(),
+        is_url,
+    }))
+}
+
+/// View, edit and track tabs for the account of `uid`, shown to its owner
+/// and to the administrator. `active` names the current one.
+fn account_tabs(viewer: Option<&User>, uid: u32, active: &str) -> Vec<Tab> {
+    if !viewer.is_some_and(|viewer| viewer.uid == uid || viewer.uid == 1) {
+        return Vec::new();
+    }
+    [
+        ("view", "View", format!("/user/{}", uid)),
+        ("edit", "Edit", format!("/user/{}/edit", uid)),
+        ("track", "Track", format!("/user/{}/track", uid)),
+    ]
+    .into_iter()
+    .map(|(name, title, path)| Tab {
+        title,
+        path,
+        active: name == active,
+    })
+    .collect()
+}
+
 pub async fn profile(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
     Path(uid): Path<u32>,
@@ -249,24 +607,32 @@ pub async fn profile(
 
     let viewer_uid = current_user.as_ref().map(|u| u.uid);
     let profile_values = ProfileValue::get_visible_for_user(&pool, uid, viewer_uid).await?;
-    let current_theme = get_default_theme(&pool).await;
 
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", &user.name);
+    let mut context = page_context(&pool, &user.name).await;
     context.insert("profile_user", &user);
     context.insert("current_user", &current_user);
     context.insert("profile_values", &profile_values);
+    context.insert("picture_url", &PictureSettings::load(&pool).await.url(&user.picture));
+    context.insert("show_tracker", &true);
+    context.insert("tabs", &account_tabs(current_user.as_ref(), uid, "view"));
 
-    let html = tera.render("user/profile.html", &context)?;
+    let html = render(&tera, "user/profile.html", &context)?;
     Ok(Html(html))
 }
 
+#[derive(Debug, Deserialize)]
+pub struct EditQuery {
+    /// Set after logging in with a one-time login link.
+    pub reset: Option<String>,
+}
+
 pub async fn edit_form(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    session: Session,
     Path(uid): Path<u32>,
+    Query(query): Query<EditQuery>,
 ) -> AppResult<Html<String>> {
     let Some(user) = current_user else {
         return Err(AppError::Unauthorized);
@@ -280,23 +646,166 @@ pub async fn edit_form(
         .await?
         .ok_or(AppError::NotFound)?;
 
-    let profile_values = ProfileValue::get_for_user(&pool, uid).await?;
-    let current_theme = get_default_theme(&pool).await;
+    let message = query
+        .reset
+        .is_some()
+        .then_some("You have just used your one-time login link. It is no longer valid; please change your password now.");
+    let require_current_password = current_password_required(&session, &user, &profile_user).await;
+    let html =
+        render_edit_form(&pool, &tera, &user, &profile_user, require_current_password, message, None).await?;
+    Ok(Html(html))
+}
 
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", &format!("Edit {}", profile_user.name));
-    context.insert("profile_user", &profile_user);
+/// Whether `user` has to give the current password of `account` to change
+/// its password or e-mail address. Administrators editing someone else's
+/// account do not, nor does an owner who came in through a one-time login
+/// link, nor an account without a local password.
+async fn current_password_required(session: &Session, user: &User, account: &User) -> bool {
+    if user.uid != account.uid || account.pass.is_empty() {
+        return false;
+    }
+    !matches!(session.get::<u32>(SESSION_PASS_RESET_KEY).await, Ok(Some(reset_uid)) if reset_uid == account.uid)
+}
+
+/// The account edit page as first shown, optionally with a status message
+/// or an error from the picture upload form.
+async fn render_edit_form(
+    pool: &Pool,
+    tera: &Tera,
+    user: &User,
+    profile_user: &User,
+    require_current_password: bool,
+    message: Option<&str>,
+    picture_error: Option<&str>,
+) -> AppResult<String> {
+    let profile_values = ProfileValue::get_for_user(pool, profile_user.uid).await?;
+
+    let mut context = page_context(pool, &format!("Edit {}", profile_user.name)).await;
+    context.insert("profile_user", profile_user);
     context.insert("current_user", &Some(user));
     context.insert("profile_values", &profile_values);
+    context.insert("tabs", &account_tabs(Some(user), profile_user.uid, "edit"));
+    context.insert("require_current_password", &require_current_password);
+    context.insert("can_delete_account", &may_delete_account(pool, user, profile_user).await?);
+    insert_picture_context(pool, &mut context, profile_user).await;
+    if let Some(message) = message {
+        context.insert("message", message);
+    }
+    if let Some(error) = picture_error {
+        context.insert("picture_error", error);
+    }
+
+    Ok(render(tera, "user/edit.html", &context)?)
+}
+
+async fn insert_picture_context(pool: &Pool, context: &mut tera::Context, profile_user: &User) {
+    let settings = PictureSettings::load(pool).await;
+    context.insert("picture_url", &settings.url(&profile_user.picture));
+    context.insert("picture_settings", &settings);
+}
+
+/// Whether `user` may delete `account`: user administrators any account but
+/// the first, everyone else only their own with "delete own account".
+async fn may_delete_account(pool: &Pool, user: &User, account: &User) -> Result<bool, sqlx::Error> {
+    if account.uid <= 1 {
+        return Ok(false);
+    }
+    if user.has_permission(pool, "administer users").await? {
+        return Ok(true);
+    }
+    Ok(user.uid == account.uid && user.has_permission(pool, "delete own account").await?)
+}
 
-    let html = tera.render("user/edit.html", &context)?;
+/// The account `uid`, provided the visitor may delete it.
+async fn deletable_account(pool: &Pool, current_user: Option<User>, uid: u32) -> AppResult<(User, User)> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    let account = match User::find_by_uid(pool, uid).await? {
+        Some(account) if account.uid > 0 => account,
+        _ => return Err(AppError::NotFound),
+    };
+    if !may_delete_account(pool, &user, &account).await? {
+        return Err(AppError::Forbidden);
+    }
+
+    Ok((user, account))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct DeleteAccountForm {
+    pub disposition: ContentDisposition,
+}
+
+/// GET /user/:uid/delete - Confirm deleting an account, choosing what
+/// happens to its content.
+pub async fn delete_confirm(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Path(uid): Path<u32>,
+) -> AppResult<Html<String>> {
+    let (user, account) = deletable_account(&pool, current_user, uid).await?;
+
+    let title = format!("Are you sure you want to delete the account {}?", account.name);
+    let mut context = page_context(&pool, &title).await;
+    context.insert("current_user", &Some(&user));
+    context.insert("account", &account);
+    context.insert("own_account", &(user.uid == account.uid));
+
+    let html = render(&tera, "user/delete.html", &context)?;
     Ok(Html(html))
 }
 
+/// POST /user/:uid/delete - Delete the account. Someone cancelling their
+/// own account is logged out and sent to the front page.
+pub async fn delete_submit(
+    State(pool): State<Pool>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    session: Session,
+    Path(uid): Path<u32>,
+    Form(form): Form<DeleteAccountForm>,
+) -> AppResult<Redirect> {
+    let (user, account) = deletable_account(&pool, current_user, uid).await?;
+
+    if !User::delete(&pool, uid, form.disposition).await? {
+        return Err(AppError::NotFound);
+    }
+    pictures::delete(&account.picture).await;
+    cache::invalidate_tags(&[cache::TAG_NODE, cache::TAG_FRONT_PAGE]);
+
+    let content = match form.disposition {
+        ContentDisposition::Reassign => "content attributed to Anonymous",
+        ContentDisposition::Unpublish => "content unpublished",
+    };
+    Watchdog::log(
+        &pool,
+        user.uid,
+        "user",
+        &format!("Deleted user {} ({}), {}.", account.name, account.mail.as_deref().unwrap_or(""), content),
+        WATCHDOG_NOTICE,
+        "",
+        &format!("/user/{}/delete", uid),
+    )
+    .await?;
+
+    if user.uid == account.uid {
+        session
+            .delete()
+            .await
+            .map_err(|e| AppError::Internal(e.to_string()))?;
+        return Ok(Redirect::to("/"));
+    }
+    Ok(Redirect::to("/admin/user"))
+}
+
 #[derive(Debug, Deserialize, Serialize)]
 pub struct EditForm {
     pub email: String,
+    /// Never echoed back into the re-rendered form.
+    #[serde(default, skip_serializing)]
+    pub current_password: Option<String>,
     pub password: Option<String>,
     pub password_confirm: Option<String>,
     #[serde(flatten)]
@@ -304,9 +813,10 @@ pub struct EditForm {
 }
 
 pub async fn edit_submit(
-    State(pool): State<MySqlPool>,
+    State(pool): State<Pool>,
     State(tera): State<Tera>,
     Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    session: Session,
     Path(uid): Path<u32>,
     Form(form): Form<EditForm>,
 ) -> AppResult<Result<Html<String>, Redirect>> {
@@ -323,42 +833,44 @@ pub async fn edit_submit(
         .ok_or(AppError::NotFound)?;
 
     let profile_values = ProfileValue::get_for_user(&pool, uid).await?;
-    let current_theme = get_default_theme(&pool).await;
+    let require_current_password = current_password_required(&session, &user, &profile_user).await;
 
-    let mut context = tera::Context::new();
-    context.insert("current_theme", &current_theme);
-    context.insert("title", &format!("Edit {}", profile_user.name));
+    let mut context = page_context(&pool, &format!("Edit {}", profile_user.name)).await;
     context.insert("profile_user", &profile_user);
     context.insert("current_user", &Some(&user));
     context.insert("profile_values", &profile_values);
+    context.insert("tabs", &account_tabs(Some(&user), uid, "edit"));
+    context.insert("require_current_password", &require_current_password);
+    insert_picture_context(&pool, &mut context, &profile_user).await;
     context.insert("form", &form);
 
-    if form.email.is_empty() || !form.email.contains('@') {
-        context.insert("error", "Valid email address is required");
-        let html = tera.render("user/edit.html", &context)?;
+    if let Some(message) = User::validate_mail(&pool, &form.email, Some(uid)).await? {
+        context.insert("error", &message);
+        let html = render(&tera, "user/edit.html", &context)?;
         return Ok(Ok(Html(html)));
     }
 
-    if let Some(existing) = User::find_by_mail(&pool, &form.email).await? {
-        if existing.uid != uid {
-            context.insert("error", "Email address is already in use");
-            let html = tera.render("user/edit.html", &context)?;
-            return Ok(Ok(Html(html)));
-        }
-    }
-
     let new_password = form.password.as_ref().filter(|p| !p.is_empty());
     if let Some(password) = new_password {
-        if password.len() < 6 {
-            context.insert("error", "Password must be at least 6 characters");
-            let html = tera.render("user/edit.html", &context)?;
+        let confirm = form.password_confirm.as_deref().unwrap_or("");
+        if let Some(message) = User::validate_password(password, confirm) {
+            context.insert("error", message);
+            let html = render(&tera, "user/edit.html", &context)?;
             return Ok(Ok(Html(html)));
         }
+    }
 
-        let confirm = form.password_confirm.as_deref().unwrap_or("");
-        if password != confirm {
-            context.insert("error", "Passwords do not match");
-            let html = tera.render("user/edit.html", &context)?;
+    let mail_changed = profile_user.mail.as_deref().unwrap_or("") != form.email;
+    if require_current_password && (new_password.is_some() || mail_changed) {
+        let current = form.current_password.as_deref().unwrap_or("");
+        if current.is_empty() || !verify_password(current, &profile_user.pass) {
+            let changed = if new_password.is_some() { "Password" } else { "E-mail address" };
+            context.insert(
+                "error",
+                &format!("Your current password is missing or incorrect; it's required to change the {}.", changed),
+            );
+            context.insert("error_field", "current_password");
+            let html = render(&tera, "user/edit.html", &context)?;
             return Ok(Ok(Html(html)));
         }
     }
@@ -373,7 +885,7 @@ pub async fn edit_submit(
                     "error",
                     &format!("{} is required", field.title.as_deref().unwrap_or(&field.name)),
                 );
-                let html = tera.render("user/edit.html", &context)?;
+                let html = render(&tera, "user/edit.html", &context)?;
                 return Ok(Ok(Html(html)));
             }
         }
@@ -383,15 +895,420 @@ pub async fn edit_submit(
         let password_hash =
             hash_password(password).map_err(|e| AppError::Internal(e.to_string()))?;
         User::update_password(&pool, uid, &password_hash).await?;
+
+        // Every other session of the account now carries a stale
+        // fingerprint and is ended on its next request; this one stays.
+        if user.uid == uid {
+            session
+                .insert(SESSION_PASS_KEY, pass_fingerprint(&password_hash))
+                .await
+                .map_err(|e| AppError::Internal(e.to_string()))?;
+            session
+                .remove::<u32>(SESSION_PASS_RESET_KEY)
+                .await
+                .map_err(|e| AppError::Internal(e.to_string()))?;
+        }
     }
 
     User::update_mail(&pool, uid, &form.email).await?;
 
+    if user.uid != uid {
+        let mut changes = Vec::new();
+        if new_password.is_some() {
+            changes.push("password");
+        }
+        if mail_changed {
+            changes.push("e-mail address");
+        }
+        if !changes.is_empty() {
+            Watchdog::log(
+                &pool,
+                user.uid,
+                "user",
+                &format!("{} changed the {} of {}.", user.name, changes.join(" and "), profile_user.name),
+                WATCHDOG_NOTICE,
+                &format!("/user/{}/edit", uid),
+                &format!("/user/{}/edit", uid),
+            )
+            .await?;
+        }
+    }
+
     for field in &all_fields {
         let field_name = format!("profile_{}", field.fid);
         let value = form.profile.get(&field_name).map(|s| s.as_str()).unwrap_or("");
-        ProfileValue::set(&pool, field.fid, uid, value).await?;
+        ProfileValue::set(&pool, field.fid, uid, value)
+            .await
+            .context("saving profile values")?;
     }
 
     Ok(Err(Redirect::to(&format!("/user/{}", uid))))
 }
+
+#[derive(Debug, Deserialize)]
+pub struct PictureForm {
+    #[serde(default)]
+    pub delete_picture: Option<String>,
+}
+
+/// POST /user/:uid/picture - Upload or delete the user picture
+pub async fn picture_submit(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    session: Session,
+    Path(uid): Path<u32>,
+    form: MultipartForm<PictureForm>,
+) -> AppResult<Result<Html<String>, Redirect>> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if user.uid != uid && user.uid != 1 {
+        return Err(AppError::Forbidden);
+    }
+
+    let profile_user = User::find_by_uid(&pool, uid)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    let require_current_password = current_password_required(&session, &user, &profile_user).await;
+
+    if form.fields.delete_picture.is_some() {
+        pictures::delete(&profile_user.picture).await;
+        User::update_picture(&pool, uid, "").await?;
+        return Ok(Err(Redirect::to(&format!("/user/{}/edit", uid))));
+    }
+
+    let Some(file) = form.file("picture") else {
+        let html = render_edit_form(
+            &pool,
+            &tera,
+            &user,
+            &profile_user,
+            require_current_password,
+            None,
+            Some("Choose a picture to upload."),
+        )
+        .await?;
+        return Ok(Ok(Html(html)));
+    };
+
+    let settings = PictureSettings::load(&pool).await;
+    let format = match settings.validate(file) {
+        Ok(format) => format,
+        Err(message) => {
+            let html = render_edit_form(
+                &pool,
+                &tera,
+                &user,
+                &profile_user,
+                require_current_password,
+                None,
+                Some(&message),
+            )
+            .await?;
+            return Ok(Ok(Html(html)));
+        }
+    };
+
+    let picture = pictures::save(uid, format, &file.data, &profile_user.picture)
+        .await
+        .map_err(|e| AppError::Internal(e.to_string()))
+        .context("saving user picture")?;
+    User::update_picture(&pool, uid, &picture).await?;
+
+    Ok(Err(Redirect::to(&format!("/user/{}/edit", uid))))
+}
+
+pub async fn track(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Path(uid): Path<u32>,
+    Query(query): Query<PageQuery>,
+) -> AppResult<Html<String>> {
+    let account = User::find_by_uid(&pool, uid)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    let viewer_uid = current_user.as_ref().map(|u| u.uid).unwrap_or(0);
+    if account.status != 1 && viewer_uid != 1 {
+        return Err(AppError::NotFound);
+    }
+
+    // Unpublished posts are only listed for their author and the superuser.
+    let include_unpublished = viewer_uid != 0 && (viewer_uid == uid || viewer_uid == 1);
+
+    let total = Node::count_by_author(&pool, uid, include_unpublished).await?;
+    let pager = Pager::new(query.page, TRACK_PER_PAGE, total);
+    let nodes =
+        Node::find_by_author(&pool, uid, include_unpublished, pager.limit(), pager.offset()).await?;
+
+    let mut context = page_context(&pool, &format!("Track posts by {}", account.name)).await;
+    context.insert("profile_user", &account);
+    context.insert("current_user", &current_user);
+    context.insert("tabs", &account_tabs(current_user.as_ref(), account.uid, "track"));
+    context.insert("nodes", &nodes);
+    context.insert("pager", &pager);
+
+    let html = render(&tera, "user/track.html", &context)?;
+    Ok(Html(html))
+}
+
+/// Discussions are private: only the user themself or a user administrator
+/// may see them.
+async fn discussions_user(
+    pool: &Pool,
+    current_user: Option<User>,
+    uid: u32,
+) -> AppResult<(User, User)> {
+    let Some(user) = current_user else {
+        return Err(AppError::Unauthorized);
+    };
+
+    if user.uid != uid && !user.has_permission(pool, "administer users").await? {
+        return Err(AppError::Forbidden);
+    }
+
+    let account = User::find_by_uid(pool, uid)
+        .await?
+        .ok_or(AppError::NotFound)?;
+
+    Ok((user, account))
+}
+
+pub async fn discussions(
+    State(pool): State<Pool>,
+    State(tera): State<Tera>,
+    Extension(CurrentUser(current_user)): Extension<CurrentUser>,
+    Path(uid): Path<u32>,
+    Query(query): Query<PageQuery>,
+) -> AppResult<Html<String>> {
+    let (user, account) = discussions_user(&pool, current_user, uid).await?;
+
+    let total = History::count_discussions(&pool, uid).await?;
+    let pager = Pager::new(query.page, DISCUSSIONS_PER_PAGE, total);
+    let discussions = History::discussions(&pool, uid, pager.limit(), pager.offset()).await?;
+
+    let key = feed_key::key(&pool, DISCUSSIONS_FEED, &account).await?;
+
+    let mut context = page_context(&pool, &format!("Discussions of {}", account.name)).await;
+    context.insert("feed_url", &format!("/user/{}/discussions/feed?key={}", uid, key));
+    context.insert("profile_user", &account);
+    context.insert("current_user", &Some(user));
+    context.insert("discussions", &discussions);
+    context.insert("pager", &pager);
+
+    let html = render(&tera, "user/discussions.html", &context)?;
+    Ok(Html(html))
+}
+
+#[derive(Debug, Deserialize)]
+pub struct FeedKeyQuery {
+    #[serde(default)]
+    pub key: String,
+}
+
+/// The discussions feed. Feed readers have no session, so instead of the
+/// visitor's login it checks the key the discussions page links with; a
+/// missing or wrong key looks like a feed that does not exist.
+pub async fn discussions_feed(
+    State(pool): State<Pool>,
+    Path(uid): Path<u32>,
+    Query(query): Query<FeedKeyQuery>,
+    headers: HeaderMap,
+) -> AppResult<Response> {
+    let account = User::find_by_uid(&pool, uid)
+        .await?
+        .filter(|account| account.status == 1)
+        .ok_or(AppError::NotFound)?;
+    if !feed_key::verify(&pool, DISCUSSIONS_FEED, &account, &query.key).await? {
+        return Err(AppError::NotFound);
+    }
+
+    let comments = History::new_discussion_comments(&pool, uid, DISCUSSIONS_FEED_ITEMS).await?;
+    let base_url = feed::base_url(&pool, &headers).await;
+
+    let items: Vec<FeedItem> = comments
+        .into_iter()
+        .map(|c| FeedItem {
+            title: format!("{} ({})", c.subject, c.node_title),
+            link: format!("{}/comment/{}", base_url, c.cid),
+            description: filter::check_markup(&c.comment, filter::FORMAT_FILTERED_HTML),
+            author: c.author_name,
+            timestamp: c.timestamp as i64,
+            guid: format!("comment {} at {}", c.cid, base_url),
+        })
+        .collect();
+
+    let xml = feed::render_channel(
+        &items,
+        &format!("New comments in discussions of {}", account.name),
+        &format!("{}/user/{}/discussions", base_url, uid),
+        "Comments posted since the last visit",
+    );
+
+    Ok(([(header::CONTENT_TYPE, feed::RSS_CONTENT_TYPE)], xml).into_response())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{create_test_user, test_site, test_tera};
+    use tower_sessions::MemoryStore;
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn discussions_feed_opens_with_its_key_alone() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let member = create_test_user(pool, &[]).await;
+        let feed = |key: String| {
+            discussions_feed(State(pool.clone()), Path(member.uid), Query(FeedKeyQuery { key }), HeaderMap::new())
+        };
+
+        let key = feed_key::key(pool, DISCUSSIONS_FEED, &member).await.unwrap();
+        let response = feed(key).await.unwrap();
+        assert_eq!(response.headers()[header::CONTENT_TYPE], feed::RSS_CONTENT_TYPE);
+
+        for key in [String::new(), "0".repeat(64)] {
+            assert!(matches!(feed(key).await, Err(AppError::NotFound)));
+        }
+    }
+
+    fn registration(username: &str, agree_terms: bool) -> RegisterForm {
+        RegisterForm {
+            username: username.to_string(),
+            email: format!("{}@example.com", username),
+            password: "secret123".to_string(),
+            password_confirm: "secret123".to_string(),
+            agree_terms: agree_terms.then(|| "1".to_string()),
+            profile: HashMap::new(),
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn registration_needs_the_terms_accepted() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        Variable::set(pool, "user_register_tos", "Be kind to one another.").await.unwrap();
+        let register = |form: RegisterForm| {
+            register_submit(State(pool.clone()), State(test_tera()), Extension(CurrentUser(None)), Form(form))
+        };
+
+        let Ok(Ok(Html(page))) = register(registration("refuser", false)).await else {
+            panic!("registration went ahead without the terms");
+        };
+        assert!(page.contains("You must agree to the terms of service"));
+        assert!(User::find_by_name(pool, "refuser").await.unwrap().is_none());
+
+        assert!(matches!(register(registration("agreer", true)).await, Ok(Err(_))));
+        let account = User::find_by_name(pool, "agreer").await.unwrap().unwrap();
+        let (accepted,): (i32,) = sqlx::query_as("SELECT tos_accepted FROM users WHERE uid = ?")
+            .bind(account.uid)
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        assert!(accepted > 0);
+    }
+
+    fn account_change(email: &str, current_password: &str, password: &str) -> EditForm {
+        EditForm {
+            email: email.to_string(),
+            current_password: Some(current_password.to_string()),
+            password: Some(password.to_string()),
+            password_confirm: Some(password.to_string()),
+            profile: HashMap::new(),
+        }
+    }
+
+    /// `actor` submitting `form` on the edit page of `account`.
+    async fn submit_edit(
+        pool: &Pool,
+        actor: &User,
+        account: &User,
+        form: EditForm,
+    ) -> AppResult<Result<Html<String>, Redirect>> {
+        edit_submit(
+            State(pool.clone()),
+            State(test_tera()),
+            Extension(CurrentUser(Some(actor.clone()))),
+            Session::new(None, Arc::new(MemoryStore::default()), None),
+            Path(account.uid),
+            Form(form),
+        )
+        .await
+    }
+
+    /// A fresh account whose password is `password`.
+    async fn account_with_password(pool: &Pool, password: &str) -> User {
+        let account = create_test_user(pool, &[]).await;
+        User::update_password(pool, account.uid, &hash_password(password).unwrap()).await.unwrap();
+        User::find_by_uid(pool, account.uid).await.unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn owners_need_their_current_password() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let owner = account_with_password(pool, "old-secret").await;
+
+        for current in ["", "wrong-secret"] {
+            let form = account_change("moved@example.com", current, "new-secret");
+            let Ok(Ok(Html(page))) = submit_edit(pool, &owner, &owner, form).await else {
+                panic!("the account changed without the current password");
+            };
+            assert!(page.contains("Your current password is missing or incorrect"));
+        }
+        let unchanged = User::find_by_uid(pool, owner.uid).await.unwrap().unwrap();
+        assert_eq!(unchanged.mail, owner.mail);
+        assert!(verify_password("old-secret", &unchanged.pass));
+
+        let form = account_change("moved@example.com", "old-secret", "new-secret");
+        assert!(matches!(submit_edit(pool, &owner, &owner, form).await, Ok(Err(_))));
+        let changed = User::find_by_uid(pool, owner.uid).await.unwrap().unwrap();
+        assert_eq!(changed.mail.as_deref(), Some("moved@example.com"));
+        assert!(verify_password("new-secret", &changed.pass));
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn administrators_change_accounts_without_their_password() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let admin = User::find_by_uid(pool, 1).await.unwrap().unwrap();
+        let account = account_with_password(pool, "old-secret").await;
+
+        let form = account_change("reset@example.com", "", "new-secret");
+        assert!(matches!(submit_edit(pool, &admin, &account, form).await, Ok(Err(_))));
+        let changed = User::find_by_uid(pool, account.uid).await.unwrap().unwrap();
+        assert_eq!(changed.mail.as_deref(), Some("reset@example.com"));
+        assert!(verify_password("new-secret", &changed.pass));
+
+        let (logged,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM watchdog WHERE uid = ? AND message LIKE ?")
+            .bind(admin.uid)
+            .bind(format!("%changed the password and e-mail address of {}%", account.name))
+            .fetch_one(pool)
+            .await
+            .unwrap();
+        assert_eq!(logged, 1);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn account_edits_check_the_mail_like_the_admin_form() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let owner = create_test_user(pool, &[]).await;
+        let other = create_test_user(pool, &[]).await;
+
+        let taken = account_change(other.mail.as_deref().unwrap(), "", "");
+        let Ok(Ok(Html(page))) = submit_edit(pool, &owner, &owner, taken).await else {
+            panic!("the account took another account's e-mail address");
+        };
+        assert!(page.contains("Email address is already registered"));
+    }
+}
@@ -0,0 +1,407 @@
+use image::imageops::FilterType;
+use image::{DynamicImage, ImageFormat};
+use serde::Serialize;
+use std::collections::HashMap;
+use std::path::{Component, Path, PathBuf};
+
+use crate::db::Pool;
+use crate::extractors::MultipartPart;
+use crate::models::Variable;
+
+/// Uploaded files live here and are served under `/static/files`.
+pub const FILES_DIR: &str = "static/files";
+
+/// Built-in styles: (machine name, label, default setting). Settings are
+/// stored as `image_style_<name>` variables in the form `WIDTHxHEIGHT` with
+/// an optional `,crop` suffix. The displays fall back to these, so unlike
+/// the styles administrators add they cannot be deleted.
+pub const IMAGE_STYLES: &[(&str, &str, &str)] = &[
+    ("thumbnail", "Thumbnail", "100x100,crop"),
+    ("medium", "Medium", "220x220"),
+];
+
+/// Variable holding the comma-separated machine names of the styles
+/// administrators added, whose settings are stored like the built-ins'.
+const CUSTOM_STYLES: &str = "image_styles_custom";
+
+/// Longest style machine name; it becomes part of a variable name and of
+/// the derivative URLs.
+const MAX_NAME_LENGTH: usize = 32;
+
+/// Where an image field is shown, each place in a style of its own.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageDisplay {
+    /// Teasers on listings such as the front page.
+    Teaser,
+    /// The full node view.
+    Full,
+}
+
+impl ImageDisplay {
+    pub const ALL: [ImageDisplay; 2] = [ImageDisplay::Teaser, ImageDisplay::Full];
+
+    /// Name of the variable holding the display's style, also used as the
+    /// form field.
+    pub fn variable(self) -> &'static str {
+        match self {
+            ImageDisplay::Teaser => "image_display_teaser",
+            ImageDisplay::Full => "image_display_full",
+        }
+    }
+
+    pub fn label(self) -> &'static str {
+        match self {
+            ImageDisplay::Teaser => "Teaser style",
+            ImageDisplay::Full => "Full view style",
+        }
+    }
+
+    fn default_style(self) -> &'static str {
+        match self {
+            ImageDisplay::Teaser => "thumbnail",
+            ImageDisplay::Full => "medium",
+        }
+    }
+
+    /// The style the display uses: the one its variable names while that
+    /// style exists, else the built-in default.
+    pub async fn style(self, pool: &Pool) -> String {
+        let configured = Variable::get_or_default(pool, self.variable(), self.default_style()).await;
+        if ImageStyle::load(pool, &configured).await.is_some() {
+            configured
+        } else {
+            self.default_style().to_string()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct ImageStyle {
+    pub name: String,
+    pub label: String,
+    pub width: u32,
+    pub height: u32,
+    pub crop: bool,
+    /// One of [`IMAGE_STYLES`], which cannot be deleted.
+    pub builtin: bool,
+}
+
+impl ImageStyle {
+    fn parse(name: &str, label: &str, setting: &str) -> Option<Self> {
+        let (dimensions, crop) = match setting.trim().split_once(',') {
+            Some((dimensions, flag)) => (dimensions, flag.trim() == "crop"),
+            None => (setting.trim(), false),
+        };
+        let (width, height) = dimensions.split_once('x')?;
+        let width: u32 = width.trim().parse().ok()?;
+        let height: u32 = height.trim().parse().ok()?;
+        if width == 0 || height == 0 {
+            return None;
+        }
+
+        Some(ImageStyle {
+            name: name.to_string(),
+            label: label.to_string(),
+            width,
+            height,
+            crop,
+            builtin: IMAGE_STYLES.iter().any(|(n, _, _)| *n == name),
+        })
+    }
+
+    pub async fn load(pool: &Pool, name: &str) -> Option<Self> {
+        if let Some((name, label, default)) = IMAGE_STYLES.iter().find(|(n, _, _)| *n == name) {
+            let setting =
+                Variable::get_or_default(pool, &format!("image_style_{}", name), default).await;
+            return Self::parse(name, label, &setting).or_else(|| Self::parse(name, label, default));
+        }
+
+        if !Self::custom_names(pool).await.iter().any(|n| n == name) {
+            return None;
+        }
+        let setting = Variable::get(pool, &format!("image_style_{}", name)).await.ok()??;
+        Self::parse(name, name, &setting)
+    }
+
+    /// The built-in styles followed by the added ones.
+    pub async fn all(pool: &Pool) -> Vec<Self> {
+        let mut names: Vec<String> = IMAGE_STYLES.iter().map(|(name, _, _)| name.to_string()).collect();
+        names.extend(Self::custom_names(pool).await);
+
+        let mut styles = Vec::new();
+        for name in names {
+            if let Some(style) = Self::load(pool, &name).await {
+                styles.push(style);
+            }
+        }
+        styles
+    }
+
+    async fn custom_names(pool: &Pool) -> Vec<String> {
+        Variable::get_or_default(pool, CUSTOM_STYLES, "")
+            .await
+            .split(',')
+            .map(str::trim)
+            .filter(|name| !name.is_empty())
+            .map(str::to_string)
+            .collect()
+    }
+
+    /// Check the machine name of a new style, returning a validation message
+    /// when it cannot be used.
+    pub async fn validate_name(pool: &Pool, name: &str) -> Option<&'static str> {
+        if let Some(message) = check_name(name) {
+            return Some(message);
+        }
+        if Self::load(pool, name).await.is_some() {
+            return Some("An image style with that name already exists.");
+        }
+        None
+    }
+
+    /// Add a style called `name` with `setting`, as made by [`Self::setting`].
+    pub async fn create(pool: &Pool, name: &str, setting: &str) -> Result<(), sqlx::Error> {
+        let mut names = Self::custom_names(pool).await;
+        names.push(name.to_string());
+        Variable::set(pool, &format!("image_style_{}", name), setting).await?;
+        Variable::set(pool, CUSTOM_STYLES, &names.join(", ")).await
+    }
+
+    /// Remove an added style and its images. Displays that used it go back
+    /// to their default.
+    pub async fn delete(pool: &Pool, name: &str) -> Result<(), sqlx::Error> {
+        let names: Vec<String> = Self::custom_names(pool).await.into_iter().filter(|n| n != name).collect();
+        Variable::set(pool, CUSTOM_STYLES, &names.join(", ")).await?;
+        Variable::delete(pool, &format!("image_style_{}", name)).await?;
+        for display in ImageDisplay::ALL {
+            if Variable::get(pool, display.variable()).await?.as_deref() == Some(name) {
+                Variable::delete(pool, display.variable()).await?;
+            }
+        }
+        flush_style(name);
+        Ok(())
+    }
+
+    pub fn setting(width: u32, height: u32, crop: bool) -> String {
+        if crop {
+            format!("{}x{},crop", width, height)
+        } else {
+            format!("{}x{}", width, height)
+        }
+    }
+}
+
+/// Problems with `name` as a style machine name that need no database.
+fn check_name(name: &str) -> Option<&'static str> {
+    if name.is_empty() || name.len() > MAX_NAME_LENGTH {
+        return Some("The image style name must be between 1 and 32 characters.");
+    }
+    if !name.chars().all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_') {
+        return Some("The image style name may only contain lowercase letters, numbers and underscores.");
+    }
+    None
+}
+
+/// Reject file paths that could escape the files directory.
+pub fn is_safe_file_path(file: &str) -> bool {
+    !file.is_empty()
+        && Path::new(file)
+            .components()
+            .all(|c| matches!(c, Component::Normal(_)))
+}
+
+/// Check that an upload is a JPEG, PNG or GIF image whose content matches
+/// its declared type. Returns the format and the size in pixels, or the
+/// reason it was refused.
+pub fn inspect_upload(file: &MultipartPart) -> Result<(ImageFormat, u32, u32), &'static str> {
+    let declared = match file.content_type.as_deref() {
+        Some("image/jpeg") => ImageFormat::Jpeg,
+        Some("image/png") => ImageFormat::Png,
+        Some("image/gif") => ImageFormat::Gif,
+        _ => return Err("must be a JPEG, PNG or GIF image"),
+    };
+    if image::guess_format(&file.data).ok() != Some(declared) {
+        return Err("is not a valid image");
+    }
+    let img = image::load_from_memory_with_format(&file.data, declared)
+        .map_err(|_| "is not a valid image")?;
+    Ok((declared, img.width(), img.height()))
+}
+
+/// Store an uploaded image as `dir/name.<extension>` below the files
+/// directory and return that path.
+pub async fn save_upload(dir: &str, name: &str, format: ImageFormat, data: &[u8]) -> std::io::Result<String> {
+    let extension = format.extensions_str().first().copied().unwrap_or("img");
+    let file = format!("{}/{}.{}", dir, name, extension);
+
+    let path = original_path(&file);
+    if let Some(parent) = path.parent() {
+        tokio::fs::create_dir_all(parent).await?;
+    }
+    tokio::fs::write(path, data).await?;
+    Ok(file)
+}
+
+/// Remove a stored file and its derivatives, including any left from styles
+/// that have since been deleted.
+pub async fn delete_file(file: &str) {
+    if !is_safe_file_path(file) {
+        return;
+    }
+    let _ = tokio::fs::remove_file(original_path(file)).await;
+    let Ok(mut styles) = tokio::fs::read_dir(Path::new(FILES_DIR).join("styles")).await else {
+        return;
+    };
+    while let Ok(Some(style)) = styles.next_entry().await {
+        let _ = tokio::fs::remove_file(style.path().join(file)).await;
+    }
+}
+
+pub fn original_path(file: &str) -> PathBuf {
+    Path::new(FILES_DIR).join(file)
+}
+
+pub fn derivative_path(style: &str, file: &str) -> PathBuf {
+    Path::new(FILES_DIR).join("styles").join(style).join(file)
+}
+
+/// Drop every generated derivative of a style so it is rebuilt on demand.
+pub fn flush_style(style: &str) {
+    let _ = std::fs::remove_dir_all(Path::new(FILES_DIR).join("styles").join(style));
+}
+
+/// Generate the derivatives of a newly stored `file` in every style, so the
+/// first visitor to see it does not wait for them. A failure is logged and
+/// left to [`generate_derivative`] to retry when the image is requested.
+pub async fn generate_derivatives(pool: &Pool, file: &str) {
+    for style in ImageStyle::all(pool).await {
+        if let Err(e) = generate_derivative(&style, file).await {
+            tracing::warn!("Could not generate the {} derivative of {}: {}", style.name, file, e);
+        }
+    }
+}
+
+/// `img` resized according to `style`. Images smaller than the style are
+/// not upscaled unless it crops.
+fn apply_style(style: &ImageStyle, img: DynamicImage) -> DynamicImage {
+    if style.crop {
+        img.resize_to_fill(style.width, style.height, FilterType::Lanczos3)
+    } else if img.width() > style.width || img.height() > style.height {
+        img.resize(style.width, style.height, FilterType::Lanczos3)
+    } else {
+        img
+    }
+}
+
+/// Resize `file` according to `style` and write the derivative next to the
+/// other files of that style.
+pub async fn generate_derivative(style: &ImageStyle, file: &str) -> Result<PathBuf, String> {
+    let source = original_path(file);
+    let target = derivative_path(&style.name, file);
+    let style = style.clone();
+
+    tokio::task::spawn_blocking(move || {
+        let img = image::open(&source).map_err(|e| e.to_string())?;
+        let resized = apply_style(&style, img);
+
+        if let Some(parent) = target.parent() {
+            std::fs::create_dir_all(parent).map_err(|e| e.to_string())?;
+        }
+        resized.save(&target).map_err(|e| e.to_string())?;
+
+        Ok(target)
+    })
+    .await
+    .map_err(|e| e.to_string())?
+}
+
+/// Tera filter mapping a stored file path to the URL of one of its
+/// derivatives: `{{ value.value_text | image_style(style="thumbnail") }}`.
+pub fn image_style_filter(
+    value: &tera::Value,
+    args: &HashMap<String, tera::Value>,
+) -> tera::Result<tera::Value> {
+    let file = match value {
+        tera::Value::String(s) => s,
+        _ => return Ok(value.clone()),
+    };
+
+    let style = args
+        .get("style")
+        .and_then(|s| s.as_str())
+        .unwrap_or(ImageDisplay::Full.default_style());
+
+    Ok(tera::Value::String(format!("/files/styles/{}/{}", style, file)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn style(setting: &str) -> ImageStyle {
+        ImageStyle::parse("test", "Test", setting).unwrap()
+    }
+
+    #[test]
+    fn settings_are_parsed() {
+        let parsed = style(" 100x50 , crop ");
+        assert_eq!((parsed.width, parsed.height, parsed.crop), (100, 50, true));
+        assert!(!style("100x50").crop);
+        for setting in ["", "100", "0x50", "100x0", "axb", "100x50x2"] {
+            assert!(ImageStyle::parse("test", "Test", setting).is_none(), "{}", setting);
+        }
+        assert_eq!(ImageStyle::setting(100, 50, true), "100x50,crop");
+        assert_eq!(ImageStyle::setting(100, 50, false), "100x50");
+    }
+
+    #[test]
+    fn built_in_styles_are_marked() {
+        assert!(ImageStyle::parse("thumbnail", "Thumbnail", "10x10").unwrap().builtin);
+        assert!(!style("10x10").builtin);
+        for (name, label, default) in IMAGE_STYLES {
+            assert!(ImageStyle::parse(name, label, default).is_some(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn style_names_are_checked() {
+        assert_eq!(check_name("large_2x"), None);
+        assert!(check_name("").is_some());
+        assert!(check_name("Large").is_some());
+        assert!(check_name("../x").is_some());
+        assert!(check_name(&"a".repeat(MAX_NAME_LENGTH + 1)).is_some());
+    }
+
+    #[test]
+    fn cropping_fills_the_exact_size() {
+        let resized = apply_style(&style("100x50,crop"), DynamicImage::new_rgb8(400, 400));
+        assert_eq!((resized.width(), resized.height()), (100, 50));
+    }
+
+    #[test]
+    fn scaling_keeps_the_aspect_ratio_and_never_upscales() {
+        let resized = apply_style(&style("100x100"), DynamicImage::new_rgb8(400, 200));
+        assert_eq!((resized.width(), resized.height()), (100, 50));
+
+        let small = apply_style(&style("100x100"), DynamicImage::new_rgb8(40, 20));
+        assert_eq!((small.width(), small.height()), (40, 20));
+    }
+
+    #[test]
+    fn unsafe_paths_are_refused() {
+        assert!(is_safe_file_path("field_images/1-1.png"));
+        for path in ["", "../secret", "/etc/passwd", "a/../../b"] {
+            assert!(!is_safe_file_path(path), "{}", path);
+        }
+    }
+
+    #[test]
+    fn filter_builds_derivative_urls() {
+        let args = HashMap::from([("style".to_string(), tera::Value::from("thumbnail"))]);
+        let url = image_style_filter(&tera::Value::from("field_images/a.png"), &args).unwrap();
+        assert_eq!(url, "/files/styles/thumbnail/field_images/a.png");
+
+        let url = image_style_filter(&tera::Value::from("a.png"), &HashMap::new()).unwrap();
+        assert_eq!(url, "/files/styles/medium/a.png");
+    }
+}
@@ -0,0 +1,125 @@
+//! Outgoing e-mail. Messages are piped to the local sendmail binary, the way
+//! PHP's `mail()` delivers them for Drupal; the `mail_sendmail_path`
+//! variable points elsewhere when it is not at the usual place. Setting
+//! `mail_transport` to "log" prints messages to stdout instead, for sites
+//! without a mail server.
+
+use std::process::{ExitStatus, Stdio};
+use tokio::io::AsyncWriteExt;
+use tokio::process::Command;
+
+use crate::db::Pool;
+use crate::models::Variable;
+
+const DEFAULT_SENDMAIL_PATH: &str = "/usr/sbin/sendmail";
+
+#[derive(Debug, thiserror::Error)]
+pub enum MailError {
+    #[error("invalid recipient address '{0}'")]
+    InvalidAddress(String),
+
+    #[error("could not run sendmail: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("sendmail exited with {0}")]
+    Failed(ExitStatus),
+
+    #[error("the site URL is not set, so the message's links cannot be made absolute")]
+    NoSiteUrl,
+}
+
+/// A plain-text message to a single recipient.
+#[derive(Debug, Clone)]
+pub struct Mail {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+/// Where messages go, from the `mail_transport` variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Transport {
+    Sendmail,
+    Log,
+}
+
+impl Transport {
+    async fn load(pool: &Pool) -> Self {
+        match Variable::get_or_default(pool, "mail_transport", "sendmail").await.as_str() {
+            "log" => Transport::Log,
+            _ => Transport::Sendmail,
+        }
+    }
+}
+
+/// Send `mail` from the site's e-mail address.
+pub async fn send(pool: &Pool, mail: &Mail) -> Result<(), MailError> {
+    let to = header_value(&mail.to);
+    if to.is_empty() || !to.contains('@') {
+        return Err(MailError::InvalidAddress(mail.to.clone()));
+    }
+
+    let from = header_value(&Variable::get_or_default(pool, "site_mail", "").await);
+
+    let mut message = String::new();
+    if !from.is_empty() {
+        message.push_str(&format!("From: {}\r\n", from));
+    }
+    message.push_str(&format!("To: {}\r\n", to));
+    message.push_str(&format!("Subject: {}\r\n", encode_header(&header_value(&mail.subject))));
+    message.push_str("MIME-Version: 1.0\r\n");
+    message.push_str("Content-Type: text/plain; charset=UTF-8\r\n");
+    message.push_str("Content-Transfer-Encoding: 8bit\r\n\r\n");
+    message.push_str(&mail.body.replace("\r\n", "\n").replace('\n', "\r\n"));
+
+    match Transport::load(pool).await {
+        Transport::Log => {
+            println!("Outgoing e-mail:\n{}\n", message.replace("\r\n", "\n"));
+            Ok(())
+        }
+        Transport::Sendmail => {
+            let sendmail = Variable::get_or_default(pool, "mail_sendmail_path", DEFAULT_SENDMAIL_PATH).await;
+            sendmail_deliver(&sendmail, &message).await
+        }
+    }
+}
+
+/// Pipe a complete `message` to the sendmail binary at `path`.
+async fn sendmail_deliver(path: &str, message: &str) -> Result<(), MailError> {
+    // -t takes the recipients from the headers, -i keeps a lone "." line
+    // from ending the message early.
+    let mut child = Command::new(path)
+        .args(["-t", "-i"])
+        .stdin(Stdio::piped())
+        .spawn()?;
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(message.as_bytes()).await?;
+    }
+    let status = child.wait().await?;
+    if !status.success() {
+        return Err(MailError::Failed(status));
+    }
+    Ok(())
+}
+
+/// `value` on one line, so user input cannot add headers of its own.
+fn header_value(value: &str) -> String {
+    value.replace(['\r', '\n'], " ").trim().to_string()
+}
+
+/// RFC 2047 "Q" encoding for header values that are not plain ASCII.
+fn encode_header(value: &str) -> String {
+    if value.is_ascii() {
+        return value.to_string();
+    }
+    let mut encoded = String::from("=?UTF-8?Q?");
+    for byte in value.bytes() {
+        match byte {
+            b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' => encoded.push(byte as char),
+            b' ' => encoded.push('_'),
+            _ => encoded.push_str(&format!("={:02X}", byte)),
+        }
+    }
+    encoded.push_str("?=");
+    encoded
+}
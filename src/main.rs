@@ -1,55 +1,104 @@
 mod auth;
+mod batch;
+mod blocks;
+mod cache;
 mod config;
+mod cron;
 mod db;
 mod error;
+mod error_page;
 mod extractors;
+mod feed;
+mod filter;
 mod handlers;
+mod image_styles;
+mod mail;
 mod models;
+mod opengraph;
+mod pager;
+mod path;
+mod pictures;
 mod statistics;
+mod templates;
+#[cfg(test)]
+mod test_support;
+mod watchdog;
 
 use axum::{
+    extract::Request,
     middleware,
     routing::{get, post},
-    Router,
+    Router, ServiceExt,
 };
-use sqlx::MySqlPool;
 use std::sync::Arc;
 use tera::Tera;
+use tower::Layer;
 use tower_http::services::ServeDir;
 use tower_sessions::{Expiry, SessionManagerLayer};
 use tower_sessions_sqlx_store::MySqlStore;
 
-use crate::auth::auth_middleware;
+use crate::auth::external::StubAuth;
+use crate::auth::{auth_middleware, ExternalAuthRegistry};
 use crate::config::Config;
+use crate::db::Pool;
 
+/// Render a unix timestamp. `type="date"` and `type="datetime"` are used for
+/// date fields, where the timestamp is a real value even at 0. A `date` is
+/// shown in UTC, the same zone it was parsed in, so it never shifts by a
+/// day; anything else is shifted by `timezone`, an offset in seconds
+/// (usually `site_timezone`).
 fn format_date_filter(
     value: &tera::Value,
-    _args: &std::collections::HashMap<String, tera::Value>,
+    args: &std::collections::HashMap<String, tera::Value>,
 ) -> tera::Result<tera::Value> {
     let timestamp = match value {
         tera::Value::Number(n) => n.as_i64().unwrap_or(0),
         _ => return Ok(value.clone()),
     };
 
-    if timestamp == 0 {
-        return Ok(tera::Value::String("Never".to_string()));
-    }
+    let format = match args.get("type").and_then(|t| t.as_str()) {
+        Some("date") => "%B %e, %Y",
+        Some("datetime") => "%B %e, %Y - %l:%M%P",
+        _ => {
+            if timestamp == 0 {
+                return Ok(tera::Value::String("Never".to_string()));
+            }
+            "%B %e, %Y - %l:%M%P"
+        }
+    };
 
+    let offset = match args.get("type").and_then(|t| t.as_str()) {
+        Some("date") => 0,
+        _ => args.get("timezone").and_then(|t| t.as_i64()).unwrap_or(0),
+    };
+    let timezone = chrono::FixedOffset::east_opt(offset as i32)
+        .unwrap_or_else(|| chrono::FixedOffset::east_opt(0).unwrap());
     let datetime = chrono::DateTime::from_timestamp(timestamp, 0)
-        .unwrap_or_else(|| chrono::DateTime::from_timestamp(0, 0).unwrap());
+        .unwrap_or_else(|| chrono::DateTime::from_timestamp(0, 0).unwrap())
+        .with_timezone(&timezone);
 
-    let formatted = datetime.format("%B %e, %Y - %l:%M%P").to_string();
+    let formatted = datetime.format(format).to_string();
     Ok(tera::Value::String(formatted))
 }
 
+/// The site's templates, with the filters they use registered.
+fn load_templates() -> tera::Result<Tera> {
+    let mut tera = Tera::new("templates/**/*.{html,txt}")?;
+    tera.register_filter("format_date", format_date_filter);
+    tera.register_filter("image_style", image_styles::image_style_filter);
+    tera.register_filter("check_markup", filter::check_markup_filter);
+    Ok(tera)
+}
+
 #[derive(Clone)]
 struct AppState {
-    pool: MySqlPool,
+    pool: Pool,
     tera: Tera,
     config: Arc<Config>,
+    external_auth: Arc<ExternalAuthRegistry>,
 }
 
-impl axum::extract::FromRef<AppState> for MySqlPool {
+impl axum::extract::FromRef<AppState> for Pool {
     fn from_ref(state: &AppState) -> Self {
         state.pool.clone()
     }
@@ -67,10 +116,16 @@ impl axum::extract::FromRef<AppState> for Arc<Config> {
     }
 }
 
+impl axum::extract::FromRef<AppState> for Arc<ExternalAuthRegistry> {
+    fn from_ref(state: &AppState) -> Self {
+        state.external_auth.clone()
+    }
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     println!("Entering main");
-    let mut tera = match Tera::new("templates/**/*.html") {
+    let tera = match load_templates() {
         Ok(t) => {
             println!("Tera initialized");
             t
@@ -81,19 +136,55 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         }
     };
 
-    tera.register_filter("format_date", format_date_filter);
-
     // tracing_subscriber::fmt::init();
 
     dotenvy::dotenv().ok();
 
-    let config = Config::from_env()?;
+    let config = Config::load()?;
     println!("Starting server on {}", config.bind_address());
     println!("Using database URL: {}", config.database.url);
 
     let pool = db::create_pool(&config.database.url).await?;
     println!("Database connection established");
 
+    filter::load_settings(&pool).await;
+
+    match db::migrations::is_installed(&pool).await {
+        Ok(true) => {}
+        // Installed before the installer recorded completion, if it has accounts.
+        Ok(false) => match db::migrations::backfill_install_marker(&pool).await {
+            Ok(true) => println!("Recorded the existing installation as complete"),
+            Ok(false) => println!("WARNING: Drupal is not installed; the installer is open to anyone at /install"),
+            Err(e) => println!("WARNING: could not record the existing installation as complete: {}", e),
+        },
+        Err(e) => println!("WARNING: could not determine the install state ({}); install pages will answer 503", e),
+    }
+
+    if std::env::args().nth(1).as_deref() == Some("migrate") {
+        db::migrations::run_migrations(&pool).await?;
+        let upgraded = models::Comment::upgrade_threads(&pool).await?;
+        if upgraded > 0 {
+            println!("Rewrote the thread of {} comment(s)", upgraded);
+        }
+        println!("Migrations completed");
+        return Ok(());
+    }
+
+    if std::env::args().nth(1).as_deref() == Some("batch") {
+        let count = batch::run_pending(&pool).await?;
+        println!("Processed {} pending batch(es)", count);
+        return Ok(());
+    }
+
+    if std::env::args().nth(1).as_deref() == Some("cron") {
+        if cron::run_cron(&pool).await? {
+            println!("Cron run completed");
+        } else {
+            println!("Cron is already running");
+        }
+        return Ok(());
+    }
+
     let session_store = MySqlStore::new(pool.clone());
     println!("Migrating session store...");
     session_store.migrate().await?;
@@ -104,34 +195,83 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let session_layer = SessionManagerLayer::new(session_store);
     
     println!("SessionManagerLayer created. Adding expiry...");
-    let session_layer = session_layer.with_expiry(Expiry::OnInactivity(time::Duration::days(7)));
+    let idle_timeout = time::Duration::seconds(config.sessions.idle_timeout as i64);
+    let session_layer = session_layer.with_expiry(Expiry::OnInactivity(idle_timeout));
     println!("Session layer created");
 
+    // External login providers, tried in order before local passwords.
+    // Register LDAP, OpenID and the like here.
+    let mut external_auth = ExternalAuthRegistry::new();
+    external_auth.register(StubAuth);
+
     let state = AppState {
         pool: pool.clone(),
         tera,
         config: Arc::new(config.clone()),
+        external_auth: Arc::new(external_auth),
     };
     println!("AppState created");
 
     let app = Router::new()
         .route("/", get(handlers::home::index))
+        .route("/rss.xml", get(handlers::feed::front_page))
+        .route("/node/feed/:type", get(handlers::feed::node_type))
+        .route("/promoted/rss.xml", get(handlers::feed::promoted))
+        .route("/promoted.json", get(handlers::feed::promoted_json))
         .route("/install", get(handlers::install::welcome))
         .route("/install/database", get(handlers::install::database))
+        .route("/install/database/status", get(handlers::install::database_status))
         .route("/install/admin", get(handlers::install::admin_form))
         .route("/install/admin", post(handlers::install::admin_submit))
         .route("/install/complete", get(handlers::install::complete))
         .route("/admin", get(handlers::admin::index))
         .route("/admin/node", get(handlers::admin::content_list))
         .route("/admin/node", post(handlers::admin::content_action))
+        .route("/admin/node/delete-confirm", post(handlers::admin::content_delete_confirm))
+        .route("/admin/node/aliases", post(handlers::admin::generate_aliases))
+        .route("/admin/node/nominations", get(handlers::admin::nominations))
+        .route("/admin/node/nominations/:nid", post(handlers::admin::nomination_decide))
         .route("/admin/node/types", get(handlers::admin::node_types))
         .route("/admin/node/types/:type", get(handlers::admin::node_type_edit_form))
         .route("/admin/node/types/:type", post(handlers::admin::node_type_edit_submit))
+        .route("/admin/node/types/:type/rename", post(handlers::admin::node_type_rename))
+        .route("/admin/node/types/:type/clone", get(handlers::admin::node_type_clone_form))
+        .route("/admin/node/types/:type/clone", post(handlers::admin::node_type_clone_submit))
         .route("/admin/user", get(handlers::admin::user_list))
         .route("/admin/user", post(handlers::admin::user_action))
+        .route("/admin/user/create", get(handlers::admin::user_create_form))
+        .route("/admin/user/create", post(handlers::admin::user_create_submit))
+        .route("/admin/user/:uid/edit", get(handlers::admin::user_edit_form))
+        .route("/admin/user/:uid/edit", post(handlers::admin::user_edit_submit))
+        .route("/admin/user/email", get(handlers::admin::mail_users_form))
+        .route("/admin/user/email", post(handlers::admin::mail_users_submit))
+        .route("/admin/user/permissions", get(handlers::admin::access_form))
+        .route("/admin/user/permissions", post(handlers::admin::access_submit))
+        .route("/admin/user/access", get(handlers::admin::access_form))
+        .route("/admin/user/access", post(handlers::admin::access_submit))
+        .route("/admin/user/roles", get(handlers::admin::roles))
+        .route("/admin/user/roles", post(handlers::admin::role_add))
+        .route("/admin/user/roles/:rid", get(handlers::admin::role_edit_form))
+        .route("/admin/user/roles/:rid", post(handlers::admin::role_edit_submit))
+        .route("/admin/user/roles/:rid/delete", post(handlers::admin::role_delete))
         .route("/admin/settings", get(handlers::admin::settings_form))
         .route("/admin/settings", post(handlers::admin::settings_submit))
+        .route("/admin/settings/filters", get(handlers::admin::filters_form))
+        .route("/admin/settings/filters", post(handlers::admin::filters_submit))
+        .route("/admin/settings/image-styles", get(handlers::admin::image_styles_form))
+        .route("/admin/settings/image-styles", post(handlers::admin::image_styles_submit))
+        .route("/admin/reports/dblog", get(handlers::admin::dblog))
+        .route("/admin/reports/dblog/:wid", get(handlers::admin::dblog_event))
         .route("/admin/reports/status", get(handlers::admin::status_report))
+        .route("/admin/reports/status/flush-caches", post(handlers::admin::flush_caches))
+        .route("/admin/reports/status/metrics", get(handlers::admin::metrics))
+        .route("/admin/reports/fields", get(handlers::admin::field_report))
+        .route("/admin/reports/recent-changes", get(handlers::admin::recent_changes))
+        .route("/admin/reports/fields/instance/:id/delete", post(handlers::admin::field_instance_delete))
+        .route("/admin/reports/fields/data/:field_name/delete", post(handlers::admin::field_data_delete))
+        .route("/admin/cron", post(handlers::admin::run_cron))
+        .route("/cron/:key", get(handlers::cron::run))
+        .route("/health", get(handlers::health::check))
         .route("/admin/modules", get(handlers::admin::modules_list))
         .route("/admin/modules", post(handlers::admin::modules_submit))
         .route("/admin/themes", get(handlers::admin::themes_list))
@@ -141,55 +281,104 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
         .route("/admin/logs/visitors", get(handlers::admin::logs_visitors))
         .route("/admin/logs/referrers", get(handlers::admin::logs_referrers))
         .route("/admin/logs/access/:aid", get(handlers::admin::logs_access_detail))
+        .route("/admin/comment/approval", get(handlers::admin::comment_approval))
+        .route("/admin/comment/approval", post(handlers::admin::comment_approval_action))
+        .route("/admin/comment/settings", get(handlers::admin::comment_settings_form))
+        .route("/admin/comment/settings", post(handlers::admin::comment_settings_submit))
         .route("/admin/logs/settings", get(handlers::admin::statistics_settings_form))
         .route("/admin/logs/settings", post(handlers::admin::statistics_settings_submit))
+        .route("/user", get(handlers::user::account))
         .route("/user/login", get(handlers::user::login_form))
         .route("/user/login", post(handlers::user::login_submit))
         .route("/user/logout", get(handlers::user::logout))
+        .route("/user/password", get(handlers::user::password_form))
+        .route("/user/password", post(handlers::user::password_submit))
+        .route("/user/reset/:uid/:timestamp/:hash", get(handlers::user::reset_login))
         .route("/user/register", get(handlers::user::register_form))
         .route("/user/register", post(handlers::user::register_submit))
         .route("/user/:uid", get(handlers::user::profile))
         .route("/user/:uid/edit", get(handlers::user::edit_form))
         .route("/user/:uid/edit", post(handlers::user::edit_submit))
+        .route("/user/:uid/picture", post(handlers::user::picture_submit))
+        .route("/user/:uid/delete", get(handlers::user::delete_confirm))
+        .route("/user/:uid/delete", post(handlers::user::delete_submit))
+        .route("/user/:uid/track", get(handlers::user::track))
+        .route("/user/:uid/discussions", get(handlers::user::discussions))
+        .route("/user/:uid/discussions/feed", get(handlers::user::discussions_feed))
+        .route("/files/styles/:style/*file", get(handlers::image::style_derivative))
         .route("/node/add", get(handlers::node::list_types))
         .route("/node/add/:type", get(handlers::node::add_form))
         .route("/node/add/:type", post(handlers::node::add_submit))
         .route("/node/:nid", get(handlers::node::view))
         .route("/node/:nid/edit", get(handlers::node::edit_form))
         .route("/node/:nid/edit", post(handlers::node::edit_submit))
+        .route("/node/:nid/delete", get(handlers::node::delete_form))
+        .route("/node/:nid/delete", post(handlers::node::delete_submit))
+        .route("/node/:nid/revisions", get(handlers::node::revisions))
+        .route("/node/:nid/nominate", post(handlers::node::nominate))
+        .route("/node/:nid/revisions/:vid/revert", post(handlers::node::revert_revision))
+        .route("/tracker", get(handlers::tracker::index))
+        .route("/batch/:bid", get(handlers::batch::progress))
         // Comment routes
+        .route("/contact", get(handlers::contact::form))
+        .route("/contact", post(handlers::contact::submit))
         .route("/comment/reply/:nid", get(handlers::comment::add_form))
         .route("/comment/reply/:nid", post(handlers::comment::add_submit))
         .route("/comment/reply/:cid/reply", get(handlers::comment::reply_form))
         .route("/comment/reply/:cid/reply", post(handlers::comment::reply_submit))
+        .route("/comments/recent", get(handlers::comment::recent))
+        .route("/comment/:cid", get(handlers::comment::permalink))
         .route("/comment/:cid/edit", get(handlers::comment::edit_form))
         .route("/comment/:cid/edit", post(handlers::comment::edit_submit))
         .route("/comment/:cid/delete", get(handlers::comment::delete_confirm))
-        .route("/comment/:cid/delete", post(handlers::comment::delete_submit));
+        .route("/comment/:cid/delete", post(handlers::comment::delete_submit))
+        .route("/comment/:cid/status", post(handlers::comment::status_submit));
 
     println!("Base routes created");
 
     let app = app.nest_service("/static", ServeDir::new("static"));
     println!("Static routes added");
 
-    let app = app.layer(middleware::from_fn_with_state(pool.clone(), auth_middleware));
-    println!("Auth middleware added");
+    let app = app.layer(middleware::from_fn_with_state(pool.clone(), watchdog::watchdog_middleware));
+    println!("Watchdog middleware added");
 
-    let app = app.layer(middleware::from_fn_with_state(pool, statistics::statistics_middleware));
+    // Themed 403 pages show the user, so this too runs inside the auth
+    // middleware, but outside the watchdog so the error is still logged.
+    let app = app.layer(middleware::from_fn_with_state(state.clone(), error_page::access_denied_middleware));
+    println!("Access denied page middleware added");
+
+    // Statistics need the current user, so they run inside the auth middleware.
+    let app = app.layer(middleware::from_fn(statistics::statistics_middleware));
     println!("Statistics middleware added");
 
+    let app = app.layer(middleware::from_fn_with_state(state.clone(), auth_middleware));
+    println!("Auth middleware added");
+
     let app = app.layer(session_layer);
     println!("Session middleware added");
 
     let app = app.with_state(state);
     println!("State added");
 
+    cron::spawn(pool.clone());
+    statistics::spawn(pool.clone());
+
+    // Alias resolution has to happen before routing, so it wraps the router
+    // instead of being added as a route layer.
+    let app = middleware::from_fn_with_state(pool, path::resolve_alias).layer(app);
+    println!("Path alias resolver added");
+
     println!("App router created");
+
     let listener = tokio::net::TcpListener::bind(config.bind_address()).await?;
     println!("Server listening on http://{}", config.bind_address());
     tracing::info!("Server listening on http://{}", config.bind_address());
 
-    axum::serve(listener, app.into_make_service_with_connect_info::<std::net::SocketAddr>()).await?;
+    axum::serve(
+        listener,
+        ServiceExt::<Request>::into_make_service_with_connect_info::<std::net::SocketAddr>(app),
+    )
+    .await?;
     println!("Server stopped");
 
     Ok(())
@@ -0,0 +1,193 @@
+use serde::{Deserialize, Serialize};
+use sqlx::QueryBuilder;
+
+use crate::db::{Connection, Db, Pool};
+use std::time::Duration;
+
+use crate::cache::{cache_get, cache_set, invalidate_tags};
+
+const ALIAS_CACHE_BIN: &str = "path";
+const ALIAS_CACHE_TAG: &str = "path";
+const ALIAS_CACHE_TTL: Duration = Duration::from_secs(3600);
+
+/// First path segments the router answers itself. An alias under one of
+/// them would be resolved before routing and take over a system page, such
+/// as `user/login`, so none may be used.
+const SYSTEM_PATHS: &[&str] = &[
+    "admin", "batch", "comment", "comments", "contact", "cron", "files", "health", "install",
+    "node", "promoted", "promoted.json", "rss.xml", "static", "tracker", "user",
+];
+
+/// Whether `path` (without a leading slash) belongs to a route of the site
+/// rather than to an alias. Compared without case, as the `url_alias`
+/// lookup is.
+pub fn is_system_path(path: &str) -> bool {
+    let first = path.split('/').next().unwrap_or("");
+    SYSTEM_PATHS.iter().any(|system| system.eq_ignore_ascii_case(first))
+}
+
+/// Maps an internal path (`src`, e.g. "node/5") to a public alias (`dst`,
+/// e.g. "about-us"). Both are stored without a leading slash.
+#[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
+pub struct Alias {
+    pub pid: u32,
+    pub src: String,
+    pub dst: String,
+}
+
+impl Alias {
+    /// Alias for an internal path, if one exists.
+    pub async fn lookup(pool: &Pool, src: &str) -> Result<Option<String>, sqlx::Error> {
+        let key = format!("src:{}", src);
+        if let Some(cached) = cache_get::<Option<String>>(ALIAS_CACHE_BIN, &key) {
+            return Ok(cached);
+        }
+
+        let result: Option<(String,)> =
+            sqlx::query_as("SELECT dst FROM url_alias WHERE src = ? ORDER BY pid DESC LIMIT 1")
+                .bind(src)
+                .fetch_optional(pool)
+                .await?;
+
+        let dst = result.map(|(dst,)| dst);
+        cache_set(ALIAS_CACHE_BIN, &key, dst.clone(), ALIAS_CACHE_TTL, &[ALIAS_CACHE_TAG]);
+        Ok(dst)
+    }
+
+    /// Internal path behind an alias, if the alias exists. Every request
+    /// path is looked up here, so only aliases that exist are cached; caching
+    /// misses would let anyone grow the cache by requesting made-up paths.
+    pub async fn source(pool: &Pool, path: &str) -> Result<Option<String>, sqlx::Error> {
+        let key = format!("dst:{}", path);
+        if let Some(cached) = cache_get::<String>(ALIAS_CACHE_BIN, &key) {
+            return Ok(Some(cached));
+        }
+
+        let result: Option<(String,)> = sqlx::query_as("SELECT src FROM url_alias WHERE dst = ?")
+            .bind(path)
+            .fetch_optional(pool)
+            .await?;
+
+        let src = result.map(|(src,)| src);
+        if let Some(src) = &src {
+            cache_set(ALIAS_CACHE_BIN, &key, src.clone(), ALIAS_CACHE_TTL, &[ALIAS_CACHE_TAG]);
+        }
+        Ok(src)
+    }
+
+    /// Replace the alias of `src`. An empty alias removes it.
+    pub async fn set(pool: &Pool, src: &str, dst: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM url_alias WHERE src = ?")
+            .bind(src)
+            .execute(pool)
+            .await?;
+
+        if !dst.is_empty() {
+            sqlx::query("INSERT INTO url_alias (src, dst) VALUES (?, ?)")
+                .bind(src)
+                .bind(dst)
+                .execute(pool)
+                .await?;
+        }
+
+        invalidate_tags(&[ALIAS_CACHE_TAG]);
+        Ok(())
+    }
+
+    /// Remove the aliases of several paths as part of a larger transaction.
+    /// Call [`Alias::clear_cache`] once it has been committed.
+    pub async fn delete_for_sources(
+        conn: &mut Connection,
+        sources: &[String],
+    ) -> Result<(), sqlx::Error> {
+        if sources.is_empty() {
+            return Ok(());
+        }
+
+        let mut query = QueryBuilder::<Db>::new("DELETE FROM url_alias WHERE src IN (");
+        let mut separated = query.separated(", ");
+        for src in sources {
+            separated.push_bind(src.as_str());
+        }
+        query.push(")");
+
+        query.build().execute(conn).await?;
+        Ok(())
+    }
+
+    pub fn clear_cache() {
+        invalidate_tags(&[ALIAS_CACHE_TAG]);
+    }
+
+    /// Check that `dst` may be used as the alias of `src`, returning a
+    /// validation message when it may not.
+    pub async fn validate(
+        pool: &Pool,
+        src: &str,
+        dst: &str,
+    ) -> Result<Option<&'static str>, sqlx::Error> {
+        if dst.is_empty() {
+            return Ok(None);
+        }
+
+        if dst.len() > 128 || dst.chars().any(|c| c.is_whitespace() || c == '?' || c == '#') {
+            return Ok(Some("The URL alias is invalid"));
+        }
+
+        if is_system_path(dst) {
+            return Ok(Some("The URL alias is reserved for a page of the site"));
+        }
+
+        match Self::source(pool, dst).await? {
+            Some(existing) if existing != src => Ok(Some("The URL alias is already in use")),
+            _ => Ok(None),
+        }
+    }
+}
+
+/// Normalise user input: trim whitespace and surrounding slashes.
+pub fn normalize_alias(input: &str) -> String {
+    input.trim().trim_matches('/').to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::test_site;
+
+    #[test]
+    fn system_paths_are_reserved() {
+        for path in ["user/login", "user/password", "admin", "node/1", "rss.xml", "static/logo.png"] {
+            assert!(is_system_path(path), "{} should be reserved", path);
+        }
+        assert!(is_system_path("Admin/settings"));
+    }
+
+    #[test]
+    fn other_paths_are_free() {
+        for path in ["about-us", "users", "news/admin", "nodes/1"] {
+            assert!(!is_system_path(path), "{} should be free", path);
+        }
+    }
+
+    #[test]
+    fn normalize_trims_slashes_and_space() {
+        assert_eq!(normalize_alias("  /about-us/ "), "about-us");
+        assert_eq!(normalize_alias("/"), "");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn only_existing_aliases_are_cached() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        Alias::set(pool, "node/1", "about-us").await.unwrap();
+
+        assert_eq!(Alias::source(pool, "no-such-page").await.unwrap(), None);
+        assert!(cache_get::<String>(ALIAS_CACHE_BIN, "dst:no-such-page").is_none());
+        assert!(cache_get::<Option<String>>(ALIAS_CACHE_BIN, "dst:no-such-page").is_none());
+
+        assert_eq!(Alias::source(pool, "about-us").await.unwrap().as_deref(), Some("node/1"));
+        assert_eq!(cache_get::<String>(ALIAS_CACHE_BIN, "dst:about-us").as_deref(), Some("node/1"));
+    }
+}
@@ -0,0 +1,122 @@
+use serde::Serialize;
+
+use crate::db::{InsertId, Pool};
+
+pub const BATCH_ACTIVE: &str = "active";
+pub const BATCH_DONE: &str = "done";
+pub const BATCH_FAILED: &str = "failed";
+
+/// A queued long-running job. `state` is the job's own JSON cursor, saved
+/// after every chunk so an interrupted batch resumes where it stopped.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct Batch {
+    pub bid: u32,
+    pub uid: u32,
+    pub job_type: String,
+    pub state: String,
+    pub total: i32,
+    pub processed: i32,
+    pub status: String,
+    pub message: Option<String>,
+    pub destination: String,
+    pub locked: i32,
+    pub created: i32,
+    pub updated: i32,
+}
+
+impl Batch {
+    pub async fn create(
+        pool: &Pool,
+        uid: u32,
+        job_type: &str,
+        state: &str,
+        total: i32,
+        destination: &str,
+    ) -> Result<u32, sqlx::Error> {
+        let now = chrono::Utc::now().timestamp() as i32;
+        let status = if total > 0 { BATCH_ACTIVE } else { BATCH_DONE };
+
+        let bid = sqlx::query(
+            "INSERT INTO batch (uid, job_type, state, total, processed, status, destination, created, updated)
+             VALUES (?, ?, ?, ?, 0, ?, ?, ?, ?)",
+        )
+        .bind(uid)
+        .bind(job_type)
+        .bind(state)
+        .bind(total)
+        .bind(status)
+        .bind(destination)
+        .bind(now)
+        .bind(now)
+        .insert_id(pool)
+        .await?;
+
+        Ok(bid)
+    }
+
+    pub async fn find_by_id(pool: &Pool, bid: u32) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Batch>("SELECT * FROM batch WHERE bid = ?")
+            .bind(bid)
+            .fetch_optional(pool)
+            .await
+    }
+
+    pub async fn active_ids(pool: &Pool) -> Result<Vec<u32>, sqlx::Error> {
+        let rows: Vec<(u32,)> =
+            sqlx::query_as("SELECT bid FROM batch WHERE status = ? ORDER BY bid")
+                .bind(BATCH_ACTIVE)
+                .fetch_all(pool)
+                .await?;
+        Ok(rows.into_iter().map(|(bid,)| bid).collect())
+    }
+
+    /// Take the processing lock of an active batch. Fails when another
+    /// request holds it, unless that lock is older than `stale_after`
+    /// seconds (the holder most likely died mid-chunk).
+    pub async fn claim(pool: &Pool, bid: u32, stale_after: i32) -> Result<bool, sqlx::Error> {
+        let now = chrono::Utc::now().timestamp() as i32;
+        let result = sqlx::query(
+            "UPDATE batch SET locked = ? WHERE bid = ? AND status = ? AND locked < ?",
+        )
+        .bind(now)
+        .bind(bid)
+        .bind(BATCH_ACTIVE)
+        .bind(now - stale_after)
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Store the outcome of a chunk and release the lock.
+    pub async fn save_progress(
+        pool: &Pool,
+        bid: u32,
+        state: &str,
+        processed: i32,
+        status: &str,
+        message: Option<&str>,
+    ) -> Result<(), sqlx::Error> {
+        let now = chrono::Utc::now().timestamp() as i32;
+        sqlx::query(
+            "UPDATE batch SET state = ?, processed = ?, status = ?, message = ?, locked = 0, updated = ?
+             WHERE bid = ?",
+        )
+        .bind(state)
+        .bind(processed)
+        .bind(status)
+        .bind(message)
+        .bind(now)
+        .bind(bid)
+        .execute(pool)
+        .await?;
+        Ok(())
+    }
+
+    pub fn percentage(&self) -> i32 {
+        if self.total <= 0 {
+            return 100;
+        }
+        (self.processed.min(self.total) * 100) / self.total
+    }
+}
@@ -1,5 +1,9 @@
 use serde::{Deserialize, Serialize};
-use sqlx::MySqlPool;
+use sqlx::QueryBuilder;
+
+use crate::db::{Connection, Db, InsertId, Pool};
+
+use super::{User, Variable};
 
 /// Comment status constants (matching Drupal 4.7)
 pub const COMMENT_PUBLISHED: i32 = 0;
@@ -10,6 +14,295 @@ pub const COMMENT_NODE_DISABLED: i32 = 0;
 pub const COMMENT_NODE_READ_ONLY: i32 = 1;
 pub const COMMENT_NODE_READ_WRITE: i32 = 2;
 
+/// Which of a node's comments a listing shows.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommentListing {
+    Published,
+    All,
+    /// Only comments waiting for approval, for moderators.
+    Unapproved,
+}
+
+impl CommentListing {
+    /// All comments for viewers who may administer or moderate comments,
+    /// otherwise only the published ones.
+    pub async fn for_viewer(pool: &Pool, viewer: Option<&User>) -> Result<Self, sqlx::Error> {
+        let moderator = match viewer {
+            Some(user) => {
+                user.has_permission(pool, "administer comments").await?
+                    || user.has_permission(pool, "moderate comments").await?
+            }
+            None => false,
+        };
+        Ok(if moderator { CommentListing::All } else { CommentListing::Published })
+    }
+
+    fn condition(self) -> &'static str {
+        match self {
+            CommentListing::Published => "c.status = 0",
+            CommentListing::All => "1 = 1",
+            CommentListing::Unapproved => "c.status = 1",
+        }
+    }
+}
+
+/// Order of the top-level threads on a node page, from the
+/// `comment_default_order` variable. Replies always follow their parent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CommentOrder {
+    NewestFirst,
+    #[default]
+    OldestFirst,
+}
+
+impl CommentOrder {
+    /// (value, label) pairs for the settings form; the values match
+    /// Drupal's COMMENT_ORDER_NEWEST_FIRST and COMMENT_ORDER_OLDEST_FIRST.
+    pub const OPTIONS: &'static [(&'static str, &'static str)] =
+        &[("1", "Date - newest first"), ("2", "Date - oldest first")];
+
+    pub async fn load(pool: &Pool) -> Self {
+        Self::from_value(&Variable::get_or_default(pool, "comment_default_order", "2").await)
+    }
+
+    pub fn from_value(value: &str) -> Self {
+        match value {
+            "1" => CommentOrder::NewestFirst,
+            _ => CommentOrder::OldestFirst,
+        }
+    }
+
+    pub fn value(self) -> &'static str {
+        match self {
+            CommentOrder::NewestFirst => "1",
+            CommentOrder::OldestFirst => "2",
+        }
+    }
+
+}
+
+/// Whether a node's comments are shown as indented threads or as one flat
+/// list by date, from the `comment_default_mode` variable.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CommentMode {
+    Flat,
+    #[default]
+    Threaded,
+}
+
+impl CommentMode {
+    /// (value, label) pairs for the settings form; the values match
+    /// Drupal's COMMENT_MODE_FLAT_EXPANDED and COMMENT_MODE_THREADED_EXPANDED.
+    pub const OPTIONS: &'static [(&'static str, &'static str)] =
+        &[("2", "Flat list - expanded"), ("4", "Threaded list - expanded")];
+
+    pub async fn load(pool: &Pool) -> Self {
+        Self::from_value(&Variable::get_or_default(pool, "comment_default_mode", "4").await)
+    }
+
+    pub fn from_value(value: &str) -> Self {
+        match value {
+            "1" | "2" => CommentMode::Flat,
+            _ => CommentMode::Threaded,
+        }
+    }
+
+    pub fn value(self) -> &'static str {
+        match self {
+            CommentMode::Flat => "2",
+            CommentMode::Threaded => "4",
+        }
+    }
+
+    /// The mode a `?mode=` query parameter asks for, if it names one.
+    pub fn from_query(value: &str) -> Option<Self> {
+        match value {
+            "flat" => Some(CommentMode::Flat),
+            "threaded" => Some(CommentMode::Threaded),
+            _ => None,
+        }
+    }
+
+    /// Name of the mode in `?mode=` links.
+    pub fn name(self) -> &'static str {
+        match self {
+            CommentMode::Flat => "flat",
+            CommentMode::Threaded => "threaded",
+        }
+    }
+}
+
+/// How a node's comment listing is laid out: which way it runs and whether
+/// replies are nested under their parent.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CommentDisplay {
+    pub order: CommentOrder,
+    pub mode: CommentMode,
+}
+
+impl CommentDisplay {
+    /// The site-wide defaults.
+    pub async fn load(pool: &Pool) -> Self {
+        CommentDisplay {
+            order: CommentOrder::load(pool).await,
+            mode: CommentMode::load(pool).await,
+        }
+    }
+
+    /// ORDER BY clause for `comments c`. Threads sort on the vancode of
+    /// their top-level comment, then on the full thread so replies stay
+    /// below their parent whichever way the threads run. A flat list sorts
+    /// on the posting date alone.
+    fn order_by(self) -> &'static str {
+        match (self.mode, self.order) {
+            (CommentMode::Flat, CommentOrder::NewestFirst) => "c.timestamp DESC, c.cid DESC",
+            (CommentMode::Flat, CommentOrder::OldestFirst) => "c.timestamp, c.cid",
+            (CommentMode::Threaded, CommentOrder::NewestFirst) => {
+                "SUBSTRING_INDEX(SUBSTRING(c.thread, 1, LENGTH(c.thread) - 1), '.', 1) DESC,
+                 SUBSTRING(c.thread, 1, LENGTH(c.thread) - 1)"
+            }
+            (CommentMode::Threaded, CommentOrder::OldestFirst) => "SUBSTRING(c.thread, 1, LENGTH(c.thread) - 1)",
+        }
+    }
+
+    /// Condition under which comment `c` is listed before comment `t`,
+    /// matching `order_by`.
+    fn before(self) -> &'static str {
+        match (self.mode, self.order) {
+            (CommentMode::Flat, CommentOrder::NewestFirst) => {
+                "(c.timestamp > t.timestamp OR (c.timestamp = t.timestamp AND c.cid > t.cid))"
+            }
+            (CommentMode::Flat, CommentOrder::OldestFirst) => {
+                "(c.timestamp < t.timestamp OR (c.timestamp = t.timestamp AND c.cid < t.cid))"
+            }
+            (CommentMode::Threaded, CommentOrder::NewestFirst) => {
+                "(SUBSTRING_INDEX(SUBSTRING(c.thread, 1, LENGTH(c.thread) - 1), '.', 1)
+                    > SUBSTRING_INDEX(SUBSTRING(t.thread, 1, LENGTH(t.thread) - 1), '.', 1)
+                  OR (SUBSTRING_INDEX(SUBSTRING(c.thread, 1, LENGTH(c.thread) - 1), '.', 1)
+                        = SUBSTRING_INDEX(SUBSTRING(t.thread, 1, LENGTH(t.thread) - 1), '.', 1)
+                      AND SUBSTRING(c.thread, 1, LENGTH(c.thread) - 1) < SUBSTRING(t.thread, 1, LENGTH(t.thread) - 1)))"
+            }
+            (CommentMode::Threaded, CommentOrder::OldestFirst) => {
+                "SUBSTRING(c.thread, 1, LENGTH(c.thread) - 1) < SUBSTRING(t.thread, 1, LENGTH(t.thread) - 1)"
+            }
+        }
+    }
+}
+
+/// Limits on the length of a comment body in characters, from the
+/// `comment_minlength` and `comment_maxlength` variables. 0 means no limit.
+#[derive(Debug, Clone, Copy, Default, Serialize)]
+pub struct CommentLength {
+    pub min: usize,
+    pub max: usize,
+}
+
+impl CommentLength {
+    pub async fn load(pool: &Pool) -> Self {
+        let limit = |value: String| value.trim().parse().unwrap_or(0);
+        Self {
+            min: limit(Variable::get_or_default(pool, "comment_minlength", "0").await),
+            max: limit(Variable::get_or_default(pool, "comment_maxlength", "0").await),
+        }
+    }
+
+    /// Check a submitted comment body, returning a message for the comment
+    /// form. Characters are counted, not bytes, ignoring surrounding
+    /// whitespace.
+    pub fn check(&self, comment: &str) -> Result<(), String> {
+        let length = comment.trim().chars().count();
+        if self.min > 0 && length < self.min {
+            return Err(format!(
+                "Your comment is too short: it has {} characters, at least {} are required.",
+                length, self.min
+            ));
+        }
+        if self.max > 0 && length > self.max {
+            return Err(format!(
+                "Your comment is too long: it has {} characters, at most {} are allowed.",
+                length, self.max
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Whether anonymous commenters leave their contact details, from the
+/// `comment_anonymous` variable.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommentAnonymous {
+    /// No name, e-mail or homepage is asked for or stored.
+    Hidden,
+    /// Name, e-mail and homepage are optional.
+    Optional,
+    /// Name and a valid e-mail address are required.
+    Required,
+}
+
+impl CommentAnonymous {
+    /// (value, label) pairs for the settings form; the values match
+    /// Drupal's COMMENT_ANONYMOUS_MAYNOT_CONTACT, _MAY_CONTACT and
+    /// _MUST_CONTACT.
+    pub const OPTIONS: &'static [(&'static str, &'static str)] = &[
+        ("0", "Anonymous posters may not enter their contact information"),
+        ("1", "Anonymous posters may leave their contact information"),
+        ("2", "Anonymous posters must leave their contact information"),
+    ];
+
+    pub async fn load(pool: &Pool) -> Self {
+        Self::from_value(&Variable::get_or_default(pool, "comment_anonymous", "1").await)
+    }
+
+    pub fn from_value(value: &str) -> Self {
+        match value {
+            "0" => CommentAnonymous::Hidden,
+            "2" => CommentAnonymous::Required,
+            _ => CommentAnonymous::Optional,
+        }
+    }
+
+    pub fn value(self) -> &'static str {
+        match self {
+            CommentAnonymous::Hidden => "0",
+            CommentAnonymous::Optional => "1",
+            CommentAnonymous::Required => "2",
+        }
+    }
+}
+
+/// Whether a new comment has to be previewed before it can be saved, from
+/// the `comment_preview` variable.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum CommentPreview {
+    #[default]
+    Optional,
+    Required,
+}
+
+impl CommentPreview {
+    /// (value, label) pairs for the settings form; the values match
+    /// Drupal's COMMENT_PREVIEW_OPTIONAL and COMMENT_PREVIEW_REQUIRED.
+    pub const OPTIONS: &'static [(&'static str, &'static str)] = &[("0", "Optional"), ("1", "Required")];
+
+    pub async fn load(pool: &Pool) -> Self {
+        Self::from_value(&Variable::get_or_default(pool, "comment_preview", "0").await)
+    }
+
+    pub fn from_value(value: &str) -> Self {
+        match value {
+            "1" => CommentPreview::Required,
+            _ => CommentPreview::Optional,
+        }
+    }
+
+    pub fn value(self) -> &'static str {
+        match self {
+            CommentPreview::Optional => "0",
+            CommentPreview::Required => "1",
+        }
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Comment {
     pub cid: u32,
@@ -44,6 +337,9 @@ pub struct CommentWithAuthor {
     pub homepage: Option<String>,
     pub author_name: Option<String>,
     pub depth: i32,
+    /// The author's user picture; only selected for the node page.
+    #[sqlx(default)]
+    pub author_picture: String,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
@@ -55,53 +351,309 @@ pub struct NodeCommentStatistics {
     pub comment_count: u32,
 }
 
+/// A comment in site-wide listings, with the title of its node. Carries no
+/// e-mail or IP address, so it can go to any template as is.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct RecentComment {
+    pub cid: u32,
+    pub nid: u32,
+    pub uid: u32,
+    pub subject: String,
+    pub comment: String,
+    pub timestamp: i32,
+    pub name: Option<String>,
+    /// Only for linking through `homepage_link`; never shown as stored.
+    #[serde(skip_serializing)]
+    pub homepage: Option<String>,
+    pub author_name: Option<String>,
+    pub node_title: String,
+}
+
 impl Comment {
-    pub async fn find_by_cid(pool: &MySqlPool, cid: u32) -> Result<Option<Self>, sqlx::Error> {
+    /// Whether comment forms on nodes of `node_type` show the subject field.
+    /// When hidden, subjects are always generated from the comment body.
+    pub async fn subject_field_enabled(pool: &Pool, node_type: &str) -> bool {
+        Variable::get_or_default(pool, &format!("comment_subject_field_{}", node_type), "1").await
+            == "1"
+    }
+
+    pub async fn find_by_cid(pool: &Pool, cid: u32) -> Result<Option<Self>, sqlx::Error> {
         sqlx::query_as("SELECT * FROM comments WHERE cid = ?")
             .bind(cid)
             .fetch_optional(pool)
             .await
     }
 
-    pub async fn find_for_node(
-        pool: &MySqlPool,
-        nid: u32,
-        include_unpublished: bool,
-    ) -> Result<Vec<CommentWithAuthor>, sqlx::Error> {
-        let query = if include_unpublished {
+    /// A single comment with its author's name and picture.
+    pub async fn find_with_author(
+        pool: &Pool,
+        cid: u32,
+    ) -> Result<Option<CommentWithAuthor>, sqlx::Error> {
+        sqlx::query_as(
             r#"
-            SELECT c.*, u.name as author_name,
+            SELECT c.*, u.name as author_name, COALESCE(u.picture, '') as author_picture,
                    (LENGTH(c.thread) - LENGTH(REPLACE(c.thread, '.', ''))) as depth
             FROM comments c
             LEFT JOIN users u ON c.uid = u.uid
-            WHERE c.nid = ?
-            ORDER BY SUBSTRING(c.thread, 1, LENGTH(c.thread) - 1)
-            "#
-        } else {
+            WHERE c.cid = ?
+            "#,
+        )
+        .bind(cid)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// One page of a node's comments in display order. `depth` is clamped
+    /// to `max_depth`, so comments nested deeper before the limit was
+    /// lowered are indented no further than new replies would be; in a flat
+    /// list it is 0 throughout.
+    pub async fn find_for_node(
+        pool: &Pool,
+        nid: u32,
+        listing: CommentListing,
+        display: CommentDisplay,
+        max_depth: Option<u32>,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<CommentWithAuthor>, sqlx::Error> {
+        let mut comments: Vec<CommentWithAuthor> = sqlx::query_as(&format!(
             r#"
-            SELECT c.*, u.name as author_name,
+            SELECT c.*, u.name as author_name, COALESCE(u.picture, '') as author_picture,
                    (LENGTH(c.thread) - LENGTH(REPLACE(c.thread, '.', ''))) as depth
             FROM comments c
             LEFT JOIN users u ON c.uid = u.uid
-            WHERE c.nid = ? AND c.status = 0
-            ORDER BY SUBSTRING(c.thread, 1, LENGTH(c.thread) - 1)
-            "#
+            WHERE c.nid = ? AND {}
+            ORDER BY {}
+            LIMIT ? OFFSET ?
+            "#,
+            listing.condition(),
+            display.order_by()
+        ))
+        .bind(nid)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await?;
+
+        let max_depth = match display.mode {
+            CommentMode::Flat => Some(0),
+            CommentMode::Threaded => max_depth,
+        };
+        if let Some(max_depth) = max_depth {
+            for comment in &mut comments {
+                comment.depth = comment.depth.min(max_depth as i32);
+            }
+        }
+        Ok(comments)
+    }
+
+    fn push_recent_filter(query: &mut QueryBuilder<'_, Db>, published_nodes_only: bool) {
+        query.push(" WHERE c.status = ").push_bind(COMMENT_PUBLISHED);
+        if published_nodes_only {
+            query.push(" AND n.status = 1");
+        }
+    }
+
+    /// The latest published comments across the site, newest first. With
+    /// `published_nodes_only`, comments on unpublished nodes are left out.
+    pub async fn recent(
+        pool: &Pool,
+        published_nodes_only: bool,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<RecentComment>, sqlx::Error> {
+        let mut query = QueryBuilder::<Db>::new(
+            "SELECT c.cid, c.nid, c.uid, c.subject, c.comment, c.timestamp, c.name, c.homepage,
+                    u.name as author_name, n.title as node_title
+             FROM comments c
+             INNER JOIN node n ON c.nid = n.nid
+             LEFT JOIN users u ON c.uid = u.uid",
+        );
+        Self::push_recent_filter(&mut query, published_nodes_only);
+        query
+            .push(" ORDER BY c.timestamp DESC, c.cid DESC LIMIT ")
+            .push_bind(limit)
+            .push(" OFFSET ")
+            .push_bind(offset);
+
+        query.build_query_as().fetch_all(pool).await
+    }
+
+    pub async fn recent_count(pool: &Pool, published_nodes_only: bool) -> Result<i64, sqlx::Error> {
+        let mut query = QueryBuilder::<Db>::new(
+            "SELECT COUNT(*) FROM comments c INNER JOIN node n ON c.nid = n.nid",
+        );
+        Self::push_recent_filter(&mut query, published_nodes_only);
+        let (count,): (i64,) = query.build_query_as().fetch_one(pool).await?;
+        Ok(count)
+    }
+
+    /// Comments waiting for approval, oldest first so the queue is worked
+    /// through in the order comments arrived.
+    pub async fn unapproved(
+        pool: &Pool,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<RecentComment>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT c.cid, c.nid, c.uid, c.subject, c.comment, c.timestamp, c.name, c.homepage,
+                    u.name as author_name, n.title as node_title
+             FROM comments c
+             INNER JOIN node n ON c.nid = n.nid
+             LEFT JOIN users u ON c.uid = u.uid
+             WHERE c.status = ?
+             ORDER BY c.timestamp, c.cid
+             LIMIT ? OFFSET ?",
+        )
+        .bind(COMMENT_NOT_PUBLISHED)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Comments posted at or after `since`, published or not.
+    pub async fn count_since(pool: &Pool, since: i64) -> Result<i64, sqlx::Error> {
+        let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM comments WHERE timestamp >= ?")
+            .bind(since)
+            .fetch_one(pool)
+            .await?;
+        Ok(count)
+    }
+
+    pub async fn count_unapproved(pool: &Pool) -> Result<i64, sqlx::Error> {
+        let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM comments WHERE status = ?")
+            .bind(COMMENT_NOT_PUBLISHED)
+            .fetch_one(pool)
+            .await?;
+        Ok(count)
+    }
+
+    pub async fn count_visible_for_node(
+        pool: &Pool,
+        nid: u32,
+        listing: CommentListing,
+    ) -> Result<i64, sqlx::Error> {
+        let (count,): (i64,) = sqlx::query_as(&format!(
+            "SELECT COUNT(*) FROM comments c WHERE c.nid = ? AND {}",
+            listing.condition()
+        ))
+        .bind(nid)
+        .fetch_one(pool)
+        .await?;
+        Ok(count)
+    }
+
+    /// Comments shown per page on nodes of `node_type`: the type's own
+    /// setting, else the site-wide `comment_default_per_page`.
+    pub async fn per_page(pool: &Pool, node_type: &str) -> u32 {
+        let type_setting = Variable::get(pool, &format!("comment_default_per_page_{}", node_type))
+            .await
+            .ok()
+            .flatten()
+            .and_then(|v| v.parse().ok())
+            .filter(|n| *n > 0);
+        match type_setting {
+            Some(per_page) => per_page,
+            None => Self::default_per_page(pool).await,
+        }
+    }
+
+    /// The `comment_max_depth` variable: how many levels replies may nest
+    /// below a top-level comment, 8 unless set. `None` (0) means no limit.
+    pub async fn max_depth(pool: &Pool) -> Option<u32> {
+        Variable::get_or_default(pool, "comment_max_depth", "8")
+            .await
+            .parse()
+            .ok()
+            .filter(|n| *n > 0)
+    }
+
+    /// Nesting level of the comment, 0 for a top-level comment. Every level
+    /// adds a `.`-separated part to the thread.
+    pub fn depth(&self) -> u32 {
+        self.thread.matches('.').count() as u32
+    }
+
+    /// The comment a reply to `parent` is attached to. Below `max_depth`
+    /// that is `parent` itself; a reply to a comment at the limit goes to
+    /// the closest ancestor it can still nest under, so it sits next to the
+    /// comment it answers and the thread never grows deeper.
+    pub async fn reply_parent(
+        pool: &Pool,
+        parent: Comment,
+        max_depth: Option<u32>,
+    ) -> Result<Comment, sqlx::Error> {
+        let Some(max_depth) = max_depth else {
+            return Ok(parent);
         };
+        let mut parent = parent;
+        while parent.depth() >= max_depth {
+            match Self::find_by_cid(pool, parent.pid).await? {
+                Some(ancestor) => parent = ancestor,
+                None => break,
+            }
+        }
+        Ok(parent)
+    }
+
+    /// What listings show for a node without comments when the viewer
+    /// cannot add one: the `comment_zero_text` variable, "No comments"
+    /// unless set. An empty text shows nothing.
+    pub async fn zero_text(pool: &Pool) -> String {
+        Variable::get_or_default(pool, "comment_zero_text", "No comments").await
+    }
+
+    /// The site-wide `comment_default_per_page`.
+    pub async fn default_per_page(pool: &Pool) -> u32 {
+        Variable::get_or_default(pool, "comment_default_per_page", "50")
+            .await
+            .parse()
+            .ok()
+            .filter(|n| *n > 0)
+            .unwrap_or(50)
+    }
+
+    /// Zero-based page of the node's comment listing on which `cid` appears,
+    /// counting the comments `find_for_node` would list before it.
+    pub async fn page_for_cid(
+        pool: &Pool,
+        cid: u32,
+        per_page: u32,
+        listing: CommentListing,
+        display: CommentDisplay,
+    ) -> Result<u32, sqlx::Error> {
+        let (before,): (i64,) = sqlx::query_as(&format!(
+            r#"
+            SELECT COUNT(*)
+            FROM comments c
+            INNER JOIN comments t ON t.cid = ? AND c.nid = t.nid
+            WHERE {} AND {}
+            "#,
+            listing.condition(),
+            display.before()
+        ))
+        .bind(cid)
+        .fetch_one(pool)
+        .await?;
 
-        sqlx::query_as(query).bind(nid).fetch_all(pool).await
+        Ok((before / per_page.max(1) as i64) as u32)
     }
 
-    pub async fn count_for_node(pool: &MySqlPool, nid: u32) -> Result<u32, sqlx::Error> {
+    pub async fn count_for_node<'c, E>(executor: E, nid: u32) -> Result<u32, sqlx::Error>
+    where
+        E: sqlx::Executor<'c, Database = Db>,
+    {
         let result: (i64,) =
             sqlx::query_as("SELECT COUNT(*) FROM comments WHERE nid = ? AND status = 0")
                 .bind(nid)
-                .fetch_one(pool)
+                .fetch_one(executor)
                 .await?;
         Ok(result.0 as u32)
     }
 
     pub async fn create(
-        pool: &MySqlPool,
+        pool: &Pool,
         nid: u32,
         pid: u32,
         uid: u32,
@@ -118,7 +670,7 @@ impl Comment {
         // Calculate thread value
         let thread = Self::calculate_thread(pool, nid, pid).await?;
 
-        let result = sqlx::query(
+        let cid = sqlx::query(
             r#"
             INSERT INTO comments (nid, pid, uid, subject, comment, hostname, timestamp, status, thread, name, mail, homepage)
             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
@@ -136,19 +688,19 @@ impl Comment {
         .bind(name)
         .bind(mail)
         .bind(homepage)
-        .execute(pool)
+        .insert_id(pool)
         .await?;
 
-        let cid = result.last_insert_id() as u32;
-
         // Update node comment statistics
         Self::update_statistics(pool, nid, uid, name, timestamp).await?;
 
         Ok(cid)
     }
 
+    /// Save an edited comment. The node's comment statistics are refreshed,
+    /// since publishing or unpublishing changes its count and last comment.
     pub async fn update(
-        pool: &MySqlPool,
+        pool: &Pool,
         cid: u32,
         subject: &str,
         comment: &str,
@@ -161,10 +713,28 @@ impl Comment {
             .bind(cid)
             .execute(pool)
             .await?;
+
+        if let Some(c) = Self::find_by_cid(pool, cid).await? {
+            Self::recalculate_statistics(&mut *pool.acquire().await?, c.nid).await?;
+        }
+        Ok(())
+    }
+
+    /// Publish or unpublish a comment, refreshing the node's statistics.
+    pub async fn set_status(pool: &Pool, cid: u32, status: i32) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE comments SET status = ? WHERE cid = ?")
+            .bind(status)
+            .bind(cid)
+            .execute(pool)
+            .await?;
+
+        if let Some(c) = Self::find_by_cid(pool, cid).await? {
+            Self::recalculate_statistics(&mut *pool.acquire().await?, c.nid).await?;
+        }
         Ok(())
     }
 
-    pub async fn delete(pool: &MySqlPool, cid: u32) -> Result<(), sqlx::Error> {
+    pub async fn delete(pool: &Pool, cid: u32) -> Result<(), sqlx::Error> {
         // Get comment info for statistics update
         let comment = Self::find_by_cid(pool, cid).await?;
 
@@ -175,14 +745,14 @@ impl Comment {
 
         // Update statistics if we found the comment
         if let Some(c) = comment {
-            Self::recalculate_statistics(pool, c.nid).await?;
+            Self::recalculate_statistics(&mut *pool.acquire().await?, c.nid).await?;
         }
 
         Ok(())
     }
 
     /// Calculate the thread value for a new comment using vancode encoding
-    async fn calculate_thread(pool: &MySqlPool, nid: u32, pid: u32) -> Result<String, sqlx::Error> {
+    async fn calculate_thread(pool: &Pool, nid: u32, pid: u32) -> Result<String, sqlx::Error> {
         if pid == 0 {
             // Top-level comment: find max thread at root level
             let result: Option<(String,)> = sqlx::query_as(
@@ -233,17 +803,15 @@ impl Comment {
             .fetch_optional(pool)
             .await?;
 
+            // The highest thread below the parent may belong to a deeper
+            // reply; the parent's child is the part right after its prefix.
+            let parent_depth = parent_prefix.split('.').count();
             let next_num = match result {
-                Some((thread,)) => {
-                    // Find the last segment
-                    let child_part = thread.trim_end_matches('/');
-                    if let Some(last_dot) = child_part.rfind('.') {
-                        let last_segment = &child_part[last_dot + 1..];
-                        vancode_to_int(last_segment) + 1
-                    } else {
-                        0
-                    }
-                }
+                Some((thread,)) => thread
+                    .trim_end_matches('/')
+                    .split('.')
+                    .nth(parent_depth)
+                    .map_or(0, |child| vancode_to_int(child) + 1),
                 None => 0,
             };
 
@@ -251,8 +819,41 @@ impl Comment {
         }
     }
 
+    /// Rewrite `thread` values written before vancodes carried their length,
+    /// which sort wrongly once a thread has 36 or more replies at one level.
+    /// The format is decided per node, see [`is_legacy_thread`]; nodes
+    /// already in the current format are left alone, so this can run any
+    /// number of times. Returns how many comments were changed.
+    pub async fn upgrade_threads(pool: &Pool) -> Result<u64, sqlx::Error> {
+        let rows: Vec<(u32, u32, String)> =
+            sqlx::query_as("SELECT cid, nid, thread FROM comments ORDER BY nid")
+                .fetch_all(pool)
+                .await?;
+
+        let mut tx = pool.begin().await?;
+        let mut changed = 0;
+        for node in rows.chunk_by(|a, b| a.1 == b.1) {
+            if !is_legacy_thread(node.iter().map(|(_, _, thread)| thread.as_str())) {
+                continue;
+            }
+            for (cid, _, thread) in node {
+                let upgraded = upgrade_thread(thread);
+                if upgraded != *thread {
+                    sqlx::query("UPDATE comments SET thread = ? WHERE cid = ?")
+                        .bind(&upgraded)
+                        .bind(cid)
+                        .execute(&mut *tx)
+                        .await?;
+                    changed += 1;
+                }
+            }
+        }
+        tx.commit().await?;
+        Ok(changed)
+    }
+
     async fn update_statistics(
-        pool: &MySqlPool,
+        pool: &Pool,
         nid: u32,
         uid: u32,
         name: Option<&str>,
@@ -265,10 +866,10 @@ impl Comment {
             INSERT INTO node_comment_statistics (nid, last_comment_timestamp, last_comment_name, last_comment_uid, comment_count)
             VALUES (?, ?, ?, ?, ?)
             ON DUPLICATE KEY UPDATE
-                last_comment_timestamp = ?,
-                last_comment_name = ?,
-                last_comment_uid = ?,
-                comment_count = ?
+                last_comment_timestamp = VALUES(last_comment_timestamp),
+                last_comment_name = VALUES(last_comment_name),
+                last_comment_uid = VALUES(last_comment_uid),
+                comment_count = VALUES(comment_count)
             "#,
         )
         .bind(nid)
@@ -276,17 +877,16 @@ impl Comment {
         .bind(name)
         .bind(uid)
         .bind(count)
-        .bind(timestamp)
-        .bind(name)
-        .bind(uid)
-        .bind(count)
         .execute(pool)
         .await?;
 
         Ok(())
     }
 
-    async fn recalculate_statistics(pool: &MySqlPool, nid: u32) -> Result<(), sqlx::Error> {
+    /// Rebuild the node's comment statistics from its published comments.
+    /// Runs on a connection so that the rebuild can be part of the
+    /// transaction that made it necessary.
+    pub async fn recalculate_statistics(conn: &mut Connection, nid: u32) -> Result<(), sqlx::Error> {
         // Get the latest comment for this node
         let latest: Option<(i32, u32, Option<String>)> = sqlx::query_as(
             r#"
@@ -297,10 +897,10 @@ impl Comment {
             "#,
         )
         .bind(nid)
-        .fetch_optional(pool)
+        .fetch_optional(&mut *conn)
         .await?;
 
-        let count = Self::count_for_node(pool, nid).await?;
+        let count = Self::count_for_node(&mut *conn, nid).await?;
 
         match latest {
             Some((timestamp, uid, name)) => {
@@ -309,10 +909,10 @@ impl Comment {
                     INSERT INTO node_comment_statistics (nid, last_comment_timestamp, last_comment_name, last_comment_uid, comment_count)
                     VALUES (?, ?, ?, ?, ?)
                     ON DUPLICATE KEY UPDATE
-                        last_comment_timestamp = ?,
-                        last_comment_name = ?,
-                        last_comment_uid = ?,
-                        comment_count = ?
+                        last_comment_timestamp = VALUES(last_comment_timestamp),
+                        last_comment_name = VALUES(last_comment_name),
+                        last_comment_uid = VALUES(last_comment_uid),
+                        comment_count = VALUES(comment_count)
                     "#,
                 )
                 .bind(nid)
@@ -320,11 +920,7 @@ impl Comment {
                 .bind(&name)
                 .bind(uid)
                 .bind(count)
-                .bind(timestamp)
-                .bind(&name)
-                .bind(uid)
-                .bind(count)
-                .execute(pool)
+                .execute(&mut *conn)
                 .await?;
             }
             None => {
@@ -334,14 +930,14 @@ impl Comment {
                     INSERT INTO node_comment_statistics (nid, last_comment_timestamp, last_comment_name, last_comment_uid, comment_count)
                     VALUES (?, 0, NULL, 0, 0)
                     ON DUPLICATE KEY UPDATE
-                        last_comment_timestamp = 0,
-                        last_comment_name = NULL,
-                        last_comment_uid = 0,
-                        comment_count = 0
+                        last_comment_timestamp = VALUES(last_comment_timestamp),
+                        last_comment_name = VALUES(last_comment_name),
+                        last_comment_uid = VALUES(last_comment_uid),
+                        comment_count = VALUES(comment_count)
                     "#,
                 )
                 .bind(nid)
-                .execute(pool)
+                .execute(&mut *conn)
                 .await?;
             }
         }
@@ -352,7 +948,7 @@ impl Comment {
 
 impl NodeCommentStatistics {
     pub async fn get_for_node(
-        pool: &MySqlPool,
+        pool: &Pool,
         nid: u32,
     ) -> Result<Option<Self>, sqlx::Error> {
         sqlx::query_as("SELECT * FROM node_comment_statistics WHERE nid = ?")
@@ -362,16 +958,55 @@ impl NodeCommentStatistics {
     }
 }
 
-/// Convert integer to vancode (base-36 with zero-padding)
+/// Convert integer to vancode as Drupal does: the number in base 36,
+/// preceded by one character giving its length, so that plain string
+/// comparison orders vancodes numerically (`0z` < `110` < `2100`).
 fn int_to_vancode(i: u32) -> String {
-    let mut result = String::new();
-    let mut n = i;
+    let digits = to_base36(i);
+    let prefix = (b'0' + digits.len() as u8 - 1) as char;
+    format!("{}{}", prefix, digits)
+}
 
-    if n == 0 {
-        return "00".to_string();
-    }
+/// Convert vancode back to integer
+fn vancode_to_int(s: &str) -> u32 {
+    from_base36(s.get(1..).unwrap_or(""))
+}
+
+/// Whether `segment` is a vancode: a length character matching the number of
+/// base 36 digits after it, which carry no leading zero.
+fn is_vancode(segment: &str) -> bool {
+    let bytes = segment.as_bytes();
+    bytes.len() >= 2
+        && bytes[0].is_ascii_digit()
+        && (bytes[0] - b'0') as usize + 2 == bytes.len()
+        && (bytes.len() == 2 || bytes[1] != b'0')
+}
+
+/// Whether the `threads` of one node's comments were written before the
+/// length prefix, as plain base 36 numbers padded to two digits. A single
+/// segment cannot tell: numbers below 36 read the same either way, and one
+/// of 1296 or more has three digits and passes for a vancode (`1ab`). But
+/// a node with such a number also has its siblings from 36 up, two digits
+/// starting with `1` to `z`, which no vancode is.
+fn is_legacy_thread<'a>(mut threads: impl Iterator<Item = &'a str>) -> bool {
+    threads.any(|thread| thread.trim_end_matches('/').split('.').any(|segment| !is_vancode(segment)))
+}
 
-    while n > 0 {
+/// `thread`, written before the length prefix, with every segment
+/// rewritten as a vancode for the same number.
+fn upgrade_thread(thread: &str) -> String {
+    let segments: Vec<String> = thread
+        .trim_end_matches('/')
+        .split('.')
+        .map(|segment| int_to_vancode(from_base36(segment)))
+        .collect();
+    format!("{}/", segments.join("."))
+}
+
+fn to_base36(i: u32) -> String {
+    let mut result = String::new();
+    let mut n = i;
+    loop {
         let digit = (n % 36) as u8;
         let c = if digit < 10 {
             (b'0' + digit) as char
@@ -380,18 +1015,13 @@ fn int_to_vancode(i: u32) -> String {
         };
         result.insert(0, c);
         n /= 36;
+        if n == 0 {
+            return result;
+        }
     }
-
-    // Pad to at least 2 characters
-    while result.len() < 2 {
-        result.insert(0, '0');
-    }
-
-    result
 }
 
-/// Convert vancode back to integer
-fn vancode_to_int(s: &str) -> u32 {
+fn from_base36(s: &str) -> u32 {
     let mut result: u32 = 0;
     for c in s.chars() {
         let digit = if c.is_ascii_digit() {
@@ -405,3 +1035,128 @@ fn vancode_to_int(s: &str) -> u32 {
     }
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{create_test_comment, create_test_node, create_test_user, test_site};
+
+    #[test]
+    fn vancodes_carry_their_length() {
+        assert_eq!(int_to_vancode(0), "00");
+        assert_eq!(int_to_vancode(35), "0z");
+        assert_eq!(int_to_vancode(36), "110");
+        assert_eq!(int_to_vancode(1295), "1zz");
+        assert_eq!(int_to_vancode(1296), "2100");
+    }
+
+    #[test]
+    fn vancodes_round_trip() {
+        for i in [0, 1, 35, 36, 37, 1295, 1296, 1679, 46655, 46656, u32::MAX] {
+            assert_eq!(vancode_to_int(&int_to_vancode(i)), i, "{}", i);
+            assert!(is_vancode(&int_to_vancode(i)), "{}", i);
+        }
+    }
+
+    #[test]
+    fn comment_length_is_checked_in_characters() {
+        let limits = CommentLength { min: 3, max: 5 };
+        assert!(limits.check("ab").unwrap_err().contains("too short"));
+        assert!(limits.check("  ab  ").is_err(), "surrounding whitespace does not count");
+        assert!(limits.check("abcdef").unwrap_err().contains("too long"));
+        for within in ["abc", "abcde", "ééééé", "😀😀😀"] {
+            assert_eq!(limits.check(within), Ok(()), "{}", within);
+        }
+
+        let unlimited = CommentLength { min: 0, max: 0 };
+        assert_eq!(unlimited.check(""), Ok(()));
+        assert_eq!(unlimited.check(&"x".repeat(100_000)), Ok(()));
+    }
+
+    #[test]
+    fn vancodes_sort_numerically_as_strings() {
+        let codes: Vec<String> = (0..2000).map(int_to_vancode).collect();
+        let mut sorted = codes.clone();
+        sorted.sort();
+        assert_eq!(sorted, codes);
+
+        // Across the boundaries where another digit is needed.
+        assert!(int_to_vancode(35) < int_to_vancode(36));
+        assert!(int_to_vancode(1295) < int_to_vancode(1296));
+    }
+
+    #[test]
+    fn replies_sort_below_their_parent() {
+        let thread = |parts: &[u32]| {
+            parts.iter().map(|&p| int_to_vancode(p)).collect::<Vec<_>>().join(".")
+        };
+        let mut threads = vec![
+            thread(&[36]),
+            thread(&[1, 1296]),
+            thread(&[1]),
+            thread(&[1, 35]),
+            thread(&[1, 36]),
+            thread(&[2]),
+        ];
+        threads.sort();
+        assert_eq!(
+            threads,
+            [thread(&[1]), thread(&[1, 35]), thread(&[1, 36]), thread(&[1, 1296]), thread(&[2]), thread(&[36])]
+        );
+    }
+
+    #[test]
+    fn legacy_threads_are_detected_per_node() {
+        // 36 and up were two digits, 1296 and up three.
+        assert!(is_legacy_thread(["00/", "10/", "1ab/"].into_iter()));
+        assert!(is_legacy_thread(["00/", "00.10/"].into_iter()));
+        // Current vancodes, including ones that look like old numbers.
+        assert!(!is_legacy_thread(["00/", "110/", "1ab/", "2100/"].into_iter()));
+        // Below 36 both formats agree, so there is nothing to rewrite.
+        assert!(!is_legacy_thread(["00/", "0z/", "00.01/"].into_iter()));
+    }
+
+    #[test]
+    fn legacy_threads_are_upgraded_segment_by_segment() {
+        assert_eq!(upgrade_thread("0a/"), "0a/");
+        assert_eq!(upgrade_thread("10/"), "110/");
+        assert_eq!(upgrade_thread("zz/"), "1zz/");
+        // 1ab is 1667 in the old format, not the vancode for 371.
+        assert_eq!(upgrade_thread("1ab/"), "21ab/");
+        assert_eq!(upgrade_thread("00.10.1ab/"), "00.110.21ab/");
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn many_siblings_keep_their_order() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let author = create_test_user(pool, &[]).await;
+        let node = create_test_node(pool, "story", &author, true).await;
+
+        let mut top_level = Vec::new();
+        for _ in 0..40 {
+            top_level.push(create_test_comment(pool, node.nid, 0, &author).await.cid);
+        }
+        let mut replies = Vec::new();
+        for _ in 0..40 {
+            replies.push(create_test_comment(pool, node.nid, top_level[0], &author).await.cid);
+        }
+
+        let display = CommentDisplay { order: CommentOrder::OldestFirst, mode: CommentMode::Threaded };
+        let comments =
+            Comment::find_for_node(pool, node.nid, CommentListing::Published, display, None, 1000, 0)
+                .await
+                .unwrap();
+
+        let expected: Vec<u32> = std::iter::once(top_level[0])
+            .chain(replies.iter().copied())
+            .chain(top_level[1..].iter().copied())
+            .collect();
+        assert_eq!(comments.iter().map(|c| c.cid).collect::<Vec<_>>(), expected);
+        for comment in &comments {
+            let depth = if replies.contains(&comment.cid) { 1 } else { 0 };
+            assert_eq!(comment.depth, depth, "comment {}", comment.cid);
+        }
+    }
+}
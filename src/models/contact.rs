@@ -0,0 +1,58 @@
+use serde::Serialize;
+
+use crate::db::{InsertId, Pool};
+
+/// A message sent through the site-wide contact form.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct ContactMessage {
+    pub mid: u32,
+    pub uid: u32,
+    pub name: String,
+    pub mail: String,
+    pub subject: String,
+    pub message: String,
+    pub hostname: String,
+    pub timestamp: i32,
+}
+
+impl ContactMessage {
+    pub async fn create(
+        pool: &Pool,
+        uid: u32,
+        name: &str,
+        mail: &str,
+        subject: &str,
+        message: &str,
+        hostname: &str,
+    ) -> Result<u32, sqlx::Error> {
+        let now = chrono::Utc::now().timestamp() as i32;
+        let mid = sqlx::query(
+            "INSERT INTO contact_log (uid, name, mail, subject, message, hostname, timestamp)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(uid)
+        .bind(name)
+        .bind(mail)
+        .bind(subject)
+        .bind(message)
+        .bind(hostname)
+        .bind(now)
+        .insert_id(pool)
+        .await?;
+
+        Ok(mid)
+    }
+
+    /// Messages sent from `hostname` in the last `window` seconds, for flood
+    /// control.
+    pub async fn count_recent(pool: &Pool, hostname: &str, window: i64) -> Result<i64, sqlx::Error> {
+        let since = chrono::Utc::now().timestamp() - window;
+        let (count,): (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM contact_log WHERE hostname = ? AND timestamp > ?")
+                .bind(hostname)
+                .bind(since)
+                .fetch_one(pool)
+                .await?;
+        Ok(count)
+    }
+}
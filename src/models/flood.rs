@@ -0,0 +1,53 @@
+use crate::db::Pool;
+
+/// Events recorded in the `flood` table, counted over a time window to slow
+/// down or refuse repeated attempts.
+pub struct Flood;
+
+impl Flood {
+    pub async fn register(pool: &Pool, event: &str, identifier: &str) -> Result<(), sqlx::Error> {
+        let now = chrono::Utc::now().timestamp() as i32;
+        sqlx::query("INSERT INTO flood (event, identifier, timestamp) VALUES (?, ?, ?)")
+            .bind(event)
+            .bind(identifier)
+            .bind(now)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    /// How often `event` was registered for `identifier` in the last
+    /// `window` seconds.
+    pub async fn count(pool: &Pool, event: &str, identifier: &str, window: i64) -> Result<u32, sqlx::Error> {
+        let since = chrono::Utc::now().timestamp() - window;
+        let (count,): (i64,) = sqlx::query_as(
+            "SELECT COUNT(*) FROM flood WHERE event = ? AND identifier = ? AND timestamp > ?",
+        )
+        .bind(event)
+        .bind(identifier)
+        .bind(since)
+        .fetch_one(pool)
+        .await?;
+        Ok(count as u32)
+    }
+
+    /// Forget the events registered for `identifier`, e.g. the failed
+    /// logins before a successful one.
+    pub async fn clear(pool: &Pool, event: &str, identifier: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM flood WHERE event = ? AND identifier = ?")
+            .bind(event)
+            .bind(identifier)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn flush_old_entries(pool: &Pool, max_age: i64) -> Result<u64, sqlx::Error> {
+        let cutoff = chrono::Utc::now().timestamp() - max_age;
+        let result = sqlx::query("DELETE FROM flood WHERE timestamp < ?")
+            .bind(cutoff)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+}
@@ -0,0 +1,129 @@
+use serde::Serialize;
+
+use crate::db::Pool;
+use super::comment::COMMENT_PUBLISHED;
+
+/// A node the user has commented on, with activity relative to their last visit.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct Discussion {
+    pub nid: u32,
+    pub title: String,
+    pub node_type: String,
+    pub comment_count: i64,
+    pub last_activity: i64,
+    pub last_comment_name: Option<String>,
+    pub new_comments: i64,
+}
+
+/// A comment posted in one of a user's discussions.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct DiscussionComment {
+    pub cid: u32,
+    pub nid: u32,
+    pub subject: String,
+    pub comment: String,
+    pub timestamp: i32,
+    pub author_name: Option<String>,
+    pub node_title: String,
+}
+
+/// Per-user "last viewed" timestamps for nodes, used to flag new comments.
+pub struct History;
+
+impl History {
+    pub async fn touch(pool: &Pool, uid: u32, nid: u32) -> Result<(), sqlx::Error> {
+        let timestamp = chrono::Utc::now().timestamp() as i32;
+
+        sqlx::query(
+            "INSERT INTO history (uid, nid, timestamp) VALUES (?, ?, ?)
+             ON DUPLICATE KEY UPDATE timestamp = VALUES(timestamp)",
+        )
+        .bind(uid)
+        .bind(nid)
+        .bind(timestamp)
+        .execute(pool)
+        .await?;
+
+        Ok(())
+    }
+
+    pub async fn count_discussions(pool: &Pool, uid: u32) -> Result<i64, sqlx::Error> {
+        let (count,): (i64,) = sqlx::query_as(
+            "SELECT COUNT(DISTINCT c.nid) FROM comments c
+             INNER JOIN node n ON n.nid = c.nid
+             WHERE c.uid = ? AND c.status = ? AND n.status = 1",
+        )
+        .bind(uid)
+        .bind(COMMENT_PUBLISHED)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(count)
+    }
+
+    /// Nodes the user took part in, most recently active first. New comment
+    /// counts are measured against the user's own history, ignoring their
+    /// own comments.
+    pub async fn discussions(
+        pool: &Pool,
+        uid: u32,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<Discussion>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT n.nid, n.title, n.type as node_type,
+                    CAST(COALESCE(s.comment_count, 0) AS SIGNED) as comment_count,
+                    CAST(GREATEST(n.changed, COALESCE(s.last_comment_timestamp, 0)) AS SIGNED) as last_activity,
+                    s.last_comment_name,
+                    (SELECT COUNT(*) FROM comments nc
+                     WHERE nc.nid = n.nid AND nc.status = ? AND nc.uid != ?
+                       AND nc.timestamp > COALESCE(h.timestamp, 0)) as new_comments
+             FROM node n
+             INNER JOIN (SELECT DISTINCT nid FROM comments WHERE uid = ? AND status = ?) p ON p.nid = n.nid
+             LEFT JOIN node_comment_statistics s ON s.nid = n.nid
+             LEFT JOIN history h ON h.nid = n.nid AND h.uid = ?
+             WHERE n.status = 1
+             ORDER BY last_activity DESC, n.nid DESC
+             LIMIT ? OFFSET ?",
+        )
+        .bind(COMMENT_PUBLISHED)
+        .bind(uid)
+        .bind(uid)
+        .bind(COMMENT_PUBLISHED)
+        .bind(uid)
+        .bind(limit)
+        .bind(offset)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Comments by others in the user's discussions, posted after the user
+    /// last viewed the node.
+    pub async fn new_discussion_comments(
+        pool: &Pool,
+        uid: u32,
+        limit: i64,
+    ) -> Result<Vec<DiscussionComment>, sqlx::Error> {
+        sqlx::query_as(
+            "SELECT c.cid, c.nid, c.subject, c.comment, c.timestamp,
+                    COALESCE(u.name, c.name) as author_name, n.title as node_title
+             FROM comments c
+             INNER JOIN node n ON n.nid = c.nid
+             INNER JOIN (SELECT DISTINCT nid FROM comments WHERE uid = ? AND status = ?) p ON p.nid = c.nid
+             LEFT JOIN users u ON u.uid = c.uid AND c.uid != 0
+             LEFT JOIN history h ON h.nid = c.nid AND h.uid = ?
+             WHERE c.status = ? AND c.uid != ? AND n.status = 1
+               AND c.timestamp > COALESCE(h.timestamp, 0)
+             ORDER BY c.timestamp DESC, c.cid DESC
+             LIMIT ?",
+        )
+        .bind(uid)
+        .bind(COMMENT_PUBLISHED)
+        .bind(uid)
+        .bind(COMMENT_PUBLISHED)
+        .bind(uid)
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+    }
+}
@@ -1,18 +1,41 @@
+pub mod alias;
+pub mod batch;
 pub mod comment;
+pub mod contact;
+pub mod flood;
+pub mod history;
 pub mod node;
 pub mod node_field;
+pub mod nomination;
+pub mod permission;
 pub mod profile;
+pub mod role;
 pub mod session;
+pub mod site_settings;
 pub mod statistics;
 pub mod system;
 pub mod user;
 pub mod variable;
+pub mod watchdog;
 
-pub use comment::{Comment, CommentWithAuthor, NodeCommentStatistics, COMMENT_NODE_DISABLED, COMMENT_NODE_READ_ONLY, COMMENT_NODE_READ_WRITE, COMMENT_PUBLISHED, COMMENT_NOT_PUBLISHED};
-pub use node::{Node, NodeType};
-pub use node_field::{get_fields_with_values, save_field_values, NodeFieldInstance};
+/// Ids bound into one `IN (...)` list by the bulk operations; longer lists
+/// are split into several statements.
+pub(crate) const IN_LIST_CHUNK: usize = 200;
+
+pub use alias::{is_system_path, normalize_alias, Alias};
+pub use comment::{Comment, CommentAnonymous, CommentLength, CommentDisplay, CommentListing, CommentMode, CommentOrder, CommentPreview, CommentWithAuthor, NodeCommentStatistics, RecentComment, COMMENT_NODE_DISABLED, COMMENT_NODE_READ_ONLY, COMMENT_NODE_READ_WRITE, COMMENT_PUBLISHED, COMMENT_NOT_PUBLISHED};
+pub use contact::ContactMessage;
+pub use flood::Flood;
+pub use history::History;
+pub use node::{Node, NodeListFilter, NodeListItem, NodeListQuery, NodeListSort, NodeOptions, NodeRevision, NodeType, NodeWithBody, TypeOptions};
+pub use node_field::{add_more_requested, field_widgets, first_image, get_fields_with_values, save_field_values, validate_field_values, FieldInstanceWithValue, NodeField, NodeFieldData, NodeFieldInstance};
+pub use nomination::{Nomination, NOMINATION_APPROVED};
+pub use permission::Permission;
 pub use profile::{ProfileField, ProfileValue};
+pub use role::Role;
+pub use site_settings::SiteSettings;
 pub use statistics::{AccessLog, NodeCounter};
 pub use system::{get_default_theme, set_default_theme, SystemItem};
-pub use user::User;
+pub use user::{ContentDisposition, User};
 pub use variable::Variable;
+pub use watchdog::{Watchdog, WatchdogFilter, WATCHDOG_ERROR, WATCHDOG_NOTICE, WATCHDOG_SEVERITIES, WATCHDOG_WARNING};
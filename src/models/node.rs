@@ -1,5 +1,16 @@
 use serde::{Deserialize, Serialize};
-use sqlx::MySqlPool;
+use sqlx::QueryBuilder;
+
+use crate::db::{Db, InsertId, Pool};
+
+use crate::image_styles;
+
+use super::alias::Alias;
+use super::node_field::NodeFieldData;
+use super::IN_LIST_CHUNK;
+use super::variable::Variable;
+use super::watchdog::{Watchdog, WATCHDOG_NOTICE};
+use super::comment::COMMENT_NODE_READ_WRITE;
 
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Node {
@@ -29,6 +40,51 @@ pub struct NodeRevision {
     pub format: i32,
 }
 
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct RevisionWithAuthor {
+    pub vid: u32,
+    pub nid: u32,
+    pub uid: u32,
+    pub title: String,
+    pub timestamp: i32,
+    pub author_name: Option<String>,
+}
+
+/// A revision in the site-wide list of recent changes, with the node's
+/// current title and the name of whoever saved the revision.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct RecentRevision {
+    pub vid: u32,
+    pub nid: u32,
+    pub uid: u32,
+    pub title: String,
+    pub node_title: String,
+    pub node_type: String,
+    pub timestamp: i32,
+    pub author_name: Option<String>,
+    /// Whether this is still the node's current revision.
+    pub is_current: bool,
+}
+
+impl NodeRevision {
+    /// The `limit` most recently created revisions across all nodes, newest
+    /// first.
+    pub async fn recent(pool: &Pool, limit: i64) -> Result<Vec<RecentRevision>, sqlx::Error> {
+        sqlx::query_as::<_, RecentRevision>(
+            "SELECT nr.vid, nr.nid, nr.uid, nr.title, n.title as node_title, n.type as node_type,
+                    nr.timestamp, u.name as author_name, (n.vid = nr.vid) as is_current
+             FROM node_revisions nr
+             INNER JOIN node n ON nr.nid = n.nid
+             LEFT JOIN users u ON nr.uid = u.uid
+             ORDER BY nr.vid DESC
+             LIMIT ?",
+        )
+        .bind(limit)
+        .fetch_all(pool)
+        .await
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct NodeWithBody {
     pub nid: u32,
@@ -44,7 +100,225 @@ pub struct NodeWithBody {
     pub comment: i32,
     pub body: Option<String>,
     pub teaser: Option<String>,
+    pub format: i32,
+    pub author_name: Option<String>,
+    /// When a scheduled node will be published; only selected where needed.
+    #[sqlx(default)]
+    pub publish_on: Option<i32>,
+    /// Who saved the current revision and when; only selected for a single node.
+    #[sqlx(default)]
+    pub revision_uid: u32,
+    #[sqlx(default)]
+    pub revision_author: Option<String>,
+    #[sqlx(default)]
+    pub revision_timestamp: i32,
+}
+
+/// Publishing, comment and authoring settings of a node: everything on the
+/// node form outside the content section, plus the body's input format.
+#[derive(Debug, Clone, Serialize)]
+pub struct NodeOptions {
+    pub format: i32,
+    pub status: bool,
+    pub promote: bool,
+    pub sticky: bool,
+    pub comment: i32,
+    pub uid: u32,
+    pub created: i32,
+    /// Publish automatically at this time; the node stays unpublished until then.
+    pub publish_on: Option<i32>,
+    /// Whether saving an existing node creates a new revision rather than
+    /// overwriting the current one.
+    pub revision: bool,
+}
+
+impl NodeOptions {
+    /// Defaults for a new node authored by `uid`: published, not promoted,
+    /// comments open, body in `format`.
+    pub fn new(uid: u32, format: i32) -> Self {
+        NodeOptions {
+            format,
+            status: true,
+            promote: false,
+            sticky: false,
+            comment: COMMENT_NODE_READ_WRITE,
+            uid,
+            created: chrono::Utc::now().timestamp() as i32,
+            publish_on: None,
+            revision: true,
+        }
+    }
+
+    /// Defaults for a new `node_type` post, with published, promoted and
+    /// sticky taken from the type's `node_options_<type>` variable.
+    pub async fn for_type(pool: &Pool, node_type: &str, uid: u32, format: i32) -> Self {
+        let defaults = TypeOptions::load(pool, node_type).await;
+        NodeOptions {
+            status: defaults.status,
+            promote: defaults.promote,
+            sticky: defaults.sticky,
+            revision: defaults.revision,
+            ..NodeOptions::new(uid, format)
+        }
+    }
+
+    /// Whether the node is saved as published. A scheduled node is not.
+    pub fn published(&self) -> bool {
+        self.status && self.publish_on.is_none()
+    }
+
+    pub fn from_node(node: &NodeWithBody) -> Self {
+        NodeOptions {
+            format: node.format,
+            status: node.status == 1,
+            promote: node.promote == 1,
+            sticky: node.sticky == 1,
+            comment: node.comment,
+            uid: node.uid,
+            created: node.created,
+            publish_on: node.publish_on,
+            revision: true,
+        }
+    }
+
+    /// The options `node` has, with whether to create a new revision taken
+    /// from the defaults of its type.
+    pub async fn for_edit(pool: &Pool, node: &NodeWithBody) -> Self {
+        NodeOptions {
+            revision: TypeOptions::load(pool, &node.node_type).await.revision,
+            ..Self::from_node(node)
+        }
+    }
+}
+
+/// Default publishing options of a content type, stored as a comma-separated
+/// list of the enabled ones ("status,promote"). Whether edits create a new
+/// revision is kept apart in `node_revision_<type>`, so types saved before
+/// it existed go on creating revisions.
+#[derive(Debug, Clone, Copy, Serialize)]
+pub struct TypeOptions {
+    pub status: bool,
+    pub promote: bool,
+    pub sticky: bool,
+    pub revision: bool,
+}
+
+impl TypeOptions {
+    pub async fn load(pool: &Pool, node_type: &str) -> Self {
+        let value =
+            Variable::get_or_default(pool, &format!("node_options_{}", node_type), "status").await;
+        let enabled: Vec<&str> = value.split(',').map(str::trim).collect();
+        TypeOptions {
+            status: enabled.contains(&"status"),
+            promote: enabled.contains(&"promote"),
+            sticky: enabled.contains(&"sticky"),
+            revision: Variable::get_or_default(pool, &format!("node_revision_{}", node_type), "1").await == "1",
+        }
+    }
+
+    pub async fn save(&self, pool: &Pool, node_type: &str) -> Result<(), sqlx::Error> {
+        let enabled: Vec<&str> = [
+            ("status", self.status),
+            ("promote", self.promote),
+            ("sticky", self.sticky),
+        ]
+        .iter()
+        .filter(|(_, on)| *on)
+        .map(|(name, _)| *name)
+        .collect();
+        Variable::set(pool, &format!("node_options_{}", node_type), &enabled.join(",")).await?;
+        let revision = if self.revision { "1" } else { "0" };
+        Variable::set(pool, &format!("node_revision_{}", node_type), revision).await
+    }
+}
+
+/// A row of a user's tracker page.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct TrackedNode {
+    pub nid: u32,
+    pub title: String,
+    pub node_type: String,
+    pub status: i32,
+    pub comment_count: i64,
+    pub last_post: i64,
+}
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct NodeTitle {
+    pub nid: u32,
+    pub title: String,
+}
+
+/// A node as shown in listings: teaser, author and comment statistics.
+/// Every listing of nodes goes through [`Node::list`].
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct NodeListItem {
+    pub nid: u32,
+    pub vid: u32,
+    pub node_type: String,
+    pub title: String,
+    pub uid: u32,
+    pub status: i32,
+    pub created: i32,
+    pub changed: i32,
+    pub promote: i32,
+    pub sticky: i32,
+    pub comment: i32,
+    pub body: Option<String>,
+    pub teaser: Option<String>,
+    pub format: i32,
     pub author_name: Option<String>,
+    pub publish_on: Option<i32>,
+    /// Published comments.
+    pub comment_count: u64,
+    /// The newer of the last change and the last comment.
+    pub last_activity: i64,
+    /// Comments awaiting approval; only counted when the query asks for it.
+    #[sqlx(default)]
+    pub unapproved_count: u64,
+    /// When the viewer last read the node; only looked up for a viewer.
+    #[sqlx(default)]
+    pub last_viewed: Option<i32>,
+    /// Published comments the viewer has not seen; only counted for a viewer.
+    #[sqlx(default)]
+    pub new_comments: i64,
+}
+
+/// Which nodes a listing shows; `None` means "any".
+#[derive(Debug, Clone, Default)]
+pub struct NodeListFilter {
+    pub node_type: Option<String>,
+    /// Name of the author.
+    pub author: Option<String>,
+    pub promoted: Option<bool>,
+    pub status: Option<i32>,
+    /// Only nodes waiting to be published by cron.
+    pub scheduled: bool,
+    /// Only nodes whose front page nomination has this status.
+    pub nomination: Option<&'static str>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NodeListSort {
+    #[default]
+    Created,
+    Changed,
+    Activity,
+    /// When the node's nomination was last decided on.
+    NominationDecided,
+}
+
+/// A listing: its filter, newest-first order and the optional counts.
+#[derive(Debug, Clone, Default)]
+pub struct NodeListQuery {
+    pub filter: NodeListFilter,
+    pub sort: NodeListSort,
+    /// Put sticky nodes first, as on the front page.
+    pub sticky_first: bool,
+    /// Count comments awaiting approval, for moderators.
+    pub unapproved_counts: bool,
+    /// Count the comments that are new to this user.
+    pub viewer: Option<u32>,
 }
 
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
@@ -57,7 +331,7 @@ pub struct NodeType {
 }
 
 impl Node {
-    pub async fn find_by_nid(pool: &MySqlPool, nid: u32) -> Result<Option<Self>, sqlx::Error> {
+    pub async fn find_by_nid(pool: &Pool, nid: u32) -> Result<Option<Self>, sqlx::Error> {
         sqlx::query_as::<_, Node>("SELECT * FROM node WHERE nid = ?")
             .bind(nid)
             .fetch_optional(pool)
@@ -65,16 +339,20 @@ impl Node {
     }
 
     pub async fn find_with_body(
-        pool: &MySqlPool,
+        pool: &Pool,
         nid: u32,
     ) -> Result<Option<NodeWithBody>, sqlx::Error> {
         sqlx::query_as::<_, NodeWithBody>(
             "SELECT n.nid, n.vid, n.type as node_type, n.title, n.uid, n.status,
                     n.created, n.changed, n.promote, n.sticky, n.comment,
-                    nr.body, nr.teaser, u.name as author_name
+                    nr.body, nr.teaser, nr.format, u.name as author_name, s.publish_on,
+                    nr.uid as revision_uid, ru.name as revision_author,
+                    nr.timestamp as revision_timestamp
              FROM node n
              INNER JOIN node_revisions nr ON n.vid = nr.vid
              LEFT JOIN users u ON n.uid = u.uid
+             LEFT JOIN users ru ON nr.uid = ru.uid
+             LEFT JOIN scheduler s ON n.nid = s.nid
              WHERE n.nid = ?",
         )
         .bind(nid)
@@ -82,69 +360,108 @@ impl Node {
         .await
     }
 
-    pub async fn find_promoted(
-        pool: &MySqlPool,
-        limit: i32,
-    ) -> Result<Vec<NodeWithBody>, sqlx::Error> {
-        sqlx::query_as::<_, NodeWithBody>(
-            "SELECT n.nid, n.vid, n.type as node_type, n.title, n.uid, n.status,
-                    n.created, n.changed, n.promote, n.sticky, n.comment,
-                    nr.body, nr.teaser, u.name as author_name
+    pub async fn count_by_author(
+        pool: &Pool,
+        uid: u32,
+        include_unpublished: bool,
+    ) -> Result<i64, sqlx::Error> {
+        let (count,): (i64,) = sqlx::query_as(
+            "SELECT COUNT(*) FROM node WHERE uid = ? AND (status = 1 OR ?)",
+        )
+        .bind(uid)
+        .bind(include_unpublished)
+        .fetch_one(pool)
+        .await?;
+
+        Ok(count)
+    }
+
+    /// Nodes written by `uid`, most recently active first. The last post
+    /// time is the newer of the node's last change and its last comment.
+    pub async fn find_by_author(
+        pool: &Pool,
+        uid: u32,
+        include_unpublished: bool,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<TrackedNode>, sqlx::Error> {
+        sqlx::query_as::<_, TrackedNode>(
+            "SELECT n.nid, n.title, n.type as node_type, n.status,
+                    CAST(COALESCE(s.comment_count, 0) AS SIGNED) as comment_count,
+                    CAST(GREATEST(n.changed, COALESCE(s.last_comment_timestamp, 0)) AS SIGNED) as last_post
              FROM node n
-             INNER JOIN node_revisions nr ON n.vid = nr.vid
-             LEFT JOIN users u ON n.uid = u.uid
-             WHERE n.status = 1 AND n.promote = 1
-             ORDER BY n.sticky DESC, n.created DESC
-             LIMIT ?",
+             LEFT JOIN node_comment_statistics s ON s.nid = n.nid
+             WHERE n.uid = ? AND (n.status = 1 OR ?)
+             ORDER BY last_post DESC, n.nid DESC
+             LIMIT ? OFFSET ?",
         )
+        .bind(uid)
+        .bind(include_unpublished)
         .bind(limit)
+        .bind(offset)
         .fetch_all(pool)
         .await
     }
 
+    /// Latest published nodes of one content type, newest first. Unpublished
+    /// nodes are left out whoever asks, since feeds are served anonymously.
+    pub async fn find_published_by_type(
+        pool: &Pool,
+        node_type: &str,
+        limit: i64,
+    ) -> Result<Vec<NodeListItem>, sqlx::Error> {
+        let query = NodeListQuery {
+            filter: NodeListFilter {
+                node_type: Some(node_type.to_string()),
+                status: Some(1),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        Self::list(pool, &query, limit, 0).await
+    }
+
     pub async fn create(
-        pool: &MySqlPool,
+        pool: &Pool,
         node_type: &str,
         title: &str,
         body: &str,
         teaser: &str,
-        uid: u32,
-        promote: bool,
-        sticky: bool,
+        options: &NodeOptions,
     ) -> Result<(u32, u32), sqlx::Error> {
         let now = chrono::Utc::now().timestamp() as i32;
 
-        let node_result = sqlx::query(
-            "INSERT INTO node (type, title, uid, status, created, changed, promote, sticky)
-             VALUES (?, ?, ?, 1, ?, ?, ?, ?)",
+        let nid = sqlx::query(
+            "INSERT INTO node (type, title, uid, status, created, changed, promote, sticky, comment)
+             VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?)",
         )
         .bind(node_type)
         .bind(title)
-        .bind(uid)
-        .bind(now)
+        .bind(options.uid)
+        .bind(if options.published() { 1 } else { 0 })
+        .bind(options.created)
         .bind(now)
-        .bind(if promote { 1 } else { 0 })
-        .bind(if sticky { 1 } else { 0 })
-        .execute(pool)
+        .bind(if options.promote { 1 } else { 0 })
+        .bind(if options.sticky { 1 } else { 0 })
+        .bind(options.comment)
+        .insert_id(pool)
         .await?;
+        Self::set_schedule(pool, nid, options.publish_on).await?;
 
-        let nid = node_result.last_insert_id() as u32;
-
-        let revision_result = sqlx::query(
-            "INSERT INTO node_revisions (nid, uid, title, body, teaser, timestamp)
-             VALUES (?, ?, ?, ?, ?, ?)",
+        let vid = sqlx::query(
+            "INSERT INTO node_revisions (nid, uid, title, body, teaser, timestamp, format)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
         )
         .bind(nid)
-        .bind(uid)
+        .bind(options.uid)
         .bind(title)
         .bind(body)
         .bind(teaser)
         .bind(now)
-        .execute(pool)
+        .bind(options.format)
+        .insert_id(pool)
         .await?;
 
-        let vid = revision_result.last_insert_id() as u32;
-
         sqlx::query("UPDATE node SET vid = ? WHERE nid = ?")
             .bind(vid)
             .bind(nid)
@@ -154,42 +471,73 @@ impl Node {
         Ok((nid, vid))
     }
 
+    /// Save a new revision, or with `options.revision` off overwrite the
+    /// current one. Returns the vid saved to. `revision_uid` is the user
+    /// making the change, which may differ from the node author in `options`.
     pub async fn update(
-        pool: &MySqlPool,
+        pool: &Pool,
         nid: u32,
         title: &str,
         body: &str,
         teaser: &str,
-        uid: u32,
-        promote: bool,
-        sticky: bool,
+        revision_uid: u32,
+        options: &NodeOptions,
     ) -> Result<u32, sqlx::Error> {
         let now = chrono::Utc::now().timestamp() as i32;
 
-        sqlx::query("UPDATE node SET title = ?, changed = ?, promote = ?, sticky = ? WHERE nid = ?")
+        sqlx::query(
+            "UPDATE node SET title = ?, uid = ?, status = ?, created = ?, changed = ?,
+                    promote = ?, sticky = ?, comment = ?
+             WHERE nid = ?",
+        )
+        .bind(title)
+        .bind(options.uid)
+        .bind(if options.published() { 1 } else { 0 })
+        .bind(options.created)
+        .bind(now)
+        .bind(if options.promote { 1 } else { 0 })
+        .bind(if options.sticky { 1 } else { 0 })
+        .bind(options.comment)
+        .bind(nid)
+        .execute(pool)
+        .await?;
+        Self::set_schedule(pool, nid, options.publish_on).await?;
+
+        if !options.revision {
+            let (vid,): (u32,) = sqlx::query_as("SELECT vid FROM node WHERE nid = ?")
+                .bind(nid)
+                .fetch_one(pool)
+                .await?;
+            sqlx::query(
+                "UPDATE node_revisions SET uid = ?, title = ?, body = ?, teaser = ?, timestamp = ?, format = ?
+                 WHERE vid = ?",
+            )
+            .bind(revision_uid)
             .bind(title)
+            .bind(body)
+            .bind(teaser)
             .bind(now)
-            .bind(if promote { 1 } else { 0 })
-            .bind(if sticky { 1 } else { 0 })
-            .bind(nid)
+            .bind(options.format)
+            .bind(vid)
             .execute(pool)
             .await?;
+            return Ok(vid);
+        }
 
-        let revision_result = sqlx::query(
-            "INSERT INTO node_revisions (nid, uid, title, body, teaser, timestamp)
-             VALUES (?, ?, ?, ?, ?, ?)",
+        let vid = sqlx::query(
+            "INSERT INTO node_revisions (nid, uid, title, body, teaser, timestamp, format)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
         )
         .bind(nid)
-        .bind(uid)
+        .bind(revision_uid)
         .bind(title)
         .bind(body)
         .bind(teaser)
         .bind(now)
-        .execute(pool)
+        .bind(options.format)
+        .insert_id(pool)
         .await?;
 
-        let vid = revision_result.last_insert_id() as u32;
-
         sqlx::query("UPDATE node SET vid = ? WHERE nid = ?")
             .bind(vid)
             .bind(nid)
@@ -198,57 +546,393 @@ impl Node {
 
         Ok(vid)
     }
+
+    /// Schedule `nid` for publishing, or clear its schedule with `None`.
+    pub async fn set_schedule(
+        pool: &Pool,
+        nid: u32,
+        publish_on: Option<i32>,
+    ) -> Result<(), sqlx::Error> {
+        match publish_on {
+            Some(publish_on) => {
+                sqlx::query(
+                    "INSERT INTO scheduler (nid, publish_on) VALUES (?, ?)
+                     ON DUPLICATE KEY UPDATE publish_on = VALUES(publish_on)",
+                )
+                .bind(nid)
+                .bind(publish_on)
+                .execute(pool)
+                .await?;
+            }
+            None => {
+                sqlx::query("DELETE FROM scheduler WHERE nid = ?")
+                    .bind(nid)
+                    .execute(pool)
+                    .await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Publish every scheduled node whose time has come. Returns how many
+    /// were published.
+    pub async fn publish_due(pool: &Pool, now: i32) -> Result<u64, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        let published = sqlx::query(
+            "UPDATE node n INNER JOIN scheduler s ON n.nid = s.nid
+             SET n.status = 1, n.changed = ?
+             WHERE s.publish_on <= ?",
+        )
+        .bind(now)
+        .bind(now)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        sqlx::query("DELETE FROM scheduler WHERE publish_on <= ?")
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+
+        tx.commit().await?;
+        Ok(published)
+    }
 }
 
 impl Node {
-    pub async fn all_for_admin(pool: &MySqlPool) -> Result<Vec<NodeWithBody>, sqlx::Error> {
-        sqlx::query_as::<_, NodeWithBody>(
+    fn push_list_filter<'a>(query: &mut QueryBuilder<'a, Db>, filter: &'a NodeListFilter) {
+        query.push(" WHERE 1 = 1");
+        if let Some(node_type) = &filter.node_type {
+            query.push(" AND n.type = ").push_bind(node_type);
+        }
+        if let Some(status) = filter.status {
+            query.push(" AND n.status = ").push_bind(status);
+        }
+        if let Some(promoted) = filter.promoted {
+            query.push(" AND n.promote = ").push_bind(promoted as i32);
+        }
+        if filter.scheduled {
+            query.push(" AND s.publish_on IS NOT NULL");
+        }
+        if let Some(author) = &filter.author {
+            query.push(" AND u.name = ").push_bind(author);
+        }
+        if let Some(nomination) = filter.nomination {
+            query
+                .push(" AND n.nid IN (SELECT nid FROM node_nomination WHERE status = ")
+                .push_bind(nomination)
+                .push(")");
+        }
+    }
+
+    /// One page of a node listing.
+    pub async fn list(
+        pool: &Pool,
+        list: &NodeListQuery,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<NodeListItem>, sqlx::Error> {
+        let mut query = QueryBuilder::new(
             "SELECT n.nid, n.vid, n.type as node_type, n.title, n.uid, n.status,
                     n.created, n.changed, n.promote, n.sticky, n.comment,
-                    nr.body, nr.teaser, u.name as author_name
-             FROM node n
+                    nr.body, nr.teaser, nr.format, u.name as author_name, s.publish_on,
+                    CAST(COALESCE(ncs.comment_count, 0) AS UNSIGNED) as comment_count,
+                    CAST(GREATEST(n.changed, COALESCE(ncs.last_comment_timestamp, 0)) AS SIGNED) as last_activity",
+        );
+        if list.unapproved_counts {
+            query.push(
+                ", CAST((SELECT COUNT(*) FROM comments c
+                         WHERE c.nid = n.nid AND c.status = 1) AS UNSIGNED) as unapproved_count",
+            );
+        }
+        if list.viewer.is_some() {
+            query.push(
+                ", h.timestamp as last_viewed,
+                   (SELECT COUNT(*) FROM comments c
+                    WHERE c.nid = n.nid AND c.status = 0
+                      AND c.timestamp > COALESCE(h.timestamp, 0)) as new_comments",
+            );
+        }
+        query.push(
+            " FROM node n
              INNER JOIN node_revisions nr ON n.vid = nr.vid
              LEFT JOIN users u ON n.uid = u.uid
-             ORDER BY n.changed DESC",
+             LEFT JOIN scheduler s ON n.nid = s.nid
+             LEFT JOIN node_comment_statistics ncs ON ncs.nid = n.nid",
+        );
+        if let Some(viewer) = list.viewer {
+            query
+                .push(" LEFT JOIN history h ON h.nid = n.nid AND h.uid = ")
+                .push_bind(viewer);
+        }
+        Self::push_list_filter(&mut query, &list.filter);
+
+        query.push(" ORDER BY ");
+        if list.sticky_first {
+            query.push("n.sticky DESC, ");
+        }
+        query.push(match list.sort {
+            NodeListSort::Created => "n.created DESC",
+            NodeListSort::Changed => "n.changed DESC",
+            NodeListSort::Activity => "last_activity DESC",
+            NodeListSort::NominationDecided => {
+                "(SELECT nn.decided FROM node_nomination nn WHERE nn.nid = n.nid) DESC"
+            }
+        });
+        query
+            .push(", n.nid DESC LIMIT ")
+            .push_bind(limit)
+            .push(" OFFSET ")
+            .push_bind(offset);
+
+        query.build_query_as::<NodeListItem>().fetch_all(pool).await
+    }
+
+    /// Number of nodes a listing with `filter` has in total, for its pager.
+    pub async fn list_count(pool: &Pool, filter: &NodeListFilter) -> Result<i64, sqlx::Error> {
+        let mut query = QueryBuilder::new(
+            "SELECT COUNT(*) FROM node n
+             LEFT JOIN users u ON n.uid = u.uid
+             LEFT JOIN scheduler s ON n.nid = s.nid",
+        );
+        Self::push_list_filter(&mut query, filter);
+
+        let (count,): (i64,) = query.build_query_as().fetch_one(pool).await?;
+        Ok(count)
+    }
+
+    /// Every node a listing with `filter` shows, for bulk operations on all
+    /// of them.
+    pub async fn list_ids(pool: &Pool, filter: &NodeListFilter) -> Result<Vec<u32>, sqlx::Error> {
+        let mut query = QueryBuilder::new(
+            "SELECT n.nid FROM node n
+             LEFT JOIN users u ON n.uid = u.uid
+             LEFT JOIN scheduler s ON n.nid = s.nid",
+        );
+        Self::push_list_filter(&mut query, filter);
+        query.push(" ORDER BY n.nid");
+
+        let rows: Vec<(u32,)> = query.build_query_as().fetch_all(pool).await?;
+        Ok(rows.into_iter().map(|(nid,)| nid).collect())
+    }
+
+    /// Titles of the given nodes, for confirmation pages.
+    pub async fn titles(pool: &Pool, nids: &[u32]) -> Result<Vec<NodeTitle>, sqlx::Error> {
+        if nids.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let mut query = QueryBuilder::new("SELECT nid, title FROM node WHERE nid IN (");
+        let mut separated = query.separated(", ");
+        for nid in nids {
+            separated.push_bind(*nid);
+        }
+        query.push(") ORDER BY title");
+
+        query.build_query_as::<NodeTitle>().fetch_all(pool).await
+    }
+
+    /// Delete nodes with their revisions, field data and aliases, all or
+    /// nothing.
+    pub async fn delete_multiple(pool: &Pool, nids: &[u32]) -> Result<(), sqlx::Error> {
+        if nids.is_empty() {
+            return Ok(());
+        }
+
+        let mut images = Vec::new();
+        for chunk in nids.chunks(IN_LIST_CHUNK) {
+            images.extend(NodeFieldData::image_files_for_nodes(pool, chunk).await?);
+        }
+
+        let mut tx = pool.begin().await?;
+
+        let statements = [
+            ("DELETE FROM node_field_data WHERE vid IN (SELECT vid FROM node_revisions WHERE nid IN (", "))"),
+            ("DELETE FROM node_revisions WHERE nid IN (", ")"),
+            ("DELETE FROM scheduler WHERE nid IN (", ")"),
+            ("DELETE FROM node WHERE nid IN (", ")"),
+        ];
+        for chunk in nids.chunks(IN_LIST_CHUNK) {
+            for (head, tail) in statements {
+                let mut query = QueryBuilder::<Db>::new(head);
+                let mut separated = query.separated(", ");
+                for nid in chunk {
+                    separated.push_bind(*nid);
+                }
+                query.push(tail);
+                query.build().execute(&mut *tx).await?;
+            }
+
+            let sources: Vec<String> = chunk.iter().map(|nid| format!("node/{}", nid)).collect();
+            Alias::delete_for_sources(&mut tx, &sources).await?;
+        }
+
+        tx.commit().await?;
+        Alias::clear_cache();
+        for file in &images {
+            image_styles::delete_file(file).await;
+        }
+        Ok(())
+    }
+
+    pub async fn revisions(
+        pool: &Pool,
+        nid: u32,
+    ) -> Result<Vec<RevisionWithAuthor>, sqlx::Error> {
+        sqlx::query_as::<_, RevisionWithAuthor>(
+            "SELECT nr.vid, nr.nid, nr.uid, nr.title, nr.timestamp, u.name as author_name
+             FROM node_revisions nr
+             LEFT JOIN users u ON nr.uid = u.uid
+             WHERE nr.nid = ?
+             ORDER BY nr.vid DESC",
         )
+        .bind(nid)
         .fetch_all(pool)
         .await
     }
 
-    pub async fn delete(pool: &MySqlPool, nid: u32) -> Result<(), sqlx::Error> {
-        sqlx::query("DELETE FROM node_field_data WHERE vid IN (SELECT vid FROM node_revisions WHERE nid = ?)")
-            .bind(nid)
-            .execute(pool)
-            .await?;
-        sqlx::query("DELETE FROM node_revisions WHERE nid = ?")
-            .bind(nid)
-            .execute(pool)
-            .await?;
-        sqlx::query("DELETE FROM node WHERE nid = ?")
-            .bind(nid)
-            .execute(pool)
-            .await?;
-        Ok(())
+    pub async fn find_revision(
+        pool: &Pool,
+        nid: u32,
+        vid: u32,
+    ) -> Result<Option<NodeRevision>, sqlx::Error> {
+        sqlx::query_as::<_, NodeRevision>(
+            "SELECT vid, nid, uid, title, body, teaser, timestamp, format
+             FROM node_revisions WHERE nid = ? AND vid = ?",
+        )
+        .bind(nid)
+        .bind(vid)
+        .fetch_optional(pool)
+        .await
     }
 
-    pub async fn set_status(pool: &MySqlPool, nid: u32, status: i32) -> Result<(), sqlx::Error> {
-        sqlx::query("UPDATE node SET status = ? WHERE nid = ?")
-            .bind(status)
-            .bind(nid)
-            .execute(pool)
+    /// Make an old revision current again by copying it, field values
+    /// included, into a new revision. Existing revisions are left untouched.
+    pub async fn revert_to_revision(
+        pool: &Pool,
+        revision: &NodeRevision,
+        uid: u32,
+    ) -> Result<u32, sqlx::Error> {
+        let now = chrono::Utc::now().timestamp() as i32;
+        let mut tx = pool.begin().await?;
+
+        let vid = sqlx::query(
+            "INSERT INTO node_revisions (nid, uid, title, body, teaser, timestamp, format)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(revision.nid)
+        .bind(uid)
+        .bind(&revision.title)
+        .bind(&revision.body)
+        .bind(&revision.teaser)
+        .bind(now)
+        .bind(revision.format)
+        .insert_id(&mut *tx)
+        .await?;
+
+        sqlx::query(
+            "INSERT INTO node_field_data (nid, vid, field_name, delta, value_text, value_int, value_float)
+             SELECT nid, ?, field_name, delta, value_text, value_int, value_float
+             FROM node_field_data WHERE vid = ?",
+        )
+        .bind(vid)
+        .bind(revision.vid)
+        .execute(&mut *tx)
+        .await?;
+
+        sqlx::query("UPDATE node SET vid = ?, title = ?, changed = ? WHERE nid = ?")
+            .bind(vid)
+            .bind(&revision.title)
+            .bind(now)
+            .bind(revision.nid)
+            .execute(&mut *tx)
             .await?;
+
+        tx.commit().await?;
+        Ok(vid)
+    }
+
+    pub async fn set_status_multiple(
+        pool: &Pool,
+        nids: &[u32],
+        status: i32,
+    ) -> Result<(), sqlx::Error> {
+        if nids.is_empty() {
+            return Ok(());
+        }
+
+        for chunk in nids.chunks(IN_LIST_CHUNK) {
+            let mut query = QueryBuilder::new("UPDATE node SET status = ");
+            query.push_bind(status).push(" WHERE nid IN (");
+            let mut separated = query.separated(", ");
+            for nid in chunk {
+                separated.push_bind(*nid);
+            }
+            query.push(")");
+
+            query.build().execute(pool).await?;
+        }
         Ok(())
     }
+
+    /// Set the status of every node a listing with `filter` shows, in one
+    /// statement. Returns the number of nodes changed.
+    pub async fn set_status_matching(
+        pool: &Pool,
+        filter: &NodeListFilter,
+        status: i32,
+    ) -> Result<u64, sqlx::Error> {
+        let mut query = QueryBuilder::new(
+            "UPDATE node n
+             LEFT JOIN users u ON n.uid = u.uid
+             LEFT JOIN scheduler s ON n.nid = s.nid
+             SET n.status = ",
+        );
+        query.push_bind(status);
+        Self::push_list_filter(&mut query, filter);
+
+        Ok(query.build().execute(pool).await?.rows_affected())
+    }
 }
 
+/// Variables keyed by content type, as `<prefix><type>`.
+const TYPE_VARIABLE_PREFIXES: &[&str] = &[
+    "node_body_format_",
+    "comment_subject_field_",
+    "comment_default_per_page_",
+    "node_options_",
+    "node_revision_",
+    "node_show_updated_",
+    "node_show_submitted_",
+];
+
+/// Permissions keyed by content type, with `{}` standing for the type.
+pub(crate) const TYPE_PERMISSIONS: &[&str] = &[
+    "create {} content",
+    "edit own {} content",
+    "edit any {} content",
+    "delete own {} content",
+    "delete any {} content",
+];
+
 impl NodeType {
-    pub async fn all(pool: &MySqlPool) -> Result<Vec<Self>, sqlx::Error> {
+    /// Whether posts of `node_type` show the "By author, date" byline.
+    /// Static pages hide it unless configured otherwise; every other type
+    /// shows it.
+    pub async fn show_submitted(pool: &Pool, node_type: &str) -> bool {
+        let default = if node_type == "page" { "0" } else { "1" };
+        Variable::get_or_default(pool, &format!("node_show_submitted_{}", node_type), default).await
+            == "1"
+    }
+
+    pub async fn all(pool: &Pool) -> Result<Vec<Self>, sqlx::Error> {
         sqlx::query_as::<_, NodeType>("SELECT * FROM node_type ORDER BY name")
             .fetch_all(pool)
             .await
     }
 
-    pub async fn find_by_type(pool: &MySqlPool, type_name: &str) -> Result<Option<Self>, sqlx::Error> {
+    pub async fn find_by_type(pool: &Pool, type_name: &str) -> Result<Option<Self>, sqlx::Error> {
         sqlx::query_as::<_, NodeType>("SELECT * FROM node_type WHERE type = ?")
             .bind(type_name)
             .fetch_optional(pool)
@@ -256,7 +940,7 @@ impl NodeType {
     }
 
     pub async fn update(
-        pool: &MySqlPool,
+        pool: &Pool,
         type_name: &str,
         name: &str,
         description: &str,
@@ -271,4 +955,260 @@ impl NodeType {
             .await?;
         Ok(())
     }
+
+    /// Check a new machine name for `type_name`, returning a validation
+    /// message when it cannot be used.
+    pub async fn validate_machine_name(
+        pool: &Pool,
+        type_name: &str,
+        new_type: &str,
+    ) -> Result<Option<String>, sqlx::Error> {
+        if new_type.is_empty() || new_type.len() > 32 {
+            return Ok(Some("The machine name must be between 1 and 32 characters long.".to_string()));
+        }
+        if !new_type
+            .chars()
+            .all(|c| c.is_ascii_lowercase() || c.is_ascii_digit() || c == '_')
+        {
+            return Ok(Some(
+                "The machine name may only contain lowercase letters, numbers and underscores.".to_string(),
+            ));
+        }
+        if new_type == type_name {
+            return Ok(Some("The new machine name is the same as the current one.".to_string()));
+        }
+        if Self::find_by_type(pool, new_type).await?.is_some() {
+            return Ok(Some(format!("The machine name {} is already in use.", new_type)));
+        }
+        Ok(None)
+    }
+
+    /// Nodes and field instances that a rename of `type_name` would move.
+    pub async fn usage(pool: &Pool, type_name: &str) -> Result<(i64, i64), sqlx::Error> {
+        let (nodes,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM node WHERE type = ?")
+            .bind(type_name)
+            .fetch_one(pool)
+            .await?;
+        let (fields,): (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM node_field_instance WHERE node_type = ?")
+                .bind(type_name)
+                .fetch_one(pool)
+                .await?;
+        Ok((nodes, fields))
+    }
+
+    /// Change the machine name of a content type, moving its nodes, field
+    /// instances, type-keyed variables and permissions along in one
+    /// transaction, and log the change. Returns the number of nodes moved.
+    pub async fn rename(
+        pool: &Pool,
+        type_name: &str,
+        new_type: &str,
+        uid: u32,
+    ) -> Result<u64, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        sqlx::query("UPDATE node_type SET type = ? WHERE type = ?")
+            .bind(new_type)
+            .bind(type_name)
+            .execute(&mut *tx)
+            .await?;
+
+        let nodes = sqlx::query("UPDATE node SET type = ? WHERE type = ?")
+            .bind(new_type)
+            .bind(type_name)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+
+        sqlx::query("UPDATE node_field_instance SET node_type = ? WHERE node_type = ?")
+            .bind(new_type)
+            .bind(type_name)
+            .execute(&mut *tx)
+            .await?;
+
+        for prefix in TYPE_VARIABLE_PREFIXES {
+            let old_name = format!("{}{}", prefix, type_name);
+            let new_name = format!("{}{}", prefix, new_type);
+            sqlx::query("DELETE FROM variable WHERE name = ?")
+                .bind(&new_name)
+                .execute(&mut *tx)
+                .await?;
+            sqlx::query("UPDATE variable SET name = ? WHERE name = ?")
+                .bind(&new_name)
+                .bind(&old_name)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        for permission in TYPE_PERMISSIONS {
+            sqlx::query("UPDATE permission SET perm = REPLACE(perm, ?, ?)")
+                .bind(permission.replace("{}", type_name))
+                .bind(permission.replace("{}", new_type))
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        let message = format!(
+            "Renamed content type {} to {}; {} posts migrated.",
+            type_name, new_type, nodes
+        );
+        Watchdog::log(
+            &mut *tx,
+            uid,
+            "content",
+            &message,
+            WATCHDOG_NOTICE,
+            &format!("/admin/node/types/{}", new_type),
+            &format!("/admin/node/types/{}/rename", type_name),
+        )
+        .await?;
+
+        tx.commit().await?;
+        Ok(nodes)
+    }
+
+    /// Create the content type `new_type`, called `name`, as a copy of
+    /// `type_name`: its description, help, type-keyed settings and field
+    /// instances, which keep sharing the same field definitions. Posts and
+    /// permissions are not copied. Returns the number of fields copied.
+    pub async fn duplicate(
+        pool: &Pool,
+        type_name: &str,
+        new_type: &str,
+        name: &str,
+        uid: u32,
+    ) -> Result<u64, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+
+        sqlx::query(
+            "INSERT INTO node_type (type, name, description, help)
+             SELECT ?, ?, description, help FROM node_type WHERE type = ?",
+        )
+        .bind(new_type)
+        .bind(name)
+        .bind(type_name)
+        .execute(&mut *tx)
+        .await?;
+
+        let fields = sqlx::query(
+            "INSERT INTO node_field_instance
+                 (field_name, node_type, label, description, required, weight,
+                  widget_type, widget_settings, display_settings)
+             SELECT field_name, ?, label, description, required, weight,
+                    widget_type, widget_settings, display_settings
+             FROM node_field_instance WHERE node_type = ?",
+        )
+        .bind(new_type)
+        .bind(type_name)
+        .execute(&mut *tx)
+        .await?
+        .rows_affected();
+
+        for prefix in TYPE_VARIABLE_PREFIXES {
+            sqlx::query(
+                "INSERT INTO variable (name, value)
+                 SELECT ?, value FROM variable WHERE name = ?
+                 ON DUPLICATE KEY UPDATE value = VALUES(value)",
+            )
+            .bind(format!("{}{}", prefix, new_type))
+            .bind(format!("{}{}", prefix, type_name))
+            .execute(&mut *tx)
+            .await?;
+        }
+
+        let message = format!(
+            "Created content type {} as a copy of {} with {} fields.",
+            new_type, type_name, fields
+        );
+        Watchdog::log(
+            &mut *tx,
+            uid,
+            "content",
+            &message,
+            WATCHDOG_NOTICE,
+            &format!("/admin/node/types/{}", new_type),
+            &format!("/admin/node/types/{}/clone", type_name),
+        )
+        .await?;
+
+        tx.commit().await?;
+        Ok(fields)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::filter::FORMAT_FILTERED_HTML;
+    use crate::models::{save_field_values, NodeField, NodeFieldData, NodeFieldInstance};
+    use crate::test_support::{create_test_node, create_test_user, test_site};
+    use std::collections::HashMap;
+
+    /// Save node `nid` with `title` and the value `note` for its note field, in a
+    /// new revision or not, the way the edit form does. Returns the vid saved to.
+    async fn save_edit(pool: &Pool, nid: u32, title: &str, note: &str, revision: bool) -> u32 {
+        let node = Node::find_with_body(pool, nid).await.unwrap().unwrap();
+        let options = NodeOptions { revision, ..NodeOptions::new(node.uid, FORMAT_FILTERED_HTML) };
+        let vid = Node::update(pool, nid, title, "Body", "Body", node.uid, &options).await.unwrap();
+        let values = HashMap::from([("field_note".to_string(), note.to_string())]);
+        save_field_values(pool, nid, vid, Some(node.vid), &node.node_type, &values, &[]).await.unwrap();
+        vid
+    }
+
+    async fn notes(pool: &Pool, vid: u32) -> Vec<String> {
+        NodeFieldData::for_revision(pool, vid)
+            .await
+            .unwrap()
+            .into_iter()
+            .filter_map(|data| data.value_text)
+            .collect()
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn reverting_copies_the_revision_and_makes_it_current() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let author = create_test_user(pool, &[]).await;
+        let node = create_test_node(pool, "page", &author, true).await;
+
+        let original = Node::find_revision(pool, node.nid, node.vid).await.unwrap().unwrap();
+        let vid = Node::revert_to_revision(pool, &original, author.uid).await.unwrap();
+
+        assert_ne!(vid, node.vid);
+        let reverted = Node::find_with_body(pool, node.nid).await.unwrap().unwrap();
+        assert_eq!((reverted.vid, reverted.title.as_str()), (vid, original.title.as_str()));
+        assert_eq!(Node::revisions(pool, node.nid).await.unwrap().len(), 2);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn edits_save_in_place_or_as_a_new_revision() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        NodeField::create(pool, "note", "text", 1, None).await.unwrap();
+        NodeFieldInstance::create(pool, "note", "page", "Note", None, false, 0, "text").await.unwrap();
+        let author = create_test_user(pool, &[]).await;
+        let node = create_test_node(pool, "page", &author, true).await;
+        let first_vid = save_edit(pool, node.nid, "First", "one", false).await;
+        assert_eq!(first_vid, node.vid);
+
+        let vid = save_edit(pool, node.nid, "In place", "two", false).await;
+        assert_eq!(vid, first_vid, "no new revision");
+        assert_eq!(Node::revisions(pool, node.nid).await.unwrap().len(), 1);
+        let saved = Node::find_with_body(pool, node.nid).await.unwrap().unwrap();
+        assert_eq!((saved.vid, saved.title.as_str()), (first_vid, "In place"));
+        assert_eq!(notes(pool, first_vid).await, ["two"]);
+
+        let vid = save_edit(pool, node.nid, "New revision", "three", true).await;
+        assert_ne!(vid, first_vid);
+        assert_eq!(Node::revisions(pool, node.nid).await.unwrap().len(), 2);
+        let saved = Node::find_with_body(pool, node.nid).await.unwrap().unwrap();
+        assert_eq!((saved.vid, saved.title.as_str()), (vid, "New revision"));
+        assert_eq!(notes(pool, vid).await, ["three"]);
+        assert_eq!(notes(pool, first_vid).await, ["two"], "the old revision keeps its values");
+        let old = Node::find_revision(pool, node.nid, first_vid).await.unwrap().unwrap();
+        assert_eq!(old.title, "In place");
+    }
 }
@@ -1,6 +1,15 @@
 use serde::{Deserialize, Serialize};
-use sqlx::MySqlPool;
-use std::collections::HashMap;
+use sqlx::QueryBuilder;
+
+use crate::db::{Db, InsertId, Pool};
+use std::collections::{BTreeSet, HashMap};
+
+use super::SiteSettings;
+use crate::extractors::MultipartPart;
+use crate::image_styles;
+
+/// Image field files are stored in this directory below the files directory.
+const FIELD_IMAGES_DIR: &str = "fields";
 
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct NodeField {
@@ -65,6 +74,34 @@ pub struct FieldInstanceWithValue {
     pub values: Vec<FieldValue>,
 }
 
+impl FieldInstanceWithValue {
+    /// Options of a list field, one per line of the field settings.
+    pub fn get_allowed_values(&self) -> Vec<String> {
+        self.settings
+            .as_ref()
+            .map(|o| o.lines().map(|l| l.trim().to_string()).filter(|l| !l.is_empty()).collect())
+            .unwrap_or_default()
+    }
+
+    fn is_list(&self) -> bool {
+        matches!(self.field_type.as_str(), "list_text" | "selection")
+    }
+
+    /// Parse a submitted value for this field, refusing anything a list
+    /// field does not offer or the field's constraints rule out.
+    fn parse_value(&self, value: &str, timezone: chrono::FixedOffset) -> Result<ParsedFieldValue, String> {
+        if self.is_list() {
+            if !self.get_allowed_values().iter().any(|allowed| allowed == value) {
+                return Err(format!("\"{}\" is not one of the allowed values", value));
+            }
+            return parse_field_value(&self.field_type, value, timezone);
+        }
+        let parsed = parse_field_value(&self.field_type, value, timezone)?;
+        FieldConstraints::parse(self.settings.as_deref()).check(value, &parsed)?;
+        Ok(parsed)
+    }
+}
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct FieldValue {
     pub delta: u32,
@@ -73,9 +110,139 @@ pub struct FieldValue {
     pub value_float: Option<f64>,
 }
 
+/// A field on the field usage report: where it is attached and how many
+/// values are stored for it.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct FieldUsage {
+    pub field_name: String,
+    pub field_type: String,
+    pub cardinality: i32,
+    pub instance_count: i64,
+    pub node_types: Option<String>,
+    pub value_count: i64,
+}
+
+/// A field instance attached to a content type that no longer exists.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct DanglingInstance {
+    pub id: u32,
+    pub field_name: String,
+    pub node_type: String,
+    pub label: String,
+}
+
+/// Stored values of a field name that has no `node_field` row.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct OrphanFieldData {
+    pub field_name: String,
+    pub value_count: i64,
+    pub node_count: i64,
+}
+
+/// Optional limits of an image field, from the field's `settings` JSON,
+/// e.g. `{"max_width": 1024, "max_height": 768}`.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct ImageFieldSettings {
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+}
+
+/// Optional constraints on the values of a text or number field, from the
+/// field's `settings` JSON, e.g. `{"max_length": 20, "pattern": "[A-Z]+"}`
+/// or `{"min": 0, "max": 100}`. Like the HTML attribute, `pattern` has to
+/// match the whole value.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default)]
+pub struct FieldConstraints {
+    pub max_length: Option<usize>,
+    pub min: Option<f64>,
+    pub max: Option<f64>,
+    pub pattern: Option<String>,
+}
+
+impl FieldConstraints {
+    pub fn parse(settings: Option<&str>) -> Self {
+        settings
+            .and_then(|settings| serde_json::from_str(settings).ok())
+            .unwrap_or_default()
+    }
+
+    /// Check a submitted value, already parsed for the field type, returning
+    /// a message for the node form. Length and pattern apply to text values,
+    /// the range to numbers.
+    fn check(&self, value: &str, parsed: &ParsedFieldValue) -> Result<(), String> {
+        let number = match parsed {
+            (None, Some(int_val), None) => Some(*int_val as f64),
+            (None, None, Some(float_val)) => Some(*float_val),
+            _ => None,
+        };
+
+        if let Some(number) = number {
+            if let Some(min) = self.min.filter(|&min| number < min) {
+                return Err(format!("{} is less than the minimum of {}", value, min));
+            }
+            if let Some(max) = self.max.filter(|&max| number > max) {
+                return Err(format!("{} is more than the maximum of {}", value, max));
+            }
+            return Ok(());
+        }
+
+        if parsed.0.is_none() {
+            return Ok(());
+        }
+        if let Some(max_length) = self.max_length {
+            let length = value.chars().count();
+            if length > max_length {
+                return Err(format!(
+                    "the value is {} characters long; the maximum is {}",
+                    length, max_length
+                ));
+            }
+        }
+        if let Some(pattern) = self.pattern.as_deref().filter(|p| !p.is_empty()) {
+            match regex::Regex::new(&format!("^(?:{})$", pattern)) {
+                Ok(regex) if !regex.is_match(value) => {
+                    return Err(format!("\"{}\" is not in the expected format", value));
+                }
+                Ok(_) => {}
+                Err(e) => tracing::warn!("Ignoring invalid field pattern {:?}: {}", pattern, e),
+            }
+        }
+        Ok(())
+    }
+}
+
+impl ImageFieldSettings {
+    pub fn parse(settings: Option<&str>) -> Self {
+        settings
+            .and_then(|settings| serde_json::from_str(settings).ok())
+            .unwrap_or_default()
+    }
+
+    /// Check an upload for the field, returning its format or a message for
+    /// the node form.
+    fn validate(&self, file: &MultipartPart) -> Result<image::ImageFormat, String> {
+        let (format, width, height) = image_styles::inspect_upload(file)
+            .map_err(|reason| format!("the uploaded file {}", reason))?;
+        let too_wide = self.max_width.is_some_and(|max| width > max);
+        let too_high = self.max_height.is_some_and(|max| height > max);
+        if too_wide || too_high {
+            return Err(format!(
+                "the image is {}x{} pixels; the maximum is {}x{}",
+                width,
+                height,
+                self.max_width.map_or("any".to_string(), |w| w.to_string()),
+                self.max_height.map_or("any".to_string(), |h| h.to_string()),
+            ));
+        }
+        Ok(format)
+    }
+}
+
 impl NodeField {
     pub async fn find_by_name(
-        pool: &MySqlPool,
+        pool: &Pool,
         field_name: &str,
     ) -> Result<Option<Self>, sqlx::Error> {
         sqlx::query_as::<_, NodeField>("SELECT * FROM node_field WHERE field_name = ?")
@@ -85,7 +252,7 @@ impl NodeField {
     }
 
     pub async fn create(
-        pool: &MySqlPool,
+        pool: &Pool,
         field_name: &str,
         field_type: &str,
         cardinality: i32,
@@ -103,11 +270,34 @@ impl NodeField {
 
         Ok(())
     }
+
+    /// Every field with its instances and the number of values stored for
+    /// it, for the field usage report.
+    pub async fn usage(pool: &Pool) -> Result<Vec<FieldUsage>, sqlx::Error> {
+        sqlx::query_as::<_, FieldUsage>(
+            "SELECT f.field_name, f.field_type, f.cardinality,
+                    COALESCE(i.instance_count, 0) AS instance_count, i.node_types,
+                    COALESCE(d.value_count, 0) AS value_count
+             FROM node_field f
+             LEFT JOIN (
+                 SELECT field_name, COUNT(*) AS instance_count,
+                        GROUP_CONCAT(node_type ORDER BY node_type SEPARATOR ', ') AS node_types
+                 FROM node_field_instance GROUP BY field_name
+             ) i ON i.field_name = f.field_name
+             LEFT JOIN (
+                 SELECT field_name, COUNT(*) AS value_count
+                 FROM node_field_data GROUP BY field_name
+             ) d ON d.field_name = f.field_name
+             ORDER BY f.field_name",
+        )
+        .fetch_all(pool)
+        .await
+    }
 }
 
 impl NodeFieldInstance {
     pub async fn for_node_type(
-        pool: &MySqlPool,
+        pool: &Pool,
         node_type: &str,
     ) -> Result<Vec<Self>, sqlx::Error> {
         sqlx::query_as::<_, NodeFieldInstance>(
@@ -119,7 +309,7 @@ impl NodeFieldInstance {
     }
 
     pub async fn create(
-        pool: &MySqlPool,
+        pool: &Pool,
         field_name: &str,
         node_type: &str,
         label: &str,
@@ -128,7 +318,7 @@ impl NodeFieldInstance {
         weight: i32,
         widget_type: &str,
     ) -> Result<u32, sqlx::Error> {
-        let result = sqlx::query(
+        let id = sqlx::query(
             "INSERT INTO node_field_instance (field_name, node_type, label, description, required, weight, widget_type)
              VALUES (?, ?, ?, ?, ?, ?, ?)",
         )
@@ -139,14 +329,14 @@ impl NodeFieldInstance {
         .bind(if required { 1i8 } else { 0i8 })
         .bind(weight)
         .bind(widget_type)
-        .execute(pool)
+        .insert_id(pool)
         .await?;
 
-        Ok(result.last_insert_id() as u32)
+        Ok(id)
     }
 
     pub async fn with_field_info(
-        pool: &MySqlPool,
+        pool: &Pool,
         node_type: &str,
     ) -> Result<Vec<FieldInstanceWithValue>, sqlx::Error> {
         let rows = sqlx::query_as::<_, FieldInstanceJoined>(
@@ -178,11 +368,41 @@ impl NodeFieldInstance {
             })
             .collect())
     }
+
+    pub async fn find_by_id(pool: &Pool, id: u32) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, NodeFieldInstance>("SELECT * FROM node_field_instance WHERE id = ?")
+            .bind(id)
+            .fetch_optional(pool)
+            .await
+    }
+
+    /// Instances whose content type has been deleted.
+    pub async fn dangling(pool: &Pool) -> Result<Vec<DanglingInstance>, sqlx::Error> {
+        sqlx::query_as::<_, DanglingInstance>(
+            "SELECT i.id, i.field_name, i.node_type, i.label
+             FROM node_field_instance i
+             LEFT JOIN node_type t ON t.type = i.node_type
+             WHERE t.type IS NULL
+             ORDER BY i.node_type, i.field_name",
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Detach a field from a content type. The field and its stored values
+    /// are left alone.
+    pub async fn delete(pool: &Pool, id: u32) -> Result<(), sqlx::Error> {
+        sqlx::query("DELETE FROM node_field_instance WHERE id = ?")
+            .bind(id)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
 }
 
 impl NodeFieldData {
     pub async fn for_revision(
-        pool: &MySqlPool,
+        pool: &Pool,
         vid: u32,
     ) -> Result<Vec<Self>, sqlx::Error> {
         sqlx::query_as::<_, NodeFieldData>(
@@ -194,7 +414,7 @@ impl NodeFieldData {
     }
 
     pub async fn save(
-        pool: &MySqlPool,
+        pool: &Pool,
         nid: u32,
         vid: u32,
         field_name: &str,
@@ -221,8 +441,64 @@ impl NodeFieldData {
         Ok(())
     }
 
+    /// Image files stored by any revision of the given nodes.
+    pub async fn image_files_for_nodes(
+        pool: &Pool,
+        nids: &[u32],
+    ) -> Result<Vec<String>, sqlx::Error> {
+        let mut query = QueryBuilder::<Db>::new(
+            "SELECT DISTINCT d.value_text FROM node_field_data d
+             INNER JOIN node_field f ON f.field_name = d.field_name
+             WHERE f.field_type = 'image' AND d.value_text IS NOT NULL AND d.nid IN (",
+        );
+        let mut separated = query.separated(", ");
+        for nid in nids {
+            separated.push_bind(*nid);
+        }
+        query.push(")");
+        let rows: Vec<(String,)> = query.build_query_as().fetch_all(pool).await?;
+        Ok(rows.into_iter().map(|(file,)| file).collect())
+    }
+
+    /// Values stored under field names that have no field definition, grouped
+    /// by field name.
+    pub async fn orphaned(pool: &Pool) -> Result<Vec<OrphanFieldData>, sqlx::Error> {
+        sqlx::query_as::<_, OrphanFieldData>(
+            "SELECT d.field_name, COUNT(*) AS value_count, COUNT(DISTINCT d.nid) AS node_count
+             FROM node_field_data d
+             LEFT JOIN node_field f ON f.field_name = d.field_name
+             WHERE f.field_name IS NULL
+             GROUP BY d.field_name
+             ORDER BY d.field_name",
+        )
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Delete every stored value of a field, in all revisions.
+    pub async fn delete_for_field(pool: &Pool, field_name: &str) -> Result<u64, sqlx::Error> {
+        let result = sqlx::query("DELETE FROM node_field_data WHERE field_name = ?")
+            .bind(field_name)
+            .execute(pool)
+            .await?;
+        Ok(result.rows_affected())
+    }
+
+    /// Whether any revision still refers to the image file `file`.
+    pub async fn image_in_use(pool: &Pool, file: &str) -> Result<bool, sqlx::Error> {
+        let (count,): (i64,) = sqlx::query_as(
+            "SELECT COUNT(*) FROM node_field_data d
+             INNER JOIN node_field f ON f.field_name = d.field_name
+             WHERE f.field_type = 'image' AND d.value_text = ?",
+        )
+        .bind(file)
+        .fetch_one(pool)
+        .await?;
+        Ok(count > 0)
+    }
+
     pub async fn delete_for_revision(
-        pool: &MySqlPool,
+        pool: &Pool,
         vid: u32,
         field_name: &str,
     ) -> Result<(), sqlx::Error> {
@@ -236,8 +512,24 @@ impl NodeFieldData {
     }
 }
 
+/// The file of the first value of the first image field that has one.
+pub fn first_image(fields: &[FieldInstanceWithValue]) -> Option<&str> {
+    fields
+        .iter()
+        .filter(|field| field.field_type == "image")
+        .find_map(|field| {
+            field
+                .values
+                .iter()
+                .filter_map(|value| Some((value.delta, value.value_text.as_deref()?)))
+                .filter(|(_, file)| !file.is_empty())
+                .min_by_key(|(delta, _)| *delta)
+                .map(|(_, file)| file)
+        })
+}
+
 pub async fn get_fields_with_values(
-    pool: &MySqlPool,
+    pool: &Pool,
     node_type: &str,
     vid: u32,
 ) -> Result<Vec<FieldInstanceWithValue>, sqlx::Error> {
@@ -266,60 +558,425 @@ pub async fn get_fields_with_values(
     Ok(fields)
 }
 
+/// Form keys of a field's values with their deltas, in delta order:
+/// `field_NAME` for single-value fields, `field_NAME_DELTA` otherwise. A
+/// multi-value field takes every delta that was posted or is stored, so
+/// its cardinality is checked rather than extra values silently dropped.
+fn field_keys(
+    field: &FieldInstanceWithValue,
+    form_data: &HashMap<String, String>,
+    files: &[MultipartPart],
+) -> Vec<(u32, String)> {
+    if field.cardinality == 1 {
+        return vec![(0, format!("field_{}", field.field_name))];
+    }
+
+    let prefix = format!("field_{}_", field.field_name);
+    let mut deltas: BTreeSet<u32> = field.values.iter().map(|value| value.delta).collect();
+    deltas.extend(
+        form_data
+            .keys()
+            .chain(files.iter().map(|file| &file.name))
+            .filter_map(|key| key.strip_prefix(&prefix)?.parse::<u32>().ok()),
+    );
+    deltas
+        .into_iter()
+        .map(|delta| (delta, format!("{}{}", prefix, delta)))
+        .collect()
+}
+
+/// Most values a field takes, `None` for unlimited (cardinality -1).
+fn max_values(field: &FieldInstanceWithValue) -> Option<usize> {
+    usize::try_from(field.cardinality).ok().filter(|&n| n > 0)
+}
+
+/// Submitted values for a field, keyed by delta, in the same shape the form
+/// posts them.
+fn submitted_values<'a>(
+    field: &FieldInstanceWithValue,
+    form_data: &'a HashMap<String, String>,
+) -> Vec<(u32, &'a str)> {
+    field_keys(field, form_data, &[])
+        .into_iter()
+        .filter_map(|(delta, key)| {
+            form_data
+                .get(&key)
+                .filter(|value| !value.trim().is_empty())
+                .map(|value| (delta, value.as_str()))
+        })
+        .collect()
+}
+
+/// Images an image field ends up with, by delta: a new upload replaces the
+/// stored image, the `_delete` checkbox removes it, otherwise it is kept.
+/// Text values posted under the field's name are ignored.
+fn submitted_images<'a>(
+    field: &FieldInstanceWithValue,
+    previous: &'a HashMap<u32, String>,
+    form_data: &HashMap<String, String>,
+    files: &'a [MultipartPart],
+) -> Vec<(u32, SubmittedImage<'a>)> {
+    field_keys(field, form_data, files)
+        .into_iter()
+        .filter_map(|(delta, key)| {
+            if let Some(file) = files.iter().find(|file| file.name == key) {
+                return Some((delta, SubmittedImage::Upload(file)));
+            }
+            if form_data.contains_key(&format!("{}_delete", key)) {
+                return None;
+            }
+            previous
+                .get(&delta)
+                .map(|path| (delta, SubmittedImage::Kept(path.as_str())))
+        })
+        .collect()
+}
+
+enum SubmittedImage<'a> {
+    Upload(&'a MultipartPart),
+    Kept(&'a str),
+}
+
+/// Stored image paths of a field, by delta.
+fn stored_images(field: &FieldInstanceWithValue) -> HashMap<u32, String> {
+    field
+        .values
+        .iter()
+        .filter_map(|value| Some((value.delta, value.value_text.clone()?)))
+        .collect()
+}
+
+/// One input of a field on the node form.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldWidgetItem {
+    /// Form key: `field_NAME`, or `field_NAME_DELTA` for multi-value fields.
+    pub key: String,
+    /// Value to prefill, in the format the field's input expects.
+    pub value: String,
+    /// The stored image, for image fields.
+    pub image: Option<String>,
+}
+
+/// The inputs of a field on the node form.
+#[derive(Debug, Clone, Serialize)]
+pub struct FieldWidget {
+    pub items: Vec<FieldWidgetItem>,
+    /// The field takes more values than it has inputs: offer "Add another
+    /// item", a submit button named `field_NAME_add_more`.
+    pub can_add_more: bool,
+}
+
+/// Whether the node form was posted with a field's "Add another item"
+/// button, to be shown again with one more input rather than saved.
+pub fn add_more_requested(form_data: &HashMap<String, String>) -> bool {
+    form_data
+        .keys()
+        .any(|key| key.starts_with("field_") && key.ends_with("_add_more"))
+}
+
+/// Inputs of every field on the node form, keyed by field name. When the
+/// form is shown again after a post, `form_data` holds what was submitted
+/// and text inputs keep it; otherwise they show the stored values. Image
+/// inputs always show the stored image. A multi-value field gets one empty
+/// input to fill in, and one more when its "Add another item" button was
+/// pressed, as far as its cardinality allows.
+pub fn field_widgets(
+    fields: &[FieldInstanceWithValue],
+    form_data: Option<&HashMap<String, String>>,
+    timezone: chrono::FixedOffset,
+) -> HashMap<String, FieldWidget> {
+    fields
+        .iter()
+        .map(|field| (field.field_name.clone(), field_widget(field, form_data, timezone)))
+        .collect()
+}
+
+fn field_widget(
+    field: &FieldInstanceWithValue,
+    form_data: Option<&HashMap<String, String>>,
+    timezone: chrono::FixedOffset,
+) -> FieldWidget {
+    let mut stored: Vec<&FieldValue> = field.values.iter().collect();
+    stored.sort_by_key(|value| value.delta);
+
+    if field.cardinality == 1 {
+        let key = format!("field_{}", field.field_name);
+        let value = match form_data {
+            Some(form_data) if field.field_type != "image" => form_data.get(&key).cloned().unwrap_or_default(),
+            _ => stored.first().map(|value| stored_value(field, value, timezone)).unwrap_or_default(),
+        };
+        let image = stored.first().and_then(|value| value.value_text.clone()).filter(|_| field.field_type == "image");
+        return FieldWidget {
+            items: vec![FieldWidgetItem { key, value, image }],
+            can_add_more: false,
+        };
+    }
+
+    let key = |delta: u32| format!("field_{}_{}", field.field_name, delta);
+    // Stored images stay under their delta, which is how a post refers to
+    // them; text values are posted in full and can simply be renumbered.
+    let mut items: Vec<FieldWidgetItem> = if field.field_type == "image" {
+        stored
+            .iter()
+            .map(|value| FieldWidgetItem {
+                key: key(value.delta),
+                value: String::new(),
+                image: value.value_text.clone(),
+            })
+            .collect()
+    } else {
+        let values: Vec<String> = match form_data {
+            Some(form_data) => submitted_values(field, form_data)
+                .into_iter()
+                .map(|(_, value)| value.to_string())
+                .collect(),
+            None => stored.iter().map(|value| stored_value(field, value, timezone)).collect(),
+        };
+        values
+            .into_iter()
+            .enumerate()
+            .map(|(delta, value)| FieldWidgetItem {
+                key: key(delta as u32),
+                value,
+                image: None,
+            })
+            .collect()
+    };
+
+    let add_more = form_data
+        .is_some_and(|form_data| form_data.contains_key(&format!("field_{}_add_more", field.field_name)));
+    let max = max_values(field).unwrap_or(usize::MAX);
+    let wanted = (items.len() + 1 + add_more as usize).min(max);
+    let mut next_delta = stored.last().map_or(0, |value| value.delta + 1).max(items.len() as u32);
+    while items.len() < wanted {
+        items.push(FieldWidgetItem {
+            key: key(next_delta),
+            value: String::new(),
+            image: None,
+        });
+        next_delta += 1;
+    }
+
+    FieldWidget {
+        can_add_more: items.len() < max,
+        items,
+    }
+}
+
+/// A stored value as the field's input expects it.
+fn stored_value(field: &FieldInstanceWithValue, value: &FieldValue, timezone: chrono::FixedOffset) -> String {
+    let datetime = || value.value_int.and_then(|timestamp| chrono::DateTime::from_timestamp(timestamp, 0));
+    match field.field_type.as_str() {
+        "date" => datetime().map(|d| d.format("%Y-%m-%d").to_string()).unwrap_or_default(),
+        "datetime" => datetime()
+            .map(|d| d.with_timezone(&timezone).format("%Y-%m-%dT%H:%M").to_string())
+            .unwrap_or_default(),
+        _ => value
+            .value_text
+            .clone()
+            .or_else(|| value.value_int.map(|v| v.to_string()))
+            .or_else(|| value.value_float.map(|v| v.to_string()))
+            .unwrap_or_default(),
+    }
+}
+
+/// Check required fields have a value, no field has more values than its
+/// cardinality allows, and every submitted value or upload is valid for its
+/// field type. `fields` carry the values being edited, if any. Returns the
+/// first error message, suitable for showing on the node form.
+pub fn validate_field_values(
+    fields: &[FieldInstanceWithValue],
+    form_data: &HashMap<String, String>,
+    files: &[MultipartPart],
+) -> Option<String> {
+    for field in fields {
+        if field.field_type == "image" {
+            let previous = stored_images(field);
+            let images = submitted_images(field, &previous, form_data, files);
+            if field.required == 1 && images.is_empty() {
+                return Some(format!("{} is required", field.label));
+            }
+            if let Some(message) = too_many_values(field, images.len()) {
+                return Some(message);
+            }
+            let settings = ImageFieldSettings::parse(field.settings.as_deref());
+            for (_, image) in images {
+                if let SubmittedImage::Upload(file) = image {
+                    if let Err(message) = settings.validate(file) {
+                        return Some(format!("{}: {}", field.label, message));
+                    }
+                }
+            }
+            continue;
+        }
+
+        let values = submitted_values(field, form_data);
+        if field.required == 1 && values.is_empty() {
+            return Some(format!("{} is required", field.label));
+        }
+        if let Some(message) = too_many_values(field, values.len()) {
+            return Some(message);
+        }
+        for (_, value) in values {
+            // The time zone only shifts a date-time, it cannot make it invalid.
+            if let Err(message) = field.parse_value(value, chrono::FixedOffset::east_opt(0).unwrap()) {
+                return Some(format!("{}: {}", field.label, message));
+            }
+        }
+    }
+
+    None
+}
+
+fn too_many_values(field: &FieldInstanceWithValue, count: usize) -> Option<String> {
+    let max = max_values(field)?;
+    (count > max).then(|| format!("{}: at most {} values are allowed", field.label, max))
+}
+
+/// Store the submitted values as revision `vid`, numbering each field's
+/// values from 0 without gaps. `previous_vid` is the
+/// revision being edited, whose images are kept unless replaced or removed;
+/// image files no revision refers to any more are deleted.
 pub async fn save_field_values(
-    pool: &MySqlPool,
+    pool: &Pool,
     nid: u32,
     vid: u32,
+    previous_vid: Option<u32>,
     node_type: &str,
     form_data: &HashMap<String, String>,
+    files: &[MultipartPart],
 ) -> Result<(), sqlx::Error> {
-    let fields = NodeFieldInstance::with_field_info(pool, node_type).await?;
+    let fields = match previous_vid {
+        Some(previous_vid) => get_fields_with_values(pool, node_type, previous_vid).await?,
+        None => NodeFieldInstance::with_field_info(pool, node_type).await?,
+    };
+    let timezone = SiteSettings::load(pool).await.timezone();
 
     for field in fields {
         NodeFieldData::delete_for_revision(pool, vid, &field.field_name).await?;
 
-        if field.cardinality == 1 {
-            let key = format!("field_{}", field.field_name);
-            if let Some(value) = form_data.get(&key) {
-                if !value.is_empty() {
-                    let (text, int_val, float_val) = parse_field_value(&field.field_type, value);
-                    NodeFieldData::save(pool, nid, vid, &field.field_name, 0, text, int_val, float_val).await?;
-                }
-            }
-        } else {
-            for delta in 0..10u32 {
-                let key = format!("field_{}_{}", field.field_name, delta);
-                if let Some(value) = form_data.get(&key) {
-                    if !value.is_empty() {
-                        let (text, int_val, float_val) = parse_field_value(&field.field_type, value);
-                        NodeFieldData::save(pool, nid, vid, &field.field_name, delta, text, int_val, float_val).await?;
+        if field.field_type == "image" {
+            let previous = stored_images(&field);
+            let settings = ImageFieldSettings::parse(field.settings.as_deref());
+            let images = submitted_images(&field, &previous, form_data, files);
+            let limit = max_values(&field).unwrap_or(images.len());
+            for (delta, (_, image)) in images.into_iter().take(limit).enumerate() {
+                let delta = delta as u32;
+                let path = match image {
+                    SubmittedImage::Kept(path) => path.to_string(),
+                    SubmittedImage::Upload(file) => {
+                        let Ok(format) = settings.validate(file) else {
+                            continue;
+                        };
+                        // The upload time keeps a replacement from reusing the
+                        // name, and so the cached styles, of the file it replaces.
+                        let name = format!(
+                            "{}-{}-{}-{}-{}",
+                            nid,
+                            vid,
+                            field.field_name,
+                            delta,
+                            chrono::Utc::now().timestamp_millis()
+                        );
+                        let path = image_styles::save_upload(FIELD_IMAGES_DIR, &name, format, &file.data)
+                            .await
+                            .map_err(sqlx::Error::Io)?;
+                        image_styles::generate_derivatives(pool, &path).await;
+                        path
                     }
+                };
+                NodeFieldData::save(pool, nid, vid, &field.field_name, delta, Some(path), None, None).await?;
+            }
+            for path in previous.values() {
+                if !NodeFieldData::image_in_use(pool, path).await? {
+                    image_styles::delete_file(path).await;
                 }
             }
+            continue;
+        }
+
+        let values: Vec<ParsedFieldValue> = submitted_values(&field, form_data)
+            .into_iter()
+            .filter_map(|(_, value)| field.parse_value(value, timezone).ok())
+            .collect();
+        let limit = max_values(&field).unwrap_or(values.len());
+        for (delta, (text, int_val, float_val)) in values.into_iter().take(limit).enumerate() {
+            NodeFieldData::save(pool, nid, vid, &field.field_name, delta as u32, text, int_val, float_val).await?;
         }
     }
 
     Ok(())
 }
 
-fn parse_field_value(field_type: &str, value: &str) -> (Option<String>, Option<i64>, Option<f64>) {
+/// Parse a date field value into a unix timestamp. `date` fields hold a
+/// calendar day, stored as midnight UTC so it never shifts by a day when
+/// shown; the day of an ISO-8601 date-time is taken as written. `datetime`
+/// fields hold a moment: `YYYY-MM-DDTHH:MM[:SS]` and plain dates are read
+/// in the site time zone, RFC 3339 values with an offset as given. Dates
+/// before 1970 give negative timestamps.
+fn parse_date(value: &str, field_type: &str, timezone: chrono::FixedOffset) -> Option<i64> {
+    use chrono::TimeZone;
+
+    let value = value.trim();
+
+    if field_type == "date" {
+        let day = value.get(..10).filter(|_| value.len() == 10 || value[10..].starts_with('T'))?;
+        return chrono::NaiveDate::parse_from_str(day, "%Y-%m-%d")
+            .ok()
+            .and_then(|date| date.and_hms_opt(0, 0, 0))
+            .map(|datetime| datetime.and_utc().timestamp());
+    }
+
+    if let Ok(datetime) = chrono::DateTime::parse_from_rfc3339(value) {
+        return Some(datetime.timestamp());
+    }
+
+    let local = ["%Y-%m-%dT%H:%M", "%Y-%m-%dT%H:%M:%S", "%Y-%m-%d %H:%M", "%Y-%m-%d %H:%M:%S"]
+        .iter()
+        .find_map(|format| chrono::NaiveDateTime::parse_from_str(value, format).ok())
+        .or_else(|| {
+            chrono::NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .and_then(|date| date.and_hms_opt(0, 0, 0))
+        })?;
+    timezone
+        .from_local_datetime(&local)
+        .single()
+        .map(|datetime| datetime.timestamp())
+}
+
+type ParsedFieldValue = (Option<String>, Option<i64>, Option<f64>);
+
+fn parse_field_value(
+    field_type: &str,
+    value: &str,
+    timezone: chrono::FixedOffset,
+) -> Result<ParsedFieldValue, String> {
     match field_type {
-        "integer" | "number_integer" => {
-            let int_val = value.parse::<i64>().ok();
-            (None, int_val, None)
-        }
-        "decimal" | "float" | "number_decimal" => {
-            let float_val = value.parse::<f64>().ok();
-            (None, None, float_val)
-        }
+        "integer" | "number_integer" => match value.trim().parse::<i64>() {
+            Ok(int_val) => Ok((None, Some(int_val), None)),
+            Err(_) => Err(format!("\"{}\" is not a whole number", value)),
+        },
+        "decimal" | "float" | "number_decimal" => match value.trim().parse::<f64>() {
+            Ok(float_val) if float_val.is_finite() => Ok((None, None, Some(float_val))),
+            _ => Err(format!("\"{}\" is not a number", value)),
+        },
         "boolean" | "checkbox" => {
             let int_val = if value == "1" || value.to_lowercase() == "true" {
                 Some(1i64)
             } else {
                 Some(0i64)
             };
-            (None, int_val, None)
+            Ok((None, int_val, None))
         }
-        _ => (Some(value.to_string()), None, None),
+        "date" => match parse_date(value, field_type, timezone) {
+            Some(timestamp) => Ok((None, Some(timestamp), None)),
+            None => Err(format!("\"{}\" is not a valid date, use YYYY-MM-DD", value)),
+        },
+        "datetime" => match parse_date(value, field_type, timezone) {
+            Some(timestamp) => Ok((None, Some(timestamp), None)),
+            None => Err(format!("\"{}\" is not a valid date and time, use YYYY-MM-DD HH:MM", value)),
+        },
+        _ => Ok((Some(value.to_string()), None, None)),
     }
 }
@@ -0,0 +1,122 @@
+use serde::Serialize;
+
+use crate::db::Pool;
+
+pub const NOMINATION_PENDING: &str = "pending";
+pub const NOMINATION_APPROVED: &str = "approved";
+pub const NOMINATION_DECLINED: &str = "declined";
+
+/// An author's request to have their post promoted to the front page. A
+/// post is nominated at most once; an editor approves or declines it.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct Nomination {
+    pub nid: u32,
+    pub uid: u32,
+    pub status: String,
+    pub created: i32,
+    pub decided: i32,
+    pub decided_by: u32,
+}
+
+/// A pending nomination on the editorial queue, with its post.
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct NominationQueueItem {
+    pub nid: u32,
+    pub title: String,
+    pub node_type: String,
+    pub uid: u32,
+    pub author_name: Option<String>,
+    pub created: i32,
+}
+
+impl Nomination {
+    pub async fn find(pool: &Pool, nid: u32) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Nomination>("SELECT * FROM node_nomination WHERE nid = ?")
+            .bind(nid)
+            .fetch_optional(pool)
+            .await
+    }
+
+    /// Nominate a post. Returns `false` when it has been nominated before.
+    pub async fn create(pool: &Pool, nid: u32, uid: u32) -> Result<bool, sqlx::Error> {
+        let now = chrono::Utc::now().timestamp() as i32;
+        let result = sqlx::query(
+            "INSERT IGNORE INTO node_nomination (nid, uid, status, created) VALUES (?, ?, ?, ?)",
+        )
+        .bind(nid)
+        .bind(uid)
+        .bind(NOMINATION_PENDING)
+        .bind(now)
+        .execute(pool)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+
+    /// Nominations `uid` made in the last `window` seconds, for flood control.
+    pub async fn count_recent(pool: &Pool, uid: u32, window: i64) -> Result<i64, sqlx::Error> {
+        let since = chrono::Utc::now().timestamp() - window;
+        let (count,): (i64,) =
+            sqlx::query_as("SELECT COUNT(*) FROM node_nomination WHERE uid = ? AND created > ?")
+                .bind(uid)
+                .bind(since)
+                .fetch_one(pool)
+                .await?;
+        Ok(count)
+    }
+
+    /// Pending nominations of published posts, oldest first.
+    pub async fn pending(pool: &Pool) -> Result<Vec<NominationQueueItem>, sqlx::Error> {
+        sqlx::query_as::<_, NominationQueueItem>(
+            "SELECT n.nid, n.title, n.type as node_type, n.uid, u.name as author_name, nn.created
+             FROM node_nomination nn
+             INNER JOIN node n ON nn.nid = n.nid
+             LEFT JOIN users u ON n.uid = u.uid
+             WHERE nn.status = ? AND n.status = 1
+             ORDER BY nn.created, nn.nid",
+        )
+        .bind(NOMINATION_PENDING)
+        .fetch_all(pool)
+        .await
+    }
+
+    /// Approve a pending nomination and promote its post, together.
+    /// Returns `false` when the nomination was not pending.
+    pub async fn approve(pool: &Pool, nid: u32, editor: u32) -> Result<bool, sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        if !Self::decide(&mut *tx, nid, editor, NOMINATION_APPROVED).await? {
+            return Ok(false);
+        }
+        sqlx::query("UPDATE node SET promote = 1 WHERE nid = ?")
+            .bind(nid)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    /// Decline a pending nomination. Returns `false` when it was not pending.
+    pub async fn decline(pool: &Pool, nid: u32, editor: u32) -> Result<bool, sqlx::Error> {
+        Self::decide(pool, nid, editor, NOMINATION_DECLINED).await
+    }
+
+    async fn decide<'c, E>(executor: E, nid: u32, editor: u32, status: &str) -> Result<bool, sqlx::Error>
+    where
+        E: sqlx::Executor<'c, Database = crate::db::Db>,
+    {
+        let now = chrono::Utc::now().timestamp() as i32;
+        let result = sqlx::query(
+            "UPDATE node_nomination SET status = ?, decided = ?, decided_by = ?
+             WHERE nid = ? AND status = ?",
+        )
+        .bind(status)
+        .bind(now)
+        .bind(editor)
+        .bind(nid)
+        .bind(NOMINATION_PENDING)
+        .execute(executor)
+        .await?;
+
+        Ok(result.rows_affected() == 1)
+    }
+}
@@ -0,0 +1,151 @@
+use serde::Serialize;
+
+use crate::db::{Db, Pool};
+use super::{node::TYPE_PERMISSIONS, NodeType};
+
+/// Permissions the site checks, grouped by the module that defines them.
+/// The per-type node permissions are added for each content type.
+const PERMISSION_REGISTRY: &[(&str, &[&str])] = &[
+    ("comment", &[
+        "access comments",
+        "administer comments",
+        "delete any comment",
+        "edit any comment",
+        "moderate comments",
+        "post comments",
+        "post comments without approval",
+    ]),
+    ("filter", &["administer filters"]),
+    ("node", &["access content", "administer nodes", "nominate content"]),
+    ("path", &["create url aliases"]),
+    ("system", &[
+        "access administration pages",
+        "access site reports",
+        "administer site configuration",
+    ]),
+    ("user", &["administer access control", "administer users", "delete own account", "mail users"]),
+];
+
+/// The comma-separated permission list each role has in the `permission`
+/// table.
+pub struct Permission;
+
+/// Permissions one module defines, for the permission matrix.
+#[derive(Debug, Clone, Serialize)]
+pub struct PermissionGroup {
+    pub module: String,
+    pub permissions: Vec<String>,
+}
+
+impl Permission {
+    /// Every permission the site knows about, grouped by module.
+    pub async fn registry(pool: &Pool) -> Result<Vec<PermissionGroup>, sqlx::Error> {
+        let mut groups: Vec<PermissionGroup> = PERMISSION_REGISTRY
+            .iter()
+            .map(|(module, permissions)| PermissionGroup {
+                module: module.to_string(),
+                permissions: permissions.iter().map(|p| p.to_string()).collect(),
+            })
+            .collect();
+
+        let mut type_permissions = Vec::new();
+        for node_type in NodeType::all(pool).await? {
+            for permission in TYPE_PERMISSIONS {
+                type_permissions.push(permission.replace("{}", &node_type.type_name));
+            }
+        }
+        if let Some(node) = groups.iter_mut().find(|g| g.module == "node") {
+            node.permissions.extend(type_permissions);
+        }
+        Ok(groups)
+    }
+
+    /// The permissions granted to role `rid`.
+    pub async fn get_for_role(pool: &Pool, rid: u32) -> Result<Vec<String>, sqlx::Error> {
+        let row: Option<(Option<String>,)> = sqlx::query_as("SELECT perm FROM permission WHERE rid = ?")
+            .bind(rid)
+            .fetch_optional(pool)
+            .await?;
+        Ok(row
+            .and_then(|(perm,)| perm)
+            .map(|perm| Self::split(&perm).map(str::to_string).collect())
+            .unwrap_or_default())
+    }
+
+    /// The permissions in a stored `perm` list. Compared whole, never as
+    /// substrings: "post comments" must not match "post comments without
+    /// approval".
+    pub fn split(perm: &str) -> impl Iterator<Item = &str> {
+        perm.split(',').map(str::trim).filter(|p| !p.is_empty())
+    }
+
+    /// `perm` with each of `permissions` it lacks appended, or `None` when
+    /// it already has them all.
+    pub fn with_granted(perm: &str, permissions: &[&str]) -> Option<Vec<String>> {
+        let mut granted: Vec<String> = Self::split(perm).map(str::to_string).collect();
+        let missing: Vec<&str> = permissions
+            .iter()
+            .copied()
+            .filter(|p| !granted.iter().any(|g| g == p))
+            .collect();
+        if missing.is_empty() {
+            return None;
+        }
+        granted.extend(missing.into_iter().map(str::to_string));
+        Some(granted)
+    }
+
+    /// Add `permissions` to those of role `rid`, keeping the ones it has.
+    pub async fn grant(pool: &Pool, rid: u32, permissions: &[&str]) -> Result<(), sqlx::Error> {
+        let current = Self::get_for_role(pool, rid).await?.join(", ");
+        if let Some(granted) = Self::with_granted(&current, permissions) {
+            Self::set_for_role(pool, rid, &granted).await?;
+        }
+        Ok(())
+    }
+
+    /// Replace the permissions of role `rid` with `permissions`. Accepts a
+    /// pool or a transaction, so several roles can be changed together.
+    pub async fn set_for_role<'c, E>(executor: E, rid: u32, permissions: &[String]) -> Result<(), sqlx::Error>
+    where
+        E: sqlx::Executor<'c, Database = Db>,
+    {
+        sqlx::query(
+            "INSERT INTO permission (rid, perm) VALUES (?, ?)
+             ON DUPLICATE KEY UPDATE perm = VALUES(perm)",
+        )
+        .bind(rid)
+        .bind(permissions.join(", "))
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_trims_and_skips_empty_items() {
+        let perms: Vec<&str> = Permission::split(" access content,, post comments ,").collect();
+        assert_eq!(perms, ["access content", "post comments"]);
+    }
+
+    #[test]
+    fn grant_adds_only_missing_permissions() {
+        let granted = Permission::with_granted(
+            "access content, post comments without approval",
+            &["post comments", "access content"],
+        );
+        assert_eq!(
+            granted.unwrap(),
+            ["access content", "post comments without approval", "post comments"]
+        );
+    }
+
+    #[test]
+    fn grant_of_held_permissions_changes_nothing() {
+        assert_eq!(Permission::with_granted("access content, post comments", &["post comments"]), None);
+    }
+}
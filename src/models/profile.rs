@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
-use sqlx::MySqlPool;
+
+use crate::db::{InsertId, Pool};
 
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct ProfileField {
@@ -40,7 +41,7 @@ pub struct ProfileFieldWithValue {
 }
 
 impl ProfileField {
-    pub async fn all(pool: &MySqlPool) -> Result<Vec<Self>, sqlx::Error> {
+    pub async fn all(pool: &Pool) -> Result<Vec<Self>, sqlx::Error> {
         sqlx::query_as::<_, ProfileField>(
             "SELECT * FROM profile_fields ORDER BY category, weight, title",
         )
@@ -48,7 +49,7 @@ impl ProfileField {
         .await
     }
 
-    pub async fn for_registration(pool: &MySqlPool) -> Result<Vec<Self>, sqlx::Error> {
+    pub async fn for_registration(pool: &Pool) -> Result<Vec<Self>, sqlx::Error> {
         sqlx::query_as::<_, ProfileField>(
             "SELECT * FROM profile_fields WHERE register = 1 ORDER BY category, weight, title",
         )
@@ -56,7 +57,7 @@ impl ProfileField {
         .await
     }
 
-    pub async fn find_by_fid(pool: &MySqlPool, fid: u32) -> Result<Option<Self>, sqlx::Error> {
+    pub async fn find_by_fid(pool: &Pool, fid: u32) -> Result<Option<Self>, sqlx::Error> {
         sqlx::query_as::<_, ProfileField>("SELECT * FROM profile_fields WHERE fid = ?")
             .bind(fid)
             .fetch_optional(pool)
@@ -64,7 +65,7 @@ impl ProfileField {
     }
 
     pub async fn create(
-        pool: &MySqlPool,
+        pool: &Pool,
         title: &str,
         name: &str,
         explanation: Option<&str>,
@@ -76,7 +77,7 @@ impl ProfileField {
         visibility: i8,
         options: Option<&str>,
     ) -> Result<u32, sqlx::Error> {
-        let result = sqlx::query(
+        let fid = sqlx::query(
             "INSERT INTO profile_fields (title, name, explanation, category, type, weight, required, register, visibility, options)
              VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?)",
         )
@@ -90,10 +91,10 @@ impl ProfileField {
         .bind(if register { 1i8 } else { 0i8 })
         .bind(visibility)
         .bind(options)
-        .execute(pool)
+        .insert_id(pool)
         .await?;
 
-        Ok(result.last_insert_id() as u32)
+        Ok(fid)
     }
 
     pub fn get_options_list(&self) -> Vec<String> {
@@ -106,7 +107,7 @@ impl ProfileField {
 
 impl ProfileValue {
     pub async fn get_for_user(
-        pool: &MySqlPool,
+        pool: &Pool,
         uid: u32,
     ) -> Result<Vec<ProfileFieldWithValue>, sqlx::Error> {
         sqlx::query_as::<_, ProfileFieldWithValue>(
@@ -123,7 +124,7 @@ impl ProfileValue {
     }
 
     pub async fn get_visible_for_user(
-        pool: &MySqlPool,
+        pool: &Pool,
         uid: u32,
         viewer_uid: Option<u32>,
     ) -> Result<Vec<ProfileFieldWithValue>, sqlx::Error> {
@@ -148,7 +149,7 @@ impl ProfileValue {
         .await
     }
 
-    pub async fn set(pool: &MySqlPool, fid: u32, uid: u32, value: &str) -> Result<(), sqlx::Error> {
+    pub async fn set(pool: &Pool, fid: u32, uid: u32, value: &str) -> Result<(), sqlx::Error> {
         sqlx::query(
             "INSERT INTO profile_values (fid, uid, value) VALUES (?, ?, ?)
              ON DUPLICATE KEY UPDATE value = VALUES(value)",
@@ -162,7 +163,7 @@ impl ProfileValue {
         Ok(())
     }
 
-    pub async fn delete_for_user(pool: &MySqlPool, uid: u32) -> Result<(), sqlx::Error> {
+    pub async fn delete_for_user(pool: &Pool, uid: u32) -> Result<(), sqlx::Error> {
         sqlx::query("DELETE FROM profile_values WHERE uid = ?")
             .bind(uid)
             .execute(pool)
@@ -0,0 +1,206 @@
+use serde::Serialize;
+
+use crate::cache;
+use crate::db::{InsertId, Pool};
+use super::{Permission, Variable};
+
+/// Names Drupal gives the built-in roles. Used when the `user_default_role`
+/// and `user_admin_role` variables are unset or name a role that is gone.
+pub const AUTHENTICATED_ROLE: &str = "authenticated user";
+pub const ADMINISTRATOR_ROLE: &str = "administrator";
+
+/// The anonymous and authenticated roles. Permission checks and account
+/// creation depend on them, so they can be neither renamed nor deleted.
+pub const ANONYMOUS_RID: u32 = 1;
+pub const AUTHENTICATED_RID: u32 = 2;
+
+/// Variables that name a role, with the name used when they are unset.
+const ROLE_VARIABLES: &[(&str, &str)] = &[
+    ("user_default_role", AUTHENTICATED_ROLE),
+    ("user_admin_role", ADMINISTRATOR_ROLE),
+];
+
+/// Longest role name the `role.name` column holds.
+const MAX_NAME_LENGTH: usize = 64;
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct Role {
+    pub rid: u32,
+    pub name: String,
+}
+
+impl Role {
+    pub async fn all(pool: &Pool) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Role>("SELECT rid, name FROM role ORDER BY rid")
+            .fetch_all(pool)
+            .await
+    }
+
+    pub async fn find_by_rid(pool: &Pool, rid: u32) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Role>("SELECT rid, name FROM role WHERE rid = ?")
+            .bind(rid)
+            .fetch_optional(pool)
+            .await
+    }
+
+    /// Whether this is one of the built-in roles that cannot be changed.
+    pub fn is_locked(&self) -> bool {
+        self.rid == ANONYMOUS_RID || self.rid == AUTHENTICATED_RID
+    }
+
+    /// Check `name` for a new role, or for renaming the role `rid`,
+    /// returning a validation message when it cannot be used.
+    pub async fn validate_name(
+        pool: &Pool,
+        name: &str,
+        rid: Option<u32>,
+    ) -> Result<Option<String>, sqlx::Error> {
+        if name.is_empty() {
+            return Ok(Some("You must specify a valid role name.".to_string()));
+        }
+        if name.chars().count() > MAX_NAME_LENGTH {
+            return Ok(Some(format!(
+                "The role name cannot be longer than {} characters.",
+                MAX_NAME_LENGTH
+            )));
+        }
+        match Self::find_by_name(pool, name).await? {
+            Some(existing) if Some(existing.rid) != rid => {
+                Ok(Some(format!("The role name {} already exists. Please choose another role name.", name)))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Add a role without any permissions. Returns its rid.
+    pub async fn create(pool: &Pool, name: &str) -> Result<u32, sqlx::Error> {
+        let rid = sqlx::query("INSERT INTO role (name) VALUES (?)")
+        .bind(name)
+        .insert_id(pool)
+        .await?;
+        Ok(rid)
+    }
+
+    /// Give the role `rid` a new name. Variables that pointed at the role by
+    /// its old name, or would have found it by default, follow the rename.
+    pub async fn rename(pool: &Pool, rid: u32, name: &str) -> Result<(), sqlx::Error> {
+        let mut following = Vec::new();
+        for &(variable, canonical) in ROLE_VARIABLES {
+            if Self::configured(pool, variable, canonical).await?.is_some_and(|r| r.rid == rid) {
+                following.push(variable);
+            }
+        }
+
+        let mut tx = pool.begin().await?;
+        sqlx::query("UPDATE role SET name = ? WHERE rid = ?")
+            .bind(name)
+            .bind(rid)
+            .execute(&mut *tx)
+            .await?;
+        for variable in &following {
+            sqlx::query(
+                "INSERT INTO variable (name, value) VALUES (?, ?)
+                 ON DUPLICATE KEY UPDATE value = VALUES(value)",
+            )
+            .bind(variable)
+            .bind(name)
+            .execute(&mut *tx)
+            .await?;
+        }
+        tx.commit().await?;
+
+        if !following.is_empty() {
+            cache::invalidate_tags(&[cache::TAG_VARIABLE]);
+        }
+        Ok(())
+    }
+
+    /// Remove the role `rid` along with its permissions and memberships.
+    pub async fn delete(pool: &Pool, rid: u32) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        sqlx::query("DELETE FROM users_roles WHERE rid = ?")
+            .bind(rid)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM permission WHERE rid = ?")
+            .bind(rid)
+            .execute(&mut *tx)
+            .await?;
+        sqlx::query("DELETE FROM role WHERE rid = ?")
+            .bind(rid)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await
+    }
+
+    /// Whether any role, the anonymous one included, has `permission`.
+    pub async fn any_granted(pool: &Pool, permission: &str) -> Result<bool, sqlx::Error> {
+        let rows: Vec<(Option<String>,)> = sqlx::query_as("SELECT perm FROM permission")
+            .fetch_all(pool)
+            .await?;
+        Ok(rows
+            .iter()
+            .filter_map(|(perm,)| perm.as_deref())
+            .any(|perm| Permission::split(perm).any(|p| p == permission)))
+    }
+
+    /// Roles the account `uid` holds.
+    pub async fn rids_for_user(pool: &Pool, uid: u32) -> Result<Vec<u32>, sqlx::Error> {
+        let rows: Vec<(u32,)> = sqlx::query_as("SELECT rid FROM users_roles WHERE uid = ?")
+            .bind(uid)
+            .fetch_all(pool)
+            .await?;
+        Ok(rows.into_iter().map(|(rid,)| rid).collect())
+    }
+
+    /// Accounts holding the role `rid`.
+    pub async fn member_count(pool: &Pool, rid: u32) -> Result<i64, sqlx::Error> {
+        let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM users_roles WHERE rid = ?")
+            .bind(rid)
+            .fetch_one(pool)
+            .await?;
+        Ok(count)
+    }
+
+    /// Every role except the anonymous one, which no account can hold.
+    pub async fn assignable(pool: &Pool) -> Result<Vec<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Role>("SELECT rid, name FROM role WHERE rid <> ? ORDER BY name")
+            .bind(ANONYMOUS_RID)
+            .fetch_all(pool)
+            .await
+    }
+
+    pub async fn find_by_name(pool: &Pool, name: &str) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, Role>("SELECT rid, name FROM role WHERE name = ?")
+            .bind(name)
+            .fetch_optional(pool)
+            .await
+    }
+
+    /// The role every new account gets.
+    pub async fn authenticated(pool: &Pool) -> Result<Option<Self>, sqlx::Error> {
+        Self::configured(pool, "user_default_role", AUTHENTICATED_ROLE).await
+    }
+
+    /// The role given to the account created by the installer.
+    pub async fn administrator(pool: &Pool) -> Result<Option<Self>, sqlx::Error> {
+        Self::configured(pool, "user_admin_role", ADMINISTRATOR_ROLE).await
+    }
+
+    /// The role named by `variable`, else the one called `canonical`. Roles
+    /// are looked up by name because imported databases number them
+    /// differently.
+    async fn configured(
+        pool: &Pool,
+        variable: &str,
+        canonical: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        if let Some(name) = Variable::get(pool, variable).await?.filter(|n| !n.is_empty()) {
+            if let Some(role) = Self::find_by_name(pool, &name).await? {
+                return Ok(Some(role));
+            }
+            tracing::warn!("Role '{}' set in {} does not exist; using '{}'", name, variable, canonical);
+        }
+        Self::find_by_name(pool, canonical).await
+    }
+}
@@ -1,8 +1,62 @@
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use sqlx::MySqlConnection;
+use tower_sessions::session::Record;
 
 pub const SESSION_USER_KEY: &str = "user_id";
 
+/// Unix time the session's user logged in, for the absolute session lifetime.
+pub const SESSION_LOGIN_KEY: &str = "login_time";
+
+/// Fingerprint of the user's password hash when the session was opened. A
+/// session whose fingerprint no longer matches is ended, so changing the
+/// password logs out everywhere else.
+pub const SESSION_PASS_KEY: &str = "pass_fingerprint";
+
+/// Uid of an account logged in with a one-time login link, which may set a
+/// new password without giving the old one.
+pub const SESSION_PASS_RESET_KEY: &str = "pass_reset";
+
+/// Where the session store keeps sessions, `MySqlStore`'s default.
+const SESSION_TABLE: &str = "`tower_sessions`.`session`";
+
 #[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct SessionData {
     pub uid: u32,
 }
+
+/// What [`SESSION_PASS_KEY`] holds for the password hash `pass`.
+pub fn pass_fingerprint(pass: &str) -> String {
+    Sha256::digest(pass.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// Delete every live session logged in as `uid`, returning how many there
+/// were. The store keeps session data as an opaque blob, so each session is
+/// decoded to see whose it is.
+pub async fn delete_for_user(conn: &mut MySqlConnection, uid: u32) -> Result<u64, sqlx::Error> {
+    let sessions: Vec<(String, Vec<u8>)> = sqlx::query_as(&format!(
+        "SELECT id, data FROM {} WHERE expiry_date > UTC_TIMESTAMP()",
+        SESSION_TABLE
+    ))
+    .fetch_all(&mut *conn)
+    .await?;
+
+    let mut deleted = 0;
+    for (id, data) in sessions {
+        let Ok(record) = rmp_serde::from_slice::<Record>(&data) else {
+            continue;
+        };
+        if record.data.get(SESSION_USER_KEY).and_then(|v| v.as_u64()) != Some(uid as u64) {
+            continue;
+        }
+        deleted += sqlx::query(&format!("DELETE FROM {} WHERE id = ?", SESSION_TABLE))
+            .bind(id)
+            .execute(&mut *conn)
+            .await?
+            .rows_affected();
+    }
+    Ok(deleted)
+}
@@ -0,0 +1,150 @@
+use serde::Serialize;
+use sqlx::QueryBuilder;
+
+use crate::db::{Db, Pool};
+use std::collections::HashMap;
+use std::time::Duration;
+
+use crate::{cache, filter};
+
+const CACHE_BIN: &str = "variable";
+const CACHE_TTL: Duration = Duration::from_secs(3600);
+
+/// Site-wide settings from the `variable` table, with their defaults. Loaded
+/// in one query and cached until a variable changes.
+#[derive(Debug, Clone, Serialize)]
+pub struct SiteSettings {
+    pub site_name: String,
+    /// Plain text; escaped wherever it is shown.
+    pub site_slogan: String,
+    /// Canonical base URL without a trailing slash; empty when unset.
+    pub site_url: String,
+    /// Image shared in link previews of pages that have none of their own;
+    /// a path on this site or an absolute URL, empty when unset.
+    pub site_default_image: String,
+    pub site_mail: String,
+    /// Limited HTML, see [`filter::filter_site_text`]. Stored as
+    /// entered and filtered on output.
+    pub site_footer: String,
+    /// Shown on the front page; limited HTML like the footer.
+    pub site_mission: String,
+    /// Name shown for content and comments by anonymous users.
+    pub anonymous: String,
+    pub theme_default: String,
+    /// Offset of the default time zone from UTC, in seconds.
+    pub date_default_timezone: i32,
+}
+
+impl SiteSettings {
+    const NAMES: &'static [&'static str] = &[
+        "site_name",
+        "site_slogan",
+        "site_url",
+        "site_default_image",
+        "site_mail",
+        "site_footer",
+        "site_mission",
+        "anonymous",
+        "theme_default",
+        "date_default_timezone",
+    ];
+
+    /// The current settings. A failed query yields the defaults, which are
+    /// not cached.
+    pub async fn load(pool: &Pool) -> Self {
+        cache::cache_get_or_build(CACHE_BIN, "site_settings", CACHE_TTL, &[cache::TAG_VARIABLE], || {
+            Self::fetch(pool)
+        })
+        .await
+        .unwrap_or_else(|e| {
+            tracing::warn!("Could not load site settings: {}", e);
+            Self::from_values(HashMap::new())
+        })
+    }
+
+    async fn fetch(pool: &Pool) -> Result<Self, sqlx::Error> {
+        let mut query = QueryBuilder::<Db>::new("SELECT name, value FROM variable WHERE name IN (");
+        let mut separated = query.separated(", ");
+        for name in Self::NAMES {
+            separated.push_bind(*name);
+        }
+        query.push(")");
+
+        let rows: Vec<(String, Option<String>)> = query.build_query_as().fetch_all(pool).await?;
+        let values = rows
+            .into_iter()
+            .filter_map(|(name, value)| Some((name, value?)))
+            .collect();
+        Ok(Self::from_values(values))
+    }
+
+    fn from_values(mut values: HashMap<String, String>) -> Self {
+        let mut take = |name: &str, default: &str| {
+            values.remove(name).unwrap_or_else(|| default.to_string())
+        };
+        SiteSettings {
+            site_name: take("site_name", "Drupal"),
+            site_slogan: take("site_slogan", ""),
+            site_url: take("site_url", ""),
+            site_default_image: take("site_default_image", ""),
+            site_mail: take("site_mail", ""),
+            site_footer: take("site_footer", ""),
+            site_mission: take("site_mission", ""),
+            anonymous: take("anonymous", "Anonymous"),
+            theme_default: take("theme_default", "bluemarine"),
+            date_default_timezone: take("date_default_timezone", "0").parse().unwrap_or(0),
+        }
+    }
+
+    pub fn timezone(&self) -> chrono::FixedOffset {
+        chrono::FixedOffset::east_opt(self.date_default_timezone)
+            .unwrap_or_else(|| chrono::FixedOffset::east_opt(0).unwrap())
+    }
+
+    /// Add what every page template needs: the theme, site name, slogan,
+    /// footer and mission, the name shown for anonymous users and the time
+    /// zone offset dates are shown in. The footer and mission are inserted
+    /// already filtered, for templates to print with `safe`; the slogan is
+    /// left for Tera to escape.
+    pub fn insert_into(&self, context: &mut tera::Context) {
+        context.insert("current_theme", &self.theme_default);
+        context.insert("anonymous_name", &self.anonymous);
+        context.insert("site_name", &self.site_name);
+        context.insert("site_slogan", &self.site_slogan);
+        context.insert("site_footer", &filter::filter_site_text(&self.site_footer));
+        context.insert("site_mission", &filter::filter_site_text(&self.site_mission));
+        context.insert("site_timezone", &self.date_default_timezone);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::test_tera;
+
+    const FOOTER: &str = r#"<em>Thanks</em><script>alert(1)</script><a href="javascript:alert(2)" onclick="steal()">home</a>"#;
+
+    fn rendered_page(values: &[(&str, &str)]) -> String {
+        let values = values.iter().map(|(name, value)| (name.to_string(), value.to_string())).collect();
+        let mut context = tera::Context::new();
+        context.insert("title", "Test");
+        SiteSettings::from_values(values).insert_into(&mut context);
+        test_tera().render("base.html", &context).unwrap()
+    }
+
+    #[test]
+    fn scripts_are_filtered_from_the_footer() {
+        let page = rendered_page(&[("site_footer", FOOTER)]);
+        assert!(page.contains("<em>Thanks</em>"));
+        assert!(!page.contains("alert("), "{}", page);
+        assert!(!page.contains("steal()"));
+        assert!(page.contains(">home</a>"));
+    }
+
+    #[test]
+    fn the_slogan_is_escaped() {
+        let page = rendered_page(&[("site_slogan", "<b>Bold</b> & <script>alert(1)</script>")]);
+        assert!(page.contains("&lt;b&gt;Bold&lt;&#x2F;b&gt; &amp; &lt;script&gt;"), "{}", page);
+        assert!(!page.contains("<script>alert"));
+    }
+}
@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
-use sqlx::MySqlPool;
+
+use crate::db::Pool;
 
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct AccessLog {
@@ -70,7 +71,7 @@ pub struct PopularNode {
 
 impl AccessLog {
     pub async fn log_access(
-        pool: &MySqlPool,
+        pool: &Pool,
         sid: &str,
         title: &str,
         path: &str,
@@ -99,7 +100,7 @@ impl AccessLog {
         Ok(())
     }
 
-    pub async fn recent_hits(pool: &MySqlPool, limit: i32) -> Result<Vec<AccessLogWithUser>, sqlx::Error> {
+    pub async fn recent_hits(pool: &Pool, limit: i32) -> Result<Vec<AccessLogWithUser>, sqlx::Error> {
         sqlx::query_as::<_, AccessLogWithUser>(
             "SELECT a.*, u.name as username
              FROM accesslog a
@@ -112,7 +113,7 @@ impl AccessLog {
         .await
     }
 
-    pub async fn find_by_aid(pool: &MySqlPool, aid: u32) -> Result<Option<AccessLogWithUser>, sqlx::Error> {
+    pub async fn find_by_aid(pool: &Pool, aid: u32) -> Result<Option<AccessLogWithUser>, sqlx::Error> {
         sqlx::query_as::<_, AccessLogWithUser>(
             "SELECT a.*, u.name as username
              FROM accesslog a
@@ -124,7 +125,7 @@ impl AccessLog {
         .await
     }
 
-    pub async fn top_pages(pool: &MySqlPool, limit: i32) -> Result<Vec<TopPage>, sqlx::Error> {
+    pub async fn top_pages(pool: &Pool, limit: i32) -> Result<Vec<TopPage>, sqlx::Error> {
         sqlx::query_as::<_, TopPage>(
             "SELECT path, title, COUNT(*) as hits, COALESCE(SUM(timer), 0) as total_time
              FROM accesslog
@@ -137,7 +138,7 @@ impl AccessLog {
         .await
     }
 
-    pub async fn top_visitors(pool: &MySqlPool, limit: i32) -> Result<Vec<TopVisitor>, sqlx::Error> {
+    pub async fn top_visitors(pool: &Pool, limit: i32) -> Result<Vec<TopVisitor>, sqlx::Error> {
         sqlx::query_as::<_, TopVisitor>(
             "SELECT a.hostname, a.uid, u.name as username, COUNT(*) as hits, COALESCE(SUM(a.timer), 0) as total_time
              FROM accesslog a
@@ -151,7 +152,7 @@ impl AccessLog {
         .await
     }
 
-    pub async fn top_referrers(pool: &MySqlPool, limit: i32) -> Result<Vec<TopReferrer>, sqlx::Error> {
+    pub async fn top_referrers(pool: &Pool, limit: i32) -> Result<Vec<TopReferrer>, sqlx::Error> {
         sqlx::query_as::<_, TopReferrer>(
             "SELECT url, COUNT(*) as hits
              FROM accesslog
@@ -165,7 +166,7 @@ impl AccessLog {
         .await
     }
 
-    pub async fn user_history(pool: &MySqlPool, uid: u32, limit: i32) -> Result<Vec<AccessLogWithUser>, sqlx::Error> {
+    pub async fn user_history(pool: &Pool, uid: u32, limit: i32) -> Result<Vec<AccessLogWithUser>, sqlx::Error> {
         sqlx::query_as::<_, AccessLogWithUser>(
             "SELECT a.*, u.name as username
              FROM accesslog a
@@ -180,7 +181,21 @@ impl AccessLog {
         .await
     }
 
-    pub async fn flush_old_entries(pool: &MySqlPool, max_age: u32) -> Result<u64, sqlx::Error> {
+    /// Who requested pages at or after `since`: distinct accounts, and
+    /// distinct addresses of anonymous visitors.
+    pub async fn count_online(pool: &Pool, since: i64) -> Result<(i64, i64), sqlx::Error> {
+        sqlx::query_as(
+            "SELECT COUNT(DISTINCT CASE WHEN uid > 0 THEN uid END),
+                    COUNT(DISTINCT CASE WHEN uid = 0 THEN hostname END)
+             FROM accesslog
+             WHERE timestamp >= ?",
+        )
+        .bind(since)
+        .fetch_one(pool)
+        .await
+    }
+
+    pub async fn flush_old_entries(pool: &Pool, max_age: u32) -> Result<u64, sqlx::Error> {
         let cutoff = chrono::Utc::now().timestamp() as u32 - max_age;
 
         let result = sqlx::query("DELETE FROM accesslog WHERE timestamp < ?")
@@ -193,7 +208,7 @@ impl AccessLog {
 }
 
 impl NodeCounter {
-    pub async fn increment(pool: &MySqlPool, nid: u32) -> Result<(), sqlx::Error> {
+    pub async fn increment(pool: &Pool, nid: u32) -> Result<(), sqlx::Error> {
         let timestamp = chrono::Utc::now().timestamp() as u32;
 
         sqlx::query(
@@ -212,21 +227,21 @@ impl NodeCounter {
         Ok(())
     }
 
-    pub async fn get(pool: &MySqlPool, nid: u32) -> Result<Option<Self>, sqlx::Error> {
+    pub async fn get(pool: &Pool, nid: u32) -> Result<Option<Self>, sqlx::Error> {
         sqlx::query_as::<_, NodeCounter>("SELECT * FROM node_counter WHERE nid = ?")
             .bind(nid)
             .fetch_optional(pool)
             .await
     }
 
-    pub async fn reset_day_counts(pool: &MySqlPool) -> Result<(), sqlx::Error> {
+    pub async fn reset_day_counts(pool: &Pool) -> Result<(), sqlx::Error> {
         sqlx::query("UPDATE node_counter SET daycount = 0")
             .execute(pool)
             .await?;
         Ok(())
     }
 
-    pub async fn delete_for_node(pool: &MySqlPool, nid: u32) -> Result<(), sqlx::Error> {
+    pub async fn delete_for_node(pool: &Pool, nid: u32) -> Result<(), sqlx::Error> {
         sqlx::query("DELETE FROM node_counter WHERE nid = ?")
             .bind(nid)
             .execute(pool)
@@ -234,7 +249,7 @@ impl NodeCounter {
         Ok(())
     }
 
-    pub async fn popular_today(pool: &MySqlPool, limit: i32) -> Result<Vec<PopularNode>, sqlx::Error> {
+    pub async fn popular_today(pool: &Pool, limit: i32) -> Result<Vec<PopularNode>, sqlx::Error> {
         sqlx::query_as::<_, PopularNode>(
             "SELECT nc.nid, n.title, nc.totalcount, nc.daycount, nc.timestamp
              FROM node_counter nc
@@ -248,7 +263,7 @@ impl NodeCounter {
         .await
     }
 
-    pub async fn popular_all_time(pool: &MySqlPool, limit: i32) -> Result<Vec<PopularNode>, sqlx::Error> {
+    pub async fn popular_all_time(pool: &Pool, limit: i32) -> Result<Vec<PopularNode>, sqlx::Error> {
         sqlx::query_as::<_, PopularNode>(
             "SELECT nc.nid, n.title, nc.totalcount, nc.daycount, nc.timestamp
              FROM node_counter nc
@@ -262,7 +277,7 @@ impl NodeCounter {
         .await
     }
 
-    pub async fn recently_viewed(pool: &MySqlPool, limit: i32) -> Result<Vec<PopularNode>, sqlx::Error> {
+    pub async fn recently_viewed(pool: &Pool, limit: i32) -> Result<Vec<PopularNode>, sqlx::Error> {
         sqlx::query_as::<_, PopularNode>(
             "SELECT nc.nid, n.title, nc.totalcount, nc.daycount, nc.timestamp
              FROM node_counter nc
@@ -1,5 +1,6 @@
 use serde::{Deserialize, Serialize};
-use sqlx::MySqlPool;
+
+use crate::db::Pool;
 
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct SystemItem {
@@ -16,7 +17,7 @@ pub struct SystemItem {
 }
 
 impl SystemItem {
-    pub async fn all_modules(pool: &MySqlPool) -> Result<Vec<Self>, sqlx::Error> {
+    pub async fn all_modules(pool: &Pool) -> Result<Vec<Self>, sqlx::Error> {
         sqlx::query_as::<_, SystemItem>(
             "SELECT * FROM system WHERE type = 'module' ORDER BY weight, name",
         )
@@ -24,7 +25,7 @@ impl SystemItem {
         .await
     }
 
-    pub async fn enabled_modules(pool: &MySqlPool) -> Result<Vec<Self>, sqlx::Error> {
+    pub async fn enabled_modules(pool: &Pool) -> Result<Vec<Self>, sqlx::Error> {
         sqlx::query_as::<_, SystemItem>(
             "SELECT * FROM system WHERE type = 'module' AND status = 1 ORDER BY weight, name",
         )
@@ -32,7 +33,7 @@ impl SystemItem {
         .await
     }
 
-    pub async fn all_themes(pool: &MySqlPool) -> Result<Vec<Self>, sqlx::Error> {
+    pub async fn all_themes(pool: &Pool) -> Result<Vec<Self>, sqlx::Error> {
         sqlx::query_as::<_, SystemItem>(
             "SELECT * FROM system WHERE type = 'theme' ORDER BY name",
         )
@@ -40,7 +41,7 @@ impl SystemItem {
         .await
     }
 
-    pub async fn enabled_themes(pool: &MySqlPool) -> Result<Vec<Self>, sqlx::Error> {
+    pub async fn enabled_themes(pool: &Pool) -> Result<Vec<Self>, sqlx::Error> {
         sqlx::query_as::<_, SystemItem>(
             "SELECT * FROM system WHERE type = 'theme' AND status = 1 ORDER BY name",
         )
@@ -49,7 +50,7 @@ impl SystemItem {
     }
 
     pub async fn find_by_name(
-        pool: &MySqlPool,
+        pool: &Pool,
         name: &str,
         item_type: &str,
     ) -> Result<Option<Self>, sqlx::Error> {
@@ -63,7 +64,7 @@ impl SystemItem {
     }
 
     pub async fn set_status(
-        pool: &MySqlPool,
+        pool: &Pool,
         name: &str,
         item_type: &str,
         status: i32,
@@ -77,7 +78,7 @@ impl SystemItem {
         Ok(())
     }
 
-    pub async fn is_module_enabled(pool: &MySqlPool, name: &str) -> Result<bool, sqlx::Error> {
+    pub async fn is_module_enabled(pool: &Pool, name: &str) -> Result<bool, sqlx::Error> {
         let result: Option<(i32,)> = sqlx::query_as(
             "SELECT status FROM system WHERE name = ? AND type = 'module'",
         )
@@ -88,19 +89,19 @@ impl SystemItem {
         Ok(result.map(|(status,)| status == 1).unwrap_or(false))
     }
 
-    pub async fn enable_module(pool: &MySqlPool, name: &str) -> Result<(), sqlx::Error> {
+    pub async fn enable_module(pool: &Pool, name: &str) -> Result<(), sqlx::Error> {
         Self::set_status(pool, name, "module", 1).await
     }
 
-    pub async fn disable_module(pool: &MySqlPool, name: &str) -> Result<(), sqlx::Error> {
+    pub async fn disable_module(pool: &Pool, name: &str) -> Result<(), sqlx::Error> {
         Self::set_status(pool, name, "module", 0).await
     }
 
-    pub async fn enable_theme(pool: &MySqlPool, name: &str) -> Result<(), sqlx::Error> {
+    pub async fn enable_theme(pool: &Pool, name: &str) -> Result<(), sqlx::Error> {
         Self::set_status(pool, name, "theme", 1).await
     }
 
-    pub async fn disable_theme(pool: &MySqlPool, name: &str) -> Result<(), sqlx::Error> {
+    pub async fn disable_theme(pool: &Pool, name: &str) -> Result<(), sqlx::Error> {
         Self::set_status(pool, name, "theme", 0).await
     }
 
@@ -112,10 +113,10 @@ impl SystemItem {
     }
 }
 
-pub async fn get_default_theme(pool: &MySqlPool) -> String {
-    crate::models::Variable::get_or_default(pool, "theme_default", "bluemarine").await
+pub async fn get_default_theme(pool: &Pool) -> String {
+    crate::models::SiteSettings::load(pool).await.theme_default
 }
 
-pub async fn set_default_theme(pool: &MySqlPool, theme: &str) -> Result<(), sqlx::Error> {
+pub async fn set_default_theme(pool: &Pool, theme: &str) -> Result<(), sqlx::Error> {
     crate::models::Variable::set(pool, "theme_default", theme).await
 }
@@ -1,5 +1,24 @@
 use serde::{Deserialize, Serialize};
-use sqlx::MySqlPool;
+use sqlx::QueryBuilder;
+
+use crate::db::{Db, InsertId, Pool};
+use std::collections::HashSet;
+use std::sync::Arc;
+use tokio::sync::OnceCell;
+
+use super::{session, Comment, Permission, Role, SiteSettings, COMMENT_NOT_PUBLISHED, COMMENT_PUBLISHED, IN_LIST_CHUNK};
+
+/// What becomes of an account's posts and comments when it is deleted.
+/// Either way they are attributed to Anonymous afterwards, since the
+/// account they pointed to is gone.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ContentDisposition {
+    /// Keep the content as it is.
+    Reassign,
+    /// Unpublish every post and comment of the account.
+    Unpublish,
+}
 
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct User {
@@ -11,9 +30,40 @@ pub struct User {
     pub status: i8,
     pub created: i32,
     pub login: i32,
+    /// Path of the user picture below `static/`, empty when there is none.
+    #[sqlx(default)]
+    pub picture: String,
+    /// Permissions of the account's roles, loaded by the first
+    /// [`User::has_permission`] call and shared with clones, so a request
+    /// checking several permissions queries them once.
+    #[serde(skip)]
+    #[sqlx(skip)]
+    permissions: Arc<OnceCell<HashSet<String>>>,
 }
 
 impl User {
+    /// The visitor who is not logged in. Permission checks for this account
+    /// go through the anonymous user role.
+    pub fn anonymous() -> Self {
+        User {
+            uid: 0,
+            name: String::new(),
+            pass: String::new(),
+            mail: None,
+            status: 0,
+            created: 0,
+            login: 0,
+            picture: String::new(),
+            permissions: Arc::default(),
+        }
+    }
+
+    /// Name shown for content and comments by anonymous users, set by the
+    /// `anonymous` variable.
+    pub async fn anonymous_name(pool: &Pool) -> String {
+        SiteSettings::load(pool).await.anonymous
+    }
+
     pub fn is_anonymous(&self) -> bool {
         self.uid == 0
     }
@@ -22,21 +72,54 @@ impl User {
         self.uid > 0
     }
 
-    pub async fn find_by_uid(pool: &MySqlPool, uid: u32) -> Result<Option<Self>, sqlx::Error> {
+    pub async fn find_by_uid(pool: &Pool, uid: u32) -> Result<Option<Self>, sqlx::Error> {
         sqlx::query_as::<_, User>("SELECT * FROM users WHERE uid = ?")
             .bind(uid)
             .fetch_optional(pool)
             .await
     }
 
-    pub async fn find_by_name(pool: &MySqlPool, name: &str) -> Result<Option<Self>, sqlx::Error> {
+    pub async fn find_by_name(pool: &Pool, name: &str) -> Result<Option<Self>, sqlx::Error> {
         sqlx::query_as::<_, User>("SELECT * FROM users WHERE name = ?")
             .bind(name)
             .fetch_optional(pool)
             .await
     }
 
-    pub async fn find_by_mail(pool: &MySqlPool, mail: &str) -> Result<Option<Self>, sqlx::Error> {
+    /// The local account mapped to `authname` at the external provider `module`.
+    pub async fn find_by_authname(
+        pool: &Pool,
+        module: &str,
+        authname: &str,
+    ) -> Result<Option<Self>, sqlx::Error> {
+        sqlx::query_as::<_, User>(
+            "SELECT u.* FROM users u
+             INNER JOIN authmap a ON a.uid = u.uid
+             WHERE a.module = ? AND a.authname = ?",
+        )
+        .bind(module)
+        .bind(authname)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Map `authname` at the external provider `module` to the account.
+    pub async fn set_authname(
+        pool: &Pool,
+        uid: u32,
+        module: &str,
+        authname: &str,
+    ) -> Result<(), sqlx::Error> {
+        sqlx::query("INSERT INTO authmap (uid, authname, module) VALUES (?, ?, ?)")
+            .bind(uid)
+            .bind(authname)
+            .bind(module)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn find_by_mail(pool: &Pool, mail: &str) -> Result<Option<Self>, sqlx::Error> {
         sqlx::query_as::<_, User>("SELECT * FROM users WHERE mail = ?")
             .bind(mail)
             .fetch_optional(pool)
@@ -44,27 +127,93 @@ impl User {
     }
 
     pub async fn create(
-        pool: &MySqlPool,
+        pool: &Pool,
         name: &str,
         pass: &str,
         mail: &str,
     ) -> Result<u32, sqlx::Error> {
         let now = chrono::Utc::now().timestamp() as i32;
 
-        let result = sqlx::query(
+        let uid = sqlx::query(
             "INSERT INTO users (name, pass, mail, status, created) VALUES (?, ?, ?, 1, ?)",
         )
         .bind(name)
         .bind(pass)
         .bind(mail)
         .bind(now)
-        .execute(pool)
+        .insert_id(pool)
         .await?;
 
-        Ok(result.last_insert_id() as u32)
+        Ok(uid)
+    }
+
+    /// Check `name` for a new account, or for renaming the account `uid`,
+    /// returning a validation message when it cannot be used.
+    pub async fn validate_name(
+        pool: &Pool,
+        name: &str,
+        uid: Option<u32>,
+    ) -> Result<Option<String>, sqlx::Error> {
+        if name.is_empty() {
+            return Ok(Some("Username is required".to_string()));
+        }
+        if name.len() < 3 {
+            return Ok(Some("Username must be at least 3 characters".to_string()));
+        }
+        if !name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-') {
+            return Ok(Some(
+                "Username may only contain letters, numbers, underscores, and hyphens".to_string(),
+            ));
+        }
+        match Self::find_by_name(pool, name).await? {
+            Some(existing) if Some(existing.uid) != uid => Ok(Some("Username is already taken".to_string())),
+            _ => Ok(None),
+        }
+    }
+
+    /// Check the e-mail address `mail` for a new account, or for the account
+    /// `uid`, returning a validation message when it cannot be used.
+    pub async fn validate_mail(
+        pool: &Pool,
+        mail: &str,
+        uid: Option<u32>,
+    ) -> Result<Option<String>, sqlx::Error> {
+        if mail.is_empty() || !mail.contains('@') {
+            return Ok(Some("Valid email address is required".to_string()));
+        }
+        match Self::find_by_mail(pool, mail).await? {
+            Some(existing) if Some(existing.uid) != uid => {
+                Ok(Some("Email address is already registered".to_string()))
+            }
+            _ => Ok(None),
+        }
+    }
+
+    /// Check a new password against its confirmation.
+    pub fn validate_password(password: &str, confirm: &str) -> Option<&'static str> {
+        if password.len() < 6 {
+            return Some("Password must be at least 6 characters");
+        }
+        if password != confirm {
+            return Some("Passwords do not match");
+        }
+        None
+    }
+
+    /// Record when the account agreed to the registration terms of service.
+    pub async fn accept_terms(pool: &Pool, uid: u32) -> Result<(), sqlx::Error> {
+        let now = chrono::Utc::now().timestamp() as i32;
+
+        sqlx::query("UPDATE users SET tos_accepted = ? WHERE uid = ?")
+            .bind(now)
+            .bind(uid)
+            .execute(pool)
+            .await?;
+
+        Ok(())
     }
 
-    pub async fn update_login(&self, pool: &MySqlPool) -> Result<(), sqlx::Error> {
+    pub async fn update_login(&self, pool: &Pool) -> Result<(), sqlx::Error> {
         let now = chrono::Utc::now().timestamp() as i32;
 
         sqlx::query("UPDATE users SET login = ? WHERE uid = ?")
@@ -76,7 +225,16 @@ impl User {
         Ok(())
     }
 
-    pub async fn update_password(pool: &MySqlPool, uid: u32, pass: &str) -> Result<(), sqlx::Error> {
+    pub async fn update_picture(pool: &Pool, uid: u32, picture: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE users SET picture = ? WHERE uid = ?")
+            .bind(picture)
+            .bind(uid)
+            .execute(pool)
+            .await?;
+        Ok(())
+    }
+
+    pub async fn update_password(pool: &Pool, uid: u32, pass: &str) -> Result<(), sqlx::Error> {
         sqlx::query("UPDATE users SET pass = ? WHERE uid = ?")
             .bind(pass)
             .bind(uid)
@@ -86,7 +244,17 @@ impl User {
         Ok(())
     }
 
-    pub async fn update_mail(pool: &MySqlPool, uid: u32, mail: &str) -> Result<(), sqlx::Error> {
+    pub async fn update_name(pool: &Pool, uid: u32, name: &str) -> Result<(), sqlx::Error> {
+        sqlx::query("UPDATE users SET name = ? WHERE uid = ?")
+            .bind(name)
+            .bind(uid)
+            .execute(pool)
+            .await?;
+
+        Ok(())
+    }
+
+    pub async fn update_mail(pool: &Pool, uid: u32, mail: &str) -> Result<(), sqlx::Error> {
         sqlx::query("UPDATE users SET mail = ? WHERE uid = ?")
             .bind(mail)
             .bind(uid)
@@ -96,7 +264,91 @@ impl User {
         Ok(())
     }
 
-    pub async fn get_roles(&self, pool: &MySqlPool) -> Result<Vec<String>, sqlx::Error> {
+    /// Delete the account `uid` with its roles, profile values, external
+    /// logins, read history and live sessions, handing its content over to
+    /// Anonymous as `disposition` says. Anonymous (0) and the first account
+    /// (1) are never deleted; `false` when there was nothing to delete.
+    pub async fn delete(
+        pool: &Pool,
+        uid: u32,
+        disposition: ContentDisposition,
+    ) -> Result<bool, sqlx::Error> {
+        if uid <= 1 {
+            return Ok(false);
+        }
+
+        let mut tx = pool.begin().await?;
+        let deleted = sqlx::query("DELETE FROM users WHERE uid = ?")
+            .bind(uid)
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        if deleted == 0 {
+            return Ok(false);
+        }
+
+        for table in ["users_roles", "profile_values", "authmap", "history"] {
+            sqlx::query(&format!("DELETE FROM {} WHERE uid = ?", table))
+                .bind(uid)
+                .execute(&mut *tx)
+                .await?;
+        }
+        session::delete_for_user(&mut tx, uid).await?;
+
+        // Nodes whose comment statistics change when the comments go.
+        let commented: Vec<(u32,)> = match disposition {
+            ContentDisposition::Reassign => Vec::new(),
+            ContentDisposition::Unpublish => {
+                sqlx::query_as("SELECT DISTINCT nid FROM comments WHERE uid = ? AND status = ?")
+                    .bind(uid)
+                    .bind(COMMENT_PUBLISHED)
+                    .fetch_all(&mut *tx)
+                    .await?
+            }
+        };
+
+        match disposition {
+            ContentDisposition::Reassign => {
+                sqlx::query("UPDATE node SET uid = 0 WHERE uid = ?")
+                    .bind(uid)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query("UPDATE comments SET uid = 0 WHERE uid = ?")
+                    .bind(uid)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+            ContentDisposition::Unpublish => {
+                sqlx::query("UPDATE node SET uid = 0, status = 0 WHERE uid = ?")
+                    .bind(uid)
+                    .execute(&mut *tx)
+                    .await?;
+                sqlx::query("UPDATE comments SET uid = 0, status = ? WHERE uid = ?")
+                    .bind(COMMENT_NOT_PUBLISHED)
+                    .bind(uid)
+                    .execute(&mut *tx)
+                    .await?;
+            }
+        }
+        for (table, column) in [
+            ("node_revisions", "uid"),
+            ("node_comment_statistics", "last_comment_uid"),
+            ("node_nomination", "uid"),
+            ("accesslog", "uid"),
+        ] {
+            sqlx::query(&format!("UPDATE {table} SET {column} = 0 WHERE {column} = ?"))
+                .bind(uid)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for (nid,) in commented {
+            Comment::recalculate_statistics(&mut tx, nid).await?;
+        }
+        tx.commit().await?;
+        Ok(true)
+    }
+
+    pub async fn get_roles(&self, pool: &Pool) -> Result<Vec<String>, sqlx::Error> {
         let roles: Vec<(String,)> = sqlx::query_as(
             "SELECT r.name FROM role r
              INNER JOIN users_roles ur ON r.rid = ur.rid
@@ -109,7 +361,18 @@ impl User {
         Ok(roles.into_iter().map(|(name,)| name).collect())
     }
 
-    pub async fn add_role(pool: &MySqlPool, uid: u32, rid: u32) -> Result<(), sqlx::Error> {
+    /// Give a new account the default role, when one can be resolved.
+    pub async fn add_default_role(pool: &Pool, uid: u32) -> Result<(), sqlx::Error> {
+        match Role::authenticated(pool).await? {
+            Some(role) => Self::add_role(pool, uid, role.rid).await,
+            None => {
+                tracing::warn!("No default role found for new account {}", uid);
+                Ok(())
+            }
+        }
+    }
+
+    pub async fn add_role(pool: &Pool, uid: u32, rid: u32) -> Result<(), sqlx::Error> {
         sqlx::query("INSERT IGNORE INTO users_roles (uid, rid) VALUES (?, ?)")
             .bind(uid)
             .bind(rid)
@@ -119,40 +382,235 @@ impl User {
         Ok(())
     }
 
+    /// Make `rids` the account's roles, adding and removing only what
+    /// differs from what it holds now.
+    pub async fn set_roles(pool: &Pool, uid: u32, rids: &[u32]) -> Result<(), sqlx::Error> {
+        let mut tx = pool.begin().await?;
+        let current: Vec<(u32,)> = sqlx::query_as("SELECT rid FROM users_roles WHERE uid = ? FOR UPDATE")
+            .bind(uid)
+            .fetch_all(&mut *tx)
+            .await?;
+        let current: HashSet<u32> = current.into_iter().map(|(rid,)| rid).collect();
+        let wanted: HashSet<u32> = rids.iter().copied().collect();
+
+        for rid in current.difference(&wanted) {
+            sqlx::query("DELETE FROM users_roles WHERE uid = ? AND rid = ?")
+                .bind(uid)
+                .bind(rid)
+                .execute(&mut *tx)
+                .await?;
+        }
+        for rid in wanted.difference(&current) {
+            sqlx::query("INSERT INTO users_roles (uid, rid) VALUES (?, ?)")
+                .bind(uid)
+                .bind(rid)
+                .execute(&mut *tx)
+                .await?;
+        }
+        tx.commit().await
+    }
+
+    /// Whether one of the account's roles grants exactly `permission`. The
+    /// first account has every permission; anonymous visitors have those of
+    /// the anonymous role.
     pub async fn has_permission(
         &self,
-        pool: &MySqlPool,
+        pool: &Pool,
         permission: &str,
     ) -> Result<bool, sqlx::Error> {
         if self.uid == 1 {
             return Ok(true);
         }
 
-        let result: Option<(i64,)> = sqlx::query_as(
-            "SELECT COUNT(*) FROM permission p
-             INNER JOIN users_roles ur ON p.rid = ur.rid
-             WHERE ur.uid = ? AND p.perm LIKE ?",
-        )
-        .bind(self.uid)
-        .bind(format!("%{}%", permission))
-        .fetch_optional(pool)
-        .await?;
+        let permissions = self
+            .permissions
+            .get_or_try_init(|| Self::load_permissions(pool, self.uid))
+            .await?;
+        Ok(permissions.contains(permission))
+    }
+
+    async fn load_permissions(pool: &Pool, uid: u32) -> Result<HashSet<String>, sqlx::Error> {
+        let rows: Vec<(Option<String>,)> = if uid == 0 {
+            sqlx::query_as("SELECT perm FROM permission WHERE rid = 1")
+                .fetch_all(pool)
+                .await?
+        } else {
+            sqlx::query_as(
+                "SELECT p.perm FROM permission p
+                 INNER JOIN users_roles ur ON p.rid = ur.rid
+                 WHERE ur.uid = ?",
+            )
+            .bind(uid)
+            .fetch_all(pool)
+            .await?
+        };
 
-        Ok(result.map(|(count,)| count > 0).unwrap_or(false))
+        Ok(Self::collect_permissions(rows.iter().filter_map(|(perm,)| perm.as_deref())))
     }
 
-    pub async fn all(pool: &MySqlPool) -> Result<Vec<Self>, sqlx::Error> {
+    /// Every permission in the `perm` lists of an account's roles.
+    fn collect_permissions<'a>(perms: impl IntoIterator<Item = &'a str>) -> HashSet<String> {
+        perms
+            .into_iter()
+            .flat_map(Permission::split)
+            .map(str::to_string)
+            .collect()
+    }
+
+    pub async fn all(pool: &Pool) -> Result<Vec<Self>, sqlx::Error> {
         sqlx::query_as::<_, User>("SELECT * FROM users WHERE uid > 0 ORDER BY name")
             .fetch_all(pool)
             .await
     }
 
-    pub async fn set_status(pool: &MySqlPool, uid: u32, status: i8) -> Result<(), sqlx::Error> {
-        sqlx::query("UPDATE users SET status = ? WHERE uid = ?")
-            .bind(status)
-            .bind(uid)
-            .execute(pool)
+    fn push_mail_recipient_filter(query: &mut QueryBuilder<'_, Db>, rid: Option<u32>) {
+        if let Some(rid) = rid {
+            query
+                .push(" INNER JOIN users_roles ur ON ur.uid = u.uid AND ur.rid = ")
+                .push_bind(rid);
+        }
+        query.push(" WHERE u.uid > 0 AND u.status = 1 AND u.mail <> ''");
+    }
+
+    /// Active accounts with an e-mail address, optionally only those with
+    /// role `rid`, as (uid, mail) pairs in uid order after `after_uid`.
+    pub async fn mail_recipients(
+        pool: &Pool,
+        rid: Option<u32>,
+        after_uid: u32,
+        limit: i64,
+    ) -> Result<Vec<(u32, String)>, sqlx::Error> {
+        let mut query = QueryBuilder::<Db>::new("SELECT u.uid, u.mail FROM users u");
+        Self::push_mail_recipient_filter(&mut query, rid);
+        query
+            .push(" AND u.uid > ")
+            .push_bind(after_uid)
+            .push(" ORDER BY u.uid LIMIT ")
+            .push_bind(limit);
+        query.build_query_as().fetch_all(pool).await
+    }
+
+    pub async fn count_mail_recipients(pool: &Pool, rid: Option<u32>) -> Result<i64, sqlx::Error> {
+        let mut query = QueryBuilder::<Db>::new("SELECT COUNT(*) FROM users u");
+        Self::push_mail_recipient_filter(&mut query, rid);
+        let (count,): (i64,) = query.build_query_as().fetch_one(pool).await?;
+        Ok(count)
+    }
+
+    /// Accounts created at or after `since`.
+    pub async fn count_created_since(pool: &Pool, since: i64) -> Result<i64, sqlx::Error> {
+        let (count,): (i64,) = sqlx::query_as("SELECT COUNT(*) FROM users WHERE uid > 0 AND created >= ?")
+            .bind(since)
+            .fetch_one(pool)
             .await?;
-        Ok(())
+        Ok(count)
+    }
+
+    /// Block (0) or unblock (1) several accounts, never user 1. Returns the
+    /// uid and name of every account whose status changed.
+    pub async fn set_status_multiple(
+        pool: &Pool,
+        uids: &[u32],
+        status: i8,
+    ) -> Result<Vec<(u32, String)>, sqlx::Error> {
+        let mut changed = Vec::new();
+        for chunk in uids.chunks(IN_LIST_CHUNK) {
+            let mut query = QueryBuilder::<Db>::new(
+                "SELECT uid, name FROM users WHERE uid > 1 AND status != ",
+            );
+            query.push_bind(status).push(" AND uid IN (");
+            let mut separated = query.separated(", ");
+            for uid in chunk {
+                separated.push_bind(*uid);
+            }
+            query.push(")");
+            let accounts: Vec<(u32, String)> = query.build_query_as().fetch_all(pool).await?;
+            if accounts.is_empty() {
+                continue;
+            }
+
+            let mut query = QueryBuilder::<Db>::new("UPDATE users SET status = ");
+            query.push_bind(status).push(" WHERE uid IN (");
+            let mut separated = query.separated(", ");
+            for (uid, _) in &accounts {
+                separated.push_bind(*uid);
+            }
+            query.push(")");
+            query.build().execute(pool).await?;
+
+            changed.extend(accounts);
+        }
+        Ok(changed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::NodeCommentStatistics;
+    use crate::test_support::{create_test_comment, create_test_node, create_test_user, test_site};
+
+    /// An account whose roles have the `perm` lists given.
+    fn user_with(perms: &[&str]) -> User {
+        let user = User { uid: 2, ..User::anonymous() };
+        user.permissions
+            .set(User::collect_permissions(perms.iter().copied()))
+            .unwrap();
+        user
+    }
+
+    /// A pool that is never connected: the permissions are already loaded.
+    fn unused_pool() -> Pool {
+        Pool::connect_lazy("mysql://localhost/unused").unwrap()
+    }
+
+    #[tokio::test]
+    async fn post_comments_does_not_match_without_approval() {
+        let pool = unused_pool();
+        let user = user_with(&["access content, post comments without approval"]);
+        assert!(!user.has_permission(&pool, "post comments").await.unwrap());
+        assert!(user.has_permission(&pool, "post comments without approval").await.unwrap());
+
+        let user = user_with(&["post comments"]);
+        assert!(user.has_permission(&pool, "post comments").await.unwrap());
+        assert!(!user.has_permission(&pool, "post comments without approval").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn prefix_of_a_permission_does_not_match() {
+        let pool = unused_pool();
+        let user = user_with(&["administer nodes foo", "access content"]);
+        assert!(!user.has_permission(&pool, "administer nodes").await.unwrap());
+        assert!(!user.has_permission(&pool, "administer").await.unwrap());
+        assert!(!user.has_permission(&pool, "access").await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn permissions_of_all_roles_are_combined() {
+        let pool = unused_pool();
+        let user = user_with(&["access content", " administer nodes ,, post comments"]);
+        for permission in ["access content", "administer nodes", "post comments"] {
+            assert!(user.has_permission(&pool, permission).await.unwrap(), "{}", permission);
+        }
+        assert!(!user.has_permission(&pool, "").await.unwrap());
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn deleting_an_account_recounts_the_comments_it_unpublished() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let author = create_test_user(pool, &[]).await;
+        let leaving = create_test_user(pool, &[]).await;
+        let node = create_test_node(pool, "story", &author, true).await;
+        create_test_comment(pool, node.nid, 0, &author).await;
+        create_test_comment(pool, node.nid, 0, &leaving).await;
+        create_test_comment(pool, node.nid, 0, &leaving).await;
+
+        assert!(User::delete(pool, leaving.uid, ContentDisposition::Unpublish).await.unwrap());
+
+        let statistics = NodeCommentStatistics::get_for_node(pool, node.nid).await.unwrap().unwrap();
+        assert_eq!(statistics.comment_count, 1);
+        assert_eq!(statistics.last_comment_uid, author.uid);
     }
 }
@@ -1,5 +1,8 @@
 use serde::{Deserialize, Serialize};
-use sqlx::MySqlPool;
+
+use crate::cache;
+use crate::db::Pool;
+use super::SiteSettings;
 
 #[derive(Debug, Clone, Serialize, Deserialize, sqlx::FromRow)]
 pub struct Variable {
@@ -8,7 +11,7 @@ pub struct Variable {
 }
 
 impl Variable {
-    pub async fn get(pool: &MySqlPool, name: &str) -> Result<Option<String>, sqlx::Error> {
+    pub async fn get(pool: &Pool, name: &str) -> Result<Option<String>, sqlx::Error> {
         let result: Option<(Option<String>,)> =
             sqlx::query_as("SELECT value FROM variable WHERE name = ?")
                 .bind(name)
@@ -18,7 +21,7 @@ impl Variable {
         Ok(result.and_then(|(v,)| v))
     }
 
-    pub async fn set(pool: &MySqlPool, name: &str, value: &str) -> Result<(), sqlx::Error> {
+    pub async fn set(pool: &Pool, name: &str, value: &str) -> Result<(), sqlx::Error> {
         sqlx::query(
             "INSERT INTO variable (name, value) VALUES (?, ?)
              ON DUPLICATE KEY UPDATE value = VALUES(value)",
@@ -27,22 +30,30 @@ impl Variable {
         .bind(value)
         .execute(pool)
         .await?;
+        cache::invalidate_tags(&[cache::TAG_VARIABLE]);
         Ok(())
     }
 
-    pub async fn delete(pool: &MySqlPool, name: &str) -> Result<(), sqlx::Error> {
+    pub async fn delete(pool: &Pool, name: &str) -> Result<(), sqlx::Error> {
         sqlx::query("DELETE FROM variable WHERE name = ?")
             .bind(name)
             .execute(pool)
             .await?;
+        cache::invalidate_tags(&[cache::TAG_VARIABLE]);
         Ok(())
     }
 
-    pub async fn get_or_default(pool: &MySqlPool, name: &str, default: &str) -> String {
+    pub async fn get_or_default(pool: &Pool, name: &str, default: &str) -> String {
         Self::get(pool, name)
             .await
             .ok()
             .flatten()
             .unwrap_or_else(|| default.to_string())
     }
+
+    /// The site's default time zone, stored in `date_default_timezone` as an
+    /// offset from UTC in seconds.
+    pub async fn default_timezone(pool: &Pool) -> chrono::FixedOffset {
+        SiteSettings::load(pool).await.timezone()
+    }
 }
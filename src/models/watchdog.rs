@@ -0,0 +1,146 @@
+use serde::Serialize;
+use sqlx::QueryBuilder;
+
+use crate::db::{Db, Pool};
+
+pub const WATCHDOG_NOTICE: u8 = 0;
+pub const WATCHDOG_WARNING: u8 = 1;
+pub const WATCHDOG_ERROR: u8 = 2;
+
+/// Severity levels as (value, label) pairs, for filters and display.
+pub const WATCHDOG_SEVERITIES: &[(u8, &str)] = &[
+    (WATCHDOG_NOTICE, "notice"),
+    (WATCHDOG_WARNING, "warning"),
+    (WATCHDOG_ERROR, "error"),
+];
+
+/// Entries in the `watchdog` system log.
+pub struct Watchdog;
+
+#[derive(Debug, Clone, Serialize, sqlx::FromRow)]
+pub struct WatchdogEntry {
+    pub wid: u32,
+    pub uid: u32,
+    #[sqlx(rename = "type")]
+    pub log_type: String,
+    pub message: String,
+    pub severity: u8,
+    pub link: String,
+    pub location: String,
+    pub timestamp: i32,
+    pub username: Option<String>,
+}
+
+/// Which entries the log report shows.
+#[derive(Debug, Clone, Default)]
+pub struct WatchdogFilter {
+    pub log_type: Option<String>,
+    pub severity: Option<u8>,
+}
+
+impl Watchdog {
+    /// Record an event. `link` points at the thing the event is about,
+    /// `location` is the page that was being requested. Accepts a pool or a
+    /// transaction so the entry can be written together with the change it
+    /// describes.
+    pub async fn log<'c, E>(
+        executor: E,
+        uid: u32,
+        log_type: &str,
+        message: &str,
+        severity: u8,
+        link: &str,
+        location: &str,
+    ) -> Result<(), sqlx::Error>
+    where
+        E: sqlx::Executor<'c, Database = Db>,
+    {
+        let now = chrono::Utc::now().timestamp() as i32;
+        sqlx::query(
+            "INSERT INTO watchdog (uid, type, message, severity, link, location, timestamp)
+             VALUES (?, ?, ?, ?, ?, ?, ?)",
+        )
+        .bind(uid)
+        .bind(log_type)
+        .bind(message)
+        .bind(severity)
+        .bind(link)
+        .bind(location)
+        .bind(now)
+        .execute(executor)
+        .await?;
+        Ok(())
+    }
+
+    fn push_filter<'a>(query: &mut QueryBuilder<'a, Db>, filter: &'a WatchdogFilter) {
+        query.push(" WHERE 1 = 1");
+        if let Some(log_type) = &filter.log_type {
+            query.push(" AND w.type = ").push_bind(log_type.as_str());
+        }
+        if let Some(severity) = filter.severity {
+            query.push(" AND w.severity = ").push_bind(severity);
+        }
+    }
+
+    pub async fn recent(
+        pool: &Pool,
+        filter: &WatchdogFilter,
+        limit: i64,
+        offset: i64,
+    ) -> Result<Vec<WatchdogEntry>, sqlx::Error> {
+        let mut query = QueryBuilder::<Db>::new(
+            "SELECT w.wid, w.uid, w.type, w.message, w.severity, w.link, w.location, w.timestamp,
+                    u.name as username
+             FROM watchdog w
+             LEFT JOIN users u ON w.uid = u.uid",
+        );
+        Self::push_filter(&mut query, filter);
+        query
+            .push(" ORDER BY w.wid DESC LIMIT ")
+            .push_bind(limit)
+            .push(" OFFSET ")
+            .push_bind(offset);
+
+        query.build_query_as().fetch_all(pool).await
+    }
+
+    pub async fn count(pool: &Pool, filter: &WatchdogFilter) -> Result<i64, sqlx::Error> {
+        let mut query = QueryBuilder::<Db>::new("SELECT COUNT(*) FROM watchdog w");
+        Self::push_filter(&mut query, filter);
+        let (count,): (i64,) = query.build_query_as().fetch_one(pool).await?;
+        Ok(count)
+    }
+
+    pub async fn find_by_wid(pool: &Pool, wid: u32) -> Result<Option<WatchdogEntry>, sqlx::Error> {
+        sqlx::query_as::<_, WatchdogEntry>(
+            "SELECT w.wid, w.uid, w.type, w.message, w.severity, w.link, w.location, w.timestamp,
+                    u.name as username
+             FROM watchdog w
+             LEFT JOIN users u ON w.uid = u.uid
+             WHERE w.wid = ?",
+        )
+        .bind(wid)
+        .fetch_optional(pool)
+        .await
+    }
+
+    /// Message types present in the log, for the report's type filter.
+    pub async fn types(pool: &Pool) -> Result<Vec<String>, sqlx::Error> {
+        let rows: Vec<(String,)> =
+            sqlx::query_as("SELECT DISTINCT type FROM watchdog ORDER BY type")
+                .fetch_all(pool)
+                .await?;
+        Ok(rows.into_iter().map(|(log_type,)| log_type).collect())
+    }
+
+    pub async fn flush_old_entries(pool: &Pool, max_age: u32) -> Result<u64, sqlx::Error> {
+        let cutoff = chrono::Utc::now().timestamp() - max_age as i64;
+
+        let result = sqlx::query("DELETE FROM watchdog WHERE timestamp < ?")
+            .bind(cutoff)
+            .execute(pool)
+            .await?;
+
+        Ok(result.rows_affected())
+    }
+}
@@ -0,0 +1,165 @@
+//! OpenGraph metadata for node pages, which social sites read to build a
+//! preview: the title, the address of the page and a representative image.
+
+use axum::http::HeaderMap;
+use regex::Regex;
+use serde::Serialize;
+use std::sync::LazyLock;
+
+use crate::{
+    db::Pool,
+    feed,
+    models::{first_image, FieldInstanceWithValue, NodeWithBody, SiteSettings},
+};
+
+/// The `src` of an `<img>` tag, quoted either way or not at all.
+static IMG_SRC: LazyLock<Regex> = LazyLock::new(|| {
+    Regex::new(r#"(?is)<img\b[^>]*?\ssrc\s*=\s*(?:"([^"]*)"|'([^']*)'|([^\s>]+))"#)
+        .expect("valid img src pattern")
+});
+
+/// What the `og:` meta tags of a page say.
+#[derive(Debug, Clone, Serialize)]
+pub struct OpenGraph {
+    pub title: String,
+    pub url: String,
+    /// Absolute URL, when the node or the site has an image.
+    pub image: Option<String>,
+}
+
+impl OpenGraph {
+    /// Metadata for `node`, whose field values are `fields`. The image is the
+    /// node's [`teaser_image`], else the site's default image.
+    pub async fn for_node(
+        pool: &Pool,
+        headers: &HeaderMap,
+        node: &NodeWithBody,
+        fields: &[FieldInstanceWithValue],
+    ) -> Self {
+        let base_url = feed::base_url(pool, headers).await;
+        let image = match teaser_image(fields, node.body.as_deref()) {
+            Some(image) => Some(image),
+            None => Some(SiteSettings::load(pool).await.site_default_image).filter(|i| !i.is_empty()),
+        };
+
+        OpenGraph {
+            title: node.title.clone(),
+            url: format!("{}/node/{}", base_url, node.nid),
+            image: image.and_then(|image| absolute_url(&base_url, &image)),
+        }
+    }
+}
+
+/// The image that stands for a node: the first value of its first image
+/// field that has one, else the first `<img>` in `body`. Field images come
+/// back as the path they are served at; body images as written.
+pub fn teaser_image(fields: &[FieldInstanceWithValue], body: Option<&str>) -> Option<String> {
+    let field_image = first_image(fields).map(|file| format!("/static/files/{}", file));
+
+    field_image.or_else(|| {
+        IMG_SRC
+            .captures_iter(body?)
+            .filter_map(|captures| captures.get(1).or(captures.get(2)).or(captures.get(3)))
+            .map(|src| src.as_str().trim())
+            .find(|src| !src.is_empty())
+            .map(str::to_string)
+    })
+}
+
+/// `src` made absolute against `base_url`. Only web addresses qualify; a
+/// `data:` or `javascript:` source yields `None`.
+fn absolute_url(base_url: &str, src: &str) -> Option<String> {
+    let src = src.replace("&amp;", "&");
+    let lower = src.to_ascii_lowercase();
+    if lower.starts_with("http://") || lower.starts_with("https://") {
+        return Some(src);
+    }
+    if let Some(rest) = src.strip_prefix("//") {
+        let scheme = base_url.split("://").next().unwrap_or("http");
+        return Some(format!("{}://{}", scheme, rest));
+    }
+    if src.split('/').next().is_some_and(|segment| segment.contains(':')) {
+        return None;
+    }
+    Some(format!("{}/{}", base_url, src.trim_start_matches('/')))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::models::{node_field::FieldValue, Variable};
+    use crate::test_support::{create_test_node, create_test_user, test_site};
+
+    const BODY: &str = r#"<p>Look:</p><img alt="x" src='/static/files/inline.png'><img src="/second.png">"#;
+
+    fn image_field(files: &[(u32, &str)]) -> FieldInstanceWithValue {
+        FieldInstanceWithValue {
+            field_name: "field_photo".to_string(),
+            field_type: "image".to_string(),
+            label: "Photo".to_string(),
+            description: None,
+            required: 0,
+            weight: 0,
+            widget_type: None,
+            cardinality: 0,
+            settings: None,
+            values: files
+                .iter()
+                .map(|(delta, file)| FieldValue {
+                    delta: *delta,
+                    value_text: Some(file.to_string()),
+                    value_int: None,
+                    value_float: None,
+                })
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn field_images_come_first() {
+        let fields = [image_field(&[(1, "second.jpg"), (0, "first.jpg")])];
+        assert_eq!(teaser_image(&fields, Some(BODY)).as_deref(), Some("/static/files/first.jpg"));
+    }
+
+    #[test]
+    fn inline_images_stand_in_for_an_empty_field() {
+        for fields in [vec![], vec![image_field(&[(0, "")])]] {
+            assert_eq!(teaser_image(&fields, Some(BODY)).as_deref(), Some("/static/files/inline.png"));
+        }
+        assert_eq!(teaser_image(&[], Some("<p>No pictures.</p>")), None);
+        assert_eq!(teaser_image(&[], None), None);
+    }
+
+    #[test]
+    fn only_web_addresses_become_absolute() {
+        let base = "https://example.org";
+        assert_eq!(absolute_url(base, "/a.png").as_deref(), Some("https://example.org/a.png"));
+        assert_eq!(absolute_url(base, "//cdn.example.net/a.png").as_deref(), Some("https://cdn.example.net/a.png"));
+        assert_eq!(absolute_url(base, "http://other.org/a?x=1&amp;y=2").as_deref(), Some("http://other.org/a?x=1&y=2"));
+        for refused in ["javascript:alert(1)", "data:image/png;base64,AAAA"] {
+            assert_eq!(absolute_url(base, refused), None);
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn the_site_image_is_the_fallback() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        Variable::set(pool, "site_url", "https://example.org").await.unwrap();
+        let author = create_test_user(pool, &[]).await;
+        let node = create_test_node(pool, "story", &author, true).await;
+
+        let og = OpenGraph::for_node(pool, &HeaderMap::new(), &node, &[]).await;
+        assert_eq!(og.url, format!("https://example.org/node/{}", node.nid));
+        assert_eq!(og.image, None);
+
+        Variable::set(pool, "site_default_image", "/static/logo.png").await.unwrap();
+        let og = OpenGraph::for_node(pool, &HeaderMap::new(), &node, &[]).await;
+        assert_eq!(og.image.as_deref(), Some("https://example.org/static/logo.png"));
+
+        let fields = [image_field(&[(0, "photo.jpg")])];
+        let og = OpenGraph::for_node(pool, &HeaderMap::new(), &node, &fields).await;
+        assert_eq!(og.image.as_deref(), Some("https://example.org/static/files/photo.jpg"));
+    }
+}
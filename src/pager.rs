@@ -0,0 +1,39 @@
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Default, Deserialize)]
+pub struct PageQuery {
+    #[serde(default)]
+    pub page: u32,
+}
+
+/// Pager state handed to templates; pages are zero-based like Drupal's
+/// `?page=N` query parameter.
+#[derive(Debug, Clone, Serialize)]
+pub struct Pager {
+    pub page: u32,
+    pub per_page: u32,
+    pub total: i64,
+    pub pages: u32,
+}
+
+impl Pager {
+    pub fn new(page: u32, per_page: u32, total: i64) -> Self {
+        let per_page = per_page.max(1);
+        let pages = ((total.max(0) as u64).div_ceil(per_page as u64)).max(1) as u32;
+
+        Pager {
+            page: page.min(pages - 1),
+            per_page,
+            total,
+            pages,
+        }
+    }
+
+    pub fn limit(&self) -> i64 {
+        self.per_page as i64
+    }
+
+    pub fn offset(&self) -> i64 {
+        self.page as i64 * self.per_page as i64
+    }
+}
@@ -0,0 +1,90 @@
+use axum::{
+    body::Body,
+    extract::State,
+    http::{Request, Uri},
+    middleware::Next,
+    response::Response,
+};
+
+use crate::db::Pool;
+use crate::models::{is_system_path, Alias};
+
+/// Rewrite aliased request paths to their internal path before routing,
+/// so `/about-us` is dispatched as `/node/5`.
+pub async fn resolve_alias(
+    State(pool): State<Pool>,
+    mut request: Request<Body>,
+    next: Next,
+) -> Response {
+    let path = request.uri().path().trim_matches('/').to_string();
+
+    // Aliases over system paths are refused when saved; skipping them here
+    // as well keeps one stored before then from taking over a page.
+    if !path.is_empty() && !is_system_path(&path) {
+        if let Ok(Some(src)) = Alias::source(&pool, &path).await {
+            let rewritten = match request.uri().query() {
+                Some(query) => format!("/{}?{}", src, query),
+                None => format!("/{}", src),
+            };
+            if let Ok(uri) = rewritten.parse::<Uri>() {
+                *request.uri_mut() = uri;
+            }
+        }
+    }
+
+    next.run(request).await
+}
+
+/// `destination` when it is a path on this site. Anything else, such as an
+/// absolute or protocol-relative URL, yields `None`, so a destination taken
+/// from the query string cannot send people off to another site. Control
+/// characters and whitespace are refused too: browsers drop them from a
+/// `Location` header, so `/\t/evil.com` would be followed as `//evil.com`,
+/// and a newline cannot go in a header at all.
+pub fn local_destination(destination: &str) -> Option<&str> {
+    if !destination.starts_with('/')
+        || destination.starts_with("//")
+        || destination.contains(|c: char| c == '\\' || c.is_control() || c.is_whitespace())
+    {
+        return None;
+    }
+    let uri: Uri = destination.parse().ok()?;
+    (uri.scheme().is_none() && uri.authority().is_none()).then_some(destination)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn local_paths_are_kept() {
+        assert_eq!(local_destination("/node/5"), Some("/node/5"));
+        assert_eq!(local_destination("/admin?tab=users"), Some("/admin?tab=users"));
+    }
+
+    #[test]
+    fn other_sites_are_refused() {
+        for destination in ["http://evil.com", "//evil.com", "/\\evil.com", "evil.com", ""] {
+            assert_eq!(local_destination(destination), None, "{:?}", destination);
+        }
+    }
+
+    #[test]
+    fn control_characters_and_whitespace_are_refused() {
+        for destination in ["/\nfoo", "/foo\r\nSet-Cookie: a=b", "/\t/evil.com", "/ /evil.com", "/foo\0"] {
+            assert_eq!(local_destination(destination), None, "{:?}", destination);
+        }
+    }
+
+    #[test]
+    fn decoded_query_destinations_are_checked() {
+        #[derive(serde::Deserialize)]
+        struct Destination {
+            destination: String,
+        }
+        for query in ["destination=/%0Afoo", "destination=/%0Dfoo", "destination=/%09/evil.com"] {
+            let Destination { destination } = serde_qs::from_str(query).unwrap();
+            assert_eq!(local_destination(&destination), None, "{}", query);
+        }
+    }
+}
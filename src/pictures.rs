@@ -0,0 +1,107 @@
+//! User pictures: uploaded on the account edit page, stored under
+//! `static/pictures/` and shown on profiles and next to comments.
+
+use image::ImageFormat;
+use serde::Serialize;
+use std::path::Path;
+
+use crate::db::Pool;
+use crate::extractors::MultipartPart;
+use crate::image_styles;
+use crate::models::Variable;
+
+/// Pictures are written here and served under `/static/pictures`.
+pub const PICTURES_DIR: &str = "static/pictures";
+
+/// Shown for users without a picture unless `user_picture_default` names
+/// another image.
+const DEFAULT_PICTURE: &str = "/static/misc/user-picture.svg";
+
+/// Limits on uploaded pictures and the fallback image, from the
+/// `user_picture_max_size` (kilobytes), `user_picture_dimensions`
+/// (`WIDTHxHEIGHT`) and `user_picture_default` variables.
+#[derive(Debug, Clone, Serialize)]
+pub struct PictureSettings {
+    pub max_size: u32,
+    pub width: u32,
+    pub height: u32,
+    pub default: String,
+}
+
+impl PictureSettings {
+    pub async fn load(pool: &Pool) -> Self {
+        let max_size = Variable::get_or_default(pool, "user_picture_max_size", "30")
+            .await
+            .parse()
+            .unwrap_or(30);
+        let dimensions = Variable::get_or_default(pool, "user_picture_dimensions", "85x85").await;
+        let (width, height) = dimensions
+            .split_once('x')
+            .and_then(|(w, h)| Some((w.trim().parse().ok()?, h.trim().parse().ok()?)))
+            .unwrap_or((85, 85));
+        let default = match Variable::get_or_default(pool, "user_picture_default", "").await {
+            url if url.trim().is_empty() => DEFAULT_PICTURE.to_string(),
+            url => url,
+        };
+
+        PictureSettings {
+            max_size,
+            width,
+            height,
+            default,
+        }
+    }
+
+    /// URL of a stored picture path, or of the default picture.
+    pub fn url(&self, picture: &str) -> String {
+        if picture.is_empty() {
+            self.default.clone()
+        } else {
+            format!("/static/{}", picture)
+        }
+    }
+
+    /// Check an upload against the limits, returning the image format or a
+    /// message for the form.
+    pub fn validate(&self, file: &MultipartPart) -> Result<ImageFormat, String> {
+        if file.data.len() > self.max_size as usize * 1024 {
+            return Err(format!("The picture is larger than {} KB.", self.max_size));
+        }
+        let (format, width, height) = image_styles::inspect_upload(file)
+            .map_err(|reason| format!("The picture {}.", reason))?;
+        if width > self.width || height > self.height {
+            return Err(format!(
+                "The picture is {}x{} pixels; the maximum is {}x{}.",
+                width, height, self.width, self.height
+            ));
+        }
+
+        Ok(format)
+    }
+}
+
+/// Write a validated picture for `uid`, replacing any previous one, and
+/// return its path below `static/`.
+pub async fn save(uid: u32, format: ImageFormat, data: &[u8], previous: &str) -> std::io::Result<String> {
+    let extension = format.extensions_str().first().copied().unwrap_or("img");
+    let file = format!("picture-{}.{}", uid, extension);
+
+    tokio::fs::create_dir_all(PICTURES_DIR).await?;
+    tokio::fs::write(Path::new(PICTURES_DIR).join(&file), data).await?;
+
+    let picture = format!("pictures/{}", file);
+    if previous != picture {
+        delete(previous).await;
+    }
+    Ok(picture)
+}
+
+/// Remove a stored picture file. Only paths inside the pictures directory
+/// are touched.
+pub async fn delete(picture: &str) {
+    if let Some(file) = picture.strip_prefix("pictures/") {
+        if !file.is_empty() && !file.contains('/') && !file.contains("..") {
+            let _ = tokio::fs::remove_file(Path::new(PICTURES_DIR).join(file)).await;
+        }
+    }
+}
@@ -1,23 +1,95 @@
 use axum::{
     body::Body,
-    extract::State,
     http::Request,
     middleware::Next,
     response::Response,
 };
-use sqlx::MySqlPool;
+use std::sync::atomic::{AtomicI64, AtomicU64, Ordering};
+use std::sync::OnceLock;
 use std::time::Instant;
+use tokio::sync::mpsc;
 
-use crate::models::{AccessLog, NodeCounter, SystemItem, Variable};
+use crate::auth::middleware::CurrentUser;
+use crate::db::Pool;
+use crate::models::{AccessLog, Node, NodeCounter, SystemItem, Variable};
 
-pub async fn statistics_middleware(
-    State(pool): State<MySqlPool>,
-    request: Request<Body>,
-    next: Next,
-) -> Response {
+/// Paths left out of the statistics unless `statistics_exclude_paths` says
+/// otherwise: administration pages, machine endpoints and feeds.
+pub const DEFAULT_EXCLUDED_PATHS: &str = "/admin\n/api\n/health\n*.xml";
+
+/// Page views waiting to be written. Once this many are queued, further
+/// ones are dropped rather than held up behind a slow database.
+const QUEUE_CAPACITY: usize = 1000;
+
+/// Most page views the worker writes in one go.
+const BATCH_SIZE: usize = 100;
+
+/// While writes keep failing, the failure is logged at most once per this
+/// many seconds.
+const FAILURE_LOG_INTERVAL: i64 = 60;
+
+static QUEUE: OnceLock<mpsc::Sender<AccessEvent>> = OnceLock::new();
+static DROPPED: AtomicU64 = AtomicU64::new(0);
+static FAILURE_LOGGED_AT: AtomicI64 = AtomicI64::new(0);
+
+/// Whether `path` matches one of the newline-separated `patterns`. A pattern
+/// with `*` is matched as a wildcard against the whole path; any other
+/// pattern matches that path and everything below it.
+pub fn path_excluded(path: &str, patterns: &str) -> bool {
+    patterns
+        .lines()
+        .map(str::trim)
+        .filter(|pattern| !pattern.is_empty())
+        .any(|pattern| {
+            if pattern.contains('*') {
+                wildcard_match(pattern, path)
+            } else {
+                let prefix = pattern.trim_end_matches('/');
+                path == prefix
+                    || path
+                        .strip_prefix(prefix)
+                        .is_some_and(|rest| rest.starts_with('/') || prefix.is_empty())
+            }
+        })
+}
+
+fn wildcard_match(pattern: &str, text: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or("");
+    let Some(mut rest) = text.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let last = parts.pop().unwrap_or("");
+    for part in parts {
+        match rest.find(part) {
+            Some(index) => rest = &rest[index + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+/// One tracked page view, queued for the statistics worker.
+struct AccessEvent {
+    path: String,
+    session_id: String,
+    referer: String,
+    host: String,
+    uid: u32,
+    timer: u32,
+}
+
+pub async fn statistics_middleware(request: Request<Body>, next: Next) -> Response {
     let start = Instant::now();
     let path = request.uri().path().to_string();
     let method = request.method().clone();
+    let uid = request
+        .extensions()
+        .get::<CurrentUser>()
+        .and_then(|CurrentUser(user)| user.as_ref())
+        .map(|user| user.uid)
+        .unwrap_or(0);
 
     // Get request info before consuming it
     let headers = request.headers().clone();
@@ -54,63 +126,248 @@ pub async fn statistics_middleware(
 
     // Only log GET requests for non-static paths
     if method == "GET" && !path.starts_with("/static") {
-        let timer = start.elapsed().as_millis() as u32;
-
-        // Spawn a task to log the access (don't block the response)
-        let pool_clone = pool.clone();
-        let path_clone = path.clone();
-        tokio::spawn(async move {
-            // Check if statistics module is enabled
-            if !SystemItem::is_module_enabled(&pool_clone, "statistics")
-                .await
-                .unwrap_or(false)
-            {
-                return;
-            }
+        enqueue(AccessEvent {
+            path,
+            session_id,
+            referer,
+            host,
+            uid,
+            timer: start.elapsed().as_millis() as u32,
+        });
+    }
 
-            // Check if access logging is enabled
-            let log_enabled = Variable::get(&pool_clone, "statistics_enable_access_log")
-                .await
-                .ok()
-                .flatten()
-                .map(|v| v == "1")
-                .unwrap_or(false);
-
-            if log_enabled {
-                // Get title from path (simplified - just use path for now)
-                let title = path_clone.clone();
-
-                let _ = AccessLog::log_access(
-                    &pool_clone,
-                    &session_id,
-                    &title,
-                    &path_clone,
-                    &referer,
-                    &host,
-                    0, // uid - would need to extract from session
-                    timer,
-                )
-                .await;
+    response
+}
+
+/// Hand `event` to the worker without waiting. When the queue is full, or
+/// the worker was never started, the event is dropped and counted instead.
+fn enqueue(event: AccessEvent) {
+    let queued = QUEUE
+        .get()
+        .is_some_and(|queue| queue.try_send(event).is_ok());
+    if !queued {
+        DROPPED.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+/// Page views dropped because the statistics queue was full since the
+/// server started.
+pub fn dropped_events() -> u64 {
+    DROPPED.load(Ordering::Relaxed)
+}
+
+/// Start the worker that writes queued page views to the database. It takes
+/// whatever has queued up, at most `BATCH_SIZE` at a time, and reads the
+/// statistics settings once for each batch.
+pub fn spawn(pool: Pool) {
+    let (sender, mut receiver) = mpsc::channel(QUEUE_CAPACITY);
+    if QUEUE.set(sender).is_err() {
+        return;
+    }
+
+    tokio::spawn(async move {
+        let mut events = Vec::with_capacity(BATCH_SIZE);
+        while receiver.recv_many(&mut events, BATCH_SIZE).await > 0 {
+            if let Err(e) = record(&pool, &events).await {
+                report_failure(&e);
             }
+            events.clear();
+        }
+    });
+}
 
-            // Check if node counter is enabled and path is a node view
-            let count_enabled = Variable::get(&pool_clone, "statistics_count_content_views")
-                .await
-                .ok()
-                .flatten()
-                .map(|v| v == "1")
-                .unwrap_or(false);
-
-            if count_enabled && path_clone.starts_with("/node/") {
-                // Extract node ID from path like /node/123
-                if let Some(nid_str) = path_clone.strip_prefix("/node/") {
-                    if let Ok(nid) = nid_str.parse::<u32>() {
-                        let _ = NodeCounter::increment(&pool_clone, nid).await;
-                    }
+/// Whether page requests are currently written to the access log.
+pub async fn access_log_enabled(pool: &Pool) -> Result<bool, sqlx::Error> {
+    Ok(SystemItem::is_module_enabled(pool, "statistics").await?
+        && Variable::get(pool, "statistics_enable_access_log")
+            .await?
+            .is_some_and(|v| v == "1"))
+}
+
+async fn record(pool: &Pool, events: &[AccessEvent]) -> Result<(), sqlx::Error> {
+    if !SystemItem::is_module_enabled(pool, "statistics").await? {
+        return Ok(());
+    }
+
+    let excluded =
+        Variable::get_or_default(pool, "statistics_exclude_paths", DEFAULT_EXCLUDED_PATHS).await;
+    let log_enabled = Variable::get(pool, "statistics_enable_access_log")
+        .await?
+        .is_some_and(|v| v == "1");
+    let count_enabled = Variable::get(pool, "statistics_count_content_views")
+        .await?
+        .is_some_and(|v| v == "1");
+
+    for event in events {
+        if path_excluded(&event.path, &excluded) {
+            continue;
+        }
+
+        if log_enabled {
+            // Get title from path (simplified - just use path for now)
+            AccessLog::log_access(
+                pool,
+                &event.session_id,
+                &event.path,
+                &event.path,
+                &event.referer,
+                &event.host,
+                event.uid,
+                event.timer,
+            )
+            .await?;
+        }
+
+        // Extract node ID from path like /node/123
+        if count_enabled {
+            if let Some(nid) = event.path.strip_prefix("/node/").and_then(|n| n.parse::<u32>().ok()) {
+                if counts_view(pool, nid, event.uid).await {
+                    NodeCounter::increment(pool, nid).await?;
                 }
             }
-        });
+        }
     }
+    Ok(())
+}
 
-    response
+/// Log a failed write, at most once per `FAILURE_LOG_INTERVAL` so a
+/// database that stays down does not flood the log.
+fn report_failure(error: &sqlx::Error) {
+    let now = chrono::Utc::now().timestamp();
+    let last = FAILURE_LOGGED_AT.load(Ordering::Relaxed);
+    if now - last >= FAILURE_LOG_INTERVAL
+        && FAILURE_LOGGED_AT
+            .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+            .is_ok()
+    {
+        tracing::error!("Recording page views failed, statistics are incomplete: {}", error);
+    }
+}
+
+/// Whether a view of node `nid` by `uid` goes into the counter. Authors
+/// re-reading their own posts are left out unless `statistics_count_own_views`
+/// is on.
+async fn counts_view(pool: &Pool, nid: u32, uid: u32) -> bool {
+    if uid == 0 {
+        return true;
+    }
+
+    let count_own_views = Variable::get_or_default(pool, "statistics_count_own_views", "0").await;
+    if count_own_views == "1" {
+        return true;
+    }
+
+    match Node::find_by_nid(pool, nid).await {
+        Ok(Some(node)) => node.uid != uid,
+        _ => true,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{create_test_node, create_test_user, test_site};
+    use axum::{http::StatusCode, middleware, routing::get, Router};
+    use sqlx::mysql::MySqlPoolOptions;
+    use std::time::Duration;
+    use tower::Service;
+
+    /// A page view of `path` by `uid`, as the middleware queues it.
+    fn view(path: &str, uid: u32) -> AccessEvent {
+        AccessEvent {
+            path: path.to_string(),
+            session_id: "session".to_string(),
+            referer: String::new(),
+            host: "127.0.0.1".to_string(),
+            uid,
+            timer: 1,
+        }
+    }
+
+    /// Turn on the statistics module with its access log and node counter.
+    async fn enable_statistics(pool: &Pool) {
+        SystemItem::enable_module(pool, "statistics").await.unwrap();
+        Variable::set(pool, "statistics_enable_access_log", "1").await.unwrap();
+        Variable::set(pool, "statistics_count_content_views", "1").await.unwrap();
+    }
+
+    #[test]
+    fn default_exclusions_cover_administration_and_feeds() {
+        for path in ["/admin", "/admin/content", "/api/nodes", "/health", "/rss.xml", "/taxonomy/term/1/feed.xml"] {
+            assert!(path_excluded(path, DEFAULT_EXCLUDED_PATHS), "{} is logged", path);
+        }
+        for path in ["/", "/node/1", "/administrators", "/xml/page"] {
+            assert!(!path_excluded(path, DEFAULT_EXCLUDED_PATHS), "{} is not logged", path);
+        }
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn excluded_paths_are_not_logged() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        enable_statistics(pool).await;
+        let author = create_test_user(pool, &[]).await;
+        let node = create_test_node(pool, "story", &author, true).await;
+        let node_path = format!("/node/{}", node.nid);
+
+        record(pool, &[view("/admin/content", 0), view("/rss.xml", 0), view(&node_path, 0)]).await.unwrap();
+
+        let paths: Vec<(String,)> = sqlx::query_as("SELECT path FROM accesslog").fetch_all(pool).await.unwrap();
+        assert_eq!(paths, vec![(node_path,)]);
+    }
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn authors_do_not_count_their_own_views() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        enable_statistics(pool).await;
+        let author = create_test_user(pool, &[]).await;
+        let reader = create_test_user(pool, &[]).await;
+        let node = create_test_node(pool, "story", &author, true).await;
+        let path = format!("/node/{}", node.nid);
+        let total_count = || async {
+            let (count,): (u64,) = sqlx::query_as("SELECT totalcount FROM node_counter WHERE nid = ?")
+                .bind(node.nid)
+                .fetch_optional(pool)
+                .await
+                .unwrap()
+                .unwrap_or((0,));
+            count
+        };
+
+        record(pool, &[view(&path, author.uid), view(&path, author.uid), view(&path, author.uid)]).await.unwrap();
+        assert_eq!(total_count().await, 0);
+        record(pool, &[view(&path, reader.uid), view(&path, 0)]).await.unwrap();
+        assert_eq!(total_count().await, 2);
+
+        Variable::set(pool, "statistics_count_own_views", "1").await.unwrap();
+        record(pool, &[view(&path, author.uid)]).await.unwrap();
+        assert_eq!(total_count().await, 3);
+    }
+
+    #[tokio::test]
+    async fn a_stuck_database_never_holds_up_responses() {
+        // Nothing listens on port 1: the worker keeps retrying the
+        // connection for its first batch while the requests pile up.
+        let pool = MySqlPoolOptions::new()
+            .acquire_timeout(Duration::from_secs(60))
+            .connect_lazy("mysql://drupal@127.0.0.1:1/drupal")
+            .unwrap();
+        spawn(pool);
+        let mut app = Router::new()
+            .route("/node/1", get(|| async { "ok" }))
+            .layer(middleware::from_fn(statistics_middleware));
+
+        let started = Instant::now();
+        for _ in 0..QUEUE_CAPACITY + 2 * BATCH_SIZE {
+            let request = Request::get("/node/1").body(Body::empty()).unwrap();
+            let response = app.call(request).await.unwrap();
+            assert_eq!(response.status(), StatusCode::OK);
+        }
+
+        assert!(started.elapsed() < Duration::from_secs(5), "took {:?}", started.elapsed());
+        assert!(dropped_events() > 0, "a full queue drops page views");
+    }
 }
@@ -0,0 +1,171 @@
+//! Rendering with context contracts. Tera only complains about a missing
+//! variable when it is printed; one that is merely tested with `{% if %}`
+//! or given a `default` silently reads as empty, so a handler that forgets
+//! to insert it produces a page with a blank spot instead of an error.
+//! Debug builds check every render against the keys listed here and fail
+//! when one is missing.
+
+use tera::{Context, Tera};
+
+/// Context keys a template needs, beyond what Tera itself insists on. A
+/// template inherits the contracts of the templates it extends.
+const TEMPLATE_CONTRACTS: &[(&str, &[&str])] = &[
+    ("base.html", &["title"]),
+    ("home.html", &[
+        "site_mission",
+        "installed",
+        "nodes",
+        "teaser_links",
+        "teaser_submitted",
+        "teaser_images",
+        "teaser_image_style",
+        "current_user",
+    ]),
+    ("node/view.html", &[
+        "node",
+        "fields",
+        "full_image_style",
+        "comments",
+        "current_user",
+        "can_edit",
+        "can_delete",
+        "can_post_comments",
+        "comment_permissions",
+        "show_submitted",
+        "show_comment_subject",
+        "comment_mode",
+    ]),
+    ("node/form.html", &["node_type", "fields", "field_widgets", "options"]),
+    ("comment/form.html", &["node", "current_user", "show_subject", "comment_length"]),
+    ("tracker.html", &["nodes", "current_user", "pager"]),
+    ("user/profile.html", &["profile_user", "profile_values", "current_user"]),
+    ("access_denied.html", &["current_user", "user_login"]),
+    ("user/edit.html", &[
+        "profile_user",
+        "profile_values",
+        "current_user",
+        "require_current_password",
+        "can_delete_account",
+    ]),
+];
+
+/// Render `template`, in debug builds only after checking that `context`
+/// holds every key the template's contract lists.
+pub fn render(tera: &Tera, template: &str, context: &Context) -> tera::Result<String> {
+    if cfg!(debug_assertions) {
+        let missing = missing_keys(tera, template, context);
+        if !missing.is_empty() {
+            let message = format!(
+                "Template '{}' rendered without {}",
+                template,
+                missing.join(", ")
+            );
+            tracing::error!("{}", message);
+            return Err(tera::Error::msg(message));
+        }
+    }
+    tera.render(template, context)
+}
+
+/// Keys the contracts of `template` and its parents require that `context`
+/// does not have.
+fn missing_keys(tera: &Tera, template: &str, context: &Context) -> Vec<&'static str> {
+    let mut names = vec![template.to_string()];
+    if let Ok(parsed) = tera.get_template(template) {
+        names.extend(parsed.parents.iter().cloned());
+    }
+
+    let mut missing = Vec::new();
+    for (name, keys) in TEMPLATE_CONTRACTS {
+        if !names.iter().any(|n| n == name) {
+            continue;
+        }
+        for &key in *keys {
+            if !context.contains_key(key) && !missing.contains(&key) {
+                missing.push(key);
+            }
+        }
+    }
+    missing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::{
+        extract::{Path, Query, State},
+        Extension,
+    };
+
+    use crate::auth::middleware::CurrentUser;
+    use crate::handlers;
+    use crate::pager::PageQuery;
+    use crate::test_support::{create_test_node, create_test_user, test_site, test_tera};
+
+    #[test]
+    fn contracts_name_existing_templates() {
+        let tera = test_tera();
+        for (name, keys) in TEMPLATE_CONTRACTS {
+            assert!(tera.get_template(name).is_ok(), "no template {}", name);
+            assert!(!keys.is_empty(), "empty contract for {}", name);
+        }
+    }
+
+    #[test]
+    fn contracts_are_inherited() {
+        let tera = test_tera();
+        let missing = missing_keys(&tera, "tracker.html", &Context::new());
+        assert!(missing.contains(&"title"), "{:?}", missing);
+        assert!(missing.contains(&"pager"), "{:?}", missing);
+    }
+
+    #[test]
+    fn full_context_satisfies_every_contract() {
+        let tera = test_tera();
+        for (name, _) in TEMPLATE_CONTRACTS {
+            let mut context = Context::new();
+            for key in missing_keys(&tera, name, &context) {
+                context.insert(key, &());
+            }
+            assert!(missing_keys(&tera, name, &context).is_empty(), "{}", name);
+        }
+    }
+
+    #[test]
+    fn render_fails_naming_the_missing_keys() {
+        let tera = test_tera();
+        let mut context = Context::new();
+        context.insert("title", "Tracker");
+        context.insert("nodes", &Vec::<()>::new());
+
+        let error = render(&tera, "tracker.html", &context).unwrap_err().to_string();
+        assert!(error.contains("tracker.html"), "{}", error);
+        assert!(error.contains("current_user") && error.contains("pager"), "{}", error);
+        assert!(!error.contains("title"), "{}", error);
+    }
+
+    /// The core pages are rendered through `render`, so any contract their
+    /// handler does not meet fails here.
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn core_pages_meet_their_contracts() {
+        let site = test_site().await;
+        let pool = &site.pool;
+        let member = create_test_user(pool, &[]).await;
+        create_test_node(pool, "story", &member, true).await;
+
+        for user in [None, Some(member.clone())] {
+            let current = || Extension(CurrentUser(user.clone()));
+            // A broken contract makes rendering, and so the handler, fail.
+            let _ = handlers::home::index(State(pool.clone()), State(test_tera()), current())
+                .await
+                .unwrap();
+            let _ = handlers::tracker::index(State(pool.clone()), State(test_tera()), current(), Query(PageQuery::default()))
+                .await
+                .unwrap();
+            let _ = handlers::user::profile(State(pool.clone()), State(test_tera()), current(), Path(member.uid))
+                .await
+                .unwrap();
+        }
+    }
+}
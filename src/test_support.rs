@@ -0,0 +1,172 @@
+//! Fixtures for tests that need a database. They run against the MySQL
+//! database named by `DRUPAL_TEST_DATABASE_URL`, which is dropped and
+//! created afresh for every test, so never point it at a real site. Such
+//! tests are marked `#[ignore]`; run them with
+//! `cargo test -- --include-ignored` once the variable is set.
+
+use sqlx::{Connection, MySqlConnection};
+use std::sync::atomic::{AtomicU32, Ordering};
+use tera::Tera;
+use tokio::sync::{Mutex, MutexGuard};
+
+use crate::cache;
+use crate::db::{self, migrations, Pool};
+use crate::filter::{self, FORMAT_FILTERED_HTML};
+use crate::models::{
+    Comment, Node, NodeOptions, NodeWithBody, Permission, Role, User, COMMENT_PUBLISHED,
+};
+
+/// Variable naming the test database.
+const DATABASE_URL: &str = "DRUPAL_TEST_DATABASE_URL";
+
+/// Tests share one database and the in-process caches, so they take turns.
+static SITE_LOCK: Mutex<()> = Mutex::const_new(());
+
+static NEXT_NAME: AtomicU32 = AtomicU32::new(1);
+
+/// A freshly installed site, held for the duration of one test.
+pub struct TestSite {
+    pub pool: Pool,
+    _lock: MutexGuard<'static, ()>,
+}
+
+/// Recreate the test database and install the site in it. Fails the test
+/// when `DRUPAL_TEST_DATABASE_URL` is unset rather than letting it pass
+/// without having run.
+pub async fn test_site() -> TestSite {
+    let url = std::env::var(DATABASE_URL)
+        .unwrap_or_else(|_| panic!("{} must be set to run database tests", DATABASE_URL));
+    let lock = SITE_LOCK.lock().await;
+
+    let (server, name) = url
+        .rsplit_once('/')
+        .map(|(server, rest)| (server, rest.split('?').next().unwrap_or("")))
+        .filter(|(_, name)| !name.is_empty())
+        .unwrap_or_else(|| panic!("{} must name a database", DATABASE_URL));
+    let mut conn = MySqlConnection::connect(server).await.expect("connecting to the test server");
+    sqlx::raw_sql(&format!("DROP DATABASE IF EXISTS `{0}`; CREATE DATABASE `{0}`", name))
+        .execute(&mut conn)
+        .await
+        .expect("recreating the test database");
+    conn.close().await.ok();
+
+    let pool = db::create_pool(&url).await.expect("connecting to the test database");
+    seed_minimal_site(&pool).await;
+    TestSite { pool, _lock: lock }
+}
+
+/// Apply the schema and install the site the way the installer does, with
+/// caches cleared of whatever an earlier test left in them. The installer's
+/// account comes first, so accounts made by [`create_test_user`] never get
+/// uid 1, which bypasses every permission check.
+pub async fn seed_minimal_site(pool: &Pool) {
+    cache::flush_all();
+    migrations::run_migrations(pool).await.expect("applying the schema");
+    let uid = User::create(pool, "admin", "", "admin@example.com")
+        .await
+        .expect("creating the first account");
+    User::add_default_role(pool, uid).await.expect("adding the default role");
+    migrations::mark_installed(pool).await.expect("marking the site installed");
+    filter::load_settings(pool).await;
+}
+
+/// The site's templates, as the server loads them.
+pub fn test_tera() -> Tera {
+    crate::load_templates().expect("loading templates")
+}
+
+fn unique_name(prefix: &str) -> String {
+    format!("{}{}", prefix, NEXT_NAME.fetch_add(1, Ordering::Relaxed))
+}
+
+/// A role with exactly `permissions`.
+pub async fn create_test_role(pool: &Pool, permissions: &[&str]) -> Role {
+    let rid = Role::create(pool, &unique_name("role")).await.expect("creating a role");
+    let permissions: Vec<String> = permissions.iter().map(|p| p.to_string()).collect();
+    Permission::set_for_role(pool, rid, &permissions).await.expect("setting permissions");
+    Role::find_by_rid(pool, rid).await.expect("loading a role").expect("role exists")
+}
+
+/// An active account with the authenticated role and each role in `roles`,
+/// given by name.
+pub async fn create_test_user(pool: &Pool, roles: &[&str]) -> User {
+    let name = unique_name("user");
+    let uid = User::create(pool, &name, "", &format!("{}@example.com", name))
+        .await
+        .expect("creating a user");
+    User::add_default_role(pool, uid).await.expect("adding the default role");
+    for role in roles {
+        let role = Role::find_by_name(pool, role)
+            .await
+            .expect("loading a role")
+            .unwrap_or_else(|| panic!("no role named {}", role));
+        User::add_role(pool, uid, role.rid).await.expect("adding a role");
+    }
+    User::find_by_uid(pool, uid).await.expect("loading a user").expect("user exists")
+}
+
+/// A `node_type` post by `author`, published or not.
+pub async fn create_test_node(
+    pool: &Pool,
+    node_type: &str,
+    author: &User,
+    status: bool,
+) -> NodeWithBody {
+    let title = unique_name("Node ");
+    let options = NodeOptions {
+        status,
+        ..NodeOptions::new(author.uid, FORMAT_FILTERED_HTML)
+    };
+    let body = format!("Body of {}", title);
+    let (nid, _) = Node::create(pool, node_type, &title, &body, &body, &options)
+        .await
+        .expect("creating a node");
+    Node::find_with_body(pool, nid).await.expect("loading a node").expect("node exists")
+}
+
+/// A published comment by `author` on node `nid`, replying to comment
+/// `pid` (0 for none).
+pub async fn create_test_comment(pool: &Pool, nid: u32, pid: u32, author: &User) -> Comment {
+    let subject = unique_name("Comment ");
+    let cid = Comment::create(
+        pool,
+        nid,
+        pid,
+        author.uid,
+        &subject,
+        &format!("Text of {}", subject),
+        "127.0.0.1",
+        None,
+        None,
+        None,
+        COMMENT_PUBLISHED,
+    )
+    .await
+    .expect("creating a comment");
+    Comment::find_by_cid(pool, cid).await.expect("loading a comment").expect("comment exists")
+}
+
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    #[ignore = "needs DRUPAL_TEST_DATABASE_URL"]
+    async fn factories_build_a_usable_site() {
+        let site = test_site().await;
+        let pool = &site.pool;
+
+        let admin = create_test_user(pool, &["administrator"]).await;
+        let member = create_test_user(pool, &[]).await;
+        assert!(admin.has_permission(pool, "administer nodes").await.unwrap());
+        assert!(!member.has_permission(pool, "administer nodes").await.unwrap());
+        assert!(member.has_permission(pool, "post comments").await.unwrap());
+
+        let node = create_test_node(pool, "page", &member, false).await;
+        assert_eq!((node.uid, node.status), (member.uid, 0));
+
+        let parent = create_test_comment(pool, node.nid, 0, &member).await;
+        let reply = create_test_comment(pool, node.nid, parent.cid, &admin).await;
+        assert_eq!(reply.pid, parent.cid);
+        assert!(reply.thread.starts_with(parent.thread.trim_end_matches('/')));
+    }
+}
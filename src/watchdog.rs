@@ -0,0 +1,57 @@
+use axum::{
+    body::Body,
+    extract::State,
+    http::Request,
+    middleware::Next,
+    response::Response,
+};
+
+use crate::db::Pool;
+use crate::{auth::middleware::CurrentUser, error::ErrorLogEntry, models::Watchdog};
+
+/// Seconds log entries are kept unless the `watchdog_clear` variable says
+/// otherwise; older ones are removed by cron.
+pub const DEFAULT_MAX_AGE: u32 = 604800;
+
+/// Record the errors handlers return in the watchdog log, along with the
+/// page and the user. Has to run inside the auth middleware to see the user.
+pub async fn watchdog_middleware(
+    State(pool): State<Pool>,
+    request: Request<Body>,
+    next: Next,
+) -> Response {
+    let location = request
+        .uri()
+        .path_and_query()
+        .map(|pq| pq.as_str().to_string())
+        .unwrap_or_default();
+    let uid = request
+        .extensions()
+        .get::<CurrentUser>()
+        .and_then(|CurrentUser(user)| user.as_ref())
+        .map(|user| user.uid)
+        .unwrap_or(0);
+
+    let response = next.run(request).await;
+
+    if let Some(entry) = response.extensions().get::<ErrorLogEntry>().cloned() {
+        tokio::spawn(async move {
+            // Before installation there is no watchdog table to write to.
+            if let Err(e) = Watchdog::log(
+                &pool,
+                uid,
+                entry.log_type,
+                &entry.message,
+                entry.severity,
+                "",
+                &location,
+            )
+            .await
+            {
+                tracing::debug!("Could not record error in the watchdog log: {}", e);
+            }
+        });
+    }
+
+    response
+}